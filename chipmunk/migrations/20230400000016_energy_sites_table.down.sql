@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS energy_site_status;
+DROP TABLE IF EXISTS energy_sites;
@@ -0,0 +1,27 @@
+CREATE TABLE public.energy_sites (
+    id SERIAL PRIMARY KEY,
+    energy_site_id BIGINT NOT NULL UNIQUE,
+    name CHARACTER VARYING(255),
+    resource_type CHARACTER VARYING(255),
+    inserted_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL
+);
+
+CREATE TABLE public.energy_site_status (
+    id SERIAL PRIMARY KEY,
+    date TIMESTAMP WITH TIME ZONE NOT NULL,
+    site_id INT4 NOT NULL,
+    solar_power FLOAT4,
+    load_power FLOAT4,
+    grid_power FLOAT4,
+    battery_power FLOAT4,
+    percentage_charged FLOAT4,
+    energy_left FLOAT4,
+    total_pack_energy FLOAT4,
+    grid_status CHARACTER VARYING(255)
+);
+
+ALTER TABLE ONLY public.energy_site_status
+    ADD CONSTRAINT energy_site_status_site_id_fkey FOREIGN KEY (site_id) REFERENCES public.energy_sites(id) ON DELETE CASCADE;
+
+CREATE INDEX energy_site_status_site_id_date_index ON public.energy_site_status USING btree (site_id, date);
@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS car_data_timestamp_index;
+DROP TABLE IF EXISTS convert_db_checkpoint;
@@ -0,0 +1,13 @@
+-- Progress of `convertdb` for each source database and converted time range, used to resume the
+-- conversion. `last_timestamp` is the timestamp of the last converted car_data row.
+CREATE TABLE public.convert_db_checkpoint (
+    source TEXT NOT NULL,
+    range_start BIGINT NOT NULL,
+    range_end BIGINT NOT NULL,
+    last_timestamp BIGINT NOT NULL,
+    num_rows BIGINT NOT NULL,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    PRIMARY KEY (source, range_start, range_end)
+);
+
+CREATE INDEX IF NOT EXISTS car_data_timestamp_index ON public.car_data USING btree (timestamp);
@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS charging_tiers;
+DROP TABLE IF EXISTS tariffs;
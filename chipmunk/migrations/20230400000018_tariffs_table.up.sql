@@ -0,0 +1,43 @@
+-- Time of use electricity prices of a geofence
+--
+-- A tariff applies on the weekdays in `weekdays` (bit 0 is Monday, bit 6 is Sunday) from
+-- `start_time` to `end_time` in the local time of `time_zone`. A window with `end_time` before
+-- `start_time` ends on the next day, a window with equal times lasts the whole day. `valid_from`
+-- and `valid_until` are inclusive, a missing date leaves the range open.
+CREATE TABLE public.tariffs (
+    id SERIAL PRIMARY KEY,
+    geofence_id integer NOT NULL,
+    name character varying(255) NOT NULL,
+    weekdays smallint DEFAULT 127 NOT NULL,
+    start_time time without time zone DEFAULT '00:00' NOT NULL,
+    end_time time without time zone DEFAULT '00:00' NOT NULL,
+    price_per_kwh FLOAT4 NOT NULL,
+    time_zone character varying(64) DEFAULT 'UTC' NOT NULL,
+    valid_from date,
+    valid_until date,
+    inserted_at timestamp(0) with time zone NOT NULL,
+    updated_at timestamp(0) with time zone NOT NULL
+);
+
+ALTER TABLE ONLY public.tariffs
+    ADD CONSTRAINT tariffs_geofence_id_fkey FOREIGN KEY (geofence_id) REFERENCES public.geofences(id) ON DELETE CASCADE;
+
+CREATE INDEX tariffs_geofence_id_index ON public.tariffs USING btree (geofence_id);
+
+-- Energy and cost of a charging process in each tariff
+CREATE TABLE public.charging_tiers (
+    id SERIAL PRIMARY KEY,
+    charging_process_id integer NOT NULL,
+    tariff_id integer,
+    name character varying(255) NOT NULL,
+    energy_kwh FLOAT4 NOT NULL,
+    cost FLOAT4
+);
+
+ALTER TABLE ONLY public.charging_tiers
+    ADD CONSTRAINT charging_tiers_charging_process_id_fkey FOREIGN KEY (charging_process_id) REFERENCES public.charging_processes(id) ON DELETE CASCADE;
+
+ALTER TABLE ONLY public.charging_tiers
+    ADD CONSTRAINT charging_tiers_tariff_id_fkey FOREIGN KEY (tariff_id) REFERENCES public.tariffs(id) ON DELETE SET NULL;
+
+CREATE INDEX charging_tiers_charging_process_id_index ON public.charging_tiers USING btree (charging_process_id);
@@ -0,0 +1 @@
+ALTER TABLE public.positions DROP COLUMN IF EXISTS streamed;
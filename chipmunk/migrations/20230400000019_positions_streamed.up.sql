@@ -0,0 +1,3 @@
+-- Positions logged from the streaming API are not stored in car_data and are kept when the data
+-- is re-processed
+ALTER TABLE public.positions ADD COLUMN streamed BOOLEAN NOT NULL DEFAULT FALSE;
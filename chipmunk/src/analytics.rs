@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+
+use sqlx::{PgConnection, PgPool};
+use ui_common::{BatteryCapacity, CapacityHistory};
+
+use crate::database::tables::{car::Car, charging_process::ChargingProcess, settings::Settings};
+use crate::database::types::Range;
+use crate::database::DBTable;
+
+/// Charging processes this long or shorter are not used for the estimates
+const MIN_DURATION_MIN: i16 = 10;
+/// Charging processes ending above this battery level are not used to estimate the efficiency,
+/// the range estimate of the car is less accurate near a full battery
+const MAX_END_BATTERY_LEVEL: i16 = 95;
+/// Smallest change of the battery level to estimate the battery capacity from, the battery level
+/// is only reported in whole percents
+const MIN_BATTERY_LEVEL_DELTA: i16 = 20;
+
+/// A charging process which is finished and long enough to derive metrics from
+fn is_usable(cp: &ChargingProcess) -> bool {
+    cp.end_date.is_some()
+        && cp.duration_min.is_some_and(|d| d > MIN_DURATION_MIN)
+        && cp.charge_energy_added.is_some_and(|e| e > 0.0)
+}
+
+/// Estimate the efficiency of a car in kWh per km of the preferred range, the same way as
+/// TeslaMate
+///
+/// The energy added divided by the range added is rounded to Wh/km for each charging process,
+/// the most common value is the efficiency, the most recent one if several are equally common.
+/// Returns `None` if no charging process can be used.
+pub fn estimate_efficiency(
+    charging_processes: &[ChargingProcess],
+    preferred_range: Range,
+) -> Option<f32> {
+    // Number of charging processes and index of the last one for each efficiency in Wh/km
+    let mut efficiencies: HashMap<i32, (usize, usize)> = HashMap::new();
+
+    for (index, cp) in charging_processes.iter().enumerate() {
+        if !is_usable(cp)
+            || cp
+                .end_battery_level
+                .is_none_or(|l| l > MAX_END_BATTERY_LEVEL)
+        {
+            continue;
+        }
+        let (start_range, end_range) = match preferred_range {
+            Range::Ideal => (cp.start_ideal_range_km, cp.end_ideal_range_km),
+            Range::Rated => (cp.start_rated_range_km, cp.end_rated_range_km),
+        };
+        let Some(range_added) = end_range.zip(start_range).map(|(e, s)| e - s) else {
+            continue;
+        };
+        if range_added <= 0.0 {
+            continue;
+        }
+        let energy_added = cp.charge_energy_added.unwrap_or_default();
+        let wh_per_km = (energy_added * 1000.0 / range_added).round() as i32;
+        let entry = efficiencies.entry(wh_per_km).or_default();
+        *entry = (entry.0 + 1, index);
+    }
+
+    efficiencies
+        .into_iter()
+        .max_by_key(|(_, count_and_index)| *count_and_index)
+        .map(|(wh_per_km, _)| wh_per_km as f32 / 1000.0)
+}
+
+/// Estimate the usable battery capacity from the energy added and the change of the battery
+/// level of each charging process, oldest first
+pub fn capacity_history(charging_processes: &[ChargingProcess]) -> Vec<BatteryCapacity> {
+    charging_processes
+        .iter()
+        .filter(|cp| is_usable(cp))
+        .filter_map(|cp| {
+            let start_battery_level = cp.start_battery_level?;
+            let end_battery_level = cp.end_battery_level?;
+            let delta = end_battery_level - start_battery_level;
+            if delta < MIN_BATTERY_LEVEL_DELTA {
+                return None;
+            }
+            let charge_energy_added = cp.charge_energy_added?;
+            let capacity_kwh = charge_energy_added * 100.0 / delta as f32;
+            Some(BatteryCapacity {
+                charging_process_id: cp.id,
+                date: cp.end_date?,
+                start_battery_level,
+                end_battery_level,
+                charge_energy_added,
+                capacity_kwh: (capacity_kwh * 10.0).round() / 10.0,
+            })
+        })
+        .collect()
+}
+
+/// Estimate the efficiency of a car from its charging processes and store it in the `cars`
+/// table, run this after a charging process is finished
+///
+/// The stored efficiency is kept if it cannot be estimated, e.g. it was converted from TeslaMate.
+pub async fn update_efficiency(conn: &mut PgConnection, car_id: i16) -> sqlx::Result<Option<f32>> {
+    let charging_processes = ChargingProcess::db_get_for_car(&mut *conn, car_id).await?;
+    let preferred_range = Settings::db_get_last_with(&mut *conn)
+        .await?
+        .preferred_range;
+    let efficiency = estimate_efficiency(&charging_processes, preferred_range);
+    if efficiency.is_some() {
+        Car::db_update_efficiency(&mut *conn, car_id, efficiency).await?;
+    }
+    Ok(efficiency)
+}
+
+/// Get the efficiency and the battery capacity history of a car
+pub async fn db_get_capacity_history(pool: &PgPool, car_id: i16) -> sqlx::Result<CapacityHistory> {
+    let car = Car::db_get_id(pool, car_id as i64).await?;
+    let charging_processes = ChargingProcess::db_get_for_car(pool, car_id).await?;
+    Ok(CapacityHistory {
+        car_id,
+        efficiency: car.efficiency,
+        capacity: capacity_history(&charging_processes),
+    })
+}
+
+#[test]
+fn test_estimate_efficiency() {
+    use chrono::Utc;
+
+    let cp = |id, energy_added, start_range, end_range, end_battery_level| ChargingProcess {
+        id,
+        end_date: Some(Utc::now()),
+        duration_min: Some(60),
+        charge_energy_added: Some(energy_added),
+        start_rated_range_km: Some(start_range),
+        end_rated_range_km: Some(end_range),
+        start_ideal_range_km: Some(start_range),
+        end_ideal_range_km: Some(end_range * 1.1),
+        end_battery_level: Some(end_battery_level),
+        ..Default::default()
+    };
+    let charging_processes = vec![
+        cp(1, 15.0, 100.0, 200.0, 80),  // 150 Wh/km
+        cp(2, 15.3, 100.0, 200.0, 80),  // 153 Wh/km
+        cp(3, 30.6, 100.0, 300.0, 90),  // 153 Wh/km
+        cp(4, 16.0, 100.0, 200.0, 100), // Ends above 95 %
+        cp(5, 16.0, 100.0, 200.0, 100), // Ends above 95 %
+        cp(6, 16.0, 100.0, 200.0, 100), // Ends above 95 %
+        ChargingProcess {
+            duration_min: Some(10),
+            ..cp(7, 15.0, 100.0, 200.0, 80)
+        },
+        ChargingProcess {
+            end_date: None,
+            ..cp(8, 15.0, 100.0, 200.0, 80)
+        },
+    ];
+
+    assert_eq!(
+        estimate_efficiency(&charging_processes, Range::Rated),
+        Some(0.153)
+    );
+    // The most recent of the equally common efficiencies
+    assert_eq!(
+        estimate_efficiency(&charging_processes[..2], Range::Rated),
+        Some(0.153)
+    );
+    assert_eq!(
+        estimate_efficiency(&charging_processes[..1], Range::Ideal),
+        Some(0.125)
+    );
+    assert_eq!(
+        estimate_efficiency(&charging_processes[3..], Range::Rated),
+        None
+    );
+    assert_eq!(estimate_efficiency(&[], Range::Rated), None);
+}
+
+#[test]
+fn test_capacity_history() {
+    use chrono::Utc;
+
+    let cp = |id, start_battery_level, end_battery_level, energy_added| ChargingProcess {
+        id,
+        end_date: Some(Utc::now()),
+        duration_min: Some(60),
+        start_battery_level: Some(start_battery_level),
+        end_battery_level: Some(end_battery_level),
+        charge_energy_added: Some(energy_added),
+        ..Default::default()
+    };
+    let history = capacity_history(&[
+        cp(1, 20, 80, 45.0),
+        cp(2, 50, 60, 7.5), // Battery level changed too little
+        cp(3, 10, 100, 66.15),
+        ChargingProcess {
+            duration_min: Some(5),
+            ..cp(4, 20, 80, 45.0)
+        },
+    ]);
+
+    assert_eq!(history.len(), 2);
+    assert_eq!(history[0].charging_process_id, 1);
+    assert_eq!(history[0].capacity_kwh, 75.0);
+    assert_eq!(history[1].charging_process_id, 3);
+    assert_eq!(history[1].capacity_kwh, 73.5);
+}
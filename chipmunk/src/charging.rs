@@ -1,94 +1,419 @@
 use chrono::{DateTime, Duration, Utc};
 
-use crate::database::tables::charges::Charges;
+use crate::database::tables::{
+    charges::Charges, charging_process::ChargingProcess, charging_tier::ChargingTier,
+    geofence::Geofence, tariff::Tariff,
+};
+use crate::database::types::BillingType;
 
-// FIXME: This function produces a different result than Teslamate. Need to figure out why.
+/// Charges further apart than this are a gap in the data, e.g. the car was offline
+const MAX_CHARGE_INTERVAL: Duration = Duration::minutes(10);
+
+/// Calculate the energy used from the grid in kWh, the same way as TeslaMate
+///
+/// The power of a charge is used for the interval since the previous charge. Returns `None` if
+/// no energy was used.
 pub fn calculate_energy_used(charges: &[Charges]) -> Option<f32> {
+    let intervals = energy_per_interval(charges);
+    if intervals.is_empty() {
+        return None;
+    }
+    Some(intervals.iter().map(|(_, _, energy)| energy).sum())
+}
+
+/// Charging power in kW of a charge, `phases` is the result of `determine_phases`
+fn charging_power(charge: &Charges, phases: Option<f32>) -> Option<f32> {
+    if charge.charger_phases.is_some() {
+        let current = charge.charger_actual_current? as f32;
+        let voltage = charge.charger_voltage? as f32;
+        Some(current * voltage * phases? / 1000.0)
+    } else {
+        charge.charger_power.map(|p| p as f32)
+    }
+}
+
+/// Energy used between consecutive charges in kWh, together with the middle and the length of
+/// the interval
+///
+/// Intervals without energy are left out. A gap longer than `MAX_CHARGE_INTERVAL` uses the
+/// lower power of the charges before and after it, charging could have stopped during the gap.
+fn energy_per_interval(charges: &[Charges]) -> Vec<(DateTime<Utc>, Duration, f32)> {
     let phases = determine_phases(charges);
-    let mut total_energy_used = 0.0;
-    let mut previous_date: Option<DateTime<Utc>> = None;
-
-    for charge in charges {
-        let energy_used = if charge.charger_phases.is_some() {
-            charge
-                .charger_actual_current
-                .zip(charge.charger_voltage)
-                .zip(phases)
-                .map_or(0.0, |((current, voltage), phases)| {
-                    (current * voltage) as f32 * phases / 1000.0
-                })
-        } else {
-            charge.charger_power.unwrap_or(0) as f32
-        };
+    let mut charges: Vec<(DateTime<Utc>, Option<f32>)> = charges
+        .iter()
+        .filter_map(|c| match c.date {
+            Some(date) => Some((date, charging_power(c, phases))),
+            None => {
+                log::warn!("No timestamp found in charge");
+                None
+            }
+        })
+        .collect();
+    charges.sort_by_key(|(date, _)| *date);
 
-        if charge.date.is_none() {
-            log::warn!("No timestamp found in charge");
-        }
+    charges
+        .windows(2)
+        .filter_map(|w| {
+            let [(previous_date, previous_power), (date, power)] = w else {
+                return None;
+            };
+            let interval = *date - *previous_date;
+            let power = if interval > MAX_CHARGE_INTERVAL {
+                power.zip(*previous_power).map(|(p1, p2)| p1.min(p2))
+            } else {
+                *power
+            }?;
+            let hours = interval.num_milliseconds() as f32 / 3_600_000.0;
+            let energy = power * hours;
+            (energy > 0.0).then_some((*previous_date + interval / 2, interval, energy))
+        })
+        .collect()
+}
 
-        let time_diff = charge
-            .date
-            .zip(previous_date)
-            .map_or(Duration::zero(), |(c, p)| c - p)
-            .num_seconds();
-        total_energy_used += energy_used * (time_diff as f32) / 3600.0;
-        previous_date = charge.date;
+/// Determine the number of phases used to calculate the charging power from the current and
+/// voltage
+///
+/// The phases reported by the car are corrected if they do not match the ratio of the reported
+/// power to current times voltage, e.g. cars reporting 3 phases with a phase to phase voltage.
+/// Returns `None` if the phases cannot be determined.
+fn determine_phases(charges: &[Charges]) -> Option<f32> {
+    let average = |values: Vec<f32>| {
+        (!values.is_empty()).then(|| values.iter().sum::<f32>() / values.len() as f32)
+    };
+    // Ratio of the reported power and the power from the current and voltage of a phase
+    let power_ratio = average(
+        charges
+            .iter()
+            .filter_map(|c| {
+                let power = c.charger_power? as f32 * 1000.0;
+                let phase_power = c.charger_actual_current? as f32 * c.charger_voltage? as f32;
+                (phase_power != 0.0).then(|| power / phase_power)
+            })
+            .collect(),
+    )?;
+    let reported_phases = average(
+        charges
+            .iter()
+            .filter_map(|c| c.charger_phases.map(f32::from))
+            .collect(),
+    )
+    .map(f32::round);
+    let voltage = average(
+        charges
+            .iter()
+            .filter_map(|c| c.charger_voltage.map(f32::from))
+            .collect(),
+    )
+    .unwrap_or_default();
+
+    if power_ratio <= 0.0 || charges.len() <= 15 {
+        return None;
     }
 
-    Some(total_energy_used)
+    if reported_phases == Some(power_ratio.round()) {
+        reported_phases
+    } else if reported_phases == Some(3.0) && (power_ratio / 3f32.sqrt() - 1.0).abs() <= 0.1 {
+        log::info!(
+            "Voltage correction: {}V -> {}V",
+            voltage.round(),
+            (voltage / 3f32.sqrt()).round()
+        );
+        Some(3f32.sqrt())
+    } else if (power_ratio.round() - power_ratio).abs() <= 0.3 {
+        log::info!(
+            "Phase correction: {reported_phases:?} -> {}",
+            power_ratio.round()
+        );
+        Some(power_ratio.round())
+    } else {
+        None
+    }
 }
 
-fn determine_phases(charges: &[Charges]) -> Option<f32> {
-    let mut total_power: f32 = 0.0;
-    let mut total_phases: i32 = 0;
-    let mut total_voltage: i32 = 0;
-    let mut count: i32 = 0;
-
-    for charge in charges {
-        if let (Some(current), Some(voltage), Some(power)) = (
-            charge.charger_actual_current,
-            charge.charger_voltage,
-            charge.charger_power,
-        ) {
-            if current != 0 {
-                total_power += power as f32 * 1000.0 / (current * voltage) as f32;
+/// Charging at a Tesla Supercharger
+pub fn is_supercharger(charges: &[Charges]) -> bool {
+    charges
+        .iter()
+        .any(|c| c.fast_charger_brand.as_deref() == Some("Tesla"))
+}
+
+/// Cost of a charging process
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ChargingCost {
+    /// Total cost, `None` if the geofence has no prices
+    pub cost: Option<f32>,
+    /// Energy and cost in each tariff, empty if the geofence has no tariffs
+    pub tiers: Vec<ChargingTier>,
+}
+
+fn round_to_cents(cost: f32) -> f32 {
+    (cost * 100.0).round() / 100.0
+}
+
+/// Calculate the cost of a charging process using the prices of the geofence it belongs to
+///
+/// If the geofence has tariffs, the energy used between consecutive charges is priced with the
+/// first tariff applying at that time. With per kWh billing, the energy used outside of all
+/// tariffs is priced at the cost per unit of the geofence. With per minute billing, the time
+/// charged outside of all tariffs is priced at the cost per unit of the geofence. Without
+/// tariffs, per kWh billing uses the larger of the energy used and the energy added, per minute
+/// billing uses the duration of the charging process. The session fee is added to the cost.
+///
+/// # Arguments
+///
+/// * `tariffs` - Tariffs of the geofence
+/// * `free_supercharging` - The charging process is at a Supercharger and the car charges for
+///   free there, the cost is zero
+pub fn calculate_cost(
+    charging_process: &ChargingProcess,
+    charges: &[Charges],
+    geofence: Option<&Geofence>,
+    tariffs: &[Tariff],
+    free_supercharging: bool,
+) -> ChargingCost {
+    if free_supercharging {
+        return ChargingCost {
+            cost: Some(0.0),
+            tiers: vec![],
+        };
+    }
+    let Some(geofence) = geofence else {
+        return ChargingCost::default();
+    };
+
+    let tiers = if tariffs.is_empty() {
+        vec![]
+    } else {
+        calculate_tiers(charges, tariffs, geofence)
+    };
+
+    let cost = if !tiers.is_empty() {
+        tiers
+            .iter()
+            .filter_map(|t| t.cost)
+            .reduce(|total, cost| total + cost)
+    } else {
+        match (geofence.cost_per_unit, geofence.billing_type) {
+            (Some(cost_per_unit), BillingType::PerKwh) => {
+                let energy = match (
+                    charging_process.charge_energy_used,
+                    charging_process.charge_energy_added,
+                ) {
+                    (Some(used), Some(added)) => Some(used.max(added)),
+                    (used, added) => used.or(added),
+                };
+                energy.map(|e| cost_per_unit * e)
             }
+            (Some(cost_per_unit), BillingType::PerMinute) => charging_process
+                .end_date
+                .map(|end| (end - charging_process.start_date).num_seconds() as f32 / 60.0)
+                .map(|minutes| cost_per_unit * minutes),
+            (None, _) => None,
         }
+    };
 
-        total_phases += charge.charger_phases.unwrap_or(0) as i32;
-        total_voltage += charge.charger_voltage.unwrap_or(0) as i32;
-        count += 1;
+    let cost = match (cost, geofence.session_fee) {
+        (Some(cost), session_fee) => Some(cost + session_fee.unwrap_or(0.0)),
+        (None, session_fee) => session_fee,
+    };
+    let mut tiers = tiers;
+    for tier in &mut tiers {
+        tier.cost = tier.cost.map(round_to_cents);
     }
-
-    if count == 0 {
-        return None;
+    ChargingCost {
+        cost: cost.map(round_to_cents),
+        tiers,
     }
+}
+
+/// Split the energy used by the charges into the tariffs applying at the time it was charged
+///
+/// Energy outside of all tariffs goes into a "Default" tier. With per kWh billing, its energy is
+/// priced at the cost per unit of the geofence. With per minute billing, the time charged outside
+/// of all tariffs is priced at the cost per unit of the geofence.
+fn calculate_tiers(
+    charges: &[Charges],
+    tariffs: &[Tariff],
+    geofence: &Geofence,
+) -> Vec<ChargingTier> {
+    let mut tiers: Vec<ChargingTier> = vec![];
+    // Time charged outside of all tariffs
+    let mut default_duration = Duration::zero();
 
-    let avg_power = total_power / count as f32;
-    let avg_phases = total_phases as f32 / count as f32;
-    let avg_voltage = total_voltage as f32 / count as f32;
-
-    if avg_power > 0.0 && count > 15 {
-        if avg_phases == avg_power.round() {
-            Some(avg_phases)
-        } else if avg_phases == 3.0 && (avg_power / f32::sqrt(avg_phases) - 1.0).abs() <= 0.1 {
-            log::info!(
-                "Voltage correction: {}V -> {}V",
-                avg_voltage.round(),
-                (avg_voltage / f32::sqrt(avg_phases)).round()
-            );
-            Some(f32::sqrt(avg_phases))
-        } else if (avg_power.round() - avg_power).abs() <= 0.3 {
-            log::info!("Phase correction: {} -> {}", avg_phases, avg_power.round());
-            Some(avg_power.round())
-        } else {
-            None
+    for (time, duration, energy) in energy_per_interval(charges) {
+        let tariff = tariffs.iter().find(|t| t.applies_at(time));
+        let tariff_id = tariff.map(|t| t.id);
+        if tariff.is_none() {
+            default_duration += duration;
+        }
+        match tiers.iter_mut().find(|t| t.tariff_id == tariff_id) {
+            Some(tier) => tier.energy_kwh += energy,
+            None => tiers.push(ChargingTier {
+                tariff_id,
+                name: tariff.map_or("Default".into(), |t| t.name.clone()),
+                energy_kwh: energy,
+                ..Default::default()
+            }),
         }
-    } else {
-        None
     }
+
+    for tier in &mut tiers {
+        let tariff = tier
+            .tariff_id
+            .and_then(|id| tariffs.iter().find(|t| t.id == id));
+        tier.cost = match (tariff, geofence.billing_type) {
+            (Some(tariff), _) => Some(tariff.price_per_kwh * tier.energy_kwh),
+            (None, BillingType::PerKwh) => geofence.cost_per_unit.map(|c| c * tier.energy_kwh),
+            (None, BillingType::PerMinute) => geofence
+                .cost_per_unit
+                .map(|c| c * default_duration.num_seconds() as f32 / 60.0),
+        };
+    }
+
+    tiers
 }
 
-// TODO: Implement this
-pub fn calculate_cost(_charges: &Charges) -> Option<f32> {
-    None
+#[test]
+fn test_calculate_cost() {
+    let start_date = Utc::now();
+    let charging_process = ChargingProcess {
+        start_date,
+        end_date: Some(start_date + Duration::try_minutes(90).unwrap()),
+        charge_energy_added: Some(20.0),
+        charge_energy_used: Some(22.5),
+        ..Default::default()
+    };
+    let per_kwh = Geofence {
+        cost_per_unit: Some(0.3),
+        billing_type: BillingType::PerKwh,
+        ..Default::default()
+    };
+    let per_minute = Geofence {
+        cost_per_unit: Some(0.1),
+        session_fee: Some(1.5),
+        billing_type: BillingType::PerMinute,
+        ..Default::default()
+    };
+
+    assert_eq!(
+        calculate_cost(&charging_process, &[], None, &[], false).cost,
+        None
+    );
+    assert_eq!(
+        calculate_cost(&charging_process, &[], None, &[], true).cost,
+        Some(0.0)
+    );
+    assert_eq!(
+        calculate_cost(&charging_process, &[], Some(&per_minute), &[], true).cost,
+        Some(0.0)
+    );
+    // The energy used is larger than the energy added
+    assert_eq!(
+        calculate_cost(&charging_process, &[], Some(&per_kwh), &[], false).cost,
+        Some(6.75)
+    );
+    let added_only = ChargingProcess {
+        charge_energy_used: None,
+        ..charging_process.clone()
+    };
+    assert_eq!(
+        calculate_cost(&added_only, &[], Some(&per_kwh), &[], false).cost,
+        Some(6.0)
+    );
+    assert_eq!(
+        calculate_cost(&charging_process, &[], Some(&per_minute), &[], false).cost,
+        Some(10.5)
+    );
+
+    // Only a session fee
+    let session_fee_only = Geofence {
+        cost_per_unit: None,
+        ..per_minute
+    };
+    assert_eq!(
+        calculate_cost(&charging_process, &[], Some(&session_fee_only), &[], false).cost,
+        Some(1.5)
+    );
+    assert_eq!(
+        calculate_cost(
+            &charging_process,
+            &[],
+            Some(&Geofence::default()),
+            &[],
+            false
+        )
+        .cost,
+        None
+    );
+}
+
+#[test]
+fn test_calculate_cost_with_tariffs() {
+    use chrono::NaiveTime;
+
+    // Charging at 11 kW from 22:00 to 02:00 UTC
+    let start_date = "2024-03-04T22:00:00Z".parse::<DateTime<Utc>>().unwrap();
+    let charges: Vec<Charges> = (0..=240)
+        .map(|minute| Charges {
+            date: Some(start_date + Duration::try_minutes(minute).unwrap()),
+            charger_power: Some(11),
+            ..Default::default()
+        })
+        .collect();
+    let charging_process = ChargingProcess {
+        start_date,
+        end_date: charges.last().unwrap().date,
+        charge_energy_used: calculate_energy_used(&charges),
+        ..Default::default()
+    };
+    let geofence = Geofence {
+        cost_per_unit: Some(0.3),
+        session_fee: Some(1.0),
+        billing_type: BillingType::PerKwh,
+        ..Default::default()
+    };
+    let off_peak = Tariff {
+        id: 1,
+        name: "Off-peak".into(),
+        start_time: NaiveTime::from_hms_opt(23, 0, 0).unwrap(),
+        end_time: NaiveTime::from_hms_opt(6, 0, 0).unwrap(),
+        price_per_kwh: 0.1,
+        ..Default::default()
+    };
+
+    let cost = calculate_cost(
+        &charging_process,
+        &charges,
+        Some(&geofence),
+        std::slice::from_ref(&off_peak),
+        false,
+    );
+    assert_eq!(cost.tiers.len(), 2);
+    let (default, off_peak_tier) = (&cost.tiers[0], &cost.tiers[1]);
+    assert_eq!(
+        (default.tariff_id, default.name.as_str()),
+        (None, "Default")
+    );
+    assert!((default.energy_kwh - 11.0).abs() < 0.01);
+    assert_eq!(default.cost, Some(3.3));
+    assert_eq!(off_peak_tier.tariff_id, Some(1));
+    assert!((off_peak_tier.energy_kwh - 33.0).abs() < 0.01);
+    assert_eq!(off_peak_tier.cost, Some(3.3));
+    assert_eq!(cost.cost, Some(7.6));
+
+    // The hour outside of the tariffs is priced per minute with per minute billing
+    let per_minute = Geofence {
+        billing_type: BillingType::PerMinute,
+        ..geofence
+    };
+    let cost = calculate_cost(
+        &charging_process,
+        &charges,
+        Some(&per_minute),
+        &[off_peak],
+        false,
+    );
+    assert_eq!(cost.tiers[0].cost, Some(18.0));
+    assert_eq!(cost.tiers[1].cost, Some(3.3));
+    assert_eq!(cost.cost, Some(22.3));
 }
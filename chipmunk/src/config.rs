@@ -5,7 +5,11 @@ use std::{
 };
 
 use anyhow::Context;
-use tesla_api::auth::AuthResponse;
+use chrono::{DateTime, Utc};
+use tesla_api::{
+    auth::AuthResponse,
+    backend::{Backend, FleetApi, FleetRegion, OwnerApi},
+};
 use tokio::sync::watch;
 
 use crate::database::{
@@ -22,6 +26,10 @@ pub struct EnvVars {
     pub car_data_database_url: Option<String>,
     pub http_port: u16,
     pub http_root: Option<String>,
+    pub tesla_backend: Arc<dyn Backend>,
+    pub streaming_fields: Vec<String>,
+    pub spool_file: String,
+    pub record_file: Option<String>,
 }
 
 pub fn load_env_vars() -> anyhow::Result<EnvVars> {
@@ -56,15 +64,66 @@ pub fn load_env_vars() -> anyhow::Result<EnvVars> {
 
     let http_root = env::var("HTTP_ROOT").ok();
 
+    let tesla_backend = load_tesla_backend()?;
+
+    // Comma separated list of fields to subscribe to using the streaming API
+    let streaming_fields = match env::var("STREAMING_FIELDS") {
+        Ok(fields) => fields
+            .split(',')
+            .map(|f| f.trim().to_string())
+            .filter(|f| !f.is_empty())
+            .collect(),
+        Err(_) => tesla_api::stream::default_fields(),
+    };
+
+    // Data is written to this file while the database is unavailable
+    let spool_file = env::var("SPOOL_FILE").unwrap_or_else(|_| "chipmunk_spool.jsonl".into());
+
+    // The received data is recorded to this file for replaying it later, zstd compressed if the
+    // file name ends with `.zst`
+    let record_file = env::var("RECORD_FILE").ok().filter(|f| !f.is_empty());
+
     Ok(EnvVars {
         encryption_key,
         database_url,
         car_data_database_url,
         http_port,
         http_root,
+        tesla_backend,
+        streaming_fields,
+        spool_file,
+        record_file,
     })
 }
 
+/// Select the Tesla API backend using the `TESLA_API` environment variable
+///
+/// `TESLA_API` can be `owner` (default) or `fleet`. The Fleet API also needs `TESLA_CLIENT_ID`,
+/// and optionally `TESLA_CLIENT_SECRET` and `TESLA_FLEET_REGION` (`na` (default), `eu` or `cn`).
+fn load_tesla_backend() -> anyhow::Result<Arc<dyn Backend>> {
+    let api = env::var("TESLA_API").unwrap_or_else(|_| "owner".into());
+    let backend: Arc<dyn Backend> = match api.to_lowercase().as_str() {
+        "owner" => Arc::new(OwnerApi),
+        "fleet" => {
+            let client_id = env::var("TESLA_CLIENT_ID")
+                .context("Please provide TESLA_CLIENT_ID to use the Fleet API")?;
+            let client_secret = env::var("TESLA_CLIENT_SECRET").ok();
+            let region = match env::var("TESLA_FLEET_REGION") {
+                Ok(region) => region
+                    .parse::<FleetRegion>()
+                    .map_err(|e| anyhow::anyhow!(e))?,
+                Err(_) => FleetRegion::NorthAmerica,
+            };
+            Arc::new(FleetApi::new(region, client_id, client_secret))
+        }
+        unknown => {
+            anyhow::bail!("Invalid value `{unknown}` for TESLA_API, expected `owner` or `fleet`")
+        }
+    };
+
+    Ok(backend)
+}
+
 #[macro_export]
 macro_rules! set_config {
     ($config_param:expr, $value:expr) => {{
@@ -146,6 +205,8 @@ pub struct Config {
     pub logging_period_ms: Arc<Mutex<Field<i32>>>,
     pub access_token: Arc<Mutex<Field<String>>>,
     pub refresh_token: Arc<Mutex<Field<String>>>,
+    pub id_token: Arc<Mutex<Field<String>>>,
+    pub access_token_expires_at: Arc<Mutex<Field<DateTime<Utc>>>>,
     pub encryption_key: Arc<Mutex<Field<String>>>,
     pub database_url: Arc<Mutex<Field<String>>>,
     pub car_data_database_url: Arc<Mutex<Field<Option<String>>>>,
@@ -154,6 +215,10 @@ pub struct Config {
     pub unit_of_length: Arc<Mutex<Field<UnitOfLength>>>,
     pub unit_of_temperature: Arc<Mutex<Field<UnitOfTemperature>>>,
     pub unit_of_pressure: Arc<Mutex<Field<UnitOfPressure>>>,
+    pub tesla_backend: Arc<dyn Backend>,
+    pub streaming_fields: Arc<Mutex<Field<Vec<String>>>>,
+    pub spool_file: Arc<Mutex<Field<String>>>,
+    pub record_file: Arc<Mutex<Field<Option<String>>>>,
 }
 
 impl Config {
@@ -175,11 +240,16 @@ impl Config {
             Settings::default()
         });
 
+        let access_token_expires_at = Utc::now()
+            + chrono::Duration::try_seconds(tokens.expires_in as i64).unwrap_or_default();
+
         Self {
             logging_enabled: Arc::new(Mutex::new(Field::new(true))),
             logging_period_ms: Arc::new(Mutex::new(Field::new(settings.logging_period_ms))),
             access_token: Arc::new(Mutex::new(Field::new(tokens.access_token))),
             refresh_token: Arc::new(Mutex::new(Field::new(tokens.refresh_token))),
+            id_token: Arc::new(Mutex::new(Field::new(tokens.id_token))),
+            access_token_expires_at: Arc::new(Mutex::new(Field::new(access_token_expires_at))),
             encryption_key: Arc::new(Mutex::new(Field::new(env_vars.encryption_key))),
             database_url: Arc::new(Mutex::new(Field::new(env_vars.database_url))),
             car_data_database_url: Arc::new(Mutex::new(Field::new(env_vars.car_data_database_url))),
@@ -188,6 +258,32 @@ impl Config {
             unit_of_length: Arc::new(Mutex::new(Field::new(settings.unit_of_length))),
             unit_of_temperature: Arc::new(Mutex::new(Field::new(settings.unit_of_temperature))),
             unit_of_pressure: Arc::new(Mutex::new(Field::new(settings.unit_of_pressure))),
+            tesla_backend: env_vars.tesla_backend,
+            streaming_fields: Arc::new(Mutex::new(Field::new(env_vars.streaming_fields))),
+            spool_file: Arc::new(Mutex::new(Field::new(env_vars.spool_file))),
+            record_file: Arc::new(Mutex::new(Field::new(env_vars.record_file))),
         }
     }
+
+    /// Copy of the config which does not write the raw vehicle data to `CAR_DATA_DATABASE_URL`,
+    /// used while reading the vehicle data from that database
+    pub fn without_car_data_database(&self) -> Self {
+        Self {
+            car_data_database_url: Arc::new(Mutex::new(Field::new(None))),
+            ..self.clone()
+        }
+    }
+
+    /// Update the tokens after they are refreshed
+    ///
+    /// The refresh token is set last, the token manager task watches it to store the new tokens in
+    /// the database.
+    pub fn set_tokens(&self, tokens: &AuthResponse) {
+        let expires_at = Utc::now()
+            + chrono::Duration::try_seconds(tokens.expires_in as i64).unwrap_or_default();
+        set_config!(self.access_token_expires_at, expires_at);
+        set_config!(self.access_token, tokens.access_token.clone());
+        set_config!(self.id_token, tokens.id_token.clone());
+        set_config!(self.refresh_token, tokens.refresh_token.clone());
+    }
 }
@@ -0,0 +1,167 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use tokio::sync::{mpsc, watch};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+use super::{send, DataSource};
+use crate::database::tables::convert_db_checkpoint::{self, ConvertDbCheckpoint};
+use crate::database::tables::vehicle_data;
+use crate::tasks::DataTypes;
+
+/// Number of rows read from the database at a time
+const BATCH_SIZE: i64 = 1000;
+/// Log the progress this often
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Vehicle data stored in the `car_data` table of a database, in timestamp order
+pub struct CarDataSource {
+    pool: PgPool,
+    /// Send the rows with a timestamp after this time in milliseconds
+    after: i64,
+    /// Send the rows up to this time in milliseconds
+    until: i64,
+    /// Stop after sending this many rows, all rows if `None`
+    max_rows: Option<i64>,
+    checkpoint: Option<Checkpoint>,
+}
+
+/// Progress of the rows sent, used by `convertdb` to continue after the processed rows
+struct Checkpoint {
+    /// Database the checkpoint is stored in
+    pool: PgPool,
+    /// Checkpoint after the last timestamp of which all rows were sent
+    tx: watch::Sender<ConvertDbCheckpoint>,
+}
+
+impl CarDataSource {
+    /// # Arguments
+    ///
+    /// * `start` - send the rows starting at this time, all rows if `None`
+    /// * `end` - send the rows up to this time, all rows if `None`
+    pub fn new(pool: PgPool, start: Option<DateTime<Utc>>, end: Option<DateTime<Utc>>) -> Self {
+        Self {
+            pool,
+            after: start.map_or(i64::MIN, |s| s.timestamp_millis() - 1),
+            until: end.map_or(i64::MAX, |e| e.timestamp_millis()),
+            max_rows: None,
+            checkpoint: None,
+        }
+    }
+
+    /// Stop after sending `max_rows` rows, the rows sharing the timestamp of the last row are
+    /// sent as well
+    pub fn with_max_rows(mut self, max_rows: i64) -> Self {
+        self.max_rows = Some(max_rows);
+        self
+    }
+
+    /// Continue after the last timestamp of `checkpoint` and store the checkpoint in `pool`
+    ///
+    /// The checkpoint is stored once the data processor received the rows after it. Returns the
+    /// checkpoint of all rows sent, store it after the data processing is done.
+    pub fn with_checkpoint(
+        mut self,
+        pool: PgPool,
+        checkpoint: ConvertDbCheckpoint,
+    ) -> (Self, watch::Receiver<ConvertDbCheckpoint>) {
+        self.after = checkpoint.last_timestamp;
+        let (tx, rx) = watch::channel(checkpoint);
+        self.checkpoint = Some(Checkpoint { pool, tx });
+        (self, rx)
+    }
+}
+
+impl DataSource for CarDataSource {
+    fn name(&self) -> String {
+        "car_data".into()
+    }
+
+    async fn run(
+        mut self,
+        data_tx: mpsc::Sender<DataTypes>,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<()> {
+        let mut num_rows =
+            vehicle_data::num_car_data_rows(&self.pool, self.after, self.until).await?;
+        if let Some(max_rows) = self.max_rows {
+            num_rows = num_rows.min(max_rows);
+        }
+        log::info!("Reading {num_rows} rows");
+
+        let mut num_sent = 0;
+        let started_at = Instant::now();
+        let mut last_progress = Instant::now();
+        // Checkpoints at the end of the previous two pages, the data processor is done with a row
+        // once the row after the next one is sent since the channel holds a single row
+        let mut page_ends: [Option<ConvertDbCheckpoint>; 2] = [None, None];
+
+        while num_sent < num_rows {
+            let batch_size = BATCH_SIZE.min(num_rows - num_sent);
+            let rows =
+                vehicle_data::db_get_page(&self.pool, self.after, self.until, batch_size).await?;
+            if rows.is_empty() {
+                break;
+            }
+
+            // Rows sent with the timestamp of the last row sent
+            let mut num_sent_with_timestamp = 0;
+            for (index, row) in rows.iter().enumerate() {
+                let data = serde_json::to_string(&row.data)?;
+                if !send(&data_tx, DataTypes::VehicleData(data), &cancellation_token).await? {
+                    return Ok(());
+                }
+                num_sent += 1;
+                num_sent_with_timestamp += 1;
+
+                let Some(ref checkpoint) = self.checkpoint else {
+                    continue;
+                };
+                if index == 0
+                    && let Some(page_end) = page_ends[0].take()
+                {
+                    convert_db_checkpoint::db_update(&checkpoint.pool, &page_end).await?;
+                }
+                if rows
+                    .get(index + 1)
+                    .is_none_or(|r| r.timestamp != row.timestamp)
+                {
+                    checkpoint.tx.send_modify(|c| {
+                        c.last_timestamp = row.timestamp;
+                        c.num_rows += num_sent_with_timestamp;
+                    });
+                    num_sent_with_timestamp = 0;
+                }
+            }
+            self.after = rows[rows.len() - 1].timestamp;
+            if let Some(ref checkpoint) = self.checkpoint {
+                page_ends = [page_ends[1].take(), Some(checkpoint.tx.borrow().clone())];
+            }
+
+            if last_progress.elapsed() >= PROGRESS_INTERVAL || num_sent >= num_rows {
+                last_progress = Instant::now();
+                log_progress(num_sent, num_rows, started_at.elapsed());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn log_progress(num_sent: i64, num_rows: i64, elapsed: Duration) {
+    let rows_per_sec = num_sent as f64 / elapsed.as_secs_f64().max(0.001);
+    let eta = Duration::from_secs_f64((num_rows - num_sent).max(0) as f64 / rows_per_sec);
+    log::info!(
+        "Read {num_sent}/{num_rows} rows ({:.1}%), {rows_per_sec:.0} rows/s, ETA {}",
+        num_sent as f64 * 100.0 / num_rows.max(1) as f64,
+        format_duration(eta)
+    );
+}
+
+/// Format a duration as hours, minutes and seconds, e.g. `1h 02m 03s`
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    format!("{}h {:02}m {:02}s", secs / 3600, secs / 60 % 60, secs % 60)
+}
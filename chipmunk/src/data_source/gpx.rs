@@ -0,0 +1,219 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use tesla_api::vehicle_data::{
+    ChargeState, ChargingState, ClimateState, DriveState, ShiftState, VehicleConfig, VehicleData,
+    VehicleState,
+};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+use super::{send, DataSource};
+use crate::tasks::DataTypes;
+use crate::utils::location::Location;
+
+/// Number of data points logged while parked before and after the drive
+const PARKED_POINTS: usize = 10;
+/// VIN of the simulated vehicle
+const VIN: &str = "5YJ3SIMULATED0001";
+/// Range of the simulated vehicle with a full battery
+const FULL_RANGE_MI: f32 = 310.0;
+/// Battery used per mile driven
+const BATTERY_PERCENT_PER_MI: f32 = 0.3;
+
+/// Simulates a drive along a GPX track, used to run chipmunk without a car
+///
+/// The vehicle is parked at the start of the track for a few data points, drives along the track
+/// and parks at the end of the track. The data points are `interval` apart, both in their
+/// timestamps and in the time they are sent.
+pub struct GpxSimulator {
+    track: Vec<Location>,
+    interval: Duration,
+}
+
+impl GpxSimulator {
+    /// Simulate a drive along the first segment of the first track in a GPX file
+    pub fn from_file(path: &Path, interval: Duration) -> anyhow::Result<Self> {
+        let file = File::open(path).with_context(|| format!("Error opening {path:?}"))?;
+        let gpx = ::gpx::read(BufReader::new(file))?;
+        let segment = gpx
+            .tracks
+            .first()
+            .and_then(|t| t.segments.first())
+            .context("No track found in GPX file")?;
+        let track = segment
+            .points
+            .iter()
+            .map(|p| Location::new(p.point().y(), p.point().x()))
+            .collect();
+
+        Ok(Self::from_locations(track, interval))
+    }
+
+    pub fn from_locations(track: Vec<Location>, interval: Duration) -> Self {
+        Self { track, interval }
+    }
+
+    /// Vehicle data of the simulated drive, the first data point is at `start_time`
+    pub fn vehicle_data(&self, start_time: DateTime<Utc>) -> Vec<VehicleData> {
+        let (Some(first), Some(last)) = (self.track.first(), self.track.last()) else {
+            return vec![];
+        };
+        // The timestamps need to advance even if the data is sent without a delay
+        let step = if self.interval.is_zero() {
+            Duration::from_secs(1)
+        } else {
+            self.interval
+        };
+
+        let mut points = vec![(*first, ShiftState::P); PARKED_POINTS];
+        points.extend(self.track.iter().map(|l| (*l, ShiftState::D)));
+        points.extend(vec![(*last, ShiftState::P); PARKED_POINTS]);
+
+        let mut odometer_mi = 10_000.0;
+        let mut battery_level = 80.0;
+        let mut prev_location = *first;
+        points
+            .into_iter()
+            .enumerate()
+            .map(|(i, (location, shift_state))| {
+                let distance_mi = prev_location.distance_to(&location).as_miles() as f32;
+                prev_location = location;
+                odometer_mi += distance_mi;
+                battery_level -= distance_mi * BATTERY_PERCENT_PER_MI;
+                let speed_mph = distance_mi / step.as_secs_f32() * 3600.0;
+
+                let timestamp = start_time + step * i as u32;
+                simulated_data(
+                    timestamp.timestamp_millis() as u64,
+                    location,
+                    shift_state,
+                    speed_mph,
+                    odometer_mi,
+                    battery_level,
+                )
+            })
+            .collect()
+    }
+}
+
+impl DataSource for GpxSimulator {
+    fn name(&self) -> String {
+        "GPX simulator".into()
+    }
+
+    async fn run(
+        self,
+        data_tx: mpsc::Sender<DataTypes>,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<()> {
+        for data in self.vehicle_data(Utc::now()) {
+            let data = serde_json::to_string(&data)?;
+            if !send(&data_tx, DataTypes::VehicleData(data), &cancellation_token).await? {
+                break;
+            }
+            tokio::select! {
+                _ = cancellation_token.cancelled() => break,
+                _ = tokio::time::sleep(self.interval) => (),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn simulated_data(
+    timestamp: u64,
+    location: Location,
+    shift_state: ShiftState,
+    speed_mph: f32,
+    odometer_mi: f32,
+    battery_level: f32,
+) -> VehicleData {
+    let driving = shift_state != ShiftState::P;
+    let range_mi = battery_level / 100.0 * FULL_RANGE_MI;
+
+    VehicleData {
+        id: Some(1),
+        vehicle_id: Some(1),
+        vin: Some(VIN.into()),
+        display_name: Some("Simulated Tesla".into()),
+        state: Some("online".into()),
+        charge_state: Some(ChargeState {
+            battery_level: Some(battery_level.round() as i16),
+            usable_battery_level: Some(battery_level.round() as i16),
+            battery_range: Some(range_mi),
+            est_battery_range: Some(range_mi),
+            ideal_battery_range: Some(range_mi),
+            charging_state: Some(ChargingState::Disconnected),
+            charge_energy_added: Some(0.0),
+            charger_power: Some(0),
+            timestamp: Some(timestamp),
+            ..Default::default()
+        }),
+        climate_state: Some(ClimateState {
+            inside_temp: Some(21.0),
+            outside_temp: Some(15.0),
+            is_climate_on: Some(driving),
+            timestamp: Some(timestamp),
+            ..Default::default()
+        }),
+        drive_state: Some(DriveState {
+            latitude: Some(location.lat),
+            longitude: Some(location.lon),
+            heading: Some(0),
+            shift_state: Some(shift_state),
+            speed: driving.then_some(speed_mph.round() as i32),
+            power: Some(if driving { speed_mph * 0.3 } else { 0.0 }),
+            timestamp: Some(timestamp),
+            ..Default::default()
+        }),
+        vehicle_config: Some(VehicleConfig {
+            car_type: Some("model3".into()),
+            trim_badging: Some("74d".into()),
+            exterior_color: Some("MidnightSilver".into()),
+            wheel_type: Some("Pinwheel18".into()),
+            spoiler_type: Some("None".into()),
+            timestamp: Some(timestamp),
+            ..Default::default()
+        }),
+        vehicle_state: Some(VehicleState {
+            car_version: Some("2024.2.7 simulated".into()),
+            locked: Some(!driving),
+            odometer: Some(odometer_mi),
+            vehicle_name: Some("Simulated Tesla".into()),
+            timestamp: Some(timestamp),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_gpx_simulator() {
+    let track = vec![
+        Location::new(37.4, -122.1),
+        Location::new(37.401, -122.1),
+        Location::new(37.402, -122.1),
+    ];
+    let data = GpxSimulator::from_locations(track, Duration::ZERO).vehicle_data(Utc::now());
+    assert_eq!(data.len(), 2 * PARKED_POINTS + 3);
+
+    let shift = |d: &VehicleData| d.drive_state.as_ref().unwrap().shift_state.clone();
+    assert_eq!(shift(&data[PARKED_POINTS - 1]), Some(ShiftState::P));
+    assert_eq!(shift(&data[PARKED_POINTS]), Some(ShiftState::D));
+    assert_eq!(shift(&data[PARKED_POINTS + 3]), Some(ShiftState::P));
+
+    // The timestamps advance by one second and the odometer by the distance driven
+    let timestamps: Vec<u64> = data.iter().filter_map(|d| d.timestamp_epoch()).collect();
+    assert!(timestamps.windows(2).all(|t| t[1] - t[0] == 1000));
+    let odometer = |d: &VehicleData| d.vehicle_state.as_ref().unwrap().odometer.unwrap();
+    let distance_mi = odometer(data.last().unwrap()) - odometer(&data[0]);
+    assert!((distance_mi - 0.138).abs() < 0.01, "{distance_mi}");
+    let speed = data[PARKED_POINTS + 1].drive_state.as_ref().unwrap().speed;
+    assert_eq!(speed, Some(249));
+}
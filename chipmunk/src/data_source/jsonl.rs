@@ -0,0 +1,49 @@
+use std::path::PathBuf;
+
+use anyhow::Context;
+use tokio::fs::File;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+use super::{send, DataSource};
+use crate::tasks::DataTypes;
+
+/// File with one vehicle data response of the Tesla API in each line
+pub struct JsonlSource {
+    path: PathBuf,
+}
+
+impl JsonlSource {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl DataSource for JsonlSource {
+    fn name(&self) -> String {
+        format!("{}", self.path.display())
+    }
+
+    async fn run(
+        self,
+        data_tx: mpsc::Sender<DataTypes>,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<()> {
+        let file = File::open(&self.path)
+            .await
+            .with_context(|| format!("Error opening {}", self.path.display()))?;
+        let mut lines = BufReader::new(file).lines();
+
+        while let Some(line) = lines.next_line().await? {
+            if line.trim().is_empty() {
+                continue;
+            }
+            if !send(&data_tx, DataTypes::VehicleData(line), &cancellation_token).await? {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+}
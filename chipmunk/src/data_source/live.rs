@@ -0,0 +1,141 @@
+use std::sync::{Arc, Mutex};
+
+use sqlx::PgPool;
+use tesla_api::rate_limiter::RateLimiter;
+use tokio::sync::{mpsc, Notify};
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+
+use super::DataSource;
+use crate::config::Config;
+use crate::supervisor::Supervisor;
+use crate::task_data_polling::data_polling_task;
+use crate::task_data_streaming::data_streaming_task;
+use crate::tasks::{get_tesla_client, tokens_from_config, DataTypes};
+
+/// IDs of a vehicle in the Tesla account
+#[derive(Debug, Clone)]
+pub struct VehicleIds {
+    /// `id` field of the vehicle, used by the REST API
+    pub id: u64,
+    /// `vehicle_id` field of the vehicle, used by the streaming API
+    pub vehicle_id: u64,
+    pub vin: String,
+}
+
+/// Polls and streams the vehicle data of the vehicles in a Tesla account
+pub struct LiveSource {
+    vehicles: Vec<VehicleIds>,
+    config: Config,
+    pool: PgPool,
+    supervisor: Supervisor,
+    /// Shared by all clients since the requests are limited per account
+    rate_limiter: Arc<Mutex<RateLimiter>>,
+}
+
+impl LiveSource {
+    pub(crate) fn new(
+        vehicles: Vec<VehicleIds>,
+        config: Config,
+        pool: PgPool,
+        supervisor: Supervisor,
+        rate_limiter: Arc<Mutex<RateLimiter>>,
+    ) -> Self {
+        Self {
+            vehicles,
+            config,
+            pool,
+            supervisor,
+            rate_limiter,
+        }
+    }
+}
+
+impl DataSource for LiveSource {
+    fn name(&self) -> String {
+        "Tesla API".into()
+    }
+
+    async fn run(
+        self,
+        data_tx: mpsc::Sender<DataTypes>,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<()> {
+        let task_tracker = TaskTracker::new();
+
+        for vehicle in self.vehicles {
+            log::info!("Logging vehicle with VIN {}", vehicle.vin);
+            // Notified by the streaming task when the vehicle is driving or charging
+            let vehicle_activity = Arc::new(Notify::new());
+
+            // Transmits streaming data
+            self.supervisor.spawn(
+                &task_tracker,
+                &format!("data_streaming_task ({})", vehicle.vin),
+                cancellation_token.clone(),
+                {
+                    let vehicle = vehicle.clone();
+                    let data_tx = data_tx.clone();
+                    let config = self.config.clone();
+                    let cancellation_token = cancellation_token.clone();
+                    let vehicle_activity = vehicle_activity.clone();
+                    move || {
+                        data_streaming_task(
+                            data_tx.clone(),
+                            config.clone(),
+                            cancellation_token.clone(),
+                            vehicle.vehicle_id,
+                            vehicle.vin.clone(),
+                            vehicle_activity.clone(),
+                        )
+                    }
+                },
+            );
+
+            // Transmits polling data
+            self.supervisor.spawn(
+                &task_tracker,
+                &format!("data_polling_task ({})", vehicle.vin),
+                cancellation_token.clone(),
+                {
+                    let data_tx = data_tx.clone();
+                    let config = self.config.clone();
+                    let pool = self.pool.clone();
+                    let cancellation_token = cancellation_token.clone();
+                    let rate_limiter = self.rate_limiter.clone();
+                    move || {
+                        let data_tx = data_tx.clone();
+                        let config = config.clone();
+                        let pool = pool.clone();
+                        let cancellation_token = cancellation_token.clone();
+                        let vehicle_activity = vehicle_activity.clone();
+                        let rate_limiter = rate_limiter.clone();
+                        async move {
+                            // Use the latest tokens, the tokens may have been refreshed since startup
+                            let mut tesla_client =
+                                get_tesla_client(&config, tokens_from_config(&config))?;
+                            tesla_client.set_rate_limiter(rate_limiter);
+                            data_polling_task(
+                                data_tx,
+                                config,
+                                cancellation_token,
+                                tesla_client,
+                                vehicle.id,
+                                vehicle_activity,
+                                &pool,
+                            )
+                            .await
+                        }
+                    }
+                },
+            );
+        }
+
+        // The supervised tasks are restarted until the source is cancelled
+        task_tracker.close();
+        cancellation_token.cancelled().await;
+        task_tracker.wait().await;
+
+        Ok(())
+    }
+}
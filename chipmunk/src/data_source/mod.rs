@@ -0,0 +1,54 @@
+use std::future::Future;
+
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+use crate::tasks::DataTypes;
+
+pub mod car_data;
+pub mod gpx;
+pub mod jsonl;
+pub mod live;
+pub mod recorder;
+pub mod replay;
+
+pub use car_data::CarDataSource;
+pub use gpx::GpxSimulator;
+pub use jsonl::JsonlSource;
+pub use live::LiveSource;
+pub use recorder::Recorder;
+pub use replay::ReplaySource;
+
+/// Source of the vehicle data and the streaming data processed by the logger
+///
+/// The data processor and the database task are the same for all sources, see
+/// `tasks::process_data_source`.
+pub trait DataSource: Send + 'static {
+    /// Name of the source shown in the logs
+    fn name(&self) -> String;
+
+    /// Send the data to `data_tx` in the order it was received
+    ///
+    /// Returns once all data is sent or when `cancellation_token` is cancelled. The data
+    /// processor exits after processing the data sent by a source that returned.
+    fn run(
+        self,
+        data_tx: mpsc::Sender<DataTypes>,
+        cancellation_token: CancellationToken,
+    ) -> impl Future<Output = anyhow::Result<()>> + Send;
+}
+
+/// Send the data unless the task is cancelled, returns false if the data was not sent
+async fn send(
+    data_tx: &mpsc::Sender<DataTypes>,
+    data: DataTypes,
+    cancellation_token: &CancellationToken,
+) -> anyhow::Result<bool> {
+    tokio::select! {
+        _ = cancellation_token.cancelled() => Ok(false),
+        result = data_tx.send(data) => {
+            result?;
+            Ok(true)
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use std::path::PathBuf;
+use std::sync::mpsc::RecvTimeoutError;
+
+use chrono::Utc;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+use super::{send, DataSource};
+use crate::recording::{Record, RecordWriter, FLUSH_INTERVAL};
+use crate::tasks::DataTypes;
+
+/// Writes the data of a source to a recording while passing it on to the data processor
+///
+/// The recording can be replayed with `ReplaySource` to reproduce issues in the data processor.
+pub struct Recorder<S> {
+    source: S,
+    path: PathBuf,
+}
+
+impl<S: DataSource> Recorder<S> {
+    /// Record the data of `source` to `path`, compressed if the file name ends with `.zst`
+    pub fn new(source: S, path: impl Into<PathBuf>) -> Self {
+        Self {
+            source,
+            path: path.into(),
+        }
+    }
+}
+
+impl<S: DataSource> DataSource for Recorder<S> {
+    fn name(&self) -> String {
+        format!(
+            "{} (recording to {})",
+            self.source.name(),
+            self.path.display()
+        )
+    }
+
+    async fn run(
+        self,
+        data_tx: mpsc::Sender<DataTypes>,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<()> {
+        let path = self.path.clone();
+        let mut writer = tokio::task::spawn_blocking(move || RecordWriter::create(path)).await??;
+        let (source_tx, mut source_rx) = mpsc::channel::<DataTypes>(1);
+
+        // The recording is written by a blocking task, the writer is not async. The records are
+        // flushed if no record was received for the flush interval.
+        let (record_tx, record_rx) = std::sync::mpsc::channel::<Record>();
+        let write = tokio::task::spawn_blocking(move || loop {
+            let result = match record_rx.recv_timeout(FLUSH_INTERVAL) {
+                Ok(record) => writer.write(&record),
+                Err(RecvTimeoutError::Timeout) => writer.flush(),
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
+            if let Err(e) = result {
+                log::error!("Error writing to recording {:?}: {e}", writer.path());
+            }
+        });
+
+        let forward = {
+            let cancellation_token = cancellation_token.clone();
+            // Move the receiver into the task, the source stops sending if forwarding stops
+            async move {
+                while let Some(data) = source_rx.recv().await {
+                    // Keep logging even if the recording cannot be written
+                    match Record::new(&data, Utc::now()) {
+                        Ok(record) => {
+                            if record_tx.send(record).is_err() {
+                                log::error!("Recording stopped, continuing without recording");
+                            }
+                        }
+                        Err(e) => log::error!("Error creating a record: {e}"),
+                    }
+                    if !send(&data_tx, data, &cancellation_token).await? {
+                        break;
+                    }
+                }
+                anyhow::Ok(())
+            }
+        };
+
+        let (source_result, forward_result) =
+            tokio::join!(self.source.run(source_tx, cancellation_token), forward);
+        // Wait until all records are written and the recording is finished
+        write.await?;
+        forward_result?;
+        source_result
+    }
+}
@@ -0,0 +1,148 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+use super::{send, DataSource};
+use crate::recording::RecordReader;
+use crate::tasks::DataTypes;
+
+/// Replays a recording made by `Recorder`
+///
+/// The time between the data points is the time between receiving them while recording, divided
+/// by `speed`.
+pub struct ReplaySource {
+    path: PathBuf,
+    speed: f64,
+}
+
+impl ReplaySource {
+    /// # Arguments
+    ///
+    /// * `speed` - 1 to replay in real time, 10 to replay ten times faster, 0 to replay without
+    ///   waiting between the data points
+    pub fn new(path: impl Into<PathBuf>, speed: f64) -> Self {
+        Self {
+            path: path.into(),
+            speed,
+        }
+    }
+
+    /// Time to wait before sending a data point received at `received_at`
+    fn delay(
+        &self,
+        prev_received_at: Option<DateTime<Utc>>,
+        received_at: DateTime<Utc>,
+    ) -> Duration {
+        let Some(prev_received_at) = prev_received_at else {
+            return Duration::ZERO;
+        };
+        if self.speed <= 0.0 {
+            return Duration::ZERO;
+        }
+        (received_at - prev_received_at)
+            .to_std()
+            .map(|d| d.div_f64(self.speed))
+            .unwrap_or_default()
+    }
+}
+
+impl DataSource for ReplaySource {
+    fn name(&self) -> String {
+        format!("recording {}", self.path.display())
+    }
+
+    async fn run(
+        self,
+        data_tx: mpsc::Sender<DataTypes>,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<()> {
+        let mut prev_received_at = None;
+
+        // The recording is read by a blocking task, the reader is not async. Reading stops when
+        // the receiver is dropped.
+        let path = self.path.clone();
+        let reader = tokio::task::spawn_blocking(move || RecordReader::open(&path)).await??;
+        let (record_tx, mut record_rx) = mpsc::channel(16);
+        let read = tokio::task::spawn_blocking(move || {
+            for record in reader {
+                if record_tx.blocking_send(record).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut i = 0;
+        while let Some(record) = record_rx.recv().await {
+            i += 1;
+            let (received_at, data) = match record.and_then(|r| Ok((r.received_at, r.into_data()?)))
+            {
+                Ok(record) => record,
+                Err(e) => {
+                    log::error!("Skipping record {i} of {}: {e}", self.path.display());
+                    continue;
+                }
+            };
+
+            let delay = self.delay(prev_received_at, received_at);
+            prev_received_at = Some(received_at);
+            tokio::select! {
+                _ = cancellation_token.cancelled() => break,
+                _ = tokio::time::sleep(delay) => (),
+            }
+
+            if !send(&data_tx, data, &cancellation_token).await? {
+                break;
+            }
+        }
+        drop(record_rx);
+        read.await?;
+
+        Ok(())
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_replay_speed() {
+    use crate::recording::{Record, RecordWriter};
+
+    let path = std::env::temp_dir().join(format!("chipmunk_replay_{}.jsonl", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    // Three data points one second apart
+    let start = Utc::now();
+    let mut writer = RecordWriter::create(&path).unwrap();
+    for i in 0..3 {
+        let data = DataTypes::VehicleData(format!(r#"{{"id":{i}}}"#));
+        let received_at = start + chrono::Duration::try_seconds(i).unwrap();
+        writer
+            .write(&Record::new(&data, received_at).unwrap())
+            .unwrap();
+    }
+    drop(writer);
+
+    // The time is paused and advanced by the delays only
+    for (speed, expected_ms) in [(0.0, 0), (20.0, 100)] {
+        let (data_tx, mut data_rx) = mpsc::channel(10);
+        let started = tokio::time::Instant::now();
+        ReplaySource::new(&path, speed)
+            .run(data_tx, CancellationToken::new())
+            .await
+            .unwrap();
+        assert_eq!(
+            started.elapsed(),
+            Duration::from_millis(expected_ms),
+            "speed {speed}"
+        );
+
+        for i in 0..3 {
+            let data = data_rx.recv().await.unwrap();
+            assert!(matches!(data, DataTypes::VehicleData(d) if d == format!(r#"{{"id":{i}}}"#)));
+        }
+        assert!(data_rx.recv().await.is_none());
+    }
+
+    std::fs::remove_file(&path).unwrap();
+}
@@ -1,3 +1,6 @@
+use std::time::Duration;
+
+use sqlx::postgres::PgPoolOptions;
 use sqlx::PgPool;
 
 pub mod tables;
@@ -9,9 +12,17 @@ pub use traits::DBTable;
 pub mod teslamate;
 pub use teslamate::Teslamate;
 
+/// Delay before retrying to connect to the database, the delay doubles after every failed attempt
+const CONNECT_INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+/// Upper limit of the delay between the connection attempts
+const CONNECT_MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// Fail the queries instead of waiting for a long time if the database becomes unreachable, the
+/// data received in the meantime is spooled to disk by the data processor
+const ACQUIRE_TIMEOUT: Duration = Duration::from_secs(5);
+
 pub async fn initialize(url: &str) -> anyhow::Result<PgPool> {
     log::info!("Connecting to postgres database: {url}");
-    let pool = PgPool::connect(url).await?;
+    let pool = connect_with_retry(url).await?;
 
     log::info!("Running database migrations");
     if let Err(e) = sqlx::migrate!("./migrations").run(&pool).await {
@@ -28,3 +39,31 @@ pub async fn initialize_car_data(url: &str) -> anyhow::Result<PgPool> {
 
     Ok(pool)
 }
+
+/// Connect to the database, retry with exponential backoff until the database is reachable
+///
+/// The database may not be up yet, e.g. when starting all containers at the same time or while
+/// the database is being upgraded. Only invalid connection options are returned as an error.
+async fn connect_with_retry(url: &str) -> anyhow::Result<PgPool> {
+    let mut backoff = CONNECT_INITIAL_BACKOFF;
+    loop {
+        match PgPoolOptions::new()
+            .acquire_timeout(ACQUIRE_TIMEOUT)
+            .connect(url)
+            .await
+        {
+            Ok(pool) => return Ok(pool),
+            Err(e @ sqlx::Error::Configuration(_)) => return Err(e.into()),
+            Err(e) => {
+                log::error!("Error connecting to database: {e}, retrying in {backoff:?}");
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(CONNECT_MAX_BACKOFF);
+            }
+        }
+    }
+}
+
+/// Check if the database is reachable
+pub async fn is_available(pool: &PgPool) -> bool {
+    sqlx::query("SELECT 1").execute(pool).await.is_ok()
+}
@@ -1,5 +1,5 @@
 use chrono::{DateTime, Utc};
-use sqlx::PgPool;
+use sqlx::{PgExecutor, PgPool};
 
 use crate::openstreetmap::{self, OsmResponse};
 
@@ -87,6 +87,25 @@ impl Address {
             );
         }
     }
+
+    /// Get the last address a car was driven to or charged at
+    pub async fn db_get_last_for_car(pool: &PgPool, car_id: i16) -> sqlx::Result<Self> {
+        sqlx::query_as!(
+            Self,
+            r#"
+                SELECT * FROM addresses
+                WHERE id IN (
+                    SELECT COALESCE(end_address_id, start_address_id) FROM drives WHERE car_id = $1
+                    UNION
+                    SELECT address_id FROM charging_processes WHERE car_id = $1
+                )
+                ORDER BY id DESC LIMIT 1
+            "#,
+            car_id
+        )
+        .fetch_one(pool)
+        .await
+    }
 }
 
 impl DBTable for Address {
@@ -94,7 +113,7 @@ impl DBTable for Address {
         "addresses"
     }
 
-    async fn db_insert(&self, pool: &PgPool) -> sqlx::Result<i64> {
+    async fn db_insert(&self, executor: impl PgExecutor<'_>) -> sqlx::Result<i64> {
         // NOTE: Using the 'ON CONFLICT' will cause the id field to increment even if the insert is
         // skipped due to the conflict. This will cause missing IDs in the table.
         let id = sqlx::query!(
@@ -148,7 +167,7 @@ impl DBTable for Address {
             self.osm_id,
             self.osm_type,
         )
-        .fetch_one(pool)
+        .fetch_one(executor)
         .await?
         .id;
 
@@ -4,7 +4,7 @@ use anyhow::Context;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
-use sqlx::PgPool;
+use sqlx::{PgExecutor, PgPool};
 use tesla_api::vehicle_data::VehicleData;
 
 use super::{car_settings::CarSettings, DBTable};
@@ -99,72 +99,18 @@ impl Car {
         }
     }
 
-    /// # Updates the efficiency of a car in the database.
-    ///
-    /// This function calculates the average efficiency of a car based on its charging processes and
-    /// updates the `efficiency` field in the `cars` table.
-    ///
-    /// The efficiency is calculated as the average of the ratio of `charge_energy_added` to the
-    /// difference between the start and end range (either `ideal` or `rated`), for all charging
-    /// processes of the car that last more than 10 minutes, end with a battery level of 95 or less,
-    /// and add more than 0.0 energy.
-    ///
-    /// The function uses a SQL query to perform the calculation and update operation in the database.
-    /// The query first calculates the efficiency for the selected car in the `charging_processes`
-    /// table and stores the results in a temporary table called `efficiency_data`. Then, it updates
-    /// the `efficiency` column in the `cars` table with the calculated efficiency values from the
-    /// temporary table.
-    ///
-    /// # Arguments
-    ///
-    /// * `pool` - A reference to the database connection pool.
-    /// * `car_id` - The ID of the car to update efficiency.
-    ///
-    /// # Returns
-    ///
-    /// Returns a `Result` indicating the success or failure of the operation. If the operation is
-    /// successful, the function returns `Ok(()) and `Err(sqlx::Error)` on failure.
-    ///
-    /// # Errors
-    ///
-    /// This function will return an error if the SQL query fails to execute, or if the number of
-    /// rows affected by the `UPDATE` operation is not 1.
-    pub async fn update_efficiency(pool: &PgPool, car_id: i16) -> sqlx::Result<()> {
+    /// Set the efficiency of a car, see `analytics::update_efficiency`
+    pub async fn db_update_efficiency(
+        executor: impl PgExecutor<'_>,
+        car_id: i16,
+        efficiency: Option<f32>,
+    ) -> sqlx::Result<()> {
         let res = sqlx::query!(
-            r#"
-            WITH efficiency_data AS (
-                SELECT
-                    ROUND(
-                        AVG(charge_energy_added / NULLIF(
-                            CASE
-                                WHEN s.preferred_range = 'ideal' THEN cp.end_ideal_range_km
-                                WHEN s.preferred_range = 'rated' THEN cp.end_rated_range_km
-                                ELSE NULL
-                            END -
-                            CASE
-                                WHEN s.preferred_range = 'ideal' THEN cp.start_ideal_range_km
-                                WHEN s.preferred_range = 'rated' THEN cp.start_rated_range_km
-                                ELSE NULL
-                            END, 0))::numeric, 5
-                    )::FLOAT4 AS efficiency
-                FROM
-                    charging_processes cp,
-                    settings s
-                WHERE
-                    cp.car_id = $1
-                    AND cp.duration_min > 10
-                    AND cp.end_battery_level <= 95
-                    AND cp.charge_energy_added > 0.0
-                    AND s.id = 1
-            )
-            UPDATE cars
-            SET efficiency = efficiency_data.efficiency
-            FROM efficiency_data
-            WHERE cars.id = $1
-            "#,
+            "UPDATE cars SET efficiency = $1 WHERE id = $2",
+            efficiency,
             car_id
         )
-        .execute(pool)
+        .execute(executor)
         .await?;
 
         if res.rows_affected() != 1 {
@@ -172,7 +118,7 @@ impl Car {
                 "Error updating efficiency. Expected to update 1 row, but updated {} rows",
                 res.rows_affected()
             );
-            log::error!("{}", msg);
+            log::error!("{msg}");
             Err(sqlx::Error::Protocol(msg))
         } else {
             Ok(())
@@ -185,7 +131,7 @@ impl DBTable for Car {
         "cars"
     }
 
-    async fn db_insert(&self, pool: &PgPool) -> sqlx::Result<i64> {
+    async fn db_insert(&self, executor: impl PgExecutor<'_>) -> sqlx::Result<i64> {
         let id = sqlx::query!(
             r#"
         INSERT INTO cars
@@ -240,7 +186,7 @@ impl DBTable for Car {
             self.display_priority,
             self.marketing_name
         )
-        .fetch_one(pool)
+        .fetch_one(executor)
         .await?
         .id;
 
@@ -253,6 +199,12 @@ impl DBTable for Car {
             .await
     }
 
+    async fn db_get_id(pool: &PgPool, id: i64) -> sqlx::Result<Self> {
+        sqlx::query_as!(Self, r#"SELECT * FROM cars WHERE id = $1"#, id as i16)
+            .fetch_one(pool)
+            .await
+    }
+
     /// Get the list of cars from the database.
     async fn db_get_all(pool: &PgPool) -> sqlx::Result<Vec<Self>> {
         sqlx::query_as!(Self, r#"SELECT * FROM cars ORDER BY id ASC"#)
@@ -1,4 +1,4 @@
-use sqlx::PgPool;
+use sqlx::{PgExecutor, PgPool};
 
 use crate::database::DBTable;
 
@@ -25,12 +25,51 @@ impl Default for CarSettings {
     }
 }
 
+impl CarSettings {
+    /// Get the settings of a car
+    ///
+    /// # Arguments
+    ///
+    /// * `eid` - `id` of the vehicle in the Tesla API
+    pub async fn db_get_for_car(pool: &PgPool, eid: i64) -> sqlx::Result<Self> {
+        sqlx::query_as!(
+            Self,
+            r#"
+                SELECT car_settings.* FROM car_settings
+                JOIN cars ON cars.settings_id = car_settings.id
+                WHERE cars.eid = $1
+            "#,
+            eid
+        )
+        .fetch_one(pool)
+        .await
+    }
+
+    /// Get the settings of a car by its `id` in the `cars` table
+    pub async fn db_get_for_car_id(
+        executor: impl PgExecutor<'_>,
+        car_id: i16,
+    ) -> sqlx::Result<Self> {
+        sqlx::query_as!(
+            Self,
+            r#"
+                SELECT car_settings.* FROM car_settings
+                JOIN cars ON cars.settings_id = car_settings.id
+                WHERE cars.id = $1
+            "#,
+            car_id
+        )
+        .fetch_one(executor)
+        .await
+    }
+}
+
 impl DBTable for CarSettings {
     fn table_name() -> &'static str {
         "car_settings"
     }
 
-    async fn db_insert(&self, pool: &PgPool) -> sqlx::Result<i64> {
+    async fn db_insert(&self, executor: impl PgExecutor<'_>) -> sqlx::Result<i64> {
         let id = sqlx::query!(
             r#"
         INSERT INTO car_settings
@@ -56,7 +95,7 @@ impl DBTable for CarSettings {
             self.free_supercharging,
             self.use_streaming_api,
         )
-        .fetch_one(pool)
+        .fetch_one(executor)
         .await?
         .id;
 
@@ -1,9 +1,12 @@
 use chrono::{DateTime, Utc};
-use sqlx::PgPool;
+use sqlx::{PgConnection, PgExecutor, PgPool};
 
-use super::{charges::Charges, DBTable};
-use crate::charging::calculate_cost;
-use crate::{charging::calculate_energy_used, database::types::ChargeStat};
+use super::{
+    car_settings::CarSettings, charges::Charges, charging_tier::ChargingTier, geofence::Geofence,
+    tariff::Tariff, DBTable,
+};
+use crate::charging::{calculate_cost, calculate_energy_used, is_supercharger, ChargingCost};
+use crate::database::types::ChargeStat;
 
 #[derive(Debug, Default, Clone, PartialEq, sqlx::FromRow)]
 pub struct ChargingProcess {
@@ -60,7 +63,7 @@ impl ChargingProcess {
             end_rated_range_km: charge_end.rated_battery_range_km,
             geofence_id,
             charge_energy_used: Some(0.0),
-            cost: calculate_cost(charge_start),
+            cost: None,
             car_id,
             position_id,
             address_id,
@@ -86,7 +89,6 @@ impl ChargingProcess {
             outside_temp_avg: charge_start.outside_temp,
             start_rated_range_km: charge_start.rated_battery_range_km,
             geofence_id,
-            cost: calculate_cost(charge_start),
             car_id,
             position_id,
             address_id,
@@ -98,13 +100,12 @@ impl ChargingProcess {
     pub fn update(&self, charges: &Charges) -> Self {
         Self {
             duration_min: charges.date.map_or(self.duration_min, |end_date| {
-                Some((self.start_date - end_date).num_minutes() as i16)
+                Some((end_date - self.start_date).num_minutes() as i16)
             }),
             outside_temp_avg: self
                 .outside_temp_avg
                 .zip(charges.outside_temp)
                 .map_or(self.outside_temp_avg, |(a, b)| Some((a + b) / 2.0)),
-            cost: calculate_cost(charges),
             end_date: charges.date,
             end_ideal_range_km: charges.ideal_battery_range_km,
             end_battery_level: charges.battery_level,
@@ -122,26 +123,67 @@ impl ChargingProcess {
         }
     }
 
-    async fn db_get_last_id(pool: &PgPool) -> sqlx::Result<i32> {
+    /// Get the ID of the last charging process of a car
+    pub async fn db_get_last_id_for_car(
+        executor: impl PgExecutor<'_>,
+        car_id: i16,
+    ) -> sqlx::Result<i32> {
         let id = sqlx::query!(
             r#"
             SELECT id
             FROM charging_processes
+            WHERE car_id = $1
             ORDER BY start_date DESC LIMIT 1
-            "#
+            "#,
+            car_id
         )
-        .fetch_one(pool)
+        .fetch_one(executor)
         .await?
         .id;
 
         Ok(id)
     }
 
-    /// Recalculate the last charging process using the list of charges associated with thie charging process
-    pub async fn db_recalculate(pool: &PgPool, charge: Option<&Charges>) -> sqlx::Result<()> {
-        let id = ChargingProcess::db_get_last_id(pool).await?;
-        let charges = Charges::db_get_for_charging_process(pool, id).await?;
-        let cost: Option<f32> = charge.and_then(calculate_cost);
+    /// Get the last charging process of a car
+    pub async fn db_get_last_for_car(pool: &PgPool, car_id: i16) -> sqlx::Result<Self> {
+        sqlx::query_as!(
+            Self,
+            r#"
+                SELECT
+                    id,
+                    start_date,
+                    end_date,
+                    charge_energy_added,
+                    start_ideal_range_km,
+                    end_ideal_range_km,
+                    start_battery_level,
+                    end_battery_level,
+                    duration_min,
+                    outside_temp_avg,
+                    car_id,
+                    position_id,
+                    address_id,
+                    start_rated_range_km,
+                    end_rated_range_km,
+                    geofence_id,
+                    charge_energy_used,
+                    cost,
+                    charging_status AS "charging_status!: ChargeStat"
+                FROM charging_processes
+                WHERE car_id = $1
+                ORDER BY start_date DESC LIMIT 1
+            "#,
+            car_id
+        )
+        .fetch_one(pool)
+        .await
+    }
+
+    /// Recalculate a charging process using the list of charges associated with this charging process
+    ///
+    /// Returns the cost of the charging process, it is updated with every new charge.
+    pub async fn db_recalculate(conn: &mut PgConnection, id: i32) -> sqlx::Result<Option<f32>> {
+        let charges = Charges::db_get_for_charging_process(&mut *conn, id).await?;
         let energy_used = calculate_energy_used(&charges);
 
         let res = sqlx::query!(
@@ -180,17 +222,15 @@ impl ChargingProcess {
                 outside_temp_avg = charges_summary.outside_temp_avg,
                 duration_min = EXTRACT(EPOCH FROM (charge_summary.end_date - charge_summary.start_date))/60,
                 charging_status = $2,
-                cost = $3,
-                charge_energy_used = $4
+                charge_energy_used = $3
             FROM charge_summary CROSS JOIN charges_summary
             WHERE charging_processes.id = $1
             "#,
             id,
             ChargeStat::Done as ChargeStat,
-            cost,
             energy_used
         )
-        .execute(pool)
+        .execute(&mut *conn)
         .await?;
 
         if res.rows_affected() != 1 {
@@ -198,10 +238,113 @@ impl ChargingProcess {
                 "Error updating charging process. Expected to update 1 row, but updated {} rows",
                 res.rows_affected()
             );
-            Err(sqlx::Error::RowNotFound)
-        } else {
-            Ok(())
+            return Err(sqlx::Error::RowNotFound);
         }
+
+        // The cost depends on the energy and the duration calculated above
+        let charging_process = Self::db_get_id_with(&mut *conn, id).await?;
+        let geofence = match charging_process.geofence_id {
+            Some(geofence_id) => Some(Geofence::db_get_id_with(&mut *conn, geofence_id).await?),
+            None => None,
+        };
+        let free_supercharging = is_supercharger(&charges)
+            && CarSettings::db_get_for_car_id(&mut *conn, charging_process.car_id)
+                .await?
+                .free_supercharging;
+        let tariffs = match charging_process.geofence_id {
+            Some(geofence_id) => Tariff::db_get_for_geofence(&mut *conn, geofence_id).await?,
+            None => vec![],
+        };
+        let ChargingCost { cost, tiers } = calculate_cost(
+            &charging_process,
+            &charges,
+            geofence.as_ref(),
+            &tariffs,
+            free_supercharging,
+        );
+
+        sqlx::query!(
+            "UPDATE charging_processes SET cost = $1 WHERE id = $2",
+            cost,
+            id
+        )
+        .execute(&mut *conn)
+        .await?;
+        ChargingTier::db_replace_for_charging_process(&mut *conn, id, &tiers).await?;
+
+        Ok(cost)
+    }
+
+    /// Get the charging processes of a car, oldest first
+    pub async fn db_get_for_car(
+        executor: impl PgExecutor<'_>,
+        car_id: i16,
+    ) -> sqlx::Result<Vec<Self>> {
+        sqlx::query_as!(
+            Self,
+            r#"
+                SELECT
+                    id,
+                    start_date,
+                    end_date,
+                    charge_energy_added,
+                    start_ideal_range_km,
+                    end_ideal_range_km,
+                    start_battery_level,
+                    end_battery_level,
+                    duration_min,
+                    outside_temp_avg,
+                    car_id,
+                    position_id,
+                    address_id,
+                    start_rated_range_km,
+                    end_rated_range_km,
+                    geofence_id,
+                    charge_energy_used,
+                    cost,
+                    charging_status AS "charging_status!: ChargeStat"
+                FROM charging_processes
+                WHERE car_id = $1
+                ORDER BY start_date ASC
+            "#,
+            car_id
+        )
+        .fetch_all(executor)
+        .await
+    }
+
+    /// Get a charging process, use this instead of `db_get_id` to read it inside a transaction
+    pub async fn db_get_id_with(executor: impl PgExecutor<'_>, id: i32) -> sqlx::Result<Self> {
+        sqlx::query_as!(
+            Self,
+            r#"
+                SELECT
+                    id,
+                    start_date,
+                    end_date,
+                    charge_energy_added,
+                    start_ideal_range_km,
+                    end_ideal_range_km,
+                    start_battery_level,
+                    end_battery_level,
+                    duration_min,
+                    outside_temp_avg,
+                    car_id,
+                    position_id,
+                    address_id,
+                    start_rated_range_km,
+                    end_rated_range_km,
+                    geofence_id,
+                    charge_energy_used,
+                    cost,
+                    charging_status AS "charging_status!: ChargeStat"
+                FROM charging_processes
+                WHERE id = $1
+            "#,
+            id
+        )
+        .fetch_one(executor)
+        .await
     }
 }
 
@@ -210,7 +353,7 @@ impl DBTable for ChargingProcess {
         "charging_processes"
     }
 
-    async fn db_insert(&self, pool: &PgPool) -> sqlx::Result<i64> {
+    async fn db_insert(&self, executor: impl PgExecutor<'_>) -> sqlx::Result<i64> {
         let id = sqlx::query!(
             r#"
         INSERT INTO charging_processes
@@ -255,14 +398,14 @@ impl DBTable for ChargingProcess {
             self.cost,
             self.charging_status.clone() as ChargeStat,
         )
-        .fetch_one(pool)
+        .fetch_one(executor)
         .await?
         .id;
 
         Ok(id as i64)
     }
 
-    async fn db_update(&self, pool: &PgPool) -> sqlx::Result<()> {
+    async fn db_update(&self, executor: impl PgExecutor<'_>) -> sqlx::Result<()> {
         sqlx::query!(
             r#"
         UPDATE charging_processes
@@ -305,7 +448,7 @@ impl DBTable for ChargingProcess {
             self.charging_status.clone() as ChargeStat,
             self.id,
         )
-        .execute(pool)
+        .execute(executor)
         .await?;
         Ok(())
     }
@@ -343,37 +486,7 @@ impl DBTable for ChargingProcess {
     }
 
     async fn db_get_id(pool: &PgPool, id: i64) -> sqlx::Result<Self> {
-        let cp = sqlx::query_as!(
-            Self,
-            r#"
-                SELECT
-                    id,
-                    start_date,
-                    end_date,
-                    charge_energy_added,
-                    start_ideal_range_km,
-                    end_ideal_range_km,
-                    start_battery_level,
-                    end_battery_level,
-                    duration_min,
-                    outside_temp_avg,
-                    car_id,
-                    position_id,
-                    address_id,
-                    start_rated_range_km,
-                    end_rated_range_km,
-                    geofence_id,
-                    charge_energy_used,
-                    cost,
-                    charging_status AS "charging_status!: ChargeStat"
-                FROM charging_processes
-                WHERE id = $1
-            "#,
-            id as i32
-        )
-        .fetch_one(pool)
-        .await?;
-        Ok(cp)
+        Self::db_get_id_with(pool, id as i32).await
     }
 
     async fn db_get_all(pool: &PgPool) -> sqlx::Result<Vec<Self>> {
@@ -408,3 +521,17 @@ impl DBTable for ChargingProcess {
         .await
     }
 }
+
+#[test]
+fn test_update_duration() {
+    let start_date = Utc::now();
+    let charging_process = ChargingProcess {
+        start_date,
+        ..Default::default()
+    };
+    let charge = Charges {
+        date: Some(start_date + chrono::Duration::minutes(25)),
+        ..Default::default()
+    };
+    assert_eq!(charging_process.update(&charge).duration_min, Some(25));
+}
@@ -0,0 +1,92 @@
+use sqlx::{PgConnection, PgExecutor, PgPool};
+
+use super::DBTable;
+
+/// Energy charged and its cost in one tariff of a charging process
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ChargingTier {
+    pub id: i32,
+    pub charging_process_id: i32,
+    /// `None` for the energy charged outside of all tariffs, priced at the geofence cost
+    pub tariff_id: Option<i32>,
+    pub name: String,
+    pub energy_kwh: f32,
+    pub cost: Option<f32>,
+}
+
+impl ChargingTier {
+    /// Get the tiers of a charging process
+    pub async fn db_get_for_charging_process(
+        executor: impl PgExecutor<'_>,
+        charging_process_id: i32,
+    ) -> sqlx::Result<Vec<Self>> {
+        sqlx::query_as!(
+            Self,
+            r#"SELECT * FROM charging_tiers WHERE charging_process_id = $1 ORDER BY id ASC"#,
+            charging_process_id
+        )
+        .fetch_all(executor)
+        .await
+    }
+
+    /// Replace the tiers of a charging process
+    pub async fn db_replace_for_charging_process(
+        conn: &mut PgConnection,
+        charging_process_id: i32,
+        tiers: &[Self],
+    ) -> sqlx::Result<()> {
+        sqlx::query!(
+            "DELETE FROM charging_tiers WHERE charging_process_id = $1",
+            charging_process_id
+        )
+        .execute(&mut *conn)
+        .await?;
+        for tier in tiers {
+            Self {
+                charging_process_id,
+                ..tier.clone()
+            }
+            .db_insert(&mut *conn)
+            .await?;
+        }
+        Ok(())
+    }
+}
+
+impl DBTable for ChargingTier {
+    fn table_name() -> &'static str {
+        "charging_tiers"
+    }
+
+    async fn db_insert(&self, executor: impl PgExecutor<'_>) -> sqlx::Result<i64> {
+        let id = sqlx::query!(
+            r#"
+        INSERT INTO charging_tiers
+        (
+            charging_process_id,
+            tariff_id,
+            name,
+            energy_kwh,
+            cost
+        )
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING id"#,
+            self.charging_process_id,
+            self.tariff_id,
+            self.name,
+            self.energy_kwh,
+            self.cost,
+        )
+        .fetch_one(executor)
+        .await?
+        .id;
+
+        Ok(id as i64)
+    }
+
+    async fn db_get_all(pool: &PgPool) -> sqlx::Result<Vec<Self>> {
+        sqlx::query_as!(Self, r#"SELECT * FROM charging_tiers ORDER BY id ASC"#)
+            .fetch_all(pool)
+            .await
+    }
+}
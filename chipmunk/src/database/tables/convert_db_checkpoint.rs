@@ -0,0 +1,110 @@
+use chrono::Utc;
+use sqlx::{postgres::PgConnectOptions, PgExecutor, PgPool};
+
+/// Progress of `convertdb`, the conversion continues after the last converted row
+///
+/// A checkpoint is kept for each source database and time range, converting another range or
+/// database starts from the beginning.
+#[derive(Debug, Clone, PartialEq, sqlx::FromRow)]
+pub struct ConvertDbCheckpoint {
+    /// Source database, see `source_name`
+    pub source: String,
+    /// Start of the converted time range, `i64::MIN` if not limited
+    pub range_start: i64,
+    /// End of the converted time range, `i64::MAX` if not limited
+    pub range_end: i64,
+    /// Timestamp of the last converted `car_data` row
+    pub last_timestamp: i64,
+    /// Number of rows converted so far
+    pub num_rows: i64,
+}
+
+impl ConvertDbCheckpoint {
+    /// Checkpoint of a conversion which did not convert any rows yet
+    pub fn new(source: String, range_start: i64, range_end: i64) -> Self {
+        Self {
+            source,
+            range_start,
+            range_end,
+            last_timestamp: range_start.saturating_sub(1),
+            num_rows: 0,
+        }
+    }
+}
+
+/// Name of the source database to key the checkpoints on, the host, the port and the database
+/// name of the URL without the credentials
+pub fn source_name(database_url: &str) -> String {
+    match database_url.parse::<PgConnectOptions>() {
+        Ok(options) => format!(
+            "{}:{}/{}",
+            options.get_host(),
+            options.get_port(),
+            options.get_database().unwrap_or_default()
+        ),
+        Err(e) => {
+            log::warn!("Cannot parse the database URL: {e}");
+            database_url.to_string()
+        }
+    }
+}
+
+pub async fn db_get(
+    pool: &PgPool,
+    source: &str,
+    range_start: i64,
+    range_end: i64,
+) -> sqlx::Result<Option<ConvertDbCheckpoint>> {
+    sqlx::query_as(
+        r#"
+            SELECT source, range_start, range_end, last_timestamp, num_rows
+            FROM convert_db_checkpoint
+            WHERE source = $1 AND range_start = $2 AND range_end = $3
+        "#,
+    )
+    .bind(source)
+    .bind(range_start)
+    .bind(range_end)
+    .fetch_optional(pool)
+    .await
+}
+
+pub async fn db_update(
+    executor: impl PgExecutor<'_>,
+    checkpoint: &ConvertDbCheckpoint,
+) -> sqlx::Result<()> {
+    sqlx::query(
+        r#"
+            INSERT INTO convert_db_checkpoint
+                (source, range_start, range_end, last_timestamp, num_rows, updated_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT (source, range_start, range_end) DO UPDATE
+            SET last_timestamp = EXCLUDED.last_timestamp,
+                num_rows = EXCLUDED.num_rows,
+                updated_at = EXCLUDED.updated_at
+        "#,
+    )
+    .bind(&checkpoint.source)
+    .bind(checkpoint.range_start)
+    .bind(checkpoint.range_end)
+    .bind(checkpoint.last_timestamp)
+    .bind(checkpoint.num_rows)
+    .bind(Utc::now())
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn db_delete(pool: &PgPool, checkpoint: &ConvertDbCheckpoint) -> sqlx::Result<()> {
+    sqlx::query(
+        "DELETE FROM convert_db_checkpoint WHERE source = $1 AND range_start = $2 AND range_end = $3",
+    )
+    .bind(&checkpoint.source)
+    .bind(checkpoint.range_start)
+    .bind(checkpoint.range_end)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
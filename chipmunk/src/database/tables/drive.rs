@@ -1,5 +1,5 @@
 use chrono::{DateTime, Utc};
-use sqlx::PgPool;
+use sqlx::{PgExecutor, PgPool};
 
 use super::{position::Position, DBTable};
 
@@ -119,6 +119,46 @@ impl Drive {
             ..self.clone()
         }
     }
+
+    /// Get the last drive of a car
+    pub async fn db_get_last_for_car(pool: &PgPool, car_id: i16) -> sqlx::Result<Self> {
+        sqlx::query_as!(
+            Self,
+            r#"
+                SELECT
+                    id,
+                    start_date,
+                    end_date,
+                    outside_temp_avg,
+                    speed_max,
+                    power_max,
+                    power_min,
+                    start_ideal_range_km,
+                    end_ideal_range_km,
+                    start_km,
+                    end_km,
+                    distance,
+                    duration_min,
+                    car_id,
+                    inside_temp_avg,
+                    start_address_id,
+                    end_address_id,
+                    start_rated_range_km,
+                    end_rated_range_km,
+                    start_position_id,
+                    end_position_id,
+                    start_geofence_id,
+                    end_geofence_id,
+                    in_progress
+                FROM drives
+                WHERE car_id = $1
+                ORDER BY start_date DESC LIMIT 1
+            "#,
+            car_id
+        )
+        .fetch_one(pool)
+        .await
+    }
 }
 
 impl DBTable for Drive {
@@ -126,7 +166,7 @@ impl DBTable for Drive {
         "drives"
     }
 
-    async fn db_insert(&self, pool: &PgPool) -> sqlx::Result<i64> {
+    async fn db_insert(&self, executor: impl PgExecutor<'_>) -> sqlx::Result<i64> {
         let id = sqlx::query!(
             r#"
         INSERT INTO drives
@@ -182,14 +222,14 @@ impl DBTable for Drive {
             self.end_geofence_id,
             self.in_progress,
         )
-        .fetch_one(pool)
+        .fetch_one(executor)
         .await?
         .id;
 
         Ok(id as i64)
     }
 
-    async fn db_update(&self, pool: &PgPool) -> sqlx::Result<()> {
+    async fn db_update(&self, executor: impl PgExecutor<'_>) -> sqlx::Result<()> {
         sqlx::query!(
             r#"
         UPDATE drives
@@ -242,7 +282,7 @@ impl DBTable for Drive {
             self.in_progress,
             self.id,
         )
-        .execute(pool)
+        .execute(executor)
         .await?;
         Ok(())
     }
@@ -0,0 +1,236 @@
+use chrono::{DateTime, Utc};
+use sqlx::{PgExecutor, PgPool};
+use tesla_api::energy_site::LiveStatus;
+
+use super::DBTable;
+
+/// Energy product (Powerwall or solar) in the Tesla account
+#[derive(Debug, Default, Clone)]
+pub struct EnergySite {
+    pub id: i32,
+    /// `energy_site_id` used by the Tesla API
+    pub energy_site_id: i64,
+    pub name: Option<String>,
+    pub resource_type: Option<String>,
+    pub inserted_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl EnergySite {
+    pub fn from(site: &tesla_api::energy_site::EnergySite) -> anyhow::Result<Self> {
+        Ok(Self {
+            id: 0,
+            energy_site_id: site.energy_site_id.try_into()?,
+            name: site.site_name.clone(),
+            resource_type: site.resource_type.clone(),
+            inserted_at: Utc::now(),
+            updated_at: Utc::now(),
+        })
+    }
+}
+
+impl DBTable for EnergySite {
+    fn table_name() -> &'static str {
+        "energy_sites"
+    }
+
+    /// Insert the energy site or update the name if it is already in the table
+    async fn db_insert(&self, executor: impl PgExecutor<'_>) -> sqlx::Result<i64> {
+        let id = sqlx::query!(
+            r#"
+        INSERT INTO energy_sites
+        (
+            energy_site_id,
+            name,
+            resource_type,
+            inserted_at,
+            updated_at
+        )
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (energy_site_id) DO UPDATE
+            SET name = EXCLUDED.name,
+                resource_type = EXCLUDED.resource_type,
+                updated_at = EXCLUDED.updated_at
+        RETURNING id"#,
+            self.energy_site_id,
+            self.name,
+            self.resource_type,
+            self.inserted_at,
+            self.updated_at,
+        )
+        .fetch_one(executor)
+        .await?
+        .id;
+
+        Ok(id as i64)
+    }
+}
+
+/// Solar production, home usage, grid power and Powerwall charge of an energy site
+///
+/// Power values are in watts, positive `battery_power` means the Powerwall is discharging and
+/// positive `grid_power` means power is imported from the grid.
+#[derive(Debug, Default, Clone)]
+pub struct EnergySiteStatus {
+    pub id: i32,
+    pub date: DateTime<Utc>,
+    /// ID of the row in the `energy_sites` table
+    pub site_id: i32,
+    pub solar_power: Option<f32>,
+    pub load_power: Option<f32>,
+    pub grid_power: Option<f32>,
+    pub battery_power: Option<f32>,
+    pub percentage_charged: Option<f32>,
+    pub energy_left: Option<f32>,
+    pub total_pack_energy: Option<f32>,
+    pub grid_status: Option<String>,
+}
+
+impl EnergySiteStatus {
+    /// # Arguments
+    ///
+    /// * `status` - live status received from the Tesla API
+    /// * `site_id` - ID of the row in the `energy_sites` table
+    pub fn from_live_status(status: &LiveStatus, site_id: i32) -> Self {
+        Self {
+            id: 0,
+            date: status.timestamp.unwrap_or_else(Utc::now),
+            site_id,
+            solar_power: status.solar_power.map(|v| v as f32),
+            load_power: status.load_power.map(|v| v as f32),
+            grid_power: status.grid_power.map(|v| v as f32),
+            battery_power: status.battery_power.map(|v| v as f32),
+            percentage_charged: status.percentage_charged.map(|v| v as f32),
+            energy_left: status.energy_left.map(|v| v as f32),
+            total_pack_energy: status.total_pack_energy.map(|v| v as f32),
+            grid_status: status.grid_status.clone(),
+        }
+    }
+
+    /// Get the status of the energy site between `start` and `end`, e.g. during a charging session
+    pub async fn db_get_range(
+        pool: &PgPool,
+        site_id: i32,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> sqlx::Result<Vec<Self>> {
+        sqlx::query_as!(
+            Self,
+            r#"
+                SELECT * FROM energy_site_status
+                WHERE site_id = $1 AND date BETWEEN $2 AND $3
+                ORDER BY date ASC
+            "#,
+            site_id,
+            start,
+            end
+        )
+        .fetch_all(pool)
+        .await
+    }
+}
+
+impl DBTable for EnergySiteStatus {
+    fn table_name() -> &'static str {
+        "energy_site_status"
+    }
+
+    async fn db_insert(&self, executor: impl PgExecutor<'_>) -> sqlx::Result<i64> {
+        let id = sqlx::query!(
+            r#"
+        INSERT INTO energy_site_status
+        (
+            date,
+            site_id,
+            solar_power,
+            load_power,
+            grid_power,
+            battery_power,
+            percentage_charged,
+            energy_left,
+            total_pack_energy,
+            grid_status
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+        RETURNING id"#,
+            self.date,
+            self.site_id,
+            self.solar_power,
+            self.load_power,
+            self.grid_power,
+            self.battery_power,
+            self.percentage_charged,
+            self.energy_left,
+            self.total_pack_energy,
+            self.grid_status,
+        )
+        .fetch_one(executor)
+        .await?
+        .id;
+
+        Ok(id as i64)
+    }
+
+    async fn db_get_last(pool: &PgPool) -> sqlx::Result<Self> {
+        sqlx::query_as!(
+            Self,
+            r#"
+                SELECT * FROM energy_site_status
+                ORDER BY date DESC LIMIT 1
+            "#
+        )
+        .fetch_one(pool)
+        .await
+    }
+}
+
+#[tokio::test]
+async fn test_energy_site_insertion() {
+    dotenvy::dotenv().ok();
+    let url = &std::env::var("TEST_DATABASE_URL")
+        .expect("Cannot get test database URL from environment variable, Please set env `TEST_DATABASE_URL`");
+    let pool = crate::database::initialize(url)
+        .await
+        .expect("Error initializing database");
+
+    let site = tesla_api::energy_site::EnergySite {
+        energy_site_id: 987654321,
+        site_name: Some("Home".into()),
+        resource_type: Some("battery".into()),
+        ..Default::default()
+    };
+    let id = EnergySite::from(&site)
+        .unwrap()
+        .db_insert(&pool)
+        .await
+        .expect("Error inserting energy site");
+    // Inserting the same site again returns the existing row
+    let same_id = EnergySite::from(&site)
+        .unwrap()
+        .db_insert(&pool)
+        .await
+        .expect("Error inserting energy site");
+    assert_eq!(id, same_id);
+
+    let status = LiveStatus {
+        solar_power: Some(4200.0),
+        load_power: Some(900.0),
+        percentage_charged: Some(64.5),
+        ..Default::default()
+    };
+    let status = EnergySiteStatus::from_live_status(&status, id as i32);
+    status
+        .db_insert(&pool)
+        .await
+        .expect("Error inserting energy site status");
+
+    let statuses = EnergySiteStatus::db_get_range(
+        &pool,
+        id as i32,
+        status.date - chrono::Duration::try_seconds(1).unwrap(),
+        status.date + chrono::Duration::try_seconds(1).unwrap(),
+    )
+    .await
+    .expect("Error reading energy site status");
+    assert!(statuses.iter().any(|s| s.solar_power == Some(4200.0)));
+}
@@ -1,12 +1,13 @@
 use chrono::{DateTime, Utc};
-use sqlx::PgPool;
+use sqlx::{PgExecutor, PgPool};
 
 use crate::database::types::BillingType;
 
 use super::DBTable;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Geofence {
+    pub id: i32,
     pub name: String,
     pub latitude: f64,
     pub longitude: f64,
@@ -21,6 +22,7 @@ pub struct Geofence {
 impl Default for Geofence {
     fn default() -> Self {
         Self {
+            id: 0,
             name: "".into(),
             latitude: 0.0,
             longitude: 0.0,
@@ -39,7 +41,7 @@ impl DBTable for Geofence {
         "geofences"
     }
 
-    async fn db_insert(&self, pool: &PgPool) -> sqlx::Result<i64> {
+    async fn db_insert(&self, executor: impl PgExecutor<'_>) -> sqlx::Result<i64> {
         let id = sqlx::query!(
             r#"
         INSERT INTO geofences
@@ -66,85 +68,129 @@ impl DBTable for Geofence {
             self.session_fee,
             self.billing_type as BillingType,
         )
-        .fetch_one(pool)
+        .fetch_one(executor)
         .await?
         .id;
 
         Ok(id as i64)
     }
-}
 
-// pub async fn apply_geofence(pool: &PgPool, lat: f32, lon: f32, radius: f32) -> anyhow::Result<()> {
-//     let except_id = -1; // TODO: find value of this from teslamate source code
+    async fn db_update(&self, executor: impl PgExecutor<'_>) -> sqlx::Result<()> {
+        let result = sqlx::query!(
+            r#"
+        UPDATE geofences
+        SET
+            name = $1,
+            latitude = $2,
+            longitude = $3,
+            radius = $4,
+            updated_at = $5,
+            cost_per_unit = $6,
+            session_fee = $7,
+            billing_type = $8
+        WHERE id = $9"#,
+            self.name,
+            self.latitude,
+            self.longitude,
+            self.radius,
+            self.updated_at,
+            self.cost_per_unit,
+            self.session_fee,
+            self.billing_type as BillingType,
+            self.id,
+        )
+        .execute(executor)
+        .await?;
 
-//     // Set start_geofence_id in drives table
-//     sqlx::query!(
-//         r#"
-//         UPDATE drives m
-//         SET start_geofence_id = (
-//           SELECT id
-//           FROM geofences g
-//           WHERE
-//             earth_box(ll_to_earth(g.latitude, g.longitude), g.radius) @> ll_to_earth(p.latitude, p.longitude) AND
-//             earth_distance(ll_to_earth(g.latitude, g.longitude), ll_to_earth(latitude, p.longitude)) < g.radius AND
-//             g.id != $4
-//           ORDER BY
-//             earth_distance(ll_to_earth(g.latitude, g.longitude), ll_to_earth(latitude, p.longitude)) ASC
-//           LIMIT 1
-//         )
-//         FROM positions p
-//         WHERE
-//           m.start_position_id = p.id AND
-//           earth_box(ll_to_earth($1::real, $2::real), $3::real) @> ll_to_earth(p.latitude, p.longitude) AND
-//           earth_distance(ll_to_earth($1, $2), ll_to_earth(latitude, p.longitude)) < $3
-//         "#, lat, lon, radius, except_id
-//     ).execute(pool).await?;
+        if result.rows_affected() == 0 {
+            return Err(sqlx::Error::RowNotFound);
+        }
+        Ok(())
+    }
 
-//     // Set end_geofence_id in drives table
-//     sqlx::query!(
-//         r#"
-//         UPDATE drives m
-//         SET end_geofence_id = (
-//           SELECT id
-//           FROM geofences g
-//           WHERE
-//             earth_box(ll_to_earth(g.latitude, g.longitude), g.radius) @> ll_to_earth(p.latitude, p.longitude) AND
-//             earth_distance(ll_to_earth(g.latitude, g.longitude), ll_to_earth(latitude, p.longitude)) < g.radius AND
-//             g.id != $4
-//           ORDER BY
-//             earth_distance(ll_to_earth(g.latitude, g.longitude), ll_to_earth(latitude, p.longitude)) ASC
-//           LIMIT 1
-//         )
-//         FROM positions p
-//         WHERE
-//           m.end_position_id = p.id AND
-//           earth_box(ll_to_earth($1::real, $2::real), $3::real) @> ll_to_earth(p.latitude, p.longitude) AND
-//           earth_distance(ll_to_earth($1, $2), ll_to_earth(latitude, p.longitude)) < $3
-//         "#, lat, lon, radius, except_id
-//     ).execute(pool).await?;
+    async fn db_get_id(pool: &PgPool, id: i64) -> sqlx::Result<Self> {
+        Self::db_get_id_with(pool, id as i32).await
+    }
+
+    async fn db_get_all(pool: &PgPool) -> sqlx::Result<Vec<Self>> {
+        Self::db_get_all_with(pool).await
+    }
+}
+
+impl Geofence {
+    /// Create a geofence from the one sent by the web interface, the timestamps are set to now
+    pub fn from_ui_struct(geofence: &ui_common::Geofence) -> Self {
+        Self {
+            id: geofence.id,
+            name: geofence.name.clone(),
+            latitude: geofence.latitude,
+            longitude: geofence.longitude,
+            radius: geofence.radius,
+            inserted_at: Utc::now(),
+            updated_at: Utc::now(),
+            cost_per_unit: geofence.cost_per_unit,
+            session_fee: geofence.session_fee,
+            billing_type: BillingType::from_ui_struct(&geofence.billing_type),
+        }
+    }
 
-//     // Set geofence_id in charging_processes table
-//     sqlx::query!(
-//         r#"
-//         UPDATE charging_processes m
-//         SET geofence_id = (
-//           SELECT id
-//           FROM geofences g
-//           WHERE
-//             earth_box(ll_to_earth(g.latitude, g.longitude), g.radius) @> ll_to_earth(p.latitude, p.longitude) AND
-//             earth_distance(ll_to_earth(g.latitude, g.longitude), ll_to_earth(latitude, p.longitude)) < g.radius AND
-//             g.id != $4
-//           ORDER BY
-//             earth_distance(ll_to_earth(g.latitude, g.longitude), ll_to_earth(latitude, p.longitude)) ASC
-//           LIMIT 1
-//         )
-//         FROM positions p
-//         WHERE
-//           m.position_id = p.id AND
-//           earth_box(ll_to_earth($1::real, $2::real), $3::real) @> ll_to_earth(p.latitude, p.longitude) AND
-//           earth_distance(ll_to_earth($1, $2), ll_to_earth(latitude, p.longitude)) < $3
-//         "#, lat, lon, radius, except_id
-//     ).execute(pool).await?;
+    pub fn to_ui_struct(&self) -> ui_common::Geofence {
+        ui_common::Geofence {
+            id: self.id,
+            name: self.name.clone(),
+            latitude: self.latitude,
+            longitude: self.longitude,
+            radius: self.radius,
+            cost_per_unit: self.cost_per_unit,
+            session_fee: self.session_fee,
+            billing_type: self.billing_type.to_ui_struct(),
+        }
+    }
 
-//     Ok(())
-// }
+    /// Get a geofence, use this instead of `db_get_id` to read it inside a transaction
+    pub async fn db_get_id_with(executor: impl PgExecutor<'_>, id: i32) -> sqlx::Result<Self> {
+        sqlx::query_as!(
+            Self,
+            r#"
+        SELECT
+            id,
+            name,
+            latitude,
+            longitude,
+            radius,
+            inserted_at,
+            updated_at,
+            cost_per_unit,
+            session_fee,
+            billing_type AS "billing_type: BillingType"
+        FROM geofences
+        WHERE id = $1"#,
+            id
+        )
+        .fetch_one(executor)
+        .await
+    }
+
+    /// Get all geofences, use this instead of `db_get_all` to read them inside a transaction
+    pub async fn db_get_all_with(executor: impl PgExecutor<'_>) -> sqlx::Result<Vec<Self>> {
+        sqlx::query_as!(
+            Self,
+            r#"
+        SELECT
+            id,
+            name,
+            latitude,
+            longitude,
+            radius,
+            inserted_at,
+            updated_at,
+            cost_per_unit,
+            session_fee,
+            billing_type AS "billing_type: BillingType"
+        FROM geofences
+        ORDER BY id ASC"#
+        )
+        .fetch_all(executor)
+        .await
+    }
+}
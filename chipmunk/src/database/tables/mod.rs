@@ -1,7 +1,10 @@
 use chrono::{DateTime, Utc};
-use sqlx::PgPool;
+use sqlx::{Acquire, PgConnection, PgPool};
 use tesla_api::vehicle_data::VehicleData;
 
+use crate::analytics;
+use crate::database::types::ChargeStat;
+use crate::geofence::GeofenceMatcher;
 use crate::DELAYED_DATAPOINT_TIME_SEC;
 
 use self::{
@@ -23,12 +26,16 @@ pub mod car;
 pub mod car_settings;
 pub mod charges;
 pub mod charging_process;
+pub mod charging_tier;
+pub mod convert_db_checkpoint;
 pub mod drive;
+pub mod energy_site;
 pub mod geofence;
 pub mod position;
 pub mod settings;
 pub mod state;
 pub mod swupdate;
+pub mod tariff;
 pub mod token;
 pub mod vehicle_data;
 
@@ -39,6 +46,8 @@ pub async fn initialize(pool: &PgPool) -> anyhow::Result<()> {
 
 #[derive(Default, Debug, Clone)]
 pub struct Tables {
+    /// ID of the car the tables belong to
+    pub car_id: i16,
     pub address: Option<Address>,
     pub car: Option<Car>,
     pub charges: Option<Charges>,
@@ -72,13 +81,6 @@ impl Tables {
         self.is_state(StateStatus::Charging)
     }
 
-    pub fn car_id(&self) -> i16 {
-        self.position.as_ref().map(|p| p.car_id).unwrap_or_else(|| {
-            log::error!("No car_id found in position table");
-            0
-        })
-    }
-
     // pub async fn from_vehicle_data(data: &VehicleData, car_id: i16) -> Self {
     //     Self {
     //         address: None,
@@ -102,29 +104,63 @@ impl Tables {
     //     }
     // }
 
+    /// Insert the tables into the database in a single transaction, nothing is inserted if any of
+    /// the tables fails
     pub async fn db_insert(&self, pool: &PgPool) -> sqlx::Result<Self> {
+        let mut tx = pool.begin().await?;
+        let tables = self.db_insert_tx(pool, &mut tx).await?;
+        tx.commit().await?;
+        Ok(tables)
+    }
+
+    /// Insert a list of tables and return the last tables with the database ID fields updated
+    ///
+    /// New positions of all the tables are inserted using a single query. Use a transaction as
+    /// `conn` to insert either all or none of the tables. The geofences are read from `pool`.
+    pub async fn db_insert_list(
+        pool: &PgPool,
+        conn: &mut PgConnection,
+        mut table_list: Vec<Tables>,
+    ) -> sqlx::Result<Self> {
+        let new_positions: Vec<Position> = table_list
+            .iter()
+            .filter_map(|t| t.position.clone())
+            .filter(|p| p.id.is_none() || p.id == Some(0))
+            .collect();
+        if !new_positions.is_empty() {
+            let mut ids = position::insert_list(&mut *conn, &new_positions)
+                .await?
+                .into_iter();
+            for p in table_list.iter_mut().filter_map(|t| t.position.as_mut()) {
+                if p.id.is_none() || p.id == Some(0) {
+                    p.id = ids.next();
+                }
+            }
+        }
+
+        let mut last_tables = Tables::default();
+        for t in table_list {
+            last_tables = t.db_insert_tx(pool, &mut *conn).await?;
+        }
+        Ok(last_tables)
+    }
+
+    async fn db_insert_tx(&self, pool: &PgPool, conn: &mut PgConnection) -> sqlx::Result<Self> {
         let mut tables = self.clone();
 
         // Insert state table
         if let Some(ref mut s) = tables.state {
             if s.id == 0 {
-                s.db_insert(pool)
-                    .await
-                    .map(|id| s.id = id as i32)
-                    .map_err(|e| log::error!("{:?}", e))
-                    .ok();
+                s.id = s.db_insert(&mut *conn).await? as i32;
             } else {
-                s.db_update(pool)
-                    .await
-                    .map_err(|e| log::error!("{:?}", e))
-                    .ok();
+                s.db_update(&mut *conn).await?;
             }
         }
 
         // Insert position and update the ID field
         if let Some(ref mut p) = tables.position {
             if p.id.is_none() || p.id == Some(0) {
-                p.db_insert(pool).await.map(|id| p.id = Some(id as i32))?; // Update id field of current_position with the id returned from the database
+                p.id = Some(p.db_insert(&mut *conn).await? as i32);
             }
         }
 
@@ -134,20 +170,43 @@ impl Tables {
                 // If address id is not 0, address is already in the database, jsut return the id
                 Some(address.id as i32)
             } else {
-                address
-                    .db_insert(pool)
-                    .await
-                    .map_err(|e| log::error!("Error inserting address into database: {e}"))
-                    .map(|id| {
+                // A missing address is not an error, use a savepoint to continue the transaction
+                // if the address cannot be inserted
+                let mut savepoint = conn.begin().await?;
+                match address.db_insert(&mut *savepoint).await {
+                    Ok(id) => {
+                        savepoint.commit().await?;
                         address.id = id;
-                        id as i32
-                    })
-                    .ok()
+                        Some(id as i32)
+                    }
+                    Err(e) => {
+                        log::error!("Error inserting address into database: {e}");
+                        savepoint.rollback().await?;
+                        None
+                    }
+                }
             }
         } else {
             None
         };
 
+        // Geofence of the current position, only looked up when a drive starts or ends or a
+        // charging process starts
+        let needs_geofence = tables
+            .drive
+            .as_ref()
+            .is_some_and(|d| d.id == 0 || !d.in_progress)
+            || tables
+                .charging_process
+                .as_ref()
+                .is_some_and(|cp| cp.id == 0);
+        let geofence_id = match tables.position {
+            Some(ref p) if needs_geofence => GeofenceMatcher::cached(pool)
+                .await?
+                .find_id(p.latitude, p.longitude),
+            _ => None,
+        };
+
         if let Some(ref mut drive) = tables.drive {
             if address_id.is_some() {
                 if drive.in_progress {
@@ -157,34 +216,25 @@ impl Tables {
                 }
             }
 
-            // let start_geofence_id = None; // TODO: Add this
-            // let end_geofence_id = None; // TODO: Add this
-
             // If starting a new drive
             if drive.id == 0 {
+                drive.start_geofence_id = geofence_id;
                 if drive.start_position_id.is_none() {
                     drive.start_position_id = tables.position.as_ref().and_then(|p| p.id);
                 }
-                let res = drive
-                    .db_insert(pool)
-                    .await
-                    .map_err(|e| log::error!("Error inserting drive into database: {e}"))
-                    .map(|id| drive.id = id as i32);
-
-                if res.is_ok() {
-                    // Update drive_id of the position entry
-                    if let Some(ref p) = tables.position {
-                        if let Err(e) = p.db_update_drive_id(pool, drive.id).await {
-                            log::error!("Error updating position with drive_id: {e}");
-                        }
-                    }
+                drive.id = drive.db_insert(&mut *conn).await? as i32;
+
+                // Update drive_id of the position entry
+                if let Some(ref p) = tables.position {
+                    p.db_update_drive_id(&mut *conn, drive.id).await?;
                 }
             } else {
                 // update the current drive
                 drive.end_position_id = tables.position.as_ref().and_then(|p| p.id);
-                if let Err(e) = drive.db_update(pool).await {
-                    log::error!("Error updating drive (id: {}): {e}", drive.id);
+                if !drive.in_progress {
+                    drive.end_geofence_id = geofence_id;
                 }
+                drive.db_update(&mut *conn).await?;
             }
         }
 
@@ -193,9 +243,10 @@ impl Tables {
                 charging_process.position_id =
                     tables.position.as_ref().and_then(|p| p.id).unwrap_or(0);
                 charging_process.address_id = address_id;
+                charging_process.geofence_id = geofence_id;
 
                 charging_process
-                    .db_insert(pool)
+                    .db_insert(&mut *conn)
                     .await
                     .map(|id| charging_process.id = id as i32)?;
             }
@@ -203,35 +254,58 @@ impl Tables {
 
         // Insert charges and update the charging process
         if let Some(ref mut charges) = tables.charges {
+            // Charges without a charging process belong to the last charging process of the car
+            charges.charging_process_id = match tables.charging_process {
+                Some(ref cp) => cp.id,
+                None => ChargingProcess::db_get_last_id_for_car(&mut *conn, tables.car_id).await?,
+            };
             charges
-                .db_insert_for_last_charging_process(pool)
+                .db_insert(&mut *conn)
                 .await
                 .map(|id| charges.id = id as i32)?;
-            ChargingProcess::db_recalculate(pool, tables.charges.as_ref()).await?;
-            Car::update_efficiency(pool, tables.car_id()).await?;
+            let cost = ChargingProcess::db_recalculate(conn, charges.charging_process_id).await?;
+            // Report the cost while charging
+            if let Some(ref mut cp) = tables.charging_process {
+                cp.cost = cost;
+            }
+            // The efficiency is estimated from finished charging processes
+            if tables
+                .charging_process
+                .as_ref()
+                .is_some_and(|cp| cp.charging_status == ChargeStat::Done)
+            {
+                analytics::update_efficiency(conn, tables.car_id).await?;
+            }
         }
 
         Ok(tables)
     }
 
-    pub async fn db_get_last(pool: &PgPool) -> Self {
-        let position = Position::db_get_last(pool)
+    /// Get the last logged tables of a car
+    pub async fn db_get_last(pool: &PgPool, car_id: i16) -> Self {
+        Self::db_get_last_at(pool, car_id, chrono::offset::Utc::now()).await
+    }
+
+    /// Get the last logged tables of a car, the drive and the charging process are continued if
+    /// they were logged less than `DELAYED_DATAPOINT_TIME_SEC` seconds before `time_now`
+    ///
+    /// Use the time of the next data point as `time_now` when processing past data.
+    pub async fn db_get_last_at(pool: &PgPool, car_id: i16, time_now: DateTime<Utc>) -> Self {
+        let position = Position::db_get_last_for_car(pool, car_id)
             .await
             .map_err(|e| log::warn!("Position: {e}"))
             .ok();
 
         let time = position.as_ref().and_then(|p| p.date);
 
-        let time_now = chrono::offset::Utc::now();
-
-        let mut state = State::db_get_last(pool)
+        let mut state = State::db_get_last_for_car(pool, car_id)
             .await
             .map_err(|e| log::warn!("State: {e}"))
             .ok();
 
         // Get the last charging process data point if it was logged less than DELAYED_DATAPOINT_TIME_SEC seconds ago
         // If it was logged more than DELAYED_DATAPOINT_TIME_SEC seconds ago, return None to create a new charging process
-        let charging_process = ChargingProcess::db_get_last(pool)
+        let charging_process = ChargingProcess::db_get_last_for_car(pool, car_id)
             .await
             .map_err(|e| log::warn!("ChargingProcess: {e}"))
             .map(|cp| {
@@ -248,11 +322,13 @@ impl Tables {
 
         // Get the last drive data point if it was logged less than DELAYED_DATAPOINT_TIME_SEC seconds ago
         // If it was logged more than DELAYED_DATAPOINT_TIME_SEC seconds ago, return None to create a new drive
-        let drive = Drive::db_get_last(pool)
+        let drive = Drive::db_get_last_for_car(pool, car_id)
             .await
             .map_err(|e| log::warn!("Drive: {e}"))
             .map(|drv| {
+                // The end date of a drive in progress is not set, use the last position instead
                 drv.end_date
+                    .or(time)
                     .map(|end_time| time_now - end_time)
                     .map(|diff| diff.num_seconds() <= DELAYED_DATAPOINT_TIME_SEC)
                     .inspect(|continue_drive| if !continue_drive {
@@ -264,15 +340,16 @@ impl Tables {
             .unwrap_or(None);
 
         Self {
-            address: Address::db_get_last(pool)
+            car_id,
+            address: Address::db_get_last_for_car(pool, car_id)
                 .await
                 .map_err(|e| log::warn!("Address: {e}"))
                 .ok(),
-            car: Car::db_get_last(pool)
+            car: Car::db_get_id(pool, car_id as i64)
                 .await
                 .map_err(|e| log::warn!("Car: {e}"))
                 .ok(),
-            charges: Charges::db_get_last(pool)
+            charges: Charges::db_get_last_for_car(pool, car_id)
                 .await
                 .map_err(|e| log::warn!("Charges: {e}"))
                 .ok(),
@@ -284,12 +361,12 @@ impl Tables {
                 .map_err(|e| log::warn!("Settings: {e}"))
                 .ok(),
             state,
-            sw_update: SoftwareUpdate::db_get_last(pool)
+            sw_update: swupdate::db_get_last_for_car(pool, car_id)
                 .await
                 .map_err(|e| log::warn!("SoftwareUpdate: {e}"))
                 .ok(),
             time,
-            raw_data: VehicleData::db_get_last(pool)
+            raw_data: vehicle_data::db_get_last_for_car(pool, car_id)
                 .await
                 .map_err(|e| log::warn!("VehicleData: {e}"))
                 .ok(),
@@ -2,7 +2,7 @@ use anyhow::Context;
 use chrono::{DateTime, Utc};
 
 use crate::srtm::get_elevation;
-use sqlx::PgPool;
+use sqlx::{PgExecutor, PgPool};
 use tesla_api::stream::StreamingData;
 use tesla_api::utils::{miles_to_km, mph_to_kmh, timestamp_to_datetime};
 use tesla_api::vehicle_data::VehicleData;
@@ -42,6 +42,9 @@ pub struct Position {
     pub tpms_pressure_fr: Option<f32>,
     pub tpms_pressure_rl: Option<f32>,
     pub tpms_pressure_rr: Option<f32>,
+    /// Logged from the streaming API, the vehicle data in `car_data` does not contain the position
+    #[sqlx(default)]
+    pub streamed: bool,
 }
 
 impl Position {
@@ -115,11 +118,13 @@ impl Position {
             tpms_pressure_fr: vehicle_state.tpms_pressure_fr,
             tpms_pressure_rl: vehicle_state.tpms_pressure_rl,
             tpms_pressure_rr: vehicle_state.tpms_pressure_rr,
+            streamed: false,
         })
     }
 
-    pub fn from_streaming_data(data: &StreamingData) -> Self {
+    pub fn from_streaming_data(data: &StreamingData, car_id: i16) -> Self {
         Self {
+            car_id,
             date: data.timestamp,
             latitude: data.est_lat,
             longitude: data.est_lng,
@@ -134,17 +139,22 @@ impl Position {
                 .est_range
                 .map(|r| Distance::from_miles(r).as_km() as f32),
             rated_battery_range_km: data.range.map(|r| Distance::from_miles(r).as_km() as f32),
+            streamed: true,
             ..Default::default()
         }
     }
 
-    pub async fn db_update_drive_id(&self, pool: &PgPool, drive_id: i32) -> sqlx::Result<()> {
+    pub async fn db_update_drive_id(
+        &self,
+        executor: impl PgExecutor<'_>,
+        drive_id: i32,
+    ) -> sqlx::Result<()> {
         sqlx::query!(
             r#"UPDATE positions SET drive_id = $1 WHERE id = $2"#,
             drive_id,
             self.id
         )
-        .execute(pool)
+        .execute(executor)
         .await?;
         Ok(())
     }
@@ -187,7 +197,8 @@ impl Position {
                     tpms_pressure_fl,
                     tpms_pressure_fr,
                     tpms_pressure_rl,
-                    tpms_pressure_rr
+                    tpms_pressure_rr,
+                    streamed
                 FROM positions WHERE drive_id = $1 AND car_id = $2
                 ORDER BY date ASC"#,
             drive_id,
@@ -196,6 +207,17 @@ impl Position {
         .fetch_all(pool)
         .await
     }
+
+    /// Get the last position of a car
+    pub async fn db_get_last_for_car(pool: &PgPool, car_id: i16) -> sqlx::Result<Self> {
+        sqlx::query_as!(
+            Self,
+            r#"SELECT * FROM positions WHERE car_id = $1 ORDER BY id DESC LIMIT 1"#,
+            car_id
+        )
+        .fetch_one(pool)
+        .await
+    }
 }
 
 impl DBTable for Position {
@@ -203,7 +225,7 @@ impl DBTable for Position {
         "positions"
     }
 
-    async fn db_insert(&self, pool: &PgPool) -> sqlx::Result<i64> {
+    async fn db_insert(&self, executor: impl PgExecutor<'_>) -> sqlx::Result<i64> {
         let id = sqlx::query!(
             r#"
         INSERT INTO positions
@@ -236,10 +258,11 @@ impl DBTable for Position {
             tpms_pressure_fl,
             tpms_pressure_fr,
             tpms_pressure_rl,
-            tpms_pressure_rr
+            tpms_pressure_rr,
+            streamed
         )
         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16,
-            $17, $18, $19, $20, $21, $22, $23, $24, $25, $26, $27, $28, $29)
+            $17, $18, $19, $20, $21, $22, $23, $24, $25, $26, $27, $28, $29, $30)
             RETURNING id"#,
             self.date,
             self.latitude,
@@ -269,9 +292,10 @@ impl DBTable for Position {
             self.tpms_pressure_fl,
             self.tpms_pressure_fr,
             self.tpms_pressure_rl,
-            self.tpms_pressure_rr
+            self.tpms_pressure_rr,
+            self.streamed
         )
-        .fetch_one(pool)
+        .fetch_one(executor)
         .await?
         .id;
 
@@ -293,8 +317,12 @@ impl DBTable for Position {
     }
 }
 
-#[allow(dead_code)]
-pub async fn insert_list(pool: &PgPool, data: &[Position]) -> sqlx::Result<()> {
+/// Insert a list of positions using a single query and return the IDs of the inserted rows in the
+/// same order as `data`
+pub async fn insert_list(
+    executor: impl PgExecutor<'_>,
+    data: &[Position],
+) -> sqlx::Result<Vec<i32>> {
     let date: Vec<_> = data.iter().map(|d| d.date).collect();
     let latitude: Vec<_> = data.iter().map(|d| d.latitude).collect();
     let longitude: Vec<_> = data.iter().map(|d| d.longitude).collect();
@@ -324,8 +352,11 @@ pub async fn insert_list(pool: &PgPool, data: &[Position]) -> sqlx::Result<()> {
     let tpms_pressure_fr: Vec<_> = data.iter().map(|d| d.tpms_pressure_fr).collect();
     let tpms_pressure_rl: Vec<_> = data.iter().map(|d| d.tpms_pressure_rl).collect();
     let tpms_pressure_rr: Vec<_> = data.iter().map(|d| d.tpms_pressure_rr).collect();
+    let streamed: Vec<_> = data.iter().map(|d| d.streamed).collect();
 
-    sqlx::query(
+    // UNNEST returns the rows in the order of the arrays and the rows are inserted and returned
+    // in the same order
+    let ids = sqlx::query_scalar(
         r#"
         INSERT INTO positions
         (
@@ -357,10 +388,12 @@ pub async fn insert_list(pool: &PgPool, data: &[Position]) -> sqlx::Result<()> {
             tpms_pressure_fl,
             tpms_pressure_fr,
             tpms_pressure_rl,
-            tpms_pressure_rr
+            tpms_pressure_rr,
+            streamed
         )
         SELECT * FROM UNNEST($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16,
-            $17, $18, $19, $20, $21, $22, $23, $24, $25, $26, $27, $28, $29)"#,
+            $17, $18, $19, $20, $21, $22, $23, $24, $25, $26, $27, $28, $29, $30)
+        RETURNING id"#,
     )
     .bind(&date)
     .bind(latitude)
@@ -391,10 +424,11 @@ pub async fn insert_list(pool: &PgPool, data: &[Position]) -> sqlx::Result<()> {
     .bind(tpms_pressure_fr)
     .bind(tpms_pressure_rl)
     .bind(tpms_pressure_rr)
-    .execute(pool)
+    .bind(streamed)
+    .fetch_all(executor)
     .await?;
 
-    Ok(())
+    Ok(ids)
 }
 
 #[allow(dead_code)]
@@ -409,3 +443,61 @@ pub async fn get_latest(pool: &sqlx::Pool<sqlx::Postgres>, car_id: i16) -> sqlx:
     .fetch_one(pool)
     .await
 }
+
+#[tokio::test]
+async fn test_position_list_insertion() {
+    use super::{car::Car, car_settings::CarSettings, DBTable};
+    use chrono::{Duration, Utc};
+
+    dotenvy::dotenv().ok();
+    let url = &std::env::var("TEST_DATABASE_URL")
+        .expect("Cannot get test database URL from environment variable, Please set env `TEST_DATABASE_URL`");
+    let pool = crate::database::initialize(url)
+        .await
+        .expect("Error initializing database");
+    // Everything is rolled back at the end of the test
+    let mut tx = pool.begin().await.expect("Error starting transaction");
+
+    let settings_id = CarSettings::default()
+        .db_insert(&mut *tx)
+        .await
+        .expect("Error inserting car settings");
+    let car_id = Car {
+        eid: 918273645,
+        vid: 918273645,
+        settings_id,
+        ..Default::default()
+    }
+    .db_insert(&mut *tx)
+    .await
+    .expect("Error inserting car") as i16;
+
+    let start = Utc::now();
+    let positions: Vec<Position> = (0..3)
+        .map(|i| Position {
+            car_id,
+            date: Some(start + Duration::try_seconds(i).unwrap()),
+            latitude: Some(12.34),
+            longitude: Some(34.56),
+            battery_level: Some(80 - i as i16),
+            ..Default::default()
+        })
+        .collect();
+    let ids = insert_list(&mut *tx, &positions)
+        .await
+        .expect("Error inserting positions");
+
+    // IDs are returned in the order of the positions
+    assert_eq!(ids.len(), 3);
+    for (position, id) in positions.iter().zip(&ids) {
+        let battery_level: Option<i16> =
+            sqlx::query_scalar("SELECT battery_level FROM positions WHERE id = $1")
+                .bind(id)
+                .fetch_one(&mut *tx)
+                .await
+                .expect("Error getting position");
+        assert_eq!(battery_level, position.battery_level);
+    }
+
+    tx.rollback().await.expect("Error rolling back transaction");
+}
@@ -1,5 +1,5 @@
 use chrono::{DateTime, Utc};
-use sqlx::PgPool;
+use sqlx::{PgExecutor, PgPool};
 
 use crate::database::{
     traits::DBTable,
@@ -42,12 +42,39 @@ impl Default for Settings {
     }
 }
 
+impl Settings {
+    /// Get the settings, use this instead of `db_get_last` to read them inside a transaction
+    pub async fn db_get_last_with(executor: impl PgExecutor<'_>) -> sqlx::Result<Self> {
+        sqlx::query_as!(
+            Self,
+            r#"
+            SELECT
+                id,
+                inserted_at,
+                updated_at,
+                unit_of_length AS "unit_of_length!: UnitOfLength",
+                unit_of_temperature AS "unit_of_temperature!: UnitOfTemperature",
+                preferred_range AS "preferred_range!: Range",
+                base_url,
+                grafana_url,
+                language,
+                unit_of_pressure AS "unit_of_pressure!: UnitOfPressure",
+                logging_period_ms,
+                log_at_startup
+            FROM settings
+            "#
+        )
+        .fetch_one(executor)
+        .await
+    }
+}
+
 impl DBTable for Settings {
     fn table_name() -> &'static str {
         "settings"
     }
 
-    async fn db_insert(&self, pool: &PgPool) -> sqlx::Result<i64> {
+    async fn db_insert(&self, executor: impl PgExecutor<'_>) -> sqlx::Result<i64> {
         let id = sqlx::query!(
             r#"
         INSERT INTO settings
@@ -91,7 +118,7 @@ impl DBTable for Settings {
             self.logging_period_ms,
             self.log_at_startup,
         )
-        .fetch_one(pool)
+        .fetch_one(executor)
         .await?
         .id;
 
@@ -99,27 +126,7 @@ impl DBTable for Settings {
     }
 
     async fn db_get_last(pool: &PgPool) -> sqlx::Result<Self> {
-        sqlx::query_as!(
-            Self,
-            r#"
-            SELECT
-                id,
-                inserted_at,
-                updated_at,
-                unit_of_length AS "unit_of_length!: UnitOfLength",
-                unit_of_temperature AS "unit_of_temperature!: UnitOfTemperature",
-                preferred_range AS "preferred_range!: Range",
-                base_url,
-                grafana_url,
-                language,
-                unit_of_pressure AS "unit_of_pressure!: UnitOfPressure",
-                logging_period_ms,
-                log_at_startup
-            FROM settings
-            "#
-        )
-        .fetch_one(pool)
-        .await
+        Self::db_get_last_with(pool).await
     }
 }
 
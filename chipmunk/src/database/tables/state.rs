@@ -1,6 +1,7 @@
 use anyhow::Context;
 use chrono::{DateTime, Utc};
-use sqlx::PgPool;
+use sqlx::{PgExecutor, PgPool};
+use tesla_api::stream::StreamingData;
 use tesla_api::vehicle_data::{ChargingState, ShiftState, VehicleData};
 use ui_common::units::Distance;
 
@@ -42,7 +43,7 @@ impl StateStatus {
         }
     }
 
-    fn from(data: &VehicleData) -> Self {
+    pub fn from(data: &VehicleData) -> Self {
         let Some(ref state) = data.state else {
             log::warn!("Value of vehicle state is None");
             return Self::Unknown;
@@ -88,6 +89,22 @@ impl StateStatus {
             }
         }
     }
+
+    /// Get the state from the streaming data
+    ///
+    /// The streaming data only tells whether the vehicle is driving, returns `Parked` if a drive
+    /// has ended and `prev_state` if the vehicle was not driving before.
+    pub fn from_streaming_data(data: &StreamingData, prev_state: StateStatus) -> Self {
+        let driving = data
+            .shift_state
+            .as_deref()
+            .is_some_and(|s| !s.is_empty() && s != "P");
+        match (driving, prev_state) {
+            (true, _) => Self::Driving,
+            (false, Self::Driving) => Self::Parked,
+            (false, state) => state,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -143,6 +160,27 @@ impl State {
             (Some(previous_state.state), Some(self.state))
         }
     }
+
+    /// Get the last state of a car
+    pub async fn db_get_last_for_car(pool: &PgPool, car_id: i16) -> sqlx::Result<Self> {
+        sqlx::query_as!(
+            Self,
+            r#"
+                SELECT
+                    id,
+                    state AS "state!: StateStatus",
+                    start_date,
+                    end_date,
+                    car_id
+                FROM states
+                WHERE car_id = $1
+                ORDER BY start_date DESC LIMIT 1
+            "#,
+            car_id
+        )
+        .fetch_one(pool)
+        .await
+    }
 }
 
 impl DBTable for State {
@@ -150,7 +188,7 @@ impl DBTable for State {
         "states"
     }
 
-    async fn db_insert(&self, pool: &PgPool) -> sqlx::Result<i64> {
+    async fn db_insert(&self, executor: impl PgExecutor<'_>) -> sqlx::Result<i64> {
         let id = sqlx::query!(
             r#"
         INSERT INTO states
@@ -167,20 +205,20 @@ impl DBTable for State {
             self.end_date,
             self.car_id,
         )
-        .fetch_one(pool)
+        .fetch_one(executor)
         .await?
         .id;
 
         Ok(id as i64)
     }
 
-    async fn db_update(&self, pool: &PgPool) -> sqlx::Result<()> {
+    async fn db_update(&self, executor: impl PgExecutor<'_>) -> sqlx::Result<()> {
         sqlx::query!(
             r#"UPDATE states SET end_date = $1 WHERE id = $2"#,
             self.end_date,
             self.id
         )
-        .execute(pool)
+        .execute(executor)
         .await?;
 
         Ok(())
@@ -1,6 +1,6 @@
 use anyhow::Context;
 use chrono::{DateTime, Utc};
-use sqlx::PgPool;
+use sqlx::{PgExecutor, PgPool};
 use tesla_api::vehicle_data::VehicleData;
 
 use super::DBTable;
@@ -37,7 +37,7 @@ impl DBTable for SoftwareUpdate {
         "updates"
     }
 
-    async fn db_insert(&self, pool: &PgPool) -> sqlx::Result<i64> {
+    async fn db_insert(&self, executor: impl PgExecutor<'_>) -> sqlx::Result<i64> {
         let id = sqlx::query!(
             r#"
         INSERT INTO updates
@@ -54,7 +54,7 @@ impl DBTable for SoftwareUpdate {
             self.version,
             self.car_id,
         )
-        .fetch_one(pool)
+        .fetch_one(executor)
         .await?
         .id;
 
@@ -85,3 +85,18 @@ pub async fn insert_end_date(pool: &PgPool, data: SoftwareUpdate) -> sqlx::Resul
 
     Ok(0)
 }
+
+/// Get the last software update of a car
+pub async fn db_get_last_for_car(pool: &PgPool, car_id: i16) -> sqlx::Result<SoftwareUpdate> {
+    sqlx::query_as!(
+        SoftwareUpdate,
+        r#"
+            SELECT * FROM updates
+            WHERE car_id = $1
+            ORDER BY start_date DESC LIMIT 1
+        "#,
+        car_id
+    )
+    .fetch_one(pool)
+    .await
+}
@@ -0,0 +1,275 @@
+use chrono::{DateTime, Datelike, Days, NaiveDate, NaiveTime, Utc};
+use chrono_tz::Tz;
+use sqlx::{PgExecutor, PgPool};
+
+use super::DBTable;
+
+/// Price per kWh at a geofence during a time window, used for time of use electricity rates
+///
+/// See the `tariffs` table migration for how the time window is interpreted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tariff {
+    pub id: i32,
+    pub geofence_id: i32,
+    /// Name of the tier, e.g. "Peak" or "Off-peak"
+    pub name: String,
+    /// Days the tariff applies, bit 0 is Monday and bit 6 is Sunday
+    pub weekdays: i16,
+    pub start_time: NaiveTime,
+    pub end_time: NaiveTime,
+    pub price_per_kwh: f32,
+    /// IANA time zone of the time window, e.g. "America/Los_Angeles"
+    pub time_zone: String,
+    pub valid_from: Option<NaiveDate>,
+    pub valid_until: Option<NaiveDate>,
+    pub inserted_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Tariff applying on every day of the week
+pub const ALL_WEEKDAYS: i16 = 0b111_1111;
+
+impl Default for Tariff {
+    fn default() -> Self {
+        Self {
+            id: 0,
+            geofence_id: 0,
+            name: "".into(),
+            weekdays: ALL_WEEKDAYS,
+            start_time: NaiveTime::MIN,
+            end_time: NaiveTime::MIN,
+            price_per_kwh: 0.0,
+            time_zone: "UTC".into(),
+            valid_from: None,
+            valid_until: None,
+            inserted_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+}
+
+impl Tariff {
+    fn tz(&self) -> Tz {
+        self.time_zone.parse().unwrap_or_else(|e| {
+            log::error!("Invalid time zone of tariff {}, using UTC: {e}", self.id);
+            Tz::UTC
+        })
+    }
+
+    /// The tariff applies on the given local date
+    fn applies_on(&self, date: NaiveDate) -> bool {
+        self.weekdays & (1 << date.weekday().num_days_from_monday()) != 0
+            && self.valid_from.is_none_or(|from| date >= from)
+            && self.valid_until.is_none_or(|until| date <= until)
+    }
+
+    /// The tariff applies at the given time
+    ///
+    /// A time window ending on the next day belongs to the day it starts, e.g. a window from
+    /// 21:00 to 07:00 on Mondays applies on Tuesday at 02:00.
+    pub fn applies_at(&self, time: DateTime<Utc>) -> bool {
+        let local = time.with_timezone(&self.tz()).naive_local();
+        let (date, time) = (local.date(), local.time());
+
+        if self.start_time < self.end_time {
+            (self.start_time..self.end_time).contains(&time) && self.applies_on(date)
+        } else if time >= self.start_time {
+            self.applies_on(date)
+        } else {
+            // Before the start time, check the window started on the previous day. A window
+            // with equal start and end times lasts the whole day and ends at the start time.
+            let ends_today = time < self.end_time || self.start_time == self.end_time;
+            ends_today
+                && date
+                    .checked_sub_days(Days::new(1))
+                    .is_some_and(|d| self.applies_on(d))
+        }
+    }
+
+    /// Create a tariff from the one sent by the web interface, the timestamps are set to now
+    pub fn from_ui_struct(tariff: &ui_common::Tariff) -> Self {
+        Self {
+            id: tariff.id,
+            geofence_id: tariff.geofence_id,
+            name: tariff.name.clone(),
+            weekdays: tariff.weekdays,
+            start_time: tariff.start_time,
+            end_time: tariff.end_time,
+            price_per_kwh: tariff.price_per_kwh,
+            time_zone: tariff.time_zone.clone(),
+            valid_from: tariff.valid_from,
+            valid_until: tariff.valid_until,
+            inserted_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    pub fn to_ui_struct(&self) -> ui_common::Tariff {
+        ui_common::Tariff {
+            id: self.id,
+            geofence_id: self.geofence_id,
+            name: self.name.clone(),
+            weekdays: self.weekdays,
+            start_time: self.start_time,
+            end_time: self.end_time,
+            price_per_kwh: self.price_per_kwh,
+            time_zone: self.time_zone.clone(),
+            valid_from: self.valid_from,
+            valid_until: self.valid_until,
+        }
+    }
+
+    /// Get a tariff, use this instead of `db_get_id` to read it inside a transaction
+    pub async fn db_get_id_with(executor: impl PgExecutor<'_>, id: i32) -> sqlx::Result<Self> {
+        sqlx::query_as!(Self, r#"SELECT * FROM tariffs WHERE id = $1"#, id)
+            .fetch_one(executor)
+            .await
+    }
+
+    /// Get the tariffs of a geofence
+    pub async fn db_get_for_geofence(
+        executor: impl PgExecutor<'_>,
+        geofence_id: i32,
+    ) -> sqlx::Result<Vec<Self>> {
+        sqlx::query_as!(
+            Self,
+            r#"SELECT * FROM tariffs WHERE geofence_id = $1 ORDER BY id ASC"#,
+            geofence_id
+        )
+        .fetch_all(executor)
+        .await
+    }
+}
+
+impl DBTable for Tariff {
+    fn table_name() -> &'static str {
+        "tariffs"
+    }
+
+    async fn db_insert(&self, executor: impl PgExecutor<'_>) -> sqlx::Result<i64> {
+        let id = sqlx::query!(
+            r#"
+        INSERT INTO tariffs
+        (
+            geofence_id,
+            name,
+            weekdays,
+            start_time,
+            end_time,
+            price_per_kwh,
+            time_zone,
+            valid_from,
+            valid_until,
+            inserted_at,
+            updated_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+        RETURNING id"#,
+            self.geofence_id,
+            self.name,
+            self.weekdays,
+            self.start_time,
+            self.end_time,
+            self.price_per_kwh,
+            self.time_zone,
+            self.valid_from,
+            self.valid_until,
+            self.inserted_at,
+            self.updated_at,
+        )
+        .fetch_one(executor)
+        .await?
+        .id;
+
+        Ok(id as i64)
+    }
+
+    /// Update the tariff, the geofence of a tariff is not changed
+    async fn db_update(&self, executor: impl PgExecutor<'_>) -> sqlx::Result<()> {
+        let result = sqlx::query!(
+            r#"
+        UPDATE tariffs
+        SET
+            name = $1,
+            weekdays = $2,
+            start_time = $3,
+            end_time = $4,
+            price_per_kwh = $5,
+            time_zone = $6,
+            valid_from = $7,
+            valid_until = $8,
+            updated_at = $9
+        WHERE id = $10"#,
+            self.name,
+            self.weekdays,
+            self.start_time,
+            self.end_time,
+            self.price_per_kwh,
+            self.time_zone,
+            self.valid_from,
+            self.valid_until,
+            self.updated_at,
+            self.id,
+        )
+        .execute(executor)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(sqlx::Error::RowNotFound);
+        }
+        Ok(())
+    }
+
+    async fn db_get_id(pool: &PgPool, id: i64) -> sqlx::Result<Self> {
+        Self::db_get_id_with(pool, id as i32).await
+    }
+
+    async fn db_get_all(pool: &PgPool) -> sqlx::Result<Vec<Self>> {
+        sqlx::query_as!(Self, r#"SELECT * FROM tariffs ORDER BY id ASC"#)
+            .fetch_all(pool)
+            .await
+    }
+}
+
+#[test]
+fn test_tariff_applies_at() {
+    let time = |s: &str| s.parse::<DateTime<Utc>>().unwrap();
+    let hm = |h, m| NaiveTime::from_hms_opt(h, m, 0).unwrap();
+
+    // Weekday evenings in California, 2024-03-04 is a Monday
+    let peak = Tariff {
+        weekdays: 0b1_1111,
+        start_time: hm(16, 0),
+        end_time: hm(21, 0),
+        time_zone: "America/Los_Angeles".into(),
+        ..Default::default()
+    };
+    assert!(peak.applies_at(time("2024-03-05T00:30:00Z"))); // Monday 16:30 PST
+    assert!(!peak.applies_at(time("2024-03-05T05:00:00Z"))); // Monday 21:00 PST
+    assert!(peak.applies_at(time("2024-03-09T00:30:00Z"))); // Friday 16:30 PST
+    assert!(!peak.applies_at(time("2024-03-10T00:30:00Z"))); // Saturday 16:30 PST
+
+    // Daylight saving time started on 2024-03-10, the same UTC times are an hour later
+    assert!(peak.applies_at(time("2024-03-11T23:30:00Z"))); // Monday 16:30 PDT
+    assert!(!peak.applies_at(time("2024-03-11T22:30:00Z"))); // Monday 15:30 PDT
+
+    // Nights starting on Sundays, valid in March
+    let night = Tariff {
+        weekdays: 1 << 6,
+        start_time: hm(21, 0),
+        end_time: hm(7, 0),
+        valid_from: NaiveDate::from_ymd_opt(2024, 3, 1),
+        valid_until: NaiveDate::from_ymd_opt(2024, 3, 31),
+        ..Default::default()
+    };
+    assert!(night.applies_at(time("2024-03-10T22:00:00Z"))); // Sunday
+    assert!(night.applies_at(time("2024-03-11T02:00:00Z"))); // Monday morning
+    assert!(!night.applies_at(time("2024-03-11T07:00:00Z")));
+    assert!(!night.applies_at(time("2024-03-11T22:00:00Z"))); // Monday
+    assert!(!night.applies_at(time("2024-04-07T22:00:00Z"))); // Sunday in April
+
+    // Whole day
+    let all_day = Tariff::default();
+    assert!(all_day.applies_at(time("2024-03-10T00:00:00Z")));
+    assert!(all_day.applies_at(time("2024-03-10T23:59:59Z")));
+}
@@ -25,6 +25,9 @@ pub struct Token {
     updated_at: DateTime<Utc>,
 }
 
+/// The tokens are stored in a single row with this ID
+const TOKEN_ID: i32 = 1;
+
 impl Token {
     pub async fn exists(pool: &PgPool) -> anyhow::Result<bool> {
         let result = sqlx::query!(
@@ -48,6 +51,7 @@ impl Token {
         }
     }
 
+    /// Store the tokens, replacing the stored ones
     pub async fn db_insert(
         pool: &PgPool,
         tokens: &AuthResponse,
@@ -68,6 +72,7 @@ impl Token {
             r#"
             INSERT INTO tokens
             (
+                id,
                 refresh_token,
                 refresh_token_iv,
                 access_token,
@@ -78,7 +83,7 @@ impl Token {
                 token_type,
                 updated_at
             )
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
                 ON CONFLICT (id) DO UPDATE
                     SET
                         refresh_token = excluded.refresh_token,
@@ -91,6 +96,7 @@ impl Token {
                         token_type = excluded.token_type,
                         updated_at = excluded.updated_at
             "#,
+            TOKEN_ID,
             refresh_token,
             refresh_token_iv,
             access_token,
@@ -110,9 +116,12 @@ impl Token {
     pub async fn db_get_last(pool: &PgPool, encryption_key: &str) -> anyhow::Result<AuthResponse> {
         log::info!("Getting tokens from database");
 
-        let token_table_res = sqlx::query_as!(Token, r#"SELECT * FROM tokens"#)
-            .fetch_one(pool)
-            .await;
+        let token_table_res = sqlx::query_as!(
+            Token,
+            r#"SELECT * FROM tokens ORDER BY updated_at DESC LIMIT 1"#
+        )
+        .fetch_one(pool)
+        .await;
 
         let token_table = match token_table_res {
             Ok(t) => t,
@@ -188,7 +197,28 @@ async fn test_key_insertion_and_retrieval() {
         .await
         .expect("Error inserting tokens to database");
 
-    Token::db_get_last(&pool, encryption_key)
+    // Storing the tokens again replaces the stored ones
+    let tokens = AuthResponse {
+        access_token: "new_access_token".into(),
+        refresh_token: "new_refresh_token".into(),
+        id_token: "new_id_token".into(),
+        ..tokens
+    };
+    Token::db_insert(&pool, &tokens, encryption_key)
+        .await
+        .expect("Error inserting tokens to database");
+    let num_rows: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM tokens")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(num_rows, 1);
+
+    let stored = Token::db_get_last(&pool, encryption_key)
         .await
         .expect("Error getting tokens from database");
+    assert_eq!(stored.access_token, tokens.access_token);
+    assert_eq!(stored.refresh_token, tokens.refresh_token);
+    assert_eq!(stored.id_token, tokens.id_token);
+    assert_eq!(stored.token_type, tokens.token_type);
+    assert!((1230..=1234).contains(&stored.expires_in));
 }
@@ -1,6 +1,6 @@
 use chrono::Utc;
 use serde_json;
-use sqlx::PgPool;
+use sqlx::{PgExecutor, PgPool};
 use std::ops::{Deref, DerefMut};
 use tesla_api::vehicle_data::VehicleData;
 
@@ -30,32 +30,73 @@ impl VehicleDataRow {
     }
 }
 
-pub async fn num_car_data_rows(pool: &PgPool) -> sqlx::Result<i64> {
-    Ok(sqlx::query!(r#"SELECT count(timestamp) FROM car_data"#)
+/// Vehicle data with the `timestamp` column of `car_data`, used for paging through the table
+#[derive(sqlx::FromRow, Debug)]
+pub struct TimestampedVehicleDataRow {
+    pub timestamp: i64,
+    pub data: sqlx::types::Json<VehicleData>,
+}
+
+/// Number of `car_data` rows with a timestamp after `after` and not after `until`
+pub async fn num_car_data_rows(pool: &PgPool, after: i64, until: i64) -> sqlx::Result<i64> {
+    sqlx::query_scalar("SELECT COUNT(*) FROM car_data WHERE timestamp > $1 AND timestamp <= $2")
+        .bind(after)
+        .bind(until)
         .fetch_one(pool)
-        .await?
-        .count
-        .unwrap_or(0))
+        .await
 }
 
-/**
-* Get list of car_data from the database
-* This function will fetch `num_rows_to_fetch` number of data points starting at offset `starting_row`
-*/
-#[allow(dead_code)]
-pub async fn db_get(
+/// Get the next page of `car_data` in timestamp order, starting after the timestamp `after` and
+/// ending at the timestamp `until`
+///
+/// Uses keyset pagination on the timestamp, the time taken does not grow with the number of rows
+/// before the page like with `OFFSET`. All rows sharing the timestamp of the last row are
+/// returned to not skip any of them on the next page, the page can be longer than `batch_size`.
+pub async fn db_get_page(
     pool: &PgPool,
+    after: i64,
+    until: i64,
     batch_size: i64,
-    row_offset: i64,
-) -> sqlx::Result<Vec<VehicleDataRow>> {
-    sqlx::query_as!(
-            VehicleDataRow,
-            r#"SELECT data as "data!:sqlx::types::Json<VehicleData>" FROM car_data ORDER BY timestamp ASC LIMIT $1 OFFSET $2"#,
-            batch_size,
-            row_offset
-        )
-        .fetch_all(pool)
-        .await
+) -> sqlx::Result<Vec<TimestampedVehicleDataRow>> {
+    let mut rows: Vec<TimestampedVehicleDataRow> = sqlx::query_as(
+        r#"
+            SELECT timestamp, data FROM car_data
+            WHERE timestamp > $1 AND timestamp <= $2
+            ORDER BY timestamp ASC LIMIT $3
+        "#,
+    )
+    .bind(after)
+    .bind(until)
+    .bind(batch_size)
+    .fetch_all(pool)
+    .await?;
+
+    let page_full = rows.len() as i64 == batch_size;
+    if let Some(last_timestamp) = rows.last().map(|r| r.timestamp).filter(|_| page_full) {
+        rows.retain(|r| r.timestamp != last_timestamp);
+        let last_rows: Vec<TimestampedVehicleDataRow> =
+            sqlx::query_as("SELECT timestamp, data FROM car_data WHERE timestamp = $1")
+                .bind(last_timestamp)
+                .fetch_all(pool)
+                .await?;
+        rows.extend(last_rows);
+    }
+
+    Ok(rows)
+}
+
+/// Check if `car_data` has an index on the timestamp, paging through the table is slow without it
+pub async fn has_timestamp_index(pool: &PgPool) -> sqlx::Result<bool> {
+    sqlx::query_scalar(
+        r#"
+            SELECT EXISTS (
+                SELECT 1 FROM pg_indexes
+                WHERE tablename = 'car_data' AND indexdef LIKE '%(timestamp)%'
+            )
+        "#,
+    )
+    .fetch_one(pool)
+    .await
 }
 
 #[allow(dead_code)]
@@ -74,12 +115,49 @@ pub async fn db_get_between(
         .await
 }
 
-pub async fn db_insert_json(data: &str, pool: &PgPool) -> anyhow::Result<()> {
-    sqlx::query(r#"INSERT INTO car_data (timestamp,data) VALUES ($1, $2::json)"#)
-        .bind(Utc::now().timestamp_millis())
-        .bind(data)
-        .execute(pool)
-        .await?;
+/// Get the last vehicle data of a car, `car_data` does not have a car_id column, the VIN in the
+/// data is used to find the car
+pub async fn db_get_last_for_car(pool: &PgPool, car_id: i16) -> sqlx::Result<VehicleData> {
+    sqlx::query_as!(
+        VehicleDataRow,
+        r#"
+            SELECT data as "data!:sqlx::types::Json<VehicleData>" FROM car_data
+            WHERE data->>'vin' = (SELECT vin FROM cars WHERE id = $1)
+            ORDER BY timestamp DESC LIMIT 1
+        "#,
+        car_id
+    )
+    .fetch_one(pool)
+    .await
+    .map(|d| d.get_data())
+}
+
+/// Insert the raw vehicle data, the timestamp is the latest time of the returned sections or the
+/// current time if the data has none
+pub async fn db_insert_json(data: &str, executor: impl PgExecutor<'_>) -> anyhow::Result<()> {
+    sqlx::query(
+        r#"
+            INSERT INTO car_data (timestamp, data)
+            VALUES (
+                COALESCE(
+                    GREATEST(
+                        ($2::json->'charge_state'->>'timestamp')::BIGINT,
+                        ($2::json->'climate_state'->>'timestamp')::BIGINT,
+                        ($2::json->'drive_state'->>'timestamp')::BIGINT,
+                        ($2::json->'gui_settings'->>'timestamp')::BIGINT,
+                        ($2::json->'vehicle_config'->>'timestamp')::BIGINT,
+                        ($2::json->'vehicle_state'->>'timestamp')::BIGINT
+                    ),
+                    $1
+                ),
+                $2::json
+            )
+        "#,
+    )
+    .bind(Utc::now().timestamp_millis())
+    .bind(data)
+    .execute(executor)
+    .await?;
 
     Ok(())
 }
@@ -89,7 +167,7 @@ impl DBTable for VehicleData {
         "car_data"
     }
 
-    async fn db_insert(&self, pool: &PgPool) -> sqlx::Result<i64> {
+    async fn db_insert(&self, executor: impl PgExecutor<'_>) -> sqlx::Result<i64> {
         let Some(timestamp) = self.timestamp_epoch() else {
             return Err(sqlx::Error::Protocol(
                 "No timestamp found in vehicle data".into(),
@@ -111,7 +189,7 @@ impl DBTable for VehicleData {
             timestamp as i64,
             data_json,
         )
-        .fetch_one(pool)
+        .fetch_one(executor)
         .await?
         .timestamp;
 
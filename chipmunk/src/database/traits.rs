@@ -1,10 +1,10 @@
-use sqlx::PgPool;
+use sqlx::{PgExecutor, PgPool};
 
 pub trait DBTable {
     // required methods
     fn table_name() -> &'static str;
     #[allow(async_fn_in_trait)]
-    async fn db_insert(&self, pool: &PgPool) -> sqlx::Result<i64>;
+    async fn db_insert(&self, executor: impl PgExecutor<'_>) -> sqlx::Result<i64>;
 
     // Optional methods
     #[allow(async_fn_in_trait)]
@@ -17,11 +17,24 @@ pub trait DBTable {
         Ok(sqlx::Row::get::<i64, _>(&resp, "count"))
     }
 
-    fn db_update(&self, _pool: &PgPool) -> impl std::future::Future<Output = sqlx::Result<()>> {
-        async {
-            #[rustfmt::skip]
-            panic!("{}", format!("`db_update` is not implemented for `{}` table!", Self::table_name()))
+    /// Delete the row with the given ID, returns `RowNotFound` if there is no such row
+    #[allow(async_fn_in_trait)]
+    async fn db_delete(executor: impl PgExecutor<'_>, id: i64) -> sqlx::Result<()> {
+        let result =
+            sqlx::query(format!(r#"DELETE FROM {} WHERE id = $1"#, Self::table_name()).as_str())
+                .bind(id)
+                .execute(executor)
+                .await?;
+        if result.rows_affected() == 0 {
+            return Err(sqlx::Error::RowNotFound);
         }
+        Ok(())
+    }
+
+    #[allow(async_fn_in_trait)]
+    async fn db_update(&self, _executor: impl PgExecutor<'_>) -> sqlx::Result<()> {
+        #[rustfmt::skip]
+        panic!("{}", format!("`db_update` is not implemented for `{}` table!", Self::table_name()))
     }
     fn db_update_last(&self, _pool: &PgPool) -> impl std::future::Future<Output = sqlx::Result<()>>
     where
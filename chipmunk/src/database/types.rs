@@ -9,6 +9,22 @@ pub enum BillingType {
     PerMinute,
 }
 
+impl BillingType {
+    pub fn from_ui_struct(billing_type: &ui_common::BillingType) -> Self {
+        match billing_type {
+            ui_common::BillingType::PerKwh => Self::PerKwh,
+            ui_common::BillingType::PerMinute => Self::PerMinute,
+        }
+    }
+
+    pub fn to_ui_struct(&self) -> ui_common::BillingType {
+        match self {
+            BillingType::PerKwh => ui_common::BillingType::PerKwh,
+            BillingType::PerMinute => ui_common::BillingType::PerMinute,
+        }
+    }
+}
+
 #[derive(sqlx::Type, Debug, Default, Clone, Copy)]
 #[sqlx(type_name = "unit_of_length", rename_all = "lowercase")]
 pub enum UnitOfLength {
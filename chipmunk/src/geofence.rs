@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use sqlx::{PgConnection, PgExecutor, PgPool};
+use ui_common::units::Distance;
+
+use crate::database::tables::{charging_process::ChargingProcess, geofence::Geofence};
+use crate::utils::location::Location;
+
+/// Finds the geofence a location belongs to
+#[derive(Debug, Default, Clone)]
+pub struct GeofenceMatcher {
+    geofences: Vec<Geofence>,
+}
+
+impl GeofenceMatcher {
+    pub fn new(geofences: Vec<Geofence>) -> Self {
+        Self { geofences }
+    }
+
+    /// Load all geofences from the database
+    pub async fn load(executor: impl PgExecutor<'_>) -> sqlx::Result<Self> {
+        Ok(Self::new(Geofence::db_get_all_with(executor).await?))
+    }
+
+    /// Get the geofences of the database of `pool`, they are loaded from the database the first
+    /// time and after they changed
+    pub async fn cached(pool: &PgPool) -> sqlx::Result<Arc<Self>> {
+        let key = cache_key(pool);
+        let cached = cache()
+            .lock()
+            .map(|cache| (cache.matchers.get(&key).cloned(), cache.generation))
+            .map_err(|e| log::error!("Error locking the geofence cache: {e}"))
+            .ok();
+        if let Some((Some(matcher), _)) = cached {
+            return Ok(matcher);
+        }
+
+        let matcher = Arc::new(Self::load(pool).await?);
+        // Geofences changed while loading them may be missing, load them again next time
+        if let Some((_, generation)) = cached
+            && let Ok(mut cache) = cache().lock()
+            && cache.generation == generation
+        {
+            cache.matchers.insert(key, Arc::clone(&matcher));
+        }
+        Ok(matcher)
+    }
+
+    /// Load the geofences from the database again the next time they are used, call this after
+    /// adding, changing or removing a geofence
+    pub fn invalidate_cache() {
+        match cache().lock() {
+            Ok(mut cache) => {
+                cache.generation += 1;
+                cache.matchers.clear();
+            }
+            Err(e) => log::error!("Error locking the geofence cache: {e}"),
+        }
+    }
+
+    pub fn geofences(&self) -> &[Geofence] {
+        &self.geofences
+    }
+
+    /// Get the geofence containing `location`, the one with the closest center if geofences
+    /// overlap
+    pub fn find(&self, location: &Location) -> Option<&Geofence> {
+        self.geofences
+            .iter()
+            .map(|g| {
+                (
+                    g,
+                    location.distance_to(&Location::new(g.latitude, g.longitude)),
+                )
+            })
+            .filter(|(g, distance)| *distance <= Distance::from_m(g.radius))
+            .min_by(|(_, d1), (_, d2)| d1.as_m().total_cmp(&d2.as_m()))
+            .map(|(g, _)| g)
+    }
+
+    /// Get the ID of the geofence containing the location given by `latitude` and `longitude`
+    pub fn find_id(&self, latitude: Option<f64>, longitude: Option<f64>) -> Option<i32> {
+        let location = Location::new(latitude?, longitude?);
+        self.find(&location).map(|g| g.id)
+    }
+}
+
+/// Geofences of each database
+#[derive(Default)]
+struct GeofenceCache {
+    /// Incremented each time the geofences change
+    generation: u64,
+    matchers: HashMap<String, Arc<GeofenceMatcher>>,
+}
+
+fn cache() -> &'static Mutex<GeofenceCache> {
+    static CACHE: OnceLock<Mutex<GeofenceCache>> = OnceLock::new();
+    CACHE.get_or_init(Mutex::default)
+}
+
+/// Host, port and name of the database of `pool`
+fn cache_key(pool: &PgPool) -> String {
+    let options = pool.connect_options();
+    format!(
+        "{}:{}/{}",
+        options.get_host(),
+        options.get_port(),
+        options.get_database().unwrap_or_default()
+    )
+}
+
+/// ID, start and end geofence ID, start and end location of a drive
+type DriveRow = (
+    i32,
+    Option<i32>,
+    Option<i32>,
+    Option<f64>,
+    Option<f64>,
+    Option<f64>,
+    Option<f64>,
+);
+/// ID, geofence ID and location of a charging process
+type ChargingProcessRow = (i32, Option<i32>, Option<f64>, Option<f64>);
+
+/// Get the IDs of the charging processes at a geofence
+pub async fn charging_process_ids(
+    executor: impl PgExecutor<'_>,
+    geofence_id: i32,
+) -> sqlx::Result<Vec<i32>> {
+    sqlx::query_scalar("SELECT id FROM charging_processes WHERE geofence_id = $1")
+        .bind(geofence_id)
+        .fetch_all(executor)
+        .await
+}
+
+/// Assign the geofences to all drives and charging processes again, run this after adding,
+/// changing or removing a geofence
+///
+/// The cost of the charging processes whose geofence changed is recalculated, as well as the
+/// cost of the charging processes in `recalculate`, e.g. the ones of a geofence whose prices
+/// changed. Returns the number of drives and charging processes that changed.
+pub async fn backfill(conn: &mut PgConnection, recalculate: &[i32]) -> sqlx::Result<u64> {
+    let matcher = GeofenceMatcher::load(&mut *conn).await?;
+    let mut num_changed = 0;
+
+    let drives: Vec<DriveRow> = sqlx::query_as(
+        r#"
+        SELECT d.id, d.start_geofence_id, d.end_geofence_id,
+            sp.latitude, sp.longitude, ep.latitude, ep.longitude
+        FROM drives d
+        LEFT JOIN positions sp ON sp.id = d.start_position_id
+        LEFT JOIN positions ep ON ep.id = d.end_position_id"#,
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    for (id, start_id, end_id, start_lat, start_lon, end_lat, end_lon) in drives {
+        let new_start_id = matcher.find_id(start_lat, start_lon);
+        let new_end_id = matcher.find_id(end_lat, end_lon);
+        if (new_start_id, new_end_id) != (start_id, end_id) {
+            sqlx::query(
+                "UPDATE drives SET start_geofence_id = $1, end_geofence_id = $2 WHERE id = $3",
+            )
+            .bind(new_start_id)
+            .bind(new_end_id)
+            .bind(id)
+            .execute(&mut *conn)
+            .await?;
+            num_changed += 1;
+        }
+    }
+
+    let charging_processes: Vec<ChargingProcessRow> = sqlx::query_as(
+        r#"
+        SELECT c.id, c.geofence_id, p.latitude, p.longitude
+        FROM charging_processes c
+        LEFT JOIN positions p ON p.id = c.position_id"#,
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    for (id, geofence_id, lat, lon) in charging_processes {
+        let new_geofence_id = matcher.find_id(lat, lon);
+        if new_geofence_id != geofence_id {
+            sqlx::query("UPDATE charging_processes SET geofence_id = $1 WHERE id = $2")
+                .bind(new_geofence_id)
+                .bind(id)
+                .execute(&mut *conn)
+                .await?;
+            num_changed += 1;
+        } else if !recalculate.contains(&id) {
+            continue;
+        }
+        // Charging processes without charges are kept as they are
+        if let Err(e) = ChargingProcess::db_recalculate(&mut *conn, id).await {
+            log::warn!("Cannot recalculate the cost of charging process {id}: {e}");
+        }
+    }
+
+    Ok(num_changed)
+}
+
+#[test]
+fn test_geofence_matcher() {
+    let geofence = |id, latitude, longitude, radius| Geofence {
+        id,
+        name: format!("geofence {id}"),
+        latitude,
+        longitude,
+        radius,
+        ..Default::default()
+    };
+    let matcher = GeofenceMatcher::new(vec![
+        geofence(1, 37.4, -122.1, 100),
+        geofence(2, 37.4005, -122.1, 100),
+        geofence(3, 40.0, -120.0, 50),
+    ]);
+
+    // About 11 m from the center of geofence 1 and 44 m from geofence 2
+    assert_eq!(
+        matcher.find(&Location::new(37.4001, -122.1)).map(|g| g.id),
+        Some(1)
+    );
+    // Inside both geofences, closer to geofence 2
+    assert_eq!(matcher.find_id(Some(37.4004), Some(-122.1)), Some(2));
+    // About 110 m from the center of geofence 1
+    assert_eq!(matcher.find_id(Some(37.399), Some(-122.1)), None);
+    assert_eq!(matcher.find_id(None, Some(-122.1)), None);
+    assert!(GeofenceMatcher::default()
+        .find(&Location::new(37.4, -122.1))
+        .is_none());
+}
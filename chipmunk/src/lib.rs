@@ -23,16 +23,25 @@
 
 use std::io::Write;
 
+pub mod analytics;
 pub mod charging;
 pub mod config;
+pub mod data_source;
 pub mod database;
+pub mod geofence;
 pub mod openstreetmap;
+pub mod recording;
+pub mod reprocess;
 pub mod server;
+pub mod spool;
 pub mod srtm;
+pub mod supervisor;
 mod task_data_polling;
 pub mod task_data_processor;
 pub mod task_data_streaming;
 mod task_database;
+mod task_energy_site;
+mod task_token_manager;
 mod task_web_server;
 pub mod tasks;
 pub mod utils;
@@ -1,8 +1,13 @@
 use chipmunk::{
     config::{load_env_vars, Config},
+    data_source::{CarDataSource, GpxSimulator, JsonlSource, ReplaySource},
     database::{self, tables::token::Token},
+    set_config,
 };
+use chrono::{DateTime, Utc};
 use clap::Parser;
+use std::path::PathBuf;
+use std::time::Duration;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -18,10 +23,48 @@ struct Cli {
     #[arg(short, long, action = clap::ArgAction::Set)]
     token: Option<String>,
 
-    /// How many row to fetch from car_data when running `convertdb`.
-    /// Use 0 to fetch all data.
+    /// How many rows to convert from car_data when running `convertdb`.
+    /// Use 0 to convert all data.
     #[arg(short, long, default_value_t = 50_000, action = clap::ArgAction::Set)]
     num_rows: i64,
+
+    /// ID of the car to re-process when running `reprocess`
+    #[arg(long, action = clap::ArgAction::Set)]
+    car_id: Option<i16>,
+
+    /// Start of the time range to process when running `reprocess`, `convertdb` or `simulate`,
+    /// e.g. 2024-05-01T00:00:00Z
+    #[arg(long, action = clap::ArgAction::Set)]
+    from: Option<DateTime<Utc>>,
+
+    /// End of the time range to process when running `reprocess`, `convertdb` or `simulate`,
+    /// defaults to now
+    #[arg(long, action = clap::ArgAction::Set)]
+    to: Option<DateTime<Utc>>,
+
+    /// Show the changes made by `reprocess` without writing them to the database
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    dry_run: bool,
+
+    /// Ignore the progress of the previous `convertdb` run and start from the beginning
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    restart: bool,
+
+    /// Record the data received from the vehicles to this file when running `tasks`, zstd
+    /// compressed if the file name ends with `.zst`. Overrides RECORD_FILE.
+    #[arg(long, action = clap::ArgAction::Set)]
+    record: Option<String>,
+
+    /// Replay speed when running `replay`, 1 to replay in real time, 0 to replay as fast as
+    /// possible
+    #[arg(long, default_value_t = 1.0, action = clap::ArgAction::Set)]
+    speed: f64,
+
+    /// File to read when running `simulate`, either a GPX track to drive along or a file with
+    /// one vehicle data response per line. Reads `car_data` from CAR_DATA_DATABASE_URL if not set.
+    /// The recording to replay when running `replay`.
+    #[arg(long, action = clap::ArgAction::Set)]
+    input: Option<PathBuf>,
 }
 
 macro_rules! print_err_and_exit {
@@ -61,21 +104,61 @@ async fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
 
     // If a token is provided, store it in the database
-    if let Some(refresh_token) = cli.token {
-        match tesla_api::auth::refresh_access_token(refresh_token.as_str()).await {
+    if let Some(refresh_token) = &cli.token {
+        match tesla_api::auth::refresh_access_token(
+            env.tesla_backend.as_ref(),
+            refresh_token.as_str(),
+        )
+        .await
+        {
             Ok(tokens) => Token::db_insert(&pool, &tokens, env.encryption_key.as_str()).await?,
             Err(e) => log::error!("{e}"),
         };
     }
 
+    if let Some(record) = cli.record.clone() {
+        set_config!(config.record_file, Some(record));
+    }
+
     if let Some(option) = cli.option.as_deref() {
         match option {
             "tasks" => chipmunk::tasks::run(&pool, &mut config)
                 .await
                 .unwrap_or_else(print_err_and_exit!()),
-            "convertdb" => chipmunk::tasks::convert_db(&pool, &config, cli.num_rows)
+            "convertdb" => chipmunk::tasks::convert_db(
+                &pool,
+                &config,
+                cli.num_rows,
+                cli.from,
+                cli.to,
+                cli.restart,
+            )
+            .await
+            .unwrap_or_else(print_err_and_exit!()),
+            "reprocess" => {
+                let (Some(car_id), Some(from)) = (cli.car_id, cli.from) else {
+                    log::error!("Please provide --car-id and --from to run `reprocess`");
+                    std::process::exit(1);
+                };
+                let to = cli.to.unwrap_or_else(Utc::now);
+                chipmunk::reprocess::reprocess(&pool, car_id, from, to, cli.dry_run)
+                    .await
+                    .map(|diff| diff.log())
+                    .unwrap_or_else(print_err_and_exit!())
+            }
+            "simulate" => simulate(&pool, &config, &cli)
                 .await
                 .unwrap_or_else(print_err_and_exit!()),
+            "replay" => {
+                let Some(input) = cli.input.as_deref() else {
+                    log::error!("Please provide the recording to replay using --input");
+                    std::process::exit(1);
+                };
+                let source = ReplaySource::new(input, cli.speed);
+                chipmunk::tasks::simulate(&pool, &config, source)
+                    .await
+                    .unwrap_or_else(print_err_and_exit!())
+            }
             unknown => log::error!("Unknown command line argument `{unknown}`"),
         };
     }
@@ -84,3 +167,33 @@ async fn main() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+/// Process the data of the source selected by `--input` without a vehicle
+async fn simulate(pool: &sqlx::PgPool, config: &Config, cli: &Cli) -> anyhow::Result<()> {
+    match cli.input.as_deref() {
+        Some(path)
+            if path
+                .extension()
+                .is_some_and(|e| e.eq_ignore_ascii_case("gpx")) =>
+        {
+            let source = GpxSimulator::from_file(path, Duration::from_secs(1))?;
+            chipmunk::tasks::simulate(pool, config, source).await
+        }
+        Some(path) => chipmunk::tasks::simulate(pool, config, JsonlSource::new(path)).await,
+        None => {
+            let car_data_database_url = config
+                .car_data_database_url
+                .lock()
+                .map(|c| c.get())
+                .map_err(|e| log::error!("Error reading `car_data_database_url` from config: {e}"))
+                .ok()
+                .flatten();
+            let Some(car_data_database_url) = car_data_database_url else {
+                anyhow::bail!("Please provide --input or CAR_DATA_DATABASE_URL");
+            };
+            let car_data_pool = database::initialize_car_data(&car_data_database_url).await?;
+            let source = CarDataSource::new(car_data_pool, cli.from, cli.to);
+            chipmunk::tasks::simulate(pool, &config.without_car_data_database(), source).await
+        }
+    }
+}
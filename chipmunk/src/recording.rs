@@ -0,0 +1,258 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::tasks::DataTypes;
+
+/// The records are flushed to the recording at least this often, see `RecordWriter::write`
+pub const FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+/// Maximum number of records written without flushing them
+const FLUSH_RECORDS: usize = 100;
+
+/// How the data was received from the vehicle
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordSource {
+    /// Vehicle data polled from the REST API
+    Poll,
+    /// Data received from the streaming API
+    Stream,
+}
+
+/// A data point received by the logger, written as one line of JSON to a recording
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Record {
+    /// Time the logger received the data
+    pub received_at: DateTime<Utc>,
+    pub source: RecordSource,
+    /// VIN of the vehicle, only set for streaming data
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vin: Option<String>,
+    /// Vehicle data response body as received or the streaming data as JSON
+    ///
+    /// The response body is kept verbatim, also if it is not valid JSON, to reproduce parsing
+    /// errors.
+    pub payload: String,
+}
+
+impl Record {
+    pub fn new(data: &DataTypes, received_at: DateTime<Utc>) -> anyhow::Result<Self> {
+        let record = match data {
+            DataTypes::VehicleData(data) => Self {
+                received_at,
+                source: RecordSource::Poll,
+                vin: None,
+                payload: data.clone(),
+            },
+            DataTypes::StreamingData(vin, data) => Self {
+                received_at,
+                source: RecordSource::Stream,
+                vin: Some(vin.clone()),
+                payload: serde_json::to_string(data)?,
+            },
+        };
+        Ok(record)
+    }
+
+    /// Convert the record back to the data sent to the data processor
+    pub fn into_data(self) -> anyhow::Result<DataTypes> {
+        let data = match self.source {
+            RecordSource::Poll => DataTypes::VehicleData(self.payload),
+            RecordSource::Stream => {
+                let vin = self.vin.context("Streaming data record without a VIN")?;
+                DataTypes::StreamingData(vin, serde_json::from_str(&self.payload)?)
+            }
+        };
+        Ok(data)
+    }
+}
+
+/// Recordings with a `.zst` extension are zstd compressed
+fn is_compressed(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("zst"))
+}
+
+/// Writes records to a recording file
+///
+/// The records are appended to an existing recording, a compressed recording then contains
+/// multiple zstd frames which are read as one.
+pub struct RecordWriter {
+    path: PathBuf,
+    writer: Box<dyn Write + Send>,
+    /// Number of records written since the last flush
+    num_unflushed: usize,
+    last_flush: Instant,
+}
+
+impl RecordWriter {
+    pub fn create(path: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let path = path.into();
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("Error opening recording {path:?}"))?;
+        let writer: Box<dyn Write + Send> = if is_compressed(&path) {
+            Box::new(zstd::Encoder::new(file, 0)?.auto_finish())
+        } else {
+            Box::new(BufWriter::new(file))
+        };
+
+        Ok(Self {
+            path,
+            writer,
+            num_unflushed: 0,
+            last_flush: Instant::now(),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Write a record, the records are flushed every `FLUSH_RECORDS` records or after
+    /// `FLUSH_INTERVAL`
+    ///
+    /// Call `flush` if no record was written for `FLUSH_INTERVAL`, the records which were not
+    /// flushed are lost if the logger is killed.
+    pub fn write(&mut self, record: &Record) -> anyhow::Result<()> {
+        serde_json::to_writer(&mut self.writer, record)?;
+        self.writer.write_all(b"\n")?;
+        self.num_unflushed += 1;
+        if self.num_unflushed >= FLUSH_RECORDS || self.last_flush.elapsed() >= FLUSH_INTERVAL {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Flush the records written so far, they can be read even if the logger is killed
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        if self.num_unflushed > 0 {
+            self.writer.flush()?;
+            self.num_unflushed = 0;
+        }
+        self.last_flush = Instant::now();
+        Ok(())
+    }
+}
+
+/// Reads the records of a recording file in the order they were written
+pub struct RecordReader {
+    lines: std::io::Lines<Box<dyn BufRead + Send>>,
+    done: bool,
+}
+
+impl RecordReader {
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let file = File::open(path).with_context(|| format!("Error opening recording {path:?}"))?;
+        let reader: Box<dyn BufRead + Send> = if is_compressed(path) {
+            Box::new(BufReader::new(zstd::Decoder::new(file)?))
+        } else {
+            Box::new(BufReader::new(file))
+        };
+
+        Ok(Self {
+            lines: reader.lines(),
+            done: false,
+        })
+    }
+}
+
+impl Iterator for RecordReader {
+    /// Invalid lines are returned as errors, reading continues with the next line
+    type Item = anyhow::Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        loop {
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                // The end of a compressed recording is incomplete if the logger was killed
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(anyhow::anyhow!("Error reading recording: {e}")));
+                }
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            return Some(serde_json::from_str(&line).map_err(|e| e.into()));
+        }
+    }
+}
+
+#[test]
+fn test_recording() {
+    use tesla_api::stream::StreamingData;
+
+    let data = vec![
+        DataTypes::VehicleData(r#"{"vin": "VIN1", "state": "online"}"#.into()),
+        DataTypes::VehicleData("not json".into()),
+        DataTypes::VehicleData(r#""not json""#.into()),
+        DataTypes::StreamingData(
+            "VIN1".into(),
+            StreamingData {
+                timestamp: Some(Utc::now()),
+                shift_state: Some("D".into()),
+                speed: Some(42.0),
+                ..Default::default()
+            },
+        ),
+    ];
+
+    for extension in ["jsonl", "jsonl.zst"] {
+        let path = std::env::temp_dir().join(format!(
+            "chipmunk_recording_{}.{extension}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+
+        // Records written by two writers are appended
+        let received_at = Utc::now();
+        let mut writer = RecordWriter::create(&path).unwrap();
+        writer
+            .write(&Record::new(&data[0], received_at).unwrap())
+            .unwrap();
+        drop(writer);
+        let mut writer = RecordWriter::create(&path).unwrap();
+        for d in &data[1..] {
+            writer.write(&Record::new(d, received_at).unwrap()).unwrap();
+        }
+        writer.flush().unwrap();
+        drop(writer);
+
+        let records: Vec<Record> = RecordReader::open(&path)
+            .unwrap()
+            .map(|r| r.unwrap())
+            .collect();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(records.len(), 4, "{extension}");
+        assert_eq!(records[0].source, RecordSource::Poll);
+        assert_eq!(records[3].source, RecordSource::Stream);
+        assert_eq!(records[3].received_at, received_at);
+
+        let replayed: Vec<DataTypes> = records
+            .into_iter()
+            .map(|r| r.into_data().unwrap())
+            .collect();
+        // The response bodies are replayed verbatim
+        for i in 0..3 {
+            assert!(
+                matches!((&replayed[i], &data[i]), (DataTypes::VehicleData(r), DataTypes::VehicleData(d)) if r == d)
+            );
+        }
+        assert!(matches!(
+            &replayed[3],
+            DataTypes::StreamingData(vin, d) if vin == "VIN1" && d.speed == Some(42.0)
+        ));
+    }
+}
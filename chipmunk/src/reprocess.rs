@@ -0,0 +1,393 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use futures::TryStreamExt;
+use sqlx::{PgConnection, PgPool};
+
+use crate::database::tables::vehicle_data::VehicleDataRow;
+use crate::database::tables::Tables;
+use crate::task_data_processor::{create_tables, fill_missing_sections};
+
+/// Derived rows which changed by re-processing, each row is summarized in one line
+#[derive(Debug, Default)]
+pub struct ReprocessDiff {
+    /// Rows which were deleted and not created again
+    pub removed: Vec<String>,
+    /// Rows which did not exist before re-processing
+    pub added: Vec<String>,
+    /// Number of rows which are the same before and after re-processing
+    pub unchanged: usize,
+    /// Start of the re-processed time range, extended to the start of the first state, drive or
+    /// charging process overlapping the requested range
+    pub start: DateTime<Utc>,
+    /// End of the re-processed time range, extended to the end of the last state, drive or
+    /// charging process overlapping the requested range
+    pub end: DateTime<Utc>,
+    /// Number of vehicle data points re-processed
+    pub num_data_points: usize,
+}
+
+impl ReprocessDiff {
+    /// Log the changes, one line for each row
+    pub fn log(&self) {
+        log::info!(
+            "Re-processed {} data points between {} and {}",
+            self.num_data_points,
+            self.start,
+            self.end
+        );
+        for line in &self.removed {
+            log::info!("- {line}");
+        }
+        for line in &self.added {
+            log::info!("+ {line}");
+        }
+        log::info!(
+            "{} removed, {} added, {} unchanged",
+            self.removed.len(),
+            self.added.len(),
+            self.unchanged
+        );
+    }
+}
+
+/// Rebuild the states, drives, charging processes, charges and positions of a car from the
+/// vehicle data stored in `car_data`
+///
+/// The derived rows in the time range are deleted and the vehicle data is run through
+/// `create_tables` again in timestamp order. The range is extended to include the states, drives
+/// and charging processes crossing its boundaries. Positions logged from the streaming API are
+/// not stored in `car_data`, they are kept and assigned to the re-created drives. Stop the logger
+/// before re-processing the most recent data.
+///
+/// Everything is done in a single transaction, with `dry_run` the transaction is rolled back and
+/// only the changes are returned.
+pub async fn reprocess(
+    pool: &PgPool,
+    car_id: i16,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    dry_run: bool,
+) -> anyhow::Result<ReprocessDiff> {
+    if start > end {
+        anyhow::bail!("Start time {start} is after the end time {end}");
+    }
+
+    let mut tx = pool.begin().await?;
+    let (start, end) = extend_range(&mut tx, car_id, start, end).await?;
+    log::info!("Re-processing data of car {car_id} between {start} and {end}");
+
+    let before = summarize(&mut tx, car_id, start, end).await?;
+    delete_derived_rows(&mut tx, car_id, start, end).await?;
+
+    let mut prev_tables = Tables::default();
+    // Partial vehicle data is completed with the previous data, the same way it is when logging
+    let mut prev_data = None;
+    let mut num_data_points = 0;
+    let mut data_stream = sqlx::query_as::<_, VehicleDataRow>(
+        r#"
+            SELECT data FROM car_data
+            WHERE data->>'vin' = (SELECT vin FROM cars WHERE id = $1)
+                AND timestamp BETWEEN $2 AND $3
+            ORDER BY timestamp ASC
+        "#,
+    )
+    .bind(car_id)
+    .bind(start.timestamp_millis())
+    .bind(end.timestamp_millis())
+    .fetch(pool);
+    while let Some(mut data) = data_stream.try_next().await? {
+        if let Some(prev_data) = &prev_data {
+            fill_missing_sections(&mut data, prev_data);
+        }
+        prev_data = Some(data.get_data());
+        let table_list = match create_tables(&data, &prev_tables, car_id).await {
+            Ok(table_list) => table_list,
+            Err(e) => {
+                log::error!("Skipping vehicle data: {e}");
+                continue;
+            }
+        };
+        prev_tables = Tables::db_insert_list(pool, &mut tx, table_list).await?;
+        num_data_points += 1;
+    }
+
+    // End the last state at the first data point after the range, the same way it is ended when
+    // logging. The state is kept open if there is no data after the range.
+    let next_data = sqlx::query_as::<_, VehicleDataRow>(
+        r#"
+            SELECT data FROM car_data
+            WHERE data->>'vin' = (SELECT vin FROM cars WHERE id = $1) AND timestamp > $2
+            ORDER BY timestamp ASC LIMIT 1
+        "#,
+    )
+    .bind(car_id)
+    .bind(end.timestamp_millis())
+    .fetch_optional(pool)
+    .await?;
+    if let Some(mut data) = next_data.filter(|_| num_data_points > 0) {
+        if let Some(prev_data) = &prev_data {
+            fill_missing_sections(&mut data, prev_data);
+        }
+        let mut table_list = create_tables(&data, &prev_tables, car_id).await?;
+        // The first tables end the previous state if the state changed, the new state is
+        // already in the database
+        if table_list.len() > 1 {
+            table_list.truncate(1);
+            Tables::db_insert_list(pool, &mut tx, table_list).await?;
+        }
+    }
+
+    assign_streamed_positions(&mut tx, car_id, start, end).await?;
+
+    let after = summarize(&mut tx, car_id, start, end).await?;
+    let (removed, added, unchanged) = diff_summaries(&before, &after);
+    let diff = ReprocessDiff {
+        removed,
+        added,
+        unchanged,
+        start,
+        end,
+        num_data_points,
+    };
+
+    if dry_run {
+        tx.rollback().await?;
+    } else {
+        tx.commit().await?;
+    }
+
+    Ok(diff)
+}
+
+/// Extend the time range to the start of the first and the end of the last state, drive or
+/// charging process overlapping the range, rows still in progress extend the range to now
+async fn extend_range(
+    conn: &mut PgConnection,
+    car_id: i16,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> sqlx::Result<(DateTime<Utc>, DateTime<Utc>)> {
+    let (first, last): (Option<DateTime<Utc>>, Option<DateTime<Utc>>) = sqlx::query_as(
+        r#"
+            SELECT MIN(start_date), MAX(COALESCE(end_date, NOW())) FROM (
+                SELECT start_date, end_date FROM states
+                WHERE car_id = $1 AND start_date <= $3 AND (end_date IS NULL OR end_date >= $2)
+                UNION ALL
+                SELECT start_date, end_date FROM drives
+                WHERE car_id = $1 AND start_date <= $3 AND (end_date IS NULL OR end_date >= $2)
+                UNION ALL
+                SELECT start_date, end_date FROM charging_processes
+                WHERE car_id = $1 AND start_date <= $3 AND (end_date IS NULL OR end_date >= $2)
+            ) AS overlapping
+        "#,
+    )
+    .bind(car_id)
+    .bind(start)
+    .bind(end)
+    .fetch_one(conn)
+    .await?;
+
+    Ok((
+        first.map_or(start, |first| first.min(start)),
+        last.map_or(end, |last| last.max(end)),
+    ))
+}
+
+async fn delete_derived_rows(
+    conn: &mut PgConnection,
+    car_id: i16,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> sqlx::Result<()> {
+    // The charges are deleted with the charging processes, the drive ID of the streamed positions
+    // is set to NULL when the drives are deleted
+    for query in [
+        "DELETE FROM charging_processes WHERE car_id = $1 AND start_date BETWEEN $2 AND $3",
+        "DELETE FROM drives WHERE car_id = $1 AND start_date BETWEEN $2 AND $3",
+        "DELETE FROM states WHERE car_id = $1 AND start_date BETWEEN $2 AND $3",
+        "DELETE FROM positions WHERE car_id = $1 AND date BETWEEN $2 AND $3 AND NOT streamed",
+    ] {
+        let result = sqlx::query(query)
+            .bind(car_id)
+            .bind(start)
+            .bind(end)
+            .execute(&mut *conn)
+            .await?;
+        log::info!("{query}: {} rows", result.rows_affected());
+    }
+
+    Ok(())
+}
+
+/// Assign the streamed positions whose drive was deleted to the re-created drive at the same time
+async fn assign_streamed_positions(
+    conn: &mut PgConnection,
+    car_id: i16,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> sqlx::Result<()> {
+    let result = sqlx::query(
+        r#"
+            UPDATE positions p SET drive_id = d.id
+            FROM drives d
+            WHERE p.car_id = $1 AND p.streamed AND p.drive_id IS NULL
+                AND p.date BETWEEN $2 AND $3
+                AND d.car_id = $1
+                AND p.date BETWEEN d.start_date AND COALESCE(d.end_date, 'infinity')
+        "#,
+    )
+    .bind(car_id)
+    .bind(start)
+    .bind(end)
+    .execute(conn)
+    .await?;
+    log::info!(
+        "Assigned {} streamed positions to drives",
+        result.rows_affected()
+    );
+
+    Ok(())
+}
+
+/// Start date, end date and distance of a drive
+type DriveRow = (DateTime<Utc>, Option<DateTime<Utc>>, Option<f32>);
+/// Start date, end date, energy added and the number of charges of a charging process
+type ChargingProcessRow = (DateTime<Utc>, Option<DateTime<Utc>>, Option<f32>, i64);
+
+/// One line for each state, drive and charging process in the time range and the number of
+/// positions and charges
+///
+/// Rows with the same line, e.g. duplicate states, are counted.
+async fn summarize(
+    conn: &mut PgConnection,
+    car_id: i16,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> sqlx::Result<BTreeMap<String, usize>> {
+    let mut summary = BTreeMap::new();
+    let mut insert = |line: String| *summary.entry(line).or_default() += 1;
+
+    let states: Vec<(String, DateTime<Utc>, Option<DateTime<Utc>>)> = sqlx::query_as(
+        r#"
+            SELECT state::TEXT, start_date, end_date FROM states
+            WHERE car_id = $1 AND start_date BETWEEN $2 AND $3
+        "#,
+    )
+    .bind(car_id)
+    .bind(start)
+    .bind(end)
+    .fetch_all(&mut *conn)
+    .await?;
+    for (state, start_date, end_date) in states {
+        insert(format!(
+            "state {start_date} - {} {state}",
+            format_end_date(end_date)
+        ));
+    }
+
+    let drives: Vec<DriveRow> = sqlx::query_as(
+        r#"
+            SELECT start_date, end_date, distance FROM drives
+            WHERE car_id = $1 AND start_date BETWEEN $2 AND $3
+        "#,
+    )
+    .bind(car_id)
+    .bind(start)
+    .bind(end)
+    .fetch_all(&mut *conn)
+    .await?;
+    for (start_date, end_date, distance) in drives {
+        insert(format!(
+            "drive {start_date} - {} {:.1} km",
+            format_end_date(end_date),
+            distance.unwrap_or_default()
+        ));
+    }
+
+    let charging_processes: Vec<ChargingProcessRow> = sqlx::query_as(
+        r#"
+                SELECT start_date, end_date, charge_energy_added,
+                    (SELECT COUNT(*) FROM charges WHERE charging_process_id = cp.id)
+                FROM charging_processes cp
+                WHERE car_id = $1 AND start_date BETWEEN $2 AND $3
+            "#,
+    )
+    .bind(car_id)
+    .bind(start)
+    .bind(end)
+    .fetch_all(&mut *conn)
+    .await?;
+    for (start_date, end_date, energy_added, num_charges) in charging_processes {
+        insert(format!(
+            "charging process {start_date} - {} {:.2} kWh, {num_charges} charges",
+            format_end_date(end_date),
+            energy_added.unwrap_or_default()
+        ));
+    }
+
+    let num_positions: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM positions WHERE car_id = $1 AND date BETWEEN $2 AND $3",
+    )
+    .bind(car_id)
+    .bind(start)
+    .bind(end)
+    .fetch_one(&mut *conn)
+    .await?;
+    insert(format!("{num_positions} positions"));
+
+    Ok(summary)
+}
+
+/// Lines removed, lines added and the number of unchanged lines between two summaries
+///
+/// A line is removed or added as many times as its count changed.
+fn diff_summaries(
+    before: &BTreeMap<String, usize>,
+    after: &BTreeMap<String, usize>,
+) -> (Vec<String>, Vec<String>, usize) {
+    let count = |summary: &BTreeMap<String, usize>, line| summary.get(line).copied().unwrap_or(0);
+    let repeat = |(line, n): (&String, usize)| std::iter::repeat_n(line.clone(), n);
+
+    let removed = before
+        .iter()
+        .map(|(line, &n)| (line, n.saturating_sub(count(after, line))))
+        .flat_map(repeat)
+        .collect();
+    let added = after
+        .iter()
+        .map(|(line, &n)| (line, n.saturating_sub(count(before, line))))
+        .flat_map(repeat)
+        .collect();
+    let unchanged = before
+        .iter()
+        .map(|(line, &n)| n.min(count(after, line)))
+        .sum();
+    (removed, added, unchanged)
+}
+
+fn format_end_date(end_date: Option<DateTime<Utc>>) -> String {
+    end_date.map_or_else(|| "in progress".into(), |d| d.to_string())
+}
+
+#[test]
+fn test_diff_summaries() {
+    let summary = |lines: &[&str]| {
+        let mut summary = BTreeMap::new();
+        for line in lines {
+            *summary.entry(line.to_string()).or_default() += 1;
+        }
+        summary
+    };
+    let before = summary(&["state a", "state a", "drive b", "10 positions"]);
+    let after = summary(&["state a", "drive b", "drive b", "drive c", "10 positions"]);
+
+    let (removed, added, unchanged) = diff_summaries(&before, &after);
+    assert_eq!(removed, vec!["state a"]);
+    assert_eq!(added, vec!["drive b", "drive c"]);
+    assert_eq!(unchanged, 3);
+
+    let (removed, added, unchanged) = diff_summaries(&before, &before);
+    assert!(removed.is_empty() && added.is_empty());
+    assert_eq!(unchanged, 4);
+}
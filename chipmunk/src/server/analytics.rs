@@ -0,0 +1,45 @@
+use anyhow::Context;
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::PgPool;
+use ui_common::{CapacityHistory, WsMessage};
+
+use crate::analytics::db_get_capacity_history;
+
+#[derive(Deserialize)]
+struct CarId {
+    car_id: i16,
+}
+
+/// Handle the `get-capacity-history` command of the web interface
+///
+/// Command data:
+/// ```json
+/// { "car_id": 1 }
+/// ```
+///
+/// Response on success:
+/// ```json
+/// { "status": true, "capacity_history": { "car_id": 1, "efficiency": 0.153, "capacity": [...] } }
+/// ```
+///
+/// Response on failure:
+/// ```json
+/// { "status": false, "reason": "error message or reason for failure" }
+/// ```
+pub async fn handle_command(pool: &PgPool, msg: &WsMessage) -> serde_json::Value {
+    match get_capacity_history(pool, msg).await {
+        Ok(history) => json!({"status": true, "capacity_history": history}),
+        Err(e) => {
+            log::error!("Error handling {:?}: {e}", msg.topic);
+            json!({"status": false, "reason": e.to_string()})
+        }
+    }
+}
+
+async fn get_capacity_history(pool: &PgPool, msg: &WsMessage) -> anyhow::Result<CapacityHistory> {
+    let CarId { car_id } = serde_json::from_value(msg.data.clone().context("No car ID provided")?)?;
+    db_get_capacity_history(pool, car_id)
+        .await
+        .with_context(|| format!("Cannot get the capacity history of car {car_id}"))
+}
@@ -0,0 +1,148 @@
+use anyhow::Context;
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::PgPool;
+use ui_common::{Topic, WsMessage};
+
+use crate::database::{tables::geofence::Geofence, DBTable};
+use crate::geofence::{backfill, charging_process_ids, GeofenceMatcher};
+
+#[derive(Deserialize)]
+struct GeofenceId {
+    id: i32,
+}
+
+/// Handle the geofence commands of the web interface
+///
+/// Response on success, with the list of all geofences after running the command:
+/// ```json
+/// { "status": true, "geofences": [{ "id": 1, "name": "Home", ... }] }
+/// ```
+///
+/// Response on failure:
+/// ```json
+/// { "status": false, "reason": "error message or reason for failure" }
+/// ```
+pub async fn handle_command(pool: &PgPool, msg: &WsMessage) -> serde_json::Value {
+    match run_command(pool, msg).await {
+        Ok(geofences) => json!({"status": true, "geofences": geofences}),
+        Err(e) => {
+            log::error!("Error handling {:?}: {e}", msg.topic);
+            json!({"status": false, "reason": e.to_string()})
+        }
+    }
+}
+
+async fn run_command(pool: &PgPool, msg: &WsMessage) -> anyhow::Result<Vec<ui_common::Geofence>> {
+    let data = || msg.data.clone().context("No geofence provided");
+
+    match msg.topic {
+        Topic::GetGeofences => (),
+        Topic::CreateGeofence | Topic::UpdateGeofence => {
+            let geofence = ui_common::Geofence::from_value(data()?)?;
+            validate(&geofence)?;
+            let geofence = Geofence::from_ui_struct(&geofence);
+
+            let mut tx = pool.begin().await?;
+            // The prices of an updated geofence may have changed
+            let charging_process_ids = if msg.topic == Topic::CreateGeofence {
+                geofence.db_insert(&mut *tx).await?;
+                vec![]
+            } else {
+                geofence
+                    .db_update(&mut *tx)
+                    .await
+                    .with_context(|| format!("Cannot update geofence {}", geofence.id))?;
+                charging_process_ids(&mut *tx, geofence.id).await?
+            };
+            let num_changed = backfill(&mut tx, &charging_process_ids).await?;
+            tx.commit().await?;
+            // The geofences may have been loaded again before the transaction was committed
+            GeofenceMatcher::invalidate_cache();
+            log::info!(
+                "Geofence `{}` saved, {num_changed} drives and charging processes changed",
+                geofence.name
+            );
+        }
+        Topic::DeleteGeofence => {
+            let GeofenceId { id } = serde_json::from_value(data()?)?;
+
+            let mut tx = pool.begin().await?;
+            // The geofence of its charging processes is removed by the database, read them first
+            let charging_process_ids = charging_process_ids(&mut *tx, id).await?;
+            Geofence::db_delete(&mut *tx, id as i64)
+                .await
+                .with_context(|| format!("Cannot delete geofence {id}"))?;
+            // Drives and charging processes of the deleted geofence can be inside another one
+            let num_changed = backfill(&mut tx, &charging_process_ids).await?;
+            tx.commit().await?;
+            GeofenceMatcher::invalidate_cache();
+            log::info!(
+                "Geofence {id} deleted, {num_changed} drives and charging processes changed"
+            );
+        }
+        _ => anyhow::bail!("Not a geofence command: {:?}", msg.topic),
+    }
+
+    Ok(Geofence::db_get_all(pool)
+        .await?
+        .iter()
+        .map(Geofence::to_ui_struct)
+        .collect())
+}
+
+fn validate(geofence: &ui_common::Geofence) -> anyhow::Result<()> {
+    if geofence.name.trim().is_empty() {
+        anyhow::bail!("Geofence name is empty");
+    }
+    if !(-90.0..=90.0).contains(&geofence.latitude)
+        || !(-180.0..=180.0).contains(&geofence.longitude)
+    {
+        anyhow::bail!(
+            "Invalid location {}, {}",
+            geofence.latitude,
+            geofence.longitude
+        );
+    }
+    if geofence.radius <= 0 {
+        anyhow::bail!("Invalid radius {}", geofence.radius);
+    }
+    if geofence.cost_per_unit.is_some_and(|c| c < 0.0)
+        || geofence.session_fee.is_some_and(|c| c < 0.0)
+    {
+        anyhow::bail!("Charging cost cannot be negative");
+    }
+    Ok(())
+}
+
+#[test]
+fn test_validate() {
+    let geofence = ui_common::Geofence {
+        name: "Home".into(),
+        latitude: 37.4,
+        longitude: -122.1,
+        radius: 50,
+        ..Default::default()
+    };
+    assert!(validate(&geofence).is_ok());
+    assert!(validate(&ui_common::Geofence {
+        name: " ".into(),
+        ..geofence.clone()
+    })
+    .is_err());
+    assert!(validate(&ui_common::Geofence {
+        latitude: 91.0,
+        ..geofence.clone()
+    })
+    .is_err());
+    assert!(validate(&ui_common::Geofence {
+        radius: 0,
+        ..geofence.clone()
+    })
+    .is_err());
+    assert!(validate(&ui_common::Geofence {
+        session_fee: Some(-1.0),
+        ..geofence
+    })
+    .is_err());
+}
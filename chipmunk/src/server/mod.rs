@@ -1,7 +1,10 @@
+pub mod analytics;
+pub mod geofence;
 pub mod status;
+pub mod tariff;
 
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     net::{IpAddr, Ipv4Addr, SocketAddr},
     path::PathBuf,
     sync::{
@@ -13,6 +16,7 @@ use std::{
 
 use futures_util::{SinkExt, StreamExt, TryFutureExt};
 use serde_json::json;
+use sqlx::PgPool;
 use status::LoggingStatus;
 use tokio::sync::broadcast::error::RecvError;
 use tokio::sync::{broadcast, mpsc, oneshot, watch, RwLock};
@@ -29,6 +33,7 @@ use crate::{
         tables::Tables,
         types::{UnitOfLength, UnitOfPressure, UnitOfTemperature},
     },
+    supervisor::Supervisor,
 };
 
 // static SERVER: OnceLock<TeslaServer> = OnceLock::new();
@@ -55,11 +60,14 @@ pub enum MpscTopic {
 
 pub struct TeslaServer {
     clients: Clients,
-    status: LoggingStatus,
+    /// Logging status of each car, keyed by the car ID
+    status: BTreeMap<i16, LoggingStatus>,
     logging_enabled_watcher: watch::Receiver<bool>,
     unit_of_length_watcher: watch::Receiver<UnitOfLength>,
     unit_of_temperature_watcher: watch::Receiver<UnitOfTemperature>,
     unit_of_pressure_watcher: watch::Receiver<UnitOfPressure>,
+    /// Restart counts and errors of the logger tasks
+    supervisor: Supervisor,
 }
 
 #[derive(Clone)]
@@ -117,11 +125,13 @@ fn find_dist_dir() -> anyhow::Result<PathBuf> {
 
 impl TeslaServer {
     pub async fn start(
+        pool: PgPool,
         config: Config,
-        tables: &Tables,
+        tables: &[Tables],
         data_from_srv_tx: mpsc::UnboundedSender<MpscTopic>,
         mut data_to_srv_rx: broadcast::Receiver<DataToServer>,
         exit_signal_rx: oneshot::Receiver<()>,
+        supervisor: Supervisor,
     ) -> anyhow::Result<()> {
         let clients = Clients::default(); // Keep track of all connected clients
         let clients_copy = clients.clone();
@@ -134,8 +144,9 @@ impl TeslaServer {
             .map(move |ws: warp::ws::Ws, clients: Clients| {
                 let tx = data_from_srv_tx.clone();
                 let config = config_clone.clone();
+                let pool = pool.clone();
                 ws.on_upgrade(move |socket| {
-                    TeslaServer::client_connected(socket, clients, tx, config)
+                    TeslaServer::client_connected(socket, clients, tx, config, pool)
                 })
             });
 
@@ -192,7 +203,15 @@ impl TeslaServer {
                 Err(e) => anyhow::bail!(e),
             };
 
-        let status = LoggingStatus::new(&config, tables);
+        let mut status: BTreeMap<i16, LoggingStatus> = tables
+            .iter()
+            .map(|t| (t.car_id, LoggingStatus::new(&config, t)))
+            .collect();
+        if status.is_empty() {
+            // No cars in the database yet, report the logging status without any vehicle data
+            // until the data from the first car is received
+            status.insert(0, LoggingStatus::new(&config, &Tables::default()));
+        }
 
         let unit_of_length_watcher = match config.unit_of_length.lock() {
             Ok(v) => v.watch(),
@@ -233,6 +252,7 @@ impl TeslaServer {
             unit_of_length_watcher,
             unit_of_temperature_watcher,
             unit_of_pressure_watcher,
+            supervisor,
         }));
 
         // Handle the messages coming from other tasks
@@ -243,7 +263,7 @@ impl TeslaServer {
                     match data_to_srv_rx.recv().await {
                         Ok(v) => match v {
                             DataToServer::Tables(tables) => {
-                                srv.lock().await.status.update(&tables, &config)
+                                srv.lock().await.update_status(&tables, &config)
                             }
                         },
                         Err(RecvError::Closed) => {
@@ -275,8 +295,9 @@ impl TeslaServer {
                     // how old the data is.
                     {
                         let mut srv_locked = srv.lock().await;
-                        let status_msg = srv_locked.get_status_msg();
-                        srv_locked.broadcast(status_msg).await;
+                        for status_msg in srv_locked.get_status_msgs() {
+                            srv_locked.broadcast(status_msg).await;
+                        }
                     }
                     tokio::time::sleep(std::time::Duration::from_secs(1)).await;
                 }
@@ -308,6 +329,7 @@ impl TeslaServer {
         clients: Clients,
         tx: mpsc::UnboundedSender<MpscTopic>,
         config: Config,
+        pool: PgPool,
     ) {
         let client_id = NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed);
 
@@ -343,7 +365,8 @@ impl TeslaServer {
             };
 
             if let Err(e) =
-                TeslaServer::handle_messages(&client_tx, msg, tx.clone(), config.clone()).await
+                TeslaServer::handle_messages(&client_tx, msg, tx.clone(), config.clone(), &pool)
+                    .await
             {
                 // log::error!("{} {}", e, e.backtrace());
                 log::error!("{}", e);
@@ -384,6 +407,7 @@ impl TeslaServer {
         msg: Message,
         tx: mpsc::UnboundedSender<MpscTopic>,
         config: Config,
+        pool: &PgPool,
     ) -> anyhow::Result<()> {
         if msg.is_close() {
             let frame = msg.close_frame();
@@ -502,6 +526,24 @@ impl TeslaServer {
                 let resp = ws_msg.response_with_data(response);
                 TeslaServer::send(client, &resp)?;
             }
+            Topic::GetGeofences
+            | Topic::CreateGeofence
+            | Topic::UpdateGeofence
+            | Topic::DeleteGeofence => {
+                let response = geofence::handle_command(pool, &ws_msg).await;
+                let resp = ws_msg.response_with_data(response);
+                TeslaServer::send(client, &resp)?;
+            }
+            Topic::GetTariffs | Topic::CreateTariff | Topic::UpdateTariff | Topic::DeleteTariff => {
+                let response = tariff::handle_command(pool, &ws_msg).await;
+                let resp = ws_msg.response_with_data(response);
+                TeslaServer::send(client, &resp)?;
+            }
+            Topic::GetCapacityHistory => {
+                let response = analytics::handle_command(pool, &ws_msg).await;
+                let resp = ws_msg.response_with_data(response);
+                TeslaServer::send(client, &resp)?;
+            }
         }
 
         Ok(())
@@ -563,7 +605,27 @@ impl TeslaServer {
         Ok(())
     }
 
-    pub fn get_status_msg(&mut self) -> String {
+    /// Update the logging status of the car the tables belong to
+    fn update_status(&mut self, tables: &Tables, config: &Config) {
+        if !self.status.contains_key(&tables.car_id) {
+            // Remove the placeholder status used before receiving data from any car
+            self.status.remove(&0);
+
+            let mut status = LoggingStatus::new(config, tables);
+            status.set_unit_of_length(*self.unit_of_length_watcher.borrow());
+            status.set_unit_of_temperature(*self.unit_of_temperature_watcher.borrow());
+            status.set_unit_of_pressure(*self.unit_of_pressure_watcher.borrow());
+            status.set_logging_status(*self.logging_enabled_watcher.borrow());
+            self.status.insert(tables.car_id, status);
+        }
+
+        if let Some(status) = self.status.get_mut(&tables.car_id) {
+            status.update(tables, config);
+        }
+    }
+
+    /// Get the logging status messages, one message per car
+    pub fn get_status_msgs(&mut self) -> Vec<String> {
         if self
             .unit_of_length_watcher
             .has_changed()
@@ -571,7 +633,9 @@ impl TeslaServer {
             .unwrap_or(false)
         {
             let new_value = *self.unit_of_length_watcher.borrow_and_update();
-            self.status.set_unit_of_length(new_value);
+            self.status
+                .values_mut()
+                .for_each(|s| s.set_unit_of_length(new_value));
         }
 
         if self
@@ -581,7 +645,9 @@ impl TeslaServer {
             .unwrap_or(false)
         {
             let new_value = *self.unit_of_temperature_watcher.borrow_and_update();
-            self.status.set_unit_of_temperature(new_value);
+            self.status
+                .values_mut()
+                .for_each(|s| s.set_unit_of_temperature(new_value));
         }
 
         if self
@@ -591,7 +657,9 @@ impl TeslaServer {
             .unwrap_or(false)
         {
             let new_value = *self.unit_of_pressure_watcher.borrow_and_update();
-            self.status.set_unit_of_pressure(new_value);
+            self.status
+                .values_mut()
+                .for_each(|s| s.set_unit_of_pressure(new_value));
         }
 
         if self
@@ -601,18 +669,30 @@ impl TeslaServer {
             .unwrap_or(false)
         {
             let new_status = *self.logging_enabled_watcher.borrow_and_update();
-            self.status.set_logging_status(new_status);
+            self.status
+                .values_mut()
+                .for_each(|s| s.set_logging_status(new_status));
         }
 
-        let msg = WsMessage {
-            id: Uuid::new_v4().to_string(),
-            r#type: MessageType::Response,
-            topic: Topic::LoggingStatus,
-            data: self.status.to_value().map_err(|e| log::error!("{e}")).ok(),
-        };
+        let tasks = self.supervisor.statuses();
+        self.status
+            .values_mut()
+            .for_each(|s| s.set_task_status(tasks.clone()));
+
+        self.status
+            .values()
+            .map(|status| {
+                let msg = WsMessage {
+                    id: Uuid::new_v4().to_string(),
+                    r#type: MessageType::Response,
+                    topic: Topic::LoggingStatus,
+                    data: status.to_value().map_err(|e| log::error!("{e}")).ok(),
+                };
 
-        msg.to_string()
-            .map_err(|e| log::error!("Error converting WsMessage to string: {e}"))
-            .unwrap_or_default()
+                msg.to_string()
+                    .map_err(|e| log::error!("Error converting WsMessage to string: {e}"))
+                    .unwrap_or_default()
+            })
+            .collect()
     }
 }
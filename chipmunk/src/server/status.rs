@@ -1,6 +1,7 @@
 use ui_common::{
     units::{Distance, DistanceUnit, PressureUnit, Temperature, TemperatureUnit},
-    Charging, Driving, Location, Logging, Offline, Parked, Sleeping, State, Status, Vehicle,
+    Charging, Driving, Location, Logging, Offline, Parked, Sleeping, State, Status, TaskStatus,
+    Vehicle,
 };
 
 use crate::{
@@ -68,7 +69,11 @@ fn charging(tables: &Tables, state: &State, curr_status: Option<&Charging>) -> O
                 .as_ref()
                 .and_then(|c| c.charge_energy_added)
                 .unwrap_or(curr_status.charge_added),
-            cost: 0, // TODO: calculate cost
+            cost: tables
+                .charging_process
+                .as_ref()
+                .and_then(|c| c.cost)
+                .or(curr_status.cost),
             time_remaining_sec: curr_status.time_remaining_sec,
         }
     } else {
@@ -78,7 +83,7 @@ fn charging(tables: &Tables, state: &State, curr_status: Option<&Charging>) -> O
             starting_battery_level: tables.charges.as_ref().and_then(|c| c.battery_level),
             current_battery_level: tables.charges.as_ref().and_then(|c| c.battery_level),
             charge_added: 0f32,
-            cost: 0,
+            cost: tables.charging_process.as_ref().and_then(|c| c.cost),
             time_remaining_sec: 0,
         }
     };
@@ -239,6 +244,7 @@ impl LoggingStatus {
 
         Self {
             status: Status {
+                car_id: tables.car_id,
                 timestamp: chrono::offset::Utc::now(),
                 app_start_time: chrono::offset::Utc::now(),
                 state: state.clone(),
@@ -249,6 +255,7 @@ impl LoggingStatus {
                 parked: parked(tables, &state, curr_status.parked.as_ref()),
                 offline: offline(&state, curr_status.offline.as_ref()),
                 sleeping: sleeping(&state, curr_status.sleeping.as_ref()),
+                tasks: vec![],
             },
         }
     }
@@ -269,6 +276,7 @@ impl LoggingStatus {
         };
 
         self.status = Status {
+            car_id: tables.car_id,
             timestamp: chrono::offset::Utc::now(),
             app_start_time: chrono::offset::Utc::now(),
             state: state.clone(),
@@ -279,6 +287,7 @@ impl LoggingStatus {
             parked: parked(tables, &state, self.status.parked.as_ref()),
             offline: offline(&state, self.status.offline.as_ref()),
             sleeping: sleeping(&state, self.status.sleeping.as_ref()),
+            tasks: std::mem::take(&mut self.status.tasks),
         };
     }
 
@@ -305,4 +314,8 @@ impl LoggingStatus {
     pub fn set_logging_status(&mut self, status: bool) {
         self.status.logging.enabled = status;
     }
+
+    pub fn set_task_status(&mut self, tasks: Vec<TaskStatus>) {
+        self.status.tasks = tasks;
+    }
 }
@@ -0,0 +1,181 @@
+use anyhow::Context;
+use chrono_tz::Tz;
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::{PgPool, Postgres, Transaction};
+use ui_common::{Topic, WsMessage};
+
+use crate::database::{
+    tables::tariff::{Tariff, ALL_WEEKDAYS},
+    DBTable,
+};
+use crate::geofence::{backfill, charging_process_ids};
+
+#[derive(Deserialize)]
+struct TariffId {
+    id: i32,
+}
+
+#[derive(Deserialize)]
+struct GeofenceId {
+    geofence_id: i32,
+}
+
+/// Handle the tariff commands of the web interface
+///
+/// `get-tariffs` takes the ID of the geofence, `{ "geofence_id": 1 }`, and `delete-tariff` the ID
+/// of the tariff, `{ "id": 1 }`. The cost of the charging processes at the geofence is
+/// recalculated after a tariff is changed.
+///
+/// Response on success, with the list of all tariffs of the geofence after running the command:
+/// ```json
+/// { "status": true, "geofence_id": 1, "tariffs": [{ "id": 1, "name": "Peak", ... }] }
+/// ```
+///
+/// Response on failure:
+/// ```json
+/// { "status": false, "reason": "error message or reason for failure" }
+/// ```
+pub async fn handle_command(pool: &PgPool, msg: &WsMessage) -> serde_json::Value {
+    match run_command(pool, msg).await {
+        Ok((geofence_id, tariffs)) => {
+            json!({"status": true, "geofence_id": geofence_id, "tariffs": tariffs})
+        }
+        Err(e) => {
+            log::error!("Error handling {:?}: {e}", msg.topic);
+            json!({"status": false, "reason": e.to_string()})
+        }
+    }
+}
+
+async fn run_command(
+    pool: &PgPool,
+    msg: &WsMessage,
+) -> anyhow::Result<(i32, Vec<ui_common::Tariff>)> {
+    let data = || msg.data.clone().context("No tariff provided");
+
+    let geofence_id = match msg.topic {
+        Topic::GetTariffs => serde_json::from_value::<GeofenceId>(data()?)?.geofence_id,
+        Topic::CreateTariff | Topic::UpdateTariff => {
+            let tariff = ui_common::Tariff::from_value(data()?)?;
+            validate(&tariff)?;
+            let tariff = Tariff::from_ui_struct(&tariff);
+
+            let mut tx = pool.begin().await?;
+            let geofence_id = if msg.topic == Topic::CreateTariff {
+                tariff.db_insert(&mut *tx).await.with_context(|| {
+                    format!("Cannot add tariff to geofence {}", tariff.geofence_id)
+                })?;
+                tariff.geofence_id
+            } else {
+                let geofence_id = Tariff::db_get_id_with(&mut *tx, tariff.id)
+                    .await
+                    .with_context(|| format!("Cannot find tariff {}", tariff.id))?
+                    .geofence_id;
+                tariff.db_update(&mut *tx).await?;
+                geofence_id
+            };
+            recalculate_cost(tx, geofence_id).await?;
+            log::info!("Tariff `{}` of geofence {geofence_id} saved", tariff.name);
+            geofence_id
+        }
+        Topic::DeleteTariff => {
+            let TariffId { id } = serde_json::from_value(data()?)?;
+
+            let mut tx = pool.begin().await?;
+            let geofence_id = Tariff::db_get_id_with(&mut *tx, id)
+                .await
+                .with_context(|| format!("Cannot find tariff {id}"))?
+                .geofence_id;
+            Tariff::db_delete(&mut *tx, id as i64).await?;
+            recalculate_cost(tx, geofence_id).await?;
+            log::info!("Tariff {id} of geofence {geofence_id} deleted");
+            geofence_id
+        }
+        _ => anyhow::bail!("Not a tariff command: {:?}", msg.topic),
+    };
+
+    let tariffs = Tariff::db_get_for_geofence(pool, geofence_id)
+        .await?
+        .iter()
+        .map(Tariff::to_ui_struct)
+        .collect();
+    Ok((geofence_id, tariffs))
+}
+
+/// Recalculate the cost of the charging processes at the geofence and commit the transaction
+async fn recalculate_cost(
+    mut tx: Transaction<'_, Postgres>,
+    geofence_id: i32,
+) -> anyhow::Result<()> {
+    let charging_process_ids = charging_process_ids(&mut *tx, geofence_id).await?;
+    backfill(&mut tx, &charging_process_ids).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+fn validate(tariff: &ui_common::Tariff) -> anyhow::Result<()> {
+    if tariff.name.trim().is_empty() {
+        anyhow::bail!("Tariff name is empty");
+    }
+    if tariff.weekdays <= 0 || tariff.weekdays & !ALL_WEEKDAYS != 0 {
+        anyhow::bail!("Invalid weekdays {:#b}", tariff.weekdays);
+    }
+    if !tariff.price_per_kwh.is_finite() || tariff.price_per_kwh < 0.0 {
+        anyhow::bail!("Invalid price {}", tariff.price_per_kwh);
+    }
+    if let Err(e) = tariff.time_zone.parse::<Tz>() {
+        anyhow::bail!("Invalid time zone: {e}");
+    }
+    if let (Some(from), Some(until)) = (tariff.valid_from, tariff.valid_until)
+        && from > until
+    {
+        anyhow::bail!("Tariff is valid from {from}, after {until}");
+    }
+    Ok(())
+}
+
+#[test]
+fn test_validate() {
+    use chrono::NaiveDate;
+
+    let tariff = ui_common::Tariff {
+        name: "Off-peak".into(),
+        weekdays: ALL_WEEKDAYS,
+        price_per_kwh: 0.12,
+        time_zone: "Europe/Berlin".into(),
+        ..Default::default()
+    };
+    assert!(validate(&tariff).is_ok());
+    assert!(validate(&ui_common::Tariff {
+        name: "".into(),
+        ..tariff.clone()
+    })
+    .is_err());
+    assert!(validate(&ui_common::Tariff {
+        weekdays: 0,
+        ..tariff.clone()
+    })
+    .is_err());
+    assert!(validate(&ui_common::Tariff {
+        weekdays: 1 << 7,
+        ..tariff.clone()
+    })
+    .is_err());
+    assert!(validate(&ui_common::Tariff {
+        price_per_kwh: -0.1,
+        ..tariff.clone()
+    })
+    .is_err());
+    assert!(validate(&ui_common::Tariff {
+        time_zone: "Europe/Nowhere".into(),
+        ..tariff.clone()
+    })
+    .is_err());
+    assert!(validate(&ui_common::Tariff {
+        valid_from: NaiveDate::from_ymd_opt(2024, 4, 1),
+        valid_until: NaiveDate::from_ymd_opt(2024, 3, 31),
+        ..tariff
+    })
+    .is_err());
+}
@@ -0,0 +1,267 @@
+use std::io::SeekFrom;
+use std::path::PathBuf;
+
+use anyhow::Context;
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::{AsyncBufReadExt, AsyncSeekExt, AsyncWriteExt, BufReader};
+
+use crate::tasks::DataTypes;
+
+/// Append-only file of the vehicle data and the streaming data which could not be written to the
+/// database
+///
+/// The data is stored as JSON lines in the order it was received and replayed through the data
+/// processor once the database is reachable again. The file is kept across restarts, data spooled
+/// before a restart is replayed after the restart.
+///
+/// The replayed data is not removed from the file, the byte offset of the first data point which
+/// was not replayed yet is kept in a second file next to it. Both files are removed once all data
+/// is replayed.
+pub struct Spool {
+    path: PathBuf,
+    offset_path: PathBuf,
+    /// Byte offset of the first data point which was not replayed yet
+    offset: u64,
+    /// Number of lines after `offset`
+    len: usize,
+}
+
+/// Position in the spool file after a data point, pass it to `Spool::consume` once the data
+/// point was replayed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpoolPosition {
+    offset: u64,
+    /// Number of lines from the start of the read
+    num_lines: usize,
+}
+
+impl Spool {
+    /// Open the spool file, the file is created when the first data point is appended
+    pub async fn open(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let mut offset_path = path.clone().into_os_string();
+        offset_path.push(".offset");
+        let mut spool = Self {
+            path,
+            offset_path: offset_path.into(),
+            offset: 0,
+            len: 0,
+        };
+        if let Err(e) = spool.load().await {
+            log::error!("Error reading spool file {:?}: {e}", spool.path);
+        }
+        if spool.len > 0 {
+            log::info!(
+                "{} data points from spool file {:?} will be written to the database",
+                spool.len,
+                spool.path
+            );
+        }
+        spool
+    }
+
+    /// Read the offset and count the lines after it
+    async fn load(&mut self) -> anyhow::Result<()> {
+        self.offset = match fs::read_to_string(&self.offset_path).await {
+            Ok(offset) => offset
+                .trim()
+                .parse()
+                .with_context(|| format!("Invalid offset in {:?}", self.offset_path))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut reader = match self.reader().await? {
+            Some(reader) => reader,
+            None => return Ok(()),
+        };
+        let mut line = String::new();
+        while reader.read_line(&mut line).await? > 0 {
+            if !line.trim().is_empty() {
+                self.len += 1;
+            }
+            line.clear();
+        }
+        Ok(())
+    }
+
+    /// Open the spool file at the first data point which was not replayed yet, `None` if the file
+    /// does not exist
+    async fn reader(&self) -> anyhow::Result<Option<BufReader<File>>> {
+        let mut file = match File::open(&self.path).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        file.seek(SeekFrom::Start(self.offset)).await?;
+        Ok(Some(BufReader::new(file)))
+    }
+
+    /// Number of data points waiting to be written to the database
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Append a data point to the end of the spool file and flush it to disk
+    pub async fn append(&mut self, data: &DataTypes) -> anyhow::Result<()> {
+        let mut line = serde_json::to_string(data)?;
+        line.push('\n');
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await
+            .with_context(|| format!("Error opening spool file {:?}", self.path))?;
+        file.write_all(line.as_bytes()).await?;
+        file.sync_data().await?;
+
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Read up to `max` data points which were not replayed yet, in the order they were appended
+    ///
+    /// Each data point is returned with the position after it. The second value is the position
+    /// after all lines read, including the lines which cannot be parsed, e.g. a partially written
+    /// line after a crash. These lines are skipped.
+    pub async fn read(
+        &self,
+        max: usize,
+    ) -> anyhow::Result<(Vec<(DataTypes, SpoolPosition)>, SpoolPosition)> {
+        let mut position = SpoolPosition {
+            offset: self.offset,
+            num_lines: 0,
+        };
+        let mut reader = match self.reader().await? {
+            Some(reader) => reader,
+            None => return Ok((vec![], position)),
+        };
+
+        let mut data = vec![];
+        let mut line = String::new();
+        while data.len() < max {
+            line.clear();
+            let num_bytes = reader.read_line(&mut line).await?;
+            if num_bytes == 0 {
+                break;
+            }
+            position.offset += num_bytes as u64;
+            if line.trim().is_empty() {
+                continue;
+            }
+            position.num_lines += 1;
+            match serde_json::from_str::<DataTypes>(&line) {
+                Ok(d) => data.push((d, position)),
+                Err(e) => log::error!("Skipping invalid line in spool file: {e}"),
+            }
+        }
+        Ok((data, position))
+    }
+
+    /// Remove the data up to `position`, returned by the last `read`, from the spool
+    ///
+    /// The files are removed if all data was replayed, otherwise the new offset is written.
+    pub async fn consume(&mut self, position: SpoolPosition) -> anyhow::Result<()> {
+        self.len = self.len.saturating_sub(position.num_lines);
+        self.offset = position.offset;
+        if self.len == 0 {
+            self.offset = 0;
+            for path in [&self.path, &self.offset_path] {
+                match fs::remove_file(path).await {
+                    Ok(()) => (),
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            return Ok(());
+        }
+
+        // Write to a temporary file first to not lose the offset if writing fails halfway
+        let mut tmp_path = self.offset_path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let mut file = File::create(&tmp_path).await?;
+        file.write_all(self.offset.to_string().as_bytes()).await?;
+        file.sync_data().await?;
+        fs::rename(&tmp_path, &self.offset_path).await?;
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_spool() {
+    use tesla_api::stream::StreamingData;
+
+    let path = std::env::temp_dir().join(format!("chipmunk_spool_{}.jsonl", std::process::id()));
+    let _ = fs::remove_file(&path).await;
+
+    let mut spool = Spool::open(&path).await;
+    assert!(spool.is_empty());
+    assert!(spool.read(10).await.unwrap().0.is_empty());
+
+    spool
+        .append(&DataTypes::VehicleData(
+            r#"{"response":{"vin":"VIN1"}}"#.into(),
+        ))
+        .await
+        .unwrap();
+    let streaming_data = StreamingData {
+        timestamp: Some(chrono::Utc::now()),
+        shift_state: Some("D".into()),
+        ..Default::default()
+    };
+    spool
+        .append(&DataTypes::StreamingData("VIN1".into(), streaming_data))
+        .await
+        .unwrap();
+    assert_eq!(spool.len(), 2);
+
+    // The spooled data is read back in order after reopening the file
+    let mut spool = Spool::open(&path).await;
+    assert_eq!(spool.len(), 2);
+    let (data, end) = spool.read(10).await.unwrap();
+    assert!(matches!(&data[0].0, DataTypes::VehicleData(d) if d.contains("VIN1")));
+    assert!(matches!(
+        &data[1].0,
+        DataTypes::StreamingData(vin, d) if vin == "VIN1" && d.shift_state.as_deref() == Some("D")
+    ));
+    assert_eq!(data[1].1, end);
+
+    // Keep the data which was not replayed, also after reopening the file
+    spool.consume(data[0].1).await.unwrap();
+    assert_eq!(spool.len(), 1);
+    let mut spool = Spool::open(&path).await;
+    assert_eq!(spool.len(), 1);
+    let (data, _) = spool.read(10).await.unwrap();
+    assert!(matches!(data[..], [(DataTypes::StreamingData(..), _)]));
+
+    // Invalid lines are skipped and data appended while replaying is read after the rest
+    fs::OpenOptions::new()
+        .append(true)
+        .open(&path)
+        .await
+        .unwrap()
+        .write_all(b"{\"Vehicle\n")
+        .await
+        .unwrap();
+    spool
+        .append(&DataTypes::VehicleData("{}".into()))
+        .await
+        .unwrap();
+    let mut spool = Spool::open(&path).await;
+    assert_eq!(spool.len(), 3);
+    let (data, position) = spool.read(1).await.unwrap();
+    assert_eq!(data.len(), 1);
+    spool.consume(position).await.unwrap();
+    let (data, end) = spool.read(10).await.unwrap();
+    assert!(matches!(&data[..], [(DataTypes::VehicleData(d), _)] if d == "{}"));
+
+    spool.consume(end).await.unwrap();
+    assert!(spool.is_empty());
+    assert!(!path.exists());
+    assert!(!spool.offset_path.exists());
+}
@@ -0,0 +1,283 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::Utc;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+use ui_common::TaskStatus;
+
+/// Delay before the first restart of a failed task
+const INITIAL_BACKOFF: Duration = Duration::from_secs(5);
+/// Upper limit of the restart delay, the delay doubles after every consecutive failure
+const MAX_BACKOFF: Duration = Duration::from_secs(5 * 60);
+/// A task running at least this long is considered healthy and the restart delay is reset
+const HEALTHY_RUN: Duration = Duration::from_secs(10 * 60);
+
+/// Restarts the supervised tasks when they fail or exit while the application is still running
+///
+/// A Tesla API outage or a dropped streaming connection only restarts the affected task, the
+/// other tasks keep running. The restart count and the last error of each task are reported in
+/// the logging status of the web interface.
+///
+/// Panicking tasks are only restarted if panics unwind. The release profile is built with
+/// `panic = "abort"`, a panic aborts the whole process there and the process has to be restarted
+/// by the service manager, e.g. the `restart` policy of the docker compose file.
+#[derive(Clone)]
+pub struct Supervisor {
+    tasks: Arc<Mutex<BTreeMap<String, TaskStatus>>>,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    healthy_run: Duration,
+}
+
+impl Default for Supervisor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Supervisor {
+    pub fn new() -> Self {
+        Self::with_backoff(INITIAL_BACKOFF, MAX_BACKOFF, HEALTHY_RUN)
+    }
+
+    /// # Arguments
+    ///
+    /// * `initial_backoff` - delay before the first restart of a failed task
+    /// * `max_backoff` - upper limit of the restart delay
+    /// * `healthy_run` - reset the restart delay if the task ran at least this long
+    pub fn with_backoff(
+        initial_backoff: Duration,
+        max_backoff: Duration,
+        healthy_run: Duration,
+    ) -> Self {
+        Self {
+            tasks: Arc::default(),
+            initial_backoff,
+            max_backoff,
+            healthy_run,
+        }
+    }
+
+    /// Status of all supervised tasks, sorted by the task name
+    pub fn statuses(&self) -> Vec<TaskStatus> {
+        self.tasks
+            .lock()
+            .map(|t| t.values().cloned().collect())
+            .map_err(|e| log::error!("Error getting lock on supervisor task status: {e}"))
+            .unwrap_or_default()
+    }
+
+    fn update_status(&self, name: &str, f: impl FnOnce(&mut TaskStatus)) {
+        match self.tasks.lock() {
+            Ok(mut tasks) => f(tasks.entry(name.to_string()).or_insert_with(|| TaskStatus {
+                name: name.to_string(),
+                ..Default::default()
+            })),
+            Err(e) => log::error!("Error getting lock on supervisor task status: {e}"),
+        }
+    }
+
+    /// Spawn a task on `tracker` and restart it with exponential backoff whenever it returns,
+    /// returns an error or panics before `cancellation_token` is cancelled
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - name of the task shown in the logs and the logging status
+    /// * `make_task` - creates a new instance of the task on every (re)start
+    pub fn spawn<F, Fut>(
+        &self,
+        tracker: &TaskTracker,
+        name: &str,
+        cancellation_token: CancellationToken,
+        make_task: F,
+    ) -> JoinHandle<()>
+    where
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        self.spawn_task(tracker, name, cancellation_token, make_task, true)
+    }
+
+    /// Same as `spawn`, but the task is not restarted when it returns `Ok(())`, e.g. after
+    /// processing all data of a data source
+    ///
+    /// The returned handle completes when the task is done or cancelled.
+    pub fn spawn_until_done<F, Fut>(
+        &self,
+        tracker: &TaskTracker,
+        name: &str,
+        cancellation_token: CancellationToken,
+        make_task: F,
+    ) -> JoinHandle<()>
+    where
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        self.spawn_task(tracker, name, cancellation_token, make_task, false)
+    }
+
+    fn spawn_task<F, Fut>(
+        &self,
+        tracker: &TaskTracker,
+        name: &str,
+        cancellation_token: CancellationToken,
+        mut make_task: F,
+        restart_when_done: bool,
+    ) -> JoinHandle<()>
+    where
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        let supervisor = self.clone();
+        let name = name.to_string();
+        supervisor.update_status(&name, |_| ());
+
+        tracker.spawn(async move {
+            let mut backoff = supervisor.initial_backoff;
+            loop {
+                supervisor.update_status(&name, |s| s.running = true);
+                let started = Instant::now();
+                // Run the task in a separate tokio task to catch panics, see the note on panics
+                // in the `Supervisor` documentation
+                let result = tokio::task::spawn(make_task()).await;
+                supervisor.update_status(&name, |s| s.running = false);
+
+                if cancellation_token.is_cancelled() {
+                    break;
+                }
+
+                let error = match result {
+                    Ok(Ok(())) if !restart_when_done => {
+                        log::info!("{name}: done");
+                        break;
+                    }
+                    Ok(Ok(())) => "task exited".to_string(),
+                    Ok(Err(e)) => e.to_string(),
+                    Err(e) if e.is_panic() => {
+                        let panic = e.into_panic();
+                        let msg = panic
+                            .downcast_ref::<&str>()
+                            .map(|s| s.to_string())
+                            .or_else(|| panic.downcast_ref::<String>().cloned())
+                            .unwrap_or_else(|| "unknown panic".to_string());
+                        format!("panicked: {msg}")
+                    }
+                    Err(e) => e.to_string(),
+                };
+
+                if started.elapsed() >= supervisor.healthy_run {
+                    backoff = supervisor.initial_backoff;
+                }
+                log::error!("{name}: {error}, restarting in {backoff:?}");
+                supervisor.update_status(&name, |s| {
+                    s.restarts += 1;
+                    s.last_error = Some(error);
+                    s.last_restart = Some(Utc::now());
+                });
+
+                tokio::select! {
+                    _ = cancellation_token.cancelled() => break,
+                    _ = tokio::time::sleep(backoff) => (),
+                }
+                backoff = (backoff * 2).min(supervisor.max_backoff);
+            }
+            tracing::info!("{name}: stopped supervising");
+        })
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn supervisor_restarts_failed_tasks() {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    let supervisor = Supervisor::with_backoff(
+        Duration::from_millis(10),
+        Duration::from_millis(40),
+        Duration::from_secs(60),
+    );
+    let tracker = TaskTracker::new();
+    let cancellation_token = CancellationToken::new();
+
+    // Fails twice and panics once, then runs until cancelled
+    let runs = Arc::new(AtomicU32::new(0));
+    supervisor.spawn(&tracker, "flaky", cancellation_token.clone(), {
+        let runs = runs.clone();
+        let cancellation_token = cancellation_token.clone();
+        move || {
+            let run = runs.fetch_add(1, Ordering::SeqCst);
+            let cancellation_token = cancellation_token.clone();
+            async move {
+                match run {
+                    0 | 1 => anyhow::bail!("connection lost #{run}"),
+                    2 => panic!("unexpected data"),
+                    _ => {
+                        cancellation_token.cancelled().await;
+                        Ok(())
+                    }
+                }
+            }
+        }
+    });
+    supervisor.spawn(&tracker, "healthy", cancellation_token.clone(), {
+        let cancellation_token = cancellation_token.clone();
+        move || {
+            let cancellation_token = cancellation_token.clone();
+            async move {
+                cancellation_token.cancelled().await;
+                Ok(())
+            }
+        }
+    });
+    // Restarted after failing, not restarted when done
+    let finite_runs = Arc::new(AtomicU32::new(0));
+    let finite = supervisor.spawn_until_done(&tracker, "finite", cancellation_token.clone(), {
+        let finite_runs = finite_runs.clone();
+        move || {
+            let run = finite_runs.fetch_add(1, Ordering::SeqCst);
+            async move {
+                match run {
+                    0 => anyhow::bail!("connection lost"),
+                    _ => Ok(()),
+                }
+            }
+        }
+    });
+    tracker.close();
+
+    // Backoff of 10 + 20 + 40 ms before the fourth run
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(runs.load(Ordering::SeqCst), 4);
+    assert!(finite.is_finished());
+    assert_eq!(finite_runs.load(Ordering::SeqCst), 2);
+
+    let statuses = supervisor.statuses();
+    assert_eq!(statuses.len(), 3);
+    let finite = &statuses[0];
+    assert_eq!(finite.name, "finite");
+    assert!(!finite.running);
+    assert_eq!(finite.restarts, 1);
+    let flaky = &statuses[1];
+    assert_eq!(flaky.name, "flaky");
+    assert!(flaky.running);
+    assert_eq!(flaky.restarts, 3);
+    assert_eq!(
+        flaky.last_error.as_deref(),
+        Some("panicked: unexpected data")
+    );
+    let healthy = &statuses[2];
+    assert_eq!(healthy.name, "healthy");
+    assert!(healthy.running);
+    assert_eq!(healthy.restarts, 0);
+    assert_eq!(healthy.last_error, None);
+
+    // Cancelled tasks are not restarted
+    cancellation_token.cancel();
+    tracker.wait().await;
+    assert_eq!(runs.load(Ordering::SeqCst), 4);
+    assert!(supervisor.statuses().iter().all(|s| !s.running));
+}
@@ -1,62 +1,290 @@
-use std::time::Duration;
-use tokio::sync::mpsc;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, Notify};
 
 use crate::config::Config;
+use crate::database::tables::car_settings::CarSettings;
+use crate::database::tables::state::StateStatus;
 use crate::get_config;
+use crate::task_data_processor::fill_missing_sections;
 use crate::tasks::DataTypes;
+use tesla_api::auth::AuthResponse;
+use tesla_api::vehicle_data::{OnlineState, VehicleData, VehicleDataEndpoints};
 use tesla_api::{TeslaClient, TeslaError};
 use tokio_util::sync::CancellationToken;
 
+/// While parked, poll once every `PARKED_POLL_FACTOR` logging periods
+const PARKED_POLL_FACTOR: u32 = 20;
+/// While the vehicle is asleep, offline or polling is suspended, check the online state once every
+/// `STATE_CHECK_FACTOR` logging periods
+const STATE_CHECK_FACTOR: u32 = 20;
+
+/// Decides how often to request the vehicle data
+///
+/// The vehicle data is polled every logging period while driving or charging and less often while
+/// parked. Once the vehicle has been idle for `suspend_after_idle_min`, polling is suspended for
+/// `suspend_min` so that the vehicle can fall asleep, requesting the vehicle data keeps it awake.
+/// Activity seen by the streaming API or the vehicle waking up ends the suspension.
+struct PollingPolicy {
+    logging_period: Duration,
+    suspend: Duration,
+    suspend_after_idle: Duration,
+    /// Only suspend polling if the vehicle is locked
+    req_not_unlocked: bool,
+    /// Last time the vehicle was driving, charging or woke up
+    last_activity: Instant,
+    /// Polling is suspended until this time
+    suspended_until: Option<Instant>,
+}
+
+impl PollingPolicy {
+    fn new(settings: &CarSettings, logging_period: Duration, now: Instant) -> Self {
+        let minutes = |m: i32| Duration::from_secs(m.max(0) as u64 * 60);
+        Self {
+            logging_period,
+            suspend: minutes(settings.suspend_min),
+            suspend_after_idle: minutes(settings.suspend_after_idle_min),
+            req_not_unlocked: settings.req_not_unlocked,
+            last_activity: now,
+            suspended_until: None,
+        }
+    }
+
+    /// Update the policy with the state of the vehicle from the latest vehicle data
+    fn update(&mut self, status: &StateStatus, locked: Option<bool>, now: Instant) {
+        match status {
+            StateStatus::Driving | StateStatus::Charging => self.activity(now),
+            StateStatus::Parked => {
+                let idle = now.saturating_duration_since(self.last_activity);
+                let can_suspend = !self.req_not_unlocked || locked == Some(true);
+                if idle >= self.suspend_after_idle && can_suspend && !self.suspend.is_zero() {
+                    log::info!(
+                        "Vehicle idle for {idle:?}, suspending polling for {:?} to let the vehicle sleep",
+                        self.suspend
+                    );
+                    self.suspended_until = Some(now + self.suspend);
+                }
+            }
+            StateStatus::Offline | StateStatus::Asleep | StateStatus::Unknown => (),
+        }
+    }
+
+    /// The vehicle started driving, charging or woke up
+    fn activity(&mut self, now: Instant) {
+        if self.suspended_until.take().is_some() {
+            log::info!("Vehicle activity detected, resuming polling");
+        }
+        self.last_activity = now;
+    }
+
+    /// Whether the vehicle data should not be requested, the idle time is restarted when the
+    /// suspension expires without the vehicle falling asleep
+    fn is_suspended(&mut self, now: Instant) -> bool {
+        match self.suspended_until {
+            Some(until) if now < until => true,
+            Some(_) => {
+                log::info!("Vehicle did not fall asleep, resuming polling");
+                self.suspended_until = None;
+                self.last_activity = now;
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Time to wait before requesting the vehicle data again
+    fn poll_interval(&self, status: &StateStatus) -> Duration {
+        match status {
+            StateStatus::Driving | StateStatus::Charging => self.logging_period,
+            StateStatus::Parked | StateStatus::Unknown => self.logging_period * PARKED_POLL_FACTOR,
+            StateStatus::Offline | StateStatus::Asleep => self.state_check_interval(),
+        }
+    }
+
+    /// Time to wait before checking the online state again while not polling the vehicle data
+    fn state_check_interval(&self) -> Duration {
+        self.logging_period * STATE_CHECK_FACTOR
+    }
+}
+
+/// Sections of the vehicle data to request in the state of the last vehicle data
+///
+/// Only the charge state and the temperatures change while charging and the location does not
+/// change while parked. The data processor takes the other sections from the last vehicle data,
+/// see `fill_missing_sections`.
+fn endpoints_for(status: Option<&StateStatus>) -> VehicleDataEndpoints {
+    match status {
+        Some(StateStatus::Charging) => {
+            VehicleDataEndpoints::CHARGE_STATE | VehicleDataEndpoints::CLIMATE_STATE
+        }
+        Some(StateStatus::Parked) => {
+            VehicleDataEndpoints::all() - VehicleDataEndpoints::LOCATION_DATA
+        }
+        _ => VehicleDataEndpoints::all(),
+    }
+}
+
 pub async fn data_polling_task(
     data_tx: mpsc::Sender<DataTypes>,
     config: Config,
     cancellation_token: CancellationToken,
     mut tesla_client: TeslaClient,
     car_id: u64,
-) {
+    vehicle_activity: Arc<Notify>,
+    pool: &sqlx::PgPool,
+) -> anyhow::Result<()> {
     let name = "data_polling_task";
     let mut _num_data_points = 0;
+    // Online state from the last check, `vehicle_data` is only requested when the vehicle is online
+    let mut online_state: Option<OnlineState> = None;
+    let mut access_token_rx = match config.access_token.lock() {
+        Ok(a) => a.watch(),
+        Err(e) => anyhow::bail!("Error getting lock on `config.access_token`: {e}"),
+    };
+    let mut logging_enabled_rx = match config.logging_enabled.lock() {
+        Ok(l) => l.watch(),
+        Err(e) => anyhow::bail!("Error getting lock on `config.logging_enabled`: {e}"),
+    };
+    let mut policy: Option<PollingPolicy> = None;
+    // Last vehicle data received while the vehicle was online, the sections which are not
+    // requested again are taken from it
+    let mut last_data: Option<VehicleData> = None;
+
     loop {
         if cancellation_token.is_cancelled() {
             break;
         }
 
-        match get_config!(config.logging_enabled) {
-            Ok(false) => {
-                //tokio::time::sleep(Duration::from_secs(1)).await;
-                continue;
-            }
-            Ok(true) => (),
+        let logging_enabled = match get_config!(config.logging_enabled) {
+            Ok(v) => v,
             Err(e) => {
                 log::error!("Error getting config value `logging_enabled`: {e}");
-                //tokio::time::sleep(Duration::from_secs(1)).await;
-                continue;
+                false
             }
+        };
+        if !logging_enabled {
+            // Wait until logging is enabled again
+            tokio::select! {
+                _ = cancellation_token.cancelled() => break,
+                _ = logging_enabled_rx.changed() => (),
+            }
+            continue;
         }
 
         let Ok(logging_period_ms) = get_config!(config.logging_period_ms) else {
-            log::error!("Error reading config value `logging_period_ms`");
-            return;
+            anyhow::bail!("Error reading config value `logging_period_ms`");
         };
+        let logging_period = Duration::from_millis(logging_period_ms as u64);
+        let now = Instant::now();
+        let policy = match policy {
+            Some(ref mut p) => {
+                p.logging_period = logging_period;
+                p
+            }
+            None => {
+                let settings = CarSettings::db_get_for_car(pool, car_id as i64)
+                    .await
+                    .unwrap_or_default();
+                policy.insert(PollingPolicy::new(&settings, logging_period, now))
+            }
+        };
+
+        // Use the new tokens if they were refreshed by another task
+        if access_token_rx.has_changed().unwrap_or(false) {
+            access_token_rx.borrow_and_update();
+            let tokens = AuthResponse {
+                access_token: get_config!(config.access_token).unwrap_or_default(),
+                refresh_token: get_config!(config.refresh_token).unwrap_or_default(),
+                ..Default::default()
+            };
+            if let Err(e) = tesla_client.set_tokens(tokens) {
+                log::error!("{name}: error updating tokens: {e}");
+            }
+        }
+
+        // Checking the online state does not wake up the vehicle, requesting the vehicle data
+        // while the vehicle is asleep or offline only returns errors
+        let suspended = policy.is_suspended(now);
+        if suspended || online_state != Some(OnlineState::Online) {
+            match tesla_client.online_state(car_id).await {
+                Ok(state) => {
+                    if online_state.as_ref() != Some(&state) {
+                        log::info!("{name}: vehicle is {state:?}");
+                        if state == OnlineState::Online && online_state.is_some() {
+                            // Vehicle woke up
+                            policy.activity(now);
+                        }
+                    }
+                    if state != OnlineState::Online {
+                        last_data = None;
+                    }
+                    online_state = Some(state);
+                }
+                Err(e) => log::error!("{name}: error getting vehicle state: {e}"),
+            }
+            if policy.is_suspended(now)
+                || matches!(
+                    online_state,
+                    Some(OnlineState::Asleep) | Some(OnlineState::Offline)
+                )
+            {
+                let interval = policy.state_check_interval();
+                if wait_for_activity(&vehicle_activity, interval, &cancellation_token).await {
+                    policy.activity(Instant::now());
+                    online_state = Some(OnlineState::Online);
+                }
+                continue;
+            }
+        }
 
-        match tesla_api::get_vehicle_data(&mut tesla_client, car_id).await {
+        let endpoints = endpoints_for(last_data.as_ref().map(StateStatus::from).as_ref());
+        match tesla_api::get_vehicle_data_with_endpoints(&mut tesla_client, car_id, endpoints).await
+        {
             Ok(data) => {
+                // The raw response is stored, only the state is taken from the merged vehicle data
+                match VehicleData::from_response_json(&data) {
+                    Ok(mut vehicle_data) => {
+                        if let Some(ref prev) = last_data {
+                            fill_missing_sections(&mut vehicle_data, prev);
+                        }
+                        last_data = Some(vehicle_data);
+                    }
+                    Err(e) => log::error!("{name}: error parsing vehicle data: {e}"),
+                }
+                let status = last_data
+                    .as_ref()
+                    .map(StateStatus::from)
+                    .unwrap_or_default();
+                let locked = last_data
+                    .as_ref()
+                    .and_then(|d| d.vehicle_state.as_ref())
+                    .and_then(|v| v.locked);
+                policy.update(&status, locked, now);
+
                 if let Err(e) = data_tx.send(DataTypes::VehicleData(data)).await {
                     // don't log error message if the channel was closed because of a cancellation request
                     if !cancellation_token.is_cancelled() {
                         log::error!("{name}: cannot send data over data_tx: {e}");
                     }
                 }
+
+                _num_data_points += 1;
+
+                let interval = policy.poll_interval(&status);
+                if wait_for_activity(&vehicle_activity, interval, &cancellation_token).await {
+                    policy.activity(Instant::now());
+                }
             }
             Err(e) => {
                 match e {
                     TeslaError::Connection(e) => log::error!("Error: `{e}`"),
                     TeslaError::Request(e) => log::error!("Error: `{e}`"),
-                    TeslaError::ApiError(e) => log::error!("Error: `{e}`"), // TODO: Error: `429 - Account or server is rate limited. This happens when too many requests are made by an account.
-                    // • Check the 'Retry-After' request header (in seconds); to determine when to make the next request.`
+                    TeslaError::ApiError(e) => log::error!("Error: `{e}`"),
                     TeslaError::NotOnline => {
-                        // TODO: Is there a way to wait for the vehicle to come online?
+                        // Check the online state before requesting the vehicle data again
                         log::info!("Vehicle is not online");
+                        online_state = None;
+                        last_data = None;
                     }
                     TeslaError::InvalidHeader(e) => log::error!("Error: `{e}`"),
                     TeslaError::ParseError(e) => log::error!("Error: `{e}`"),
@@ -67,16 +295,100 @@ pub async fn data_polling_task(
                     TeslaError::InvalidResponse(ref msg) => log::error!("Error: `{e}` - {msg}"),
                     TeslaError::TestInProgress => log::info!("{e}"),
                     TeslaError::Retry(e) => log::info!("{e}"),
+                    TeslaError::CommandFailed(e) => log::error!("Error: `{e}`"),
+                    TeslaError::RateLimited { retry_after } => {
+                        // The client holds back the next request until `retry_after` expires
+                        log::warn!("Rate limited, retrying after {retry_after:?}");
+                    }
+                }
+                tokio::select! {
+                    _ = cancellation_token.cancelled() => break,
+                    _ = tokio::time::sleep(logging_period) => (),
                 }
-                tokio::time::sleep(Duration::from_millis(logging_period_ms as u64)).await;
-                continue;
             }
         };
+    }
 
-        _num_data_points += 1;
+    tracing::warn!("exiting {name}");
+    Ok(())
+}
 
-        tokio::time::sleep(Duration::from_millis(logging_period_ms as u64)).await;
+/// Wait for `duration` or until the streaming task sees the vehicle driving or charging
+///
+/// Returns `true` if activity was seen, the wait also ends when the task is cancelled.
+async fn wait_for_activity(
+    vehicle_activity: &Notify,
+    duration: Duration,
+    cancellation_token: &CancellationToken,
+) -> bool {
+    tokio::select! {
+        _ = vehicle_activity.notified() => true,
+        _ = tokio::time::sleep(duration) => false,
+        _ = cancellation_token.cancelled() => false,
     }
+}
 
-    tracing::warn!("exiting {name}");
+#[test]
+fn polling_policy() {
+    let settings = CarSettings {
+        suspend_min: 21,
+        suspend_after_idle_min: 15,
+        req_not_unlocked: true,
+        ..Default::default()
+    };
+    let minutes = |m: u64| Duration::from_secs(m * 60);
+    let start = Instant::now();
+    let mut policy = PollingPolicy::new(&settings, Duration::from_secs(1), start);
+
+    assert_eq!(
+        policy.poll_interval(&StateStatus::Driving),
+        Duration::from_secs(1)
+    );
+    assert_eq!(
+        policy.poll_interval(&StateStatus::Parked),
+        Duration::from_secs(20)
+    );
+
+    // Keep polling while driving
+    policy.update(&StateStatus::Driving, Some(false), start + minutes(30));
+    assert!(!policy.is_suspended(start + minutes(30)));
+
+    // Parked but not idle long enough
+    policy.update(&StateStatus::Parked, Some(true), start + minutes(40));
+    assert!(!policy.is_suspended(start + minutes(40)));
+
+    // Idle but unlocked
+    policy.update(&StateStatus::Parked, Some(false), start + minutes(46));
+    assert!(!policy.is_suspended(start + minutes(46)));
+
+    // Idle and locked, suspend polling for `suspend_min`
+    policy.update(&StateStatus::Parked, Some(true), start + minutes(46));
+    assert!(policy.is_suspended(start + minutes(50)));
+
+    // Suspension expires without the vehicle falling asleep, the idle time starts again
+    assert!(!policy.is_suspended(start + minutes(67)));
+    policy.update(&StateStatus::Parked, Some(true), start + minutes(70));
+    assert!(!policy.is_suspended(start + minutes(70)));
+
+    // Streaming activity ends the suspension
+    policy.update(&StateStatus::Parked, Some(true), start + minutes(90));
+    assert!(policy.is_suspended(start + minutes(91)));
+    policy.activity(start + minutes(92));
+    assert!(!policy.is_suspended(start + minutes(92)));
+}
+
+#[test]
+fn vehicle_data_endpoints() {
+    assert_eq!(endpoints_for(None), VehicleDataEndpoints::all());
+    assert_eq!(
+        endpoints_for(Some(&StateStatus::Driving)),
+        VehicleDataEndpoints::all()
+    );
+    assert_eq!(
+        endpoints_for(Some(&StateStatus::Charging)),
+        VehicleDataEndpoints::CHARGE_STATE | VehicleDataEndpoints::CLIMATE_STATE
+    );
+    assert!(
+        !endpoints_for(Some(&StateStatus::Parked)).contains(VehicleDataEndpoints::LOCATION_DATA)
+    );
 }
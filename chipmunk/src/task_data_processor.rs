@@ -10,162 +10,368 @@ use crate::database::tables::state::{State, StateStatus};
 use crate::database::tables::Tables;
 use crate::database::types::ChargeStat;
 use crate::database::DBTable;
+use crate::spool::Spool;
 use crate::tasks::{DataTypes, DatabaseDataType, DatabaseRespType};
-use crate::{database, DELAYED_DATAPOINT_TIME_SEC};
+use crate::{database, get_config, DELAYED_DATAPOINT_TIME_SEC};
+use chrono::{DateTime, Utc};
+use std::collections::hash_map::Entry;
 use std::collections::HashMap;
+use std::time::Duration;
+use tesla_api::stream::StreamingData;
 use tesla_api::vehicle_data::VehicleData;
 use tokio::sync::{broadcast, mpsc};
 use tokio_util::sync::CancellationToken;
 
+/// Positions from the vehicle data are not logged if they are less than this many milliseconds
+/// newer than the last position received from the streaming API
+const STREAMED_POSITION_WINDOW_MS: i64 = 1000;
+
+/// Check if the database is reachable again and replay the spooled data this often
+const SPOOL_REPLAY_INTERVAL: Duration = Duration::from_secs(10);
+/// Maximum number of spooled data points written at once, new data is received in between
+const SPOOL_REPLAY_CHUNK_SIZE: usize = 100;
+
+/// Data cached between the data points
+#[derive(Default)]
+struct Cache {
+    vin_id_map: HashMap<String, i16>,
+    /// Last vehicle data of each car, keyed by the car ID. The sections missing from partial
+    /// vehicle data are taken from it.
+    last_data_map: HashMap<i16, VehicleData>,
+    /// Last logged tables of each car, keyed by the car ID
+    prev_tables_map: HashMap<i16, Tables>,
+    /// Time of the last logged streaming data of each car, keyed by the car ID
+    last_streamed_map: HashMap<i16, DateTime<Utc>>,
+}
+
+/// Channels to the database task and to the consumers of the processed data
+struct Channels<'a> {
+    processed_data_tx: &'a broadcast::Sender<Tables>,
+    database_tx: &'a mpsc::Sender<DatabaseDataType>,
+    database_resp_rx: &'a mut mpsc::Receiver<DatabaseRespType>,
+}
+
 pub async fn data_processor_task(
-    mut vehicle_data_rx: mpsc::Receiver<DataTypes>,
+    vehicle_data_rx: &mut mpsc::Receiver<DataTypes>,
     processed_data_tx: broadcast::Sender<Tables>,
     database_tx: mpsc::Sender<DatabaseDataType>,
     mut database_resp_rx: mpsc::Receiver<DatabaseRespType>,
-    _config: Config,
+    config: Config,
     cancellation_token: CancellationToken,
     pool: &sqlx::PgPool,
 ) {
-    use mpsc::error::*;
     let name = "data_processor_task";
-    let mut vin_id_map = database::tables::car::get_vin_id_map(pool).await;
-    let mut prev_tables = Tables::db_get_last(pool).await;
+    let mut cache = Cache {
+        vin_id_map: database::tables::car::get_vin_id_map(pool).await,
+        ..Default::default()
+    };
+    let mut channels = Channels {
+        processed_data_tx: &processed_data_tx,
+        database_tx: &database_tx,
+        database_resp_rx: &mut database_resp_rx,
+    };
+    let spool_file = get_config!(config.spool_file).unwrap_or_default();
+    let mut spool = Spool::open(spool_file).await;
+    let mut replay_interval = tokio::time::interval(SPOOL_REPLAY_INTERVAL);
+    replay_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
 
     loop {
-        if cancellation_token.is_cancelled() {
+        let data = tokio::select! {
+            _ = cancellation_token.cancelled() => break,
+            _ = replay_interval.tick(), if !spool.is_empty() => {
+                if replay_spool(&mut spool, &mut cache, &mut channels, &cancellation_token, pool).await {
+                    // Write the next chunk right after receiving the data waiting in the channel
+                    replay_interval.reset_immediately();
+                }
+                continue;
+            }
+            data = vehicle_data_rx.recv() => data,
+        };
+        let Some(data) = data else {
+            // The channel is closed when the data source has sent all its data
+            if !cancellation_token.is_cancelled() {
+                log::info!("vehicle_data_rx channel closed, exiting {name}");
+            }
             break;
+        };
+
+        // Keep the order of the data points, the new data is written after the spooled data
+        if !spool.is_empty() {
+            append_to_spool(&mut spool, &data).await;
+            continue;
         }
 
-        tokio::task::yield_now().await;
-
-        match vehicle_data_rx.try_recv() {
-            Ok(v) => match v {
-                DataTypes::VehicleData(data) => {
-                    if let Err(e) = database_tx
-                        .send(DatabaseDataType::RawData(data.clone()))
-                        .await
-                    {
-                        log::error!("{name}: cannot send raw vehicle data over database_tx: {e}");
-                    }
-
-                    let vehicle_data = match VehicleData::from_response_json(&data) {
-                        Ok(data) => data,
-                        Err(e) => {
-                            log::error!("Error parsing vehicle data to json: {e}");
-                            continue;
-                        }
-                    };
-
-                    let car_id_opt;
-                    (vin_id_map, car_id_opt) = get_car_id(pool, vin_id_map, &vehicle_data).await;
-
-                    let Some(car_id) = car_id_opt else {
-                        log::error!("Error getting car ID");
-                        continue;
-                    };
-
-                    let table_list = match create_tables(&vehicle_data, &prev_tables, car_id).await
-                    {
-                        Ok(table_list) => table_list,
-                        Err(e) => {
-                            log::error!("Error adding to database: {e}");
-                            continue;
-                        }
-                    };
-
-                    // Send the tables to the database task
-                    if let Err(e) = database_tx.send(DatabaseDataType::Tables(table_list)).await {
-                        log::error!("{name}: cannot send table_list over database_tx: {e}");
-                    }
-
-                    // Wait for the response from database task with the updated tables with
-                    // database id fields
-                    if let Some(resp) = database_resp_rx.recv().await {
-                        if let DatabaseRespType::Tables(prev_tables_resp) = resp {
-                            prev_tables = prev_tables_resp;
-                        } else {
-                            log::error!("Unexpected response type received from database task");
-                        }
-                    } else {
-                        log::error!("No response received from database task");
-                    }
-
-                    if let Err(e) = processed_data_tx.send(prev_tables.clone()) {
-                        log::error!("{name}: cannot send data over data_tx: {e}");
-                    }
-                }
-                DataTypes::StreamingData(data) => {
-                    log::error!("{data:#?}");
-                    let position = Position::from_streaming_data(&data);
-                    let charges = Charges::from_streaming_data(&data);
-
-                    // let drive = DriveState {
-                    //     heading: data.est_heading.map(|h| h as i32),
-                    //     shift_state: data.shift_state.and_then(|s| {
-                    //         ShiftState::from_str(&s)
-                    //             .map_err(|e| log::error!("{e}"))
-                    //             .ok()
-                    //     }),
-                    //     ..Default::default()
-                    // };
-                    let Some(prev_state) = prev_tables.state.clone() else {
-                        continue;
-                    };
-
-                    let tables =
-                        continue_logging(&prev_tables, prev_state, position, Some(charges)).await;
-
-                    prev_tables = match send_to_database_task(
-                        vec![tables],
-                        &database_tx,
-                        &mut database_resp_rx,
-                    )
-                    .await
-                    {
-                        Ok(tables) => tables,
-                        Err(e) => {
-                            log::error!("{name}: Error sending to database task: {e}");
-                            continue;
-                        }
-                    };
-
-                    if let Err(e) = processed_data_tx.send(prev_tables.clone()) {
-                        log::error!("{name}: cannot send data over data_tx: {e}");
-                    }
-                }
-            },
-            Err(TryRecvError::Empty) => (),
-            Err(TryRecvError::Disconnected) => {
-                // don't log error message if the channel was disconnected because of cancellation request
-                if !cancellation_token.is_cancelled() {
-                    log::error!("vehicle_data_rx channel disconnected, exiting {name}");
-                }
-                break;
+        if let Err(e) = process_data(&data, &mut cache, &mut channels, pool).await {
+            // Errors other than write errors, e.g. inserting a new car, can also be caused by
+            // the database becoming unavailable
+            if e.is::<WriteError>() || !database::is_available(pool).await {
+                log::warn!("{name}: data not written to database, writing it to spool file: {e}");
+                // The last logged tables may not have been committed to the database
+                cache.prev_tables_map.clear();
+                append_to_spool(&mut spool, &data).await;
+            } else {
+                log::error!("{name}: {e}");
             }
         }
     }
     tracing::warn!("exiting {name}");
 }
 
-async fn send_to_database_task(
-    tables: Vec<Tables>,
-    database_tx: &mpsc::Sender<DatabaseDataType>,
-    database_resp_rx: &mut mpsc::Receiver<DatabaseRespType>,
-) -> anyhow::Result<Tables> {
-    // Send the tables to the database task
-    database_tx.send(DatabaseDataType::Tables(tables)).await?;
-
-    // Wait for the response from database task with the updated tables with
-    // database id fields
-    let Some(resp) = database_resp_rx.recv().await else {
-        anyhow::bail!("No response received from database task");
+async fn append_to_spool(spool: &mut Spool, data: &DataTypes) {
+    if let Err(e) = spool.append(data).await {
+        log::error!("Error writing data to spool file, the data is lost: {e}");
+    }
+}
+
+/// Process the next chunk of the spooled data in order if the database is reachable again
+///
+/// The data which could not be processed before the database became unavailable again or the
+/// task was cancelled is kept in the spool file. Returns true if the chunk was written and there
+/// is more data in the spool file.
+async fn replay_spool(
+    spool: &mut Spool,
+    cache: &mut Cache,
+    channels: &mut Channels<'_>,
+    cancellation_token: &CancellationToken,
+    pool: &sqlx::PgPool,
+) -> bool {
+    if !database::is_available(pool).await {
+        log::debug!(
+            "Database is unavailable, {} data points in spool file",
+            spool.len()
+        );
+        return false;
+    }
+
+    let (data_list, end) = match spool.read(SPOOL_REPLAY_CHUNK_SIZE).await {
+        Ok(data) => data,
+        Err(e) => {
+            log::error!("Error reading spool file: {e}");
+            return false;
+        }
     };
+    log::info!(
+        "Database is available, writing {} of {} spooled data points",
+        data_list.len(),
+        spool.len()
+    );
 
-    let DatabaseRespType::Tables(prev_tables_resp) = resp else {
-        anyhow::bail!("Unexpected response type received from database task");
+    // Read the last logged tables from the database
+    cache.prev_tables_map.clear();
+    cache.vin_id_map = database::tables::car::get_vin_id_map(pool).await;
+
+    let mut replayed = Some(end);
+    let mut processed = None;
+    for (data, position) in &data_list {
+        if cancellation_token.is_cancelled() {
+            replayed = processed;
+            break;
+        }
+        if let Err(e) = process_data(data, cache, channels, pool).await {
+            if !database::is_available(pool).await {
+                log::warn!("Database is unavailable again: {e}");
+                cache.prev_tables_map.clear();
+                replayed = processed;
+                break;
+            }
+            log::error!("Error writing spooled data: {e}");
+        }
+        processed = Some(*position);
+    }
+
+    let Some(position) = replayed else {
+        return false;
     };
+    if let Err(e) = spool.consume(position).await {
+        log::error!("Error updating spool file: {e}");
+        return false;
+    }
+    replayed == Some(end) && !spool.is_empty()
+}
+
+/// Create the tables from the vehicle data or the streaming data and write them to the database
+///
+/// Returns an error if the data was not written to the database.
+async fn process_data(
+    data: &DataTypes,
+    cache: &mut Cache,
+    channels: &mut Channels<'_>,
+    pool: &sqlx::PgPool,
+) -> anyhow::Result<()> {
+    let name = "data_processor_task";
+    match data {
+        DataTypes::VehicleData(data) => {
+            let mut vehicle_data = match VehicleData::from_response_json(data) {
+                Ok(vehicle_data) => vehicle_data,
+                Err(e) => {
+                    // Keep the raw data even if no tables can be created from it
+                    send_raw_data_to_database_task(data, channels).await?;
+                    anyhow::bail!("Error parsing vehicle data to json: {e}");
+                }
+            };
+
+            let car_id_opt;
+            (cache.vin_id_map, car_id_opt) =
+                get_car_id(pool, std::mem::take(&mut cache.vin_id_map), &vehicle_data).await;
+
+            let Some(car_id) = car_id_opt else {
+                send_raw_data_to_database_task(data, channels).await?;
+                anyhow::bail!("Error getting car ID");
+            };
+
+            if let Some(last_data) = cache.last_data_map.get(&car_id) {
+                fill_missing_sections(&mut vehicle_data, last_data);
+            }
+            cache.last_data_map.insert(car_id, vehicle_data.clone());
+
+            let prev_tables = get_prev_tables(
+                pool,
+                &mut cache.prev_tables_map,
+                car_id,
+                vehicle_data.timestamp_utc(),
+            )
+            .await;
+
+            let last_streamed = cache.last_streamed_map.get(&car_id).copied();
+            let table_list = match create_tables_with_streamed(
+                &vehicle_data,
+                prev_tables,
+                car_id,
+                last_streamed,
+            )
+            .await
+            {
+                Ok(table_list) => table_list,
+                Err(e) => {
+                    send_raw_data_to_database_task(data, channels).await?;
+                    anyhow::bail!("Error adding to database: {e}");
+                }
+            };
+
+            let prev_tables =
+                send_to_database_task(table_list, Some(data.clone()), channels).await?;
+
+            if let Err(e) = channels.processed_data_tx.send(prev_tables.clone()) {
+                log::error!("{name}: cannot send data over data_tx: {e}");
+            }
+            cache.prev_tables_map.insert(car_id, prev_tables);
+        }
+        DataTypes::StreamingData(vin, data) => {
+            // The car is added to the database when the first vehicle data is received,
+            // skip the streaming data until then
+            let Some(&car_id) = cache.vin_id_map.get(vin) else {
+                log::debug!("No car with VIN {vin} in the database, skipping streaming data");
+                return Ok(());
+            };
+
+            let prev_tables =
+                get_prev_tables(pool, &mut cache.prev_tables_map, car_id, data.timestamp).await;
+            let Some(mut table_list) =
+                create_tables_from_streaming_data(data, prev_tables, car_id).await
+            else {
+                return Ok(());
+            };
+            // Keep the last vehicle data, the streaming data does not replace it
+            if let Some(t) = table_list.last_mut() {
+                t.raw_data = prev_tables.raw_data.clone();
+            }
+
+            let prev_tables = send_to_database_task(table_list, None, channels).await?;
+
+            if let Some(timestamp) = data.timestamp {
+                cache.last_streamed_map.insert(car_id, timestamp);
+            }
+            if let Err(e) = channels.processed_data_tx.send(prev_tables.clone()) {
+                log::error!("{name}: cannot send data over data_tx: {e}");
+            }
+            cache.prev_tables_map.insert(car_id, prev_tables);
+        }
+    }
+
+    Ok(())
+}
+
+/// Get the last logged tables of a car, the tables are read from the database the first time
+/// data is received for the car
+///
+/// `time` is the time of the data, the drive and the charging process logged shortly before it
+/// are continued, see `Tables::db_get_last_at`. This is the current time for live data but not
+/// for past data, e.g. when converting `car_data`.
+async fn get_prev_tables<'a>(
+    pool: &sqlx::PgPool,
+    prev_tables_map: &'a mut HashMap<i16, Tables>,
+    car_id: i16,
+    time: Option<DateTime<Utc>>,
+) -> &'a Tables {
+    match prev_tables_map.entry(car_id) {
+        Entry::Occupied(entry) => entry.into_mut(),
+        Entry::Vacant(entry) => {
+            let time = time.unwrap_or_else(Utc::now);
+            entry.insert(Tables::db_get_last_at(pool, car_id, time).await)
+        }
+    }
+}
+
+/// The data was not written to the database and is spooled
+#[derive(Debug)]
+struct WriteError(anyhow::Error);
+
+impl std::fmt::Display for WriteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for WriteError {}
+
+/// Send a request to the database task and wait for the response
+async fn request_database_task(
+    data: DatabaseDataType,
+    channels: &mut Channels<'_>,
+) -> Result<DatabaseRespType, WriteError> {
+    channels
+        .database_tx
+        .send(data)
+        .await
+        .map_err(|e| WriteError(e.into()))?;
 
-    Ok(prev_tables_resp)
+    match channels.database_resp_rx.recv().await {
+        Some(DatabaseRespType::Error(e)) => Err(WriteError(anyhow::anyhow!(
+            "Error writing data to database: {e}"
+        ))),
+        Some(resp) => Ok(resp),
+        None => Err(WriteError(anyhow::anyhow!(
+            "No response received from database task"
+        ))),
+    }
+}
+
+/// Write the raw vehicle data which no tables were created from
+async fn send_raw_data_to_database_task(
+    data: &str,
+    channels: &mut Channels<'_>,
+) -> Result<(), WriteError> {
+    match request_database_task(DatabaseDataType::RawData(data.to_string()), channels).await? {
+        DatabaseRespType::RawData => Ok(()),
+        _ => Err(WriteError(anyhow::anyhow!(
+            "Unexpected response type received from database task"
+        ))),
+    }
+}
+
+/// Write the tables and the raw vehicle data they were created from and return the last tables
+/// with the database id fields
+async fn send_to_database_task(
+    tables: Vec<Tables>,
+    raw_data: Option<String>,
+    channels: &mut Channels<'_>,
+) -> Result<Tables, WriteError> {
+    match request_database_task(DatabaseDataType::Tables(tables, raw_data), channels).await? {
+        DatabaseRespType::Tables(prev_tables_resp) => Ok(prev_tables_resp),
+        _ => Err(WriteError(anyhow::anyhow!(
+            "Unexpected response type received from database task"
+        ))),
+    }
 }
 
 async fn get_car_id(
@@ -214,15 +420,153 @@ async fn get_car_id(
     (vin_id_map, Some(car_id))
 }
 
+/// Take the sections which were not requested from the previous vehicle data
+///
+/// The values of the copied sections are assumed to be unchanged, their timestamps are set to the
+/// time of the new data. The location is kept if the drive state was requested without it. Only
+/// use the result to create the tables, the vehicle data is stored as received.
+pub fn fill_missing_sections(data: &mut VehicleData, prev: &VehicleData) {
+    let timestamp = [
+        data.charge_state.as_ref().and_then(|s| s.timestamp),
+        data.climate_state.as_ref().and_then(|s| s.timestamp),
+        data.drive_state.as_ref().and_then(|s| s.timestamp),
+        data.gui_settings.as_ref().and_then(|s| s.timestamp),
+        data.vehicle_config.as_ref().and_then(|s| s.timestamp),
+        data.vehicle_state.as_ref().and_then(|s| s.timestamp),
+    ]
+    .into_iter()
+    .flatten()
+    .max();
+
+    macro_rules! fill {
+        ($($section:ident),*) => {$(
+            if data.$section.is_none() {
+                data.$section = prev.$section.clone().map(|mut s| {
+                    s.timestamp = timestamp.or(s.timestamp);
+                    s
+                });
+            }
+        )*};
+    }
+    fill!(
+        charge_state,
+        climate_state,
+        drive_state,
+        gui_settings,
+        vehicle_config,
+        vehicle_state
+    );
+
+    match (data.drive_state.as_mut(), prev.drive_state.as_ref()) {
+        (Some(drive_state), Some(prev)) if drive_state.latitude.is_none() => {
+            drive_state.latitude = prev.latitude;
+            drive_state.longitude = prev.longitude;
+            drive_state.native_latitude = prev.native_latitude;
+            drive_state.native_longitude = prev.native_longitude;
+            drive_state.heading = drive_state.heading.or(prev.heading);
+        }
+        _ => (),
+    }
+}
+
 pub async fn create_tables(
     data: &VehicleData,
     prev_tables: &Tables,
     car_id: i16,
+) -> anyhow::Result<Vec<Tables>> {
+    create_tables_with_streamed(data, prev_tables, car_id, None).await
+}
+
+/// Same as `create_tables`, but the position from the vehicle data is not logged again if the
+/// streaming API has already sent the positions up to that time
+///
+/// # Arguments
+///
+/// * `last_streamed` - time of the last logged streaming data of the car
+pub async fn create_tables_with_streamed(
+    data: &VehicleData,
+    prev_tables: &Tables,
+    car_id: i16,
+    last_streamed: Option<DateTime<Utc>>,
 ) -> anyhow::Result<Vec<Tables>> {
     let current_state = State::from(data, car_id)?;
-    let current_position = Position::from(data, car_id, None).await?;
+    let mut current_position = Position::from(data, car_id, None).await?;
     let current_charge = Charges::from(data, 0).map_err(|e| log::error!("{e}")).ok();
 
+    let streamed = current_position
+        .date
+        .zip(last_streamed)
+        .is_some_and(|(date, streamed)| {
+            date <= streamed
+                + chrono::Duration::try_milliseconds(STREAMED_POSITION_WINDOW_MS)
+                    .unwrap_or_default()
+        });
+    if let Some(prev_position) = prev_tables.position.as_ref().filter(|_| streamed) {
+        // The previous position was already inserted into the database and is not inserted again
+        current_position = prev_position.clone();
+    }
+
+    let mut table_list =
+        log_data_point(prev_tables, current_state, current_position, current_charge).await;
+
+    // Insert raw vehicle data into the last table
+    if let Some(t) = table_list.last_mut() {
+        t.raw_data = Some(data.clone());
+    }
+
+    Ok(table_list)
+}
+
+/// Create the tables from the streaming data
+///
+/// The streaming data is used to log the start, the end and the route of a drive, the other
+/// states are logged using the vehicle data. Returns `None` if the data is not logged, e.g. if
+/// the vehicle is not driving or the data is not newer than the last logged position.
+pub async fn create_tables_from_streaming_data(
+    data: &StreamingData,
+    prev_tables: &Tables,
+    car_id: i16,
+) -> Option<Vec<Tables>> {
+    let prev_state = prev_tables
+        .state
+        .as_ref()
+        .map_or(StateStatus::Unknown, |s| s.state);
+    let state = StateStatus::from_streaming_data(data, prev_state);
+    if state != StateStatus::Driving && prev_state != StateStatus::Driving {
+        return None;
+    }
+
+    let timestamp = data.timestamp?;
+    // Skip the data points the streaming API sends again after reconnecting
+    if prev_tables
+        .position
+        .as_ref()
+        .and_then(|p| p.date)
+        .is_some_and(|date| timestamp <= date)
+    {
+        return None;
+    }
+
+    let current_state = State {
+        state,
+        start_date: timestamp,
+        car_id,
+        ..State::default()
+    };
+    let current_position = Position::from_streaming_data(data, car_id);
+
+    Some(log_data_point(prev_tables, current_state, current_position, None).await)
+}
+
+/// Continue logging the current state or end the previous state and start the new state if the
+/// state has changed
+async fn log_data_point(
+    prev_tables: &Tables,
+    current_state: State,
+    current_position: Position,
+    current_charge: Option<Charges>,
+) -> Vec<Tables> {
+    let car_id = current_state.car_id;
     let mut table_list = vec![];
 
     if let Some(tables) =
@@ -263,12 +607,7 @@ pub async fn create_tables(
         }
     }
 
-    // Insert raw vehicle data into the last table
-    if let Some(t) = table_list.last_mut() {
-        t.raw_data = Some(data.clone());
-    }
-
-    Ok(table_list)
+    table_list
 }
 
 async fn start_logging_for_state(
@@ -321,6 +660,7 @@ async fn start_logging_for_state(
     let position = new_state.is_online().then_some(current_position);
 
     Tables {
+        car_id,
         address,
         car: None,
         charges,
@@ -378,6 +718,7 @@ async fn continue_logging(
     });
 
     Tables {
+        car_id: current_state.car_id,
         drive,
         address: None,
         car: None,
@@ -456,6 +797,7 @@ async fn end_logging_for_state(
     });
 
     Tables {
+        car_id: curr_position.car_id,
         address,
         car: None,
         charges,
@@ -598,6 +940,7 @@ async fn check_hidden_process(
             if let Some(cp) = charging_process {
                 // Tables for charging
                 table_list.push(Tables {
+                    car_id,
                     address: address.clone(),
                     charging_process: Some(cp),
                     charges: Some(prev_charge),
@@ -614,6 +957,7 @@ async fn check_hidden_process(
                 });
                 // Tables to log current_charge (prev_charge was included in the table above)
                 table_list.push(Tables {
+                    car_id,
                     charges: Some(current_charge.clone()),
                     time: curr_position.date,
                     ..Default::default()
@@ -635,3 +979,70 @@ async fn check_hidden_process(
 
     Some(table_list)
 }
+
+#[test]
+fn test_fill_missing_sections() {
+    use tesla_api::vehicle_data::{ChargeState, ClimateState, DriveState};
+
+    // Response without any of the sections
+    let empty = || VehicleData {
+        charge_state: None,
+        climate_state: None,
+        drive_state: None,
+        gui_settings: None,
+        vehicle_config: None,
+        vehicle_state: None,
+        ..Default::default()
+    };
+    let prev = VehicleData {
+        charge_state: Some(ChargeState {
+            battery_level: Some(50),
+            timestamp: Some(1000),
+            ..Default::default()
+        }),
+        climate_state: Some(ClimateState {
+            outside_temp: Some(20.0),
+            timestamp: Some(1000),
+            ..Default::default()
+        }),
+        drive_state: Some(DriveState {
+            latitude: Some(12.34),
+            longitude: Some(34.56),
+            timestamp: Some(1000),
+            ..Default::default()
+        }),
+        ..empty()
+    };
+
+    // Only the charge state was requested while charging
+    let mut data = VehicleData {
+        charge_state: Some(ChargeState {
+            battery_level: Some(51),
+            timestamp: Some(2000),
+            ..Default::default()
+        }),
+        ..empty()
+    };
+    fill_missing_sections(&mut data, &prev);
+    let charge_state = data.charge_state.as_ref().unwrap();
+    assert_eq!(charge_state.battery_level, Some(51));
+    let climate_state = data.climate_state.as_ref().unwrap();
+    assert_eq!(climate_state.outside_temp, Some(20.0));
+    assert_eq!(climate_state.timestamp, Some(2000));
+    assert_eq!(data.drive_state.as_ref().unwrap().timestamp, Some(2000));
+    assert!(data.vehicle_state.is_none());
+
+    // The location was not requested while parked
+    let mut data = VehicleData {
+        drive_state: Some(DriveState {
+            timestamp: Some(3000),
+            ..Default::default()
+        }),
+        ..empty()
+    };
+    fill_missing_sections(&mut data, &prev);
+    let drive_state = data.drive_state.as_ref().unwrap();
+    assert_eq!(drive_state.latitude, Some(12.34));
+    assert_eq!(drive_state.longitude, Some(34.56));
+    assert_eq!(drive_state.timestamp, Some(3000));
+}
@@ -1,8 +1,11 @@
+use std::sync::Arc;
+
 use crate::config::Config;
 use crate::get_config;
 use crate::tasks::DataTypes;
-use tesla_api::stream::StreamingData;
-use tokio::sync::mpsc;
+use tesla_api::auth::AuthResponse;
+use tesla_api::stream::{StreamEvent, StreamingData};
+use tokio::sync::{mpsc, Notify};
 use tokio_util::sync::CancellationToken;
 
 pub async fn data_streaming_task(
@@ -10,67 +13,107 @@ pub async fn data_streaming_task(
     config: Config,
     cancellation_token: CancellationToken,
     vehicle_id: u64,
-) {
-    use mpsc::error::*;
+    vin: String,
+    vehicle_activity: Arc<Notify>,
+) -> anyhow::Result<()> {
     let name = "data_stream_task";
-    let (streaming_data_tx, mut streaming_data_rx) = tokio::sync::mpsc::channel::<StreamingData>(1);
+    let (streaming_data_tx, mut streaming_data_rx) = tokio::sync::mpsc::channel::<StreamEvent>(1);
 
-    let access_token = match get_config!(config.access_token) {
-        Ok(v) => v,
-        Err(e) => {
-            log::error!("Error getting config `access_token`: {e}");
-            return;
-        }
+    let backend = config.tesla_backend.clone();
+    let fields = get_config!(config.streaming_fields).unwrap_or_default();
+    let mut access_token_rx = match config.access_token.lock() {
+        Ok(a) => a.watch(),
+        Err(e) => anyhow::bail!("Error getting lock on `config.access_token`: {e}"),
     };
 
-    let streaming_data_tx = streaming_data_tx.clone();
-    let cancellation_token_clone = cancellation_token.clone();
-
-    let stream_data_handler_task = tokio::task::spawn({
+    let mut stream_data_handler_task = tokio::task::spawn({
+        let config = config.clone();
+        let cancellation_token = cancellation_token.clone();
         async move {
             let name = format!("{name}::stream_data_handler_task");
             loop {
-                match streaming_data_rx.try_recv() {
-                    Ok(data) => {
-                        if let Err(e) = data_tx.send(DataTypes::StreamingData(data)).await {
+                let event = tokio::select! {
+                    _ = cancellation_token.cancelled() => break,
+                    event = streaming_data_rx.recv() => event,
+                };
+                match event {
+                    Some(StreamEvent::Data(data)) => {
+                        if is_active(&data) {
+                            // Resume polling if it was suspended to let the vehicle sleep
+                            vehicle_activity.notify_one();
+                        }
+                        if let Err(e) = data_tx
+                            .send(DataTypes::StreamingData(vin.clone(), data))
+                            .await
+                        {
                             // don't log error message if the channel was closed because of a cancellation request
                             if !cancellation_token.is_cancelled() {
                                 log::error!("{name}: cannot send data over data_tx: {e}");
                             }
                         }
                     }
-                    Err(TryRecvError::Empty) => (),
-                    Err(TryRecvError::Disconnected) => {
-                        // don't log error message if the channel was disconnected because of cancellation request
+                    Some(StreamEvent::TokenExpired(Some(tokens))) => config.set_tokens(&tokens),
+                    Some(event) => log::info!("{name}: streaming event {event:?}"),
+                    None => {
+                        // don't log error message if the channel was closed because of cancellation request
                         if !cancellation_token.is_cancelled() {
-                            log::error!("streaming_data_rx channel disconnected, exiting {name}");
+                            log::error!("streaming_data_rx channel closed, exiting {name}");
                         }
                         break;
                     }
                 }
-                if cancellation_token.is_cancelled() {
-                    break;
-                }
-                tokio::task::yield_now().await;
             }
         }
     });
 
-    tokio::select! {
-        result = tesla_api::stream::start(
-            &access_token,
-            vehicle_id,
-            streaming_data_tx,
-            cancellation_token_clone,
-        ) => {
-            match result {
-                Ok(_) => log::warn!("Vehicle data streaming stopped"),
-                Err(e) => log::error!("Error streaming: {e}"),
+    loop {
+        let tokens = match (
+            get_config!(config.access_token),
+            get_config!(config.refresh_token),
+        ) {
+            (Ok(access_token), Ok(refresh_token)) => AuthResponse {
+                access_token,
+                refresh_token,
+                ..Default::default()
+            },
+            (Err(e), _) | (_, Err(e)) => {
+                anyhow::bail!("Error getting config `access_token` or `refresh_token`: {e}")
             }
+        };
+        access_token_rx.borrow_and_update();
+
+        tokio::select! {
+            result = tesla_api::stream::start(
+                backend.clone(),
+                tokens,
+                vehicle_id,
+                fields.clone(),
+                streaming_data_tx.clone(),
+                cancellation_token.clone(),
+            ) => {
+                match result {
+                    Ok(_) => log::warn!("Vehicle data streaming stopped"),
+                    Err(e) => anyhow::bail!("Error streaming: {e}"),
+                }
+                break;
+            }
+            status = &mut stream_data_handler_task => {
+                anyhow::bail!("stream data handler task exited: {status:?}");
+            }
+            // Restart streaming with the new access token
+            Ok(_) = access_token_rx.changed() => log::info!("Access token changed, restarting streaming"),
         }
-        // status = streaming_task => log::warn!("stream data task exited: {status:?}"),
-        status = stream_data_handler_task => log::warn!("stream data handler task exited: {status:?}"),
     }
 
     tracing::warn!("exiting {name}");
+    Ok(())
+}
+
+/// Whether the vehicle is driving or charging
+fn is_active(data: &StreamingData) -> bool {
+    let moving = data.shift_state.as_deref().is_some_and(|s| s != "P")
+        || data.speed.is_some_and(|s| s > 0.0);
+    // Power is negative while charging
+    let charging = data.power.is_some_and(|p| p < 0.0);
+    moving || charging
 }
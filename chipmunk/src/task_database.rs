@@ -7,6 +7,25 @@ use crate::database::tables::Tables;
 use crate::tasks::{DatabaseDataType, DatabaseRespType};
 use tokio_util::sync::CancellationToken;
 
+/// Write all tables of a data point and the raw vehicle data they were created from in a single
+/// transaction and return the last tables with the database IDs
+///
+/// The transaction is committed before the IDs are returned, the data processor must not keep the
+/// IDs of rows which may still be rolled back.
+async fn insert_tables(
+    pool: &sqlx::PgPool,
+    table_list: Vec<Tables>,
+    raw_data: Option<&str>,
+) -> anyhow::Result<Tables> {
+    let mut tx = pool.begin().await?;
+    if let Some(raw_data) = raw_data {
+        database::tables::vehicle_data::db_insert_json(raw_data, &mut *tx).await?;
+    }
+    let tables = Tables::db_insert_list(pool, &mut tx, table_list).await?;
+    tx.commit().await?;
+    Ok(tables)
+}
+
 pub async fn database_task(
     mut data_rx: mpsc::Receiver<DatabaseDataType>,
     data_resp_tx: mpsc::Sender<DatabaseRespType>,
@@ -14,7 +33,6 @@ pub async fn database_task(
     cancellation_token: CancellationToken,
     pool: &sqlx::PgPool,
 ) {
-    use mpsc::error::*;
     let name = "database_task";
 
     let car_data_database_url = config
@@ -39,49 +57,52 @@ pub async fn database_task(
     };
 
     loop {
-        match data_rx.try_recv() {
-            Ok(data) => match data {
-                DatabaseDataType::RawData(d) => {
-                    if let Some(ref car_data_pool) = car_data_db_pool {
-                        if let Err(e) =
-                            database::tables::vehicle_data::db_insert_json(&d, car_data_pool).await
-                        {
-                            log::error!("Error logging to `{car_data_database_url:?}`: {e}");
-                        };
+        let data = tokio::select! {
+            _ = cancellation_token.cancelled() => break,
+            data = data_rx.recv() => data,
+        };
+        let Some(data) = data else {
+            // The channel is closed when the data processor exits after processing all data
+            if !cancellation_token.is_cancelled() {
+                log::info!("data_rx channel closed, exiting {name}");
+            }
+            break;
+        };
+
+        let raw_data = match &data {
+            DatabaseDataType::RawData(d) => Some(d),
+            DatabaseDataType::Tables(_, raw_data) => raw_data.as_ref(),
+        };
+        if let (Some(car_data_pool), Some(d)) = (&car_data_db_pool, raw_data) {
+            let _ = database::tables::vehicle_data::db_insert_json(d, car_data_pool)
+                .await
+                .map_err(|e| log::error!("Error logging to `{car_data_database_url:?}`: {e}"));
+        }
+
+        // The data processor spools the data if it was not written
+        let resp = match data {
+            DatabaseDataType::RawData(d) => {
+                match database::tables::vehicle_data::db_insert_json(&d, pool).await {
+                    Ok(()) => DatabaseRespType::RawData,
+                    Err(e) => {
+                        log::error!("Error inserting raw vehicle data into database: {e}");
+                        DatabaseRespType::Error(e.to_string())
                     }
-                    if let Err(e) = database::tables::vehicle_data::db_insert_json(&d, pool).await {
-                        log::error!("{e}");
-                    };
                 }
-                DatabaseDataType::Tables(table_list) => {
-                    let mut last_tables = Tables::default();
-                    for t in table_list {
-                        match t.db_insert(pool).await {
-                            Ok(updated_tables) => last_tables = updated_tables,
-                            Err(e) => log::error!("Error inserting tables into database: {:?}", e),
-                        }
-                    }
-                    if let Err(e) = data_resp_tx
-                        .send(DatabaseRespType::Tables(last_tables))
-                        .await
-                    {
-                        log::error!("Error sending response from database task: {e}");
+            }
+            DatabaseDataType::Tables(table_list, raw_data) => {
+                match insert_tables(pool, table_list, raw_data.as_deref()).await {
+                    Ok(last_tables) => DatabaseRespType::Tables(last_tables),
+                    Err(e) => {
+                        log::error!("Error inserting tables into database: {e:?}");
+                        DatabaseRespType::Error(e.to_string())
                     }
                 }
-            },
-            Err(TryRecvError::Disconnected) => {
-                // don't log error message if the channel was closed because of a cancellation request
-                if !cancellation_token.is_cancelled() {
-                    log::error!("data_rx channel closed, exiting {name}");
-                }
-                break;
             }
-            Err(TryRecvError::Empty) => (),
-        }
-        if cancellation_token.is_cancelled() {
-            break;
+        };
+        if let Err(e) = data_resp_tx.send(resp).await {
+            log::error!("Error sending response from database task: {e}");
         }
-        tokio::task::yield_now().await;
     }
     tracing::warn!("exiting {name}");
 }
@@ -0,0 +1,83 @@
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::database::tables::energy_site::{EnergySite, EnergySiteStatus};
+use crate::database::DBTable;
+use crate::get_config;
+use tesla_api::auth::AuthResponse;
+use tesla_api::TeslaClient;
+use tokio_util::sync::CancellationToken;
+
+/// Interval between the live status requests, the values are averaged by the energy gateway so
+/// polling more often does not add much detail
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Logs the solar production, home usage and Powerwall charge of an energy site
+pub async fn energy_site_task(
+    config: Config,
+    cancellation_token: CancellationToken,
+    mut tesla_client: TeslaClient,
+    site: tesla_api::energy_site::EnergySite,
+    pool: &sqlx::PgPool,
+) -> anyhow::Result<()> {
+    let name = "energy_site_task";
+    let energy_site_id = site.energy_site_id;
+
+    // ID of the site in the database
+    let site_id = match EnergySite::from(&site) {
+        Ok(s) => match s.db_insert(pool).await {
+            Ok(id) => id as i32,
+            Err(e) => {
+                anyhow::bail!("error inserting energy site {energy_site_id} to database: {e}")
+            }
+        },
+        Err(e) => anyhow::bail!("invalid energy site id {energy_site_id}: {e}"),
+    };
+    let mut access_token_rx = match config.access_token.lock() {
+        Ok(a) => a.watch(),
+        Err(e) => anyhow::bail!("Error getting lock on `config.access_token`: {e}"),
+    };
+
+    loop {
+        let logging_enabled = match get_config!(config.logging_enabled) {
+            Ok(v) => v,
+            Err(e) => {
+                log::error!("Error getting config value `logging_enabled`: {e}");
+                false
+            }
+        };
+
+        if logging_enabled {
+            // Use the new tokens if they were refreshed by another task
+            if access_token_rx.has_changed().unwrap_or(false) {
+                access_token_rx.borrow_and_update();
+                let tokens = AuthResponse {
+                    access_token: get_config!(config.access_token).unwrap_or_default(),
+                    refresh_token: get_config!(config.refresh_token).unwrap_or_default(),
+                    ..Default::default()
+                };
+                if let Err(e) = tesla_client.set_tokens(tokens) {
+                    log::error!("{name}: error updating tokens: {e}");
+                }
+            }
+
+            match tesla_client.energy_site_live_status(energy_site_id).await {
+                Ok(status) => {
+                    let status = EnergySiteStatus::from_live_status(&status, site_id);
+                    if let Err(e) = status.db_insert(pool).await {
+                        log::error!("{name}: error inserting energy site status: {e}");
+                    }
+                }
+                Err(e) => log::error!("{name}: error getting energy site status: {e}"),
+            }
+        }
+
+        tokio::select! {
+            _ = cancellation_token.cancelled() => break,
+            _ = tokio::time::sleep(POLL_INTERVAL) => (),
+        }
+    }
+
+    tracing::warn!("exiting {name}");
+    Ok(())
+}
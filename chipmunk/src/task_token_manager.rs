@@ -0,0 +1,105 @@
+use chrono::Utc;
+use std::time::Duration;
+use tesla_api::auth::AuthResponse;
+
+use crate::config::Config;
+use crate::database::tables::token::Token;
+use crate::get_config;
+use tokio_util::sync::CancellationToken;
+
+/// Refresh the access token this long before it expires
+const REFRESH_MARGIN: Duration = Duration::from_secs(10 * 60);
+/// Wait this long before trying again if refreshing the access token fails
+const RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// Refreshes the access token before it expires and stores the new tokens in the database
+///
+/// The new tokens are pushed to the other tasks using `config.access_token` and
+/// `config.refresh_token`. Tokens refreshed elsewhere (e.g. by the Tesla API client after a 401
+/// response or by the web interface) are also stored in the database, otherwise the rotated
+/// refresh token is lost when chipmunk restarts.
+pub async fn token_manager_task(
+    config: Config,
+    cancellation_token: CancellationToken,
+    pool: &sqlx::PgPool,
+) -> anyhow::Result<()> {
+    let name = "token_manager_task";
+
+    let Ok(encryption_key) = get_config!(config.encryption_key) else {
+        anyhow::bail!("Error reading config value `encryption_key`");
+    };
+    let mut refresh_token_rx = match config.refresh_token.lock() {
+        Ok(r) => r.watch(),
+        Err(e) => anyhow::bail!("Error getting lock on `config.refresh_token`: {e}"),
+    };
+
+    loop {
+        let expires_at = get_config!(config.access_token_expires_at).unwrap_or_default();
+        let refresh_in = (expires_at - Utc::now())
+            .to_std()
+            .unwrap_or_default()
+            .saturating_sub(REFRESH_MARGIN);
+        log::debug!("{name}: refreshing access token in {refresh_in:?}");
+
+        tokio::select! {
+            _ = cancellation_token.cancelled() => break,
+            Ok(_) = refresh_token_rx.changed() => {
+                // Tokens were refreshed by another task, store them in the database
+                refresh_token_rx.borrow_and_update();
+                store_tokens(&config, pool, &encryption_key).await;
+            }
+            _ = tokio::time::sleep(refresh_in) => {
+                let refresh_token = get_config!(config.refresh_token).unwrap_or_default();
+                match tesla_api::auth::refresh_access_token(
+                    config.tesla_backend.as_ref(),
+                    &refresh_token,
+                )
+                .await
+                {
+                    Ok(tokens) => {
+                        log::info!("Access token refreshed");
+                        config.set_tokens(&tokens);
+                        // Already storing the tokens, ignore the change notification
+                        refresh_token_rx.borrow_and_update();
+                        if let Err(e) = Token::db_insert(pool, &tokens, &encryption_key).await {
+                            log::error!("{name}: error storing tokens: {e}");
+                        }
+                    }
+                    Err(e) => {
+                        log::error!("{name}: error refreshing access token: {e}");
+                        tokio::select! {
+                            _ = cancellation_token.cancelled() => break,
+                            _ = tokio::time::sleep(RETRY_DELAY) => (),
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    tracing::warn!("exiting {name}");
+    Ok(())
+}
+
+async fn store_tokens(config: &Config, pool: &sqlx::PgPool, encryption_key: &str) {
+    let expires_at = get_config!(config.access_token_expires_at).unwrap_or_default();
+    let (Ok(access_token), Ok(refresh_token), Ok(id_token)) = (
+        get_config!(config.access_token),
+        get_config!(config.refresh_token),
+        get_config!(config.id_token),
+    ) else {
+        log::error!("Error reading tokens from config");
+        return;
+    };
+
+    let tokens = AuthResponse {
+        access_token,
+        refresh_token,
+        id_token,
+        expires_in: (expires_at - Utc::now()).num_seconds() as i32,
+        token_type: "Bearer".into(),
+    };
+    if let Err(e) = Token::db_insert(pool, &tokens, encryption_key).await {
+        log::error!("Error storing tokens: {e}");
+    }
+}
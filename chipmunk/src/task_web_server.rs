@@ -2,15 +2,19 @@ use crate::config::Config;
 use crate::database::tables::Tables;
 use crate::server::{DataToServer, MpscTopic, TeslaServer};
 use crate::set_config;
-use tokio::sync::mpsc::{self, unbounded_channel};
+use crate::supervisor::Supervisor;
+use sqlx::PgPool;
+use tokio::sync::mpsc::unbounded_channel;
 use tokio::sync::{broadcast, oneshot};
 use tokio_util::sync::CancellationToken;
 
 pub async fn web_server_task(
     mut data_rx: broadcast::Receiver<Tables>,
+    pool: PgPool,
     config: Config,
-    tables: &Tables,
+    tables: &[Tables],
     cancellation_token: CancellationToken,
+    supervisor: Supervisor,
 ) {
     use broadcast::error::*;
     let name = "web_server_task";
@@ -24,65 +28,57 @@ pub async fn web_server_task(
         async move {
             let name = format!("{name}::message_handler_task");
             loop {
-                match data_from_server_rx.try_recv() {
-                    Ok(value) => match value {
-                        MpscTopic::Logging(value) => {
+                tokio::select! {
+                    _ = cancellation_token.cancelled() => {
+                        if let Err(e) = server_exit_signal_tx.send(()) {
+                            log::error!("Error sending exit signal to server: {e:?}")
+                        }
+                        break;
+                    }
+                    value = data_from_server_rx.recv() => match value {
+                        Some(MpscTopic::Logging(value)) => {
                             set_config!(config.logging_enabled, value);
                         }
-                        MpscTopic::RefreshToken(refresh_token) => {
-                            if let Err(e) =
-                                tesla_api::auth::refresh_access_token(refresh_token.as_str())
-                                    .await
-                                    .map(|t| {
-                                        set_config!(config.access_token, t.access_token);
-                                        set_config!(config.refresh_token, t.refresh_token);
-                                    })
+                        Some(MpscTopic::RefreshToken(refresh_token)) => {
+                            if let Err(e) = tesla_api::auth::refresh_access_token(
+                                config.tesla_backend.as_ref(),
+                                refresh_token.as_str(),
+                            )
+                            .await
+                            .map(|t| config.set_tokens(&t))
                             {
                                 log::error!("{e}");
-                                continue;
                             }
                         }
-                    },
-                    Err(e) => match e {
-                        mpsc::error::TryRecvError::Disconnected => {
+                        None => {
                             log::error!("server_rx channel closed, exiting {name}");
                             break;
                         }
-                        mpsc::error::TryRecvError::Empty => (),
                     },
-                }
-
-                match data_rx.try_recv() {
-                    Ok(data) => {
-                        if let Err(e) = data_to_server_tx.send(DataToServer::Tables(data)) {
-                            log::error!("Error sending data to web server: {e}");
+                    data = data_rx.recv() => match data {
+                        Ok(data) => {
+                            if let Err(e) = data_to_server_tx.send(DataToServer::Tables(data)) {
+                                log::error!("Error sending data to web server: {e}");
+                            }
                         }
-                    }
-                    Err(TryRecvError::Closed) => {
-                        // don't log error message if the channel was closed because of a cancellation request
-                        if !cancellation_token.is_cancelled() {
-                            log::error!("data_rx channel closed, exiting {name}");
+                        Err(RecvError::Closed) => {
+                            // don't log error message if the channel was closed because of a cancellation request
+                            if !cancellation_token.is_cancelled() {
+                                log::error!("data_rx channel closed, exiting {name}");
+                            }
+                            break;
                         }
-                        break;
-                    }
-                    Err(TryRecvError::Empty) => (),
-                    Err(TryRecvError::Lagged(n)) => {
-                        log::warn!("{name} lagged too far behind; {n} messages skipped")
-                    }
-                }
-                if cancellation_token.is_cancelled() {
-                    if let Err(e) = server_exit_signal_tx.send(()) {
-                        log::error!("Error sending exit signal to server: {e:?}")
-                    }
-                    break;
+                        Err(RecvError::Lagged(n)) => {
+                            log::warn!("{name} lagged too far behind; {n} messages skipped")
+                        }
+                    },
                 }
-                tokio::task::yield_now().await;
             }
         }
     });
 
     tokio::select! {
-        result = TeslaServer::start(config, tables, data_from_server_tx, data_to_server_rx, server_exit_signal_rx) => {
+        result = TeslaServer::start(pool, config, tables, data_from_server_tx, data_to_server_rx, server_exit_signal_rx, supervisor) => {
             match result {
                 Ok(_) => log::warn!("web server exited"),
                 Err(e) => log::error!("Web server exited: {e}"),
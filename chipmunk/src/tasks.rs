@@ -1,68 +1,76 @@
-use anyhow::anyhow;
-use std::ops::Deref;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 use std::time::Duration;
 
 use crate::config::Config;
+use crate::data_source::live::VehicleIds;
+use crate::data_source::{CarDataSource, DataSource, LiveSource, Recorder};
+use crate::database::tables::convert_db_checkpoint::{self, ConvertDbCheckpoint};
 use crate::database::tables::token::Token;
 use crate::database::tables::{vehicle_data, Tables};
-use crate::task_data_polling::data_polling_task;
+use crate::supervisor::Supervisor;
 use crate::task_data_processor::data_processor_task;
-use crate::task_data_streaming::data_streaming_task;
 use crate::task_database::database_task;
+use crate::task_energy_site::energy_site_task;
+use crate::task_token_manager::token_manager_task;
 use crate::task_web_server::web_server_task;
 use crate::{database, get_config, set_config};
+use chrono::{DateTime, Utc};
+use tesla_api::auth::AuthResponse;
+use tesla_api::energy_site::EnergySite;
 use tesla_api::stream::StreamingData;
-use tesla_api::vehicle_data::VehicleData;
-use tesla_api::{TeslaClient, TeslaError};
+use tesla_api::{Product, TeslaClient, TeslaError};
 use tokio::sync::broadcast;
 use tokio::sync::mpsc;
 use tokio_util::sync::CancellationToken;
 use tokio_util::task::TaskTracker;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum DataTypes {
     VehicleData(String),
-    StreamingData(StreamingData),
+    /// VIN of the vehicle and the streaming data received from the vehicle
+    StreamingData(String, StreamingData),
 }
 
 pub enum DatabaseDataType {
+    /// Raw vehicle data which no tables were created from
     RawData(String),
-    Tables(Vec<Tables>),
+    /// Tables and the raw vehicle data they were created from, written in a single transaction
+    Tables(Vec<Tables>, Option<String>),
 }
 
 pub enum DatabaseRespType {
-    _RawData(String),
+    /// The raw vehicle data was written to the database
+    RawData,
     Tables(Tables),
+    /// The data was not written to the database
+    Error(String),
 }
 
-fn handle_token_expiry(_pool: &sqlx::PgPool) {
+fn handle_token_expiry(config: &Config, tokens: &AuthResponse) {
     log::info!("Running `handle_token_expiry` callback");
+    // The token manager task stores the new tokens in the database
+    config.set_tokens(tokens);
 }
 
 pub async fn run(pool: &sqlx::PgPool, config: &mut Config) -> anyhow::Result<()> {
-    // Channel for vehicle data and streaming data
-    let (vehicle_data_tx, vehicle_data_rx) = mpsc::channel::<DataTypes>(1);
     // channel for parsed data
-    let (processed_data_tx, data_rx) = broadcast::channel::<Tables>(1);
-    // channel to send date to database task
-    let (database_tx, database_rx) = mpsc::channel::<DatabaseDataType>(1);
-    // channel to receive response from database task
-    let (database_resp_tx, database_resp_rx) = mpsc::channel::<DatabaseRespType>(1);
+    let (processed_data_tx, _) = broadcast::channel::<Tables>(1);
 
     let cancellation_token = CancellationToken::new();
     let task_tracker = TaskTracker::new();
+    // Restarts the failed tasks
+    let supervisor = Supervisor::new();
 
     // Starts web server and use the processed data to show logging status to the user
-    let web_server_task_handle = {
-        let config = config.clone();
-        let pool = pool.clone();
-        let cancellation_token = cancellation_token.clone();
-
-        task_tracker.spawn(async move {
-            let tables = Tables::db_get_last(&pool).await;
-            web_server_task(data_rx, config, &tables, cancellation_token).await;
-        })
-    };
+    spawn_web_server(
+        pool,
+        config,
+        &processed_data_tx,
+        &task_tracker,
+        &cancellation_token,
+        &supervisor,
+    );
 
     let encryption_key = match config.encryption_key.lock().map(|c| c.get()) {
         Ok(v) => v,
@@ -71,6 +79,7 @@ pub async fn run(pool: &sqlx::PgPool, config: &mut Config) -> anyhow::Result<()>
             anyhow::bail!("{e}");
         }
     };
+
     // Read tokens from the database if exists, if not, get from the user and store in the database
     let tokens = match Token::db_get_last(pool, &encryption_key).await {
         Ok(t) => t,
@@ -90,7 +99,12 @@ pub async fn run(pool: &sqlx::PgPool, config: &mut Config) -> anyhow::Result<()>
                     continue;
                 }
 
-                match tesla_api::auth::refresh_access_token(refresh_token.as_str()).await {
+                match tesla_api::auth::refresh_access_token(
+                    config.tesla_backend.as_ref(),
+                    refresh_token.as_str(),
+                )
+                .await
+                {
                     Ok(tokens) => {
                         Token::db_insert(pool, &tokens, encryption_key.as_str()).await?;
                         break;
@@ -105,96 +119,290 @@ pub async fn run(pool: &sqlx::PgPool, config: &mut Config) -> anyhow::Result<()>
         }
     };
 
-    let pool_clone = pool.clone();
-    let mut tesla_client = tesla_api::get_tesla_client(
-        tokens.clone(),
-        Some(Box::new(move || handle_token_expiry(&pool_clone))),
-    )?;
+    config.set_tokens(&tokens);
+
+    // Refreshes the access token before it expires
+    supervisor.spawn(
+        &task_tracker,
+        "token_manager_task",
+        cancellation_token.clone(),
+        {
+            let config = config.clone();
+            let pool = pool.clone();
+            let cancellation_token = cancellation_token.clone();
+            move || {
+                let config = config.clone();
+                let pool = pool.clone();
+                let cancellation_token = cancellation_token.clone();
+                async move { token_manager_task(config, cancellation_token, &pool).await }
+            }
+        },
+    );
 
-    let (car_id, vehicle_id) = match get_ids(&mut tesla_client).await {
-        Some((car_id, vehicle_id)) => (car_id, vehicle_id),
-        None => anyhow::bail!("Cannot read vehicle IDs"),
-    };
+    log::info!("Using Tesla {}", config.tesla_backend.name());
+    let mut tesla_client = get_tesla_client(config, tokens.clone())?;
 
-    // Transmits streaming data
-    let data_stream_task_handle = {
-        let vehicle_data_tx = vehicle_data_tx.clone();
-        let config = config.clone();
-        let cancellation_token = cancellation_token.clone();
-        task_tracker.spawn(async move {
-            data_streaming_task(vehicle_data_tx, config, cancellation_token, vehicle_id).await;
-        })
-    };
+    let (vehicles, energy_sites) = get_products(&mut tesla_client).await;
+    if vehicles.is_empty() && energy_sites.is_empty() {
+        anyhow::bail!("Cannot find any vehicles or energy sites in the account");
+    }
 
-    // Transmits polling data
-    let data_polling_task_handle = {
-        let vehicle_data_tx = vehicle_data_tx.clone();
-        let config = config.clone();
-        let cancellation_token = cancellation_token.clone();
-        task_tracker.spawn(async move {
-            data_polling_task(
-                vehicle_data_tx,
+    // All products share the rate limiter since the requests are limited per account
+    let rate_limiter = tesla_client.rate_limiter();
+
+    for site in energy_sites {
+        log::info!(
+            "Logging energy site {} ({})",
+            site.energy_site_id,
+            site.site_name.as_deref().unwrap_or("unnamed")
+        );
+
+        supervisor.spawn(
+            &task_tracker,
+            &format!("energy_site_task ({})", site.energy_site_id),
+            cancellation_token.clone(),
+            {
+                let config = config.clone();
+                let pool = pool.clone();
+                let cancellation_token = cancellation_token.clone();
+                let rate_limiter = rate_limiter.clone();
+                move || {
+                    let config = config.clone();
+                    let pool = pool.clone();
+                    let cancellation_token = cancellation_token.clone();
+                    let rate_limiter = rate_limiter.clone();
+                    let site = site.clone();
+                    async move {
+                        let mut tesla_client =
+                            get_tesla_client(&config, tokens_from_config(&config))?;
+                        tesla_client.set_rate_limiter(rate_limiter);
+                        energy_site_task(config, cancellation_token, tesla_client, site, &pool)
+                            .await
+                    }
+                }
+            },
+        );
+    }
+
+    let source = LiveSource::new(
+        vehicles,
+        config.clone(),
+        pool.clone(),
+        supervisor.clone(),
+        rate_limiter,
+    );
+    let record_file = get_config!(config.record_file).ok().flatten();
+    match record_file {
+        Some(record_file) => {
+            process_data_source(
+                pool,
                 config,
+                Recorder::new(source, record_file),
+                processed_data_tx,
+                task_tracker,
                 cancellation_token,
-                tesla_client,
-                car_id,
+                &supervisor,
             )
-            .await;
-        })
-    };
-
-    // Receives polling and streaming data, parse the data and transmits the processed data
-    let data_processor_task_handle = {
-        let cancellation_token = cancellation_token.clone();
-        let data_tx = processed_data_tx.clone();
-        let config = config.clone();
-        let database_tx = database_tx.clone();
-        let pool = pool.clone();
-        task_tracker.spawn(async move {
-            data_processor_task(
-                vehicle_data_rx,
-                data_tx,
-                database_tx,
-                database_resp_rx,
+            .await
+        }
+        None => {
+            process_data_source(
+                pool,
                 config,
+                source,
+                processed_data_tx,
+                task_tracker,
                 cancellation_token,
-                &pool,
+                &supervisor,
             )
-            .await;
-        })
-    };
+            .await
+        }
+    }
+}
+
+/// Process the data of a simulated or recorded data source instead of logging a vehicle
+///
+/// The web server shows the processed data while the source is running. Returns when all data
+/// of the source is processed or when Ctrl+C is pressed.
+pub async fn simulate(
+    pool: &sqlx::PgPool,
+    config: &Config,
+    source: impl DataSource,
+) -> anyhow::Result<()> {
+    let (processed_data_tx, _) = broadcast::channel::<Tables>(1);
+    let cancellation_token = CancellationToken::new();
+    let task_tracker = TaskTracker::new();
+    let supervisor = Supervisor::new();
+
+    spawn_web_server(
+        pool,
+        config,
+        &processed_data_tx,
+        &task_tracker,
+        &cancellation_token,
+        &supervisor,
+    );
+
+    process_data_source(
+        pool,
+        config,
+        source,
+        processed_data_tx,
+        task_tracker,
+        cancellation_token,
+        &supervisor,
+    )
+    .await
+}
+
+/// Starts web server and use the processed data to show logging status to the user
+fn spawn_web_server(
+    pool: &sqlx::PgPool,
+    config: &Config,
+    processed_data_tx: &broadcast::Sender<Tables>,
+    task_tracker: &TaskTracker,
+    cancellation_token: &CancellationToken,
+    supervisor: &Supervisor,
+) {
+    supervisor.spawn(
+        task_tracker,
+        "web_server_task",
+        cancellation_token.clone(),
+        {
+            let config = config.clone();
+            let pool = pool.clone();
+            let cancellation_token = cancellation_token.clone();
+            let processed_data_tx = processed_data_tx.clone();
+            let supervisor = supervisor.clone();
+            move || {
+                let data_rx = processed_data_tx.subscribe();
+                let config = config.clone();
+                let pool = pool.clone();
+                let cancellation_token = cancellation_token.clone();
+                let supervisor = supervisor.clone();
+                async move {
+                    let mut tables = vec![];
+                    for car_id in database::tables::car::get_vin_id_map(&pool).await.values() {
+                        tables.push(Tables::db_get_last(&pool, *car_id).await);
+                    }
+                    web_server_task(
+                        data_rx,
+                        pool,
+                        config,
+                        &tables,
+                        cancellation_token,
+                        supervisor,
+                    )
+                    .await;
+                    Ok(())
+                }
+            }
+        },
+    );
+}
 
-    let database_task_handle = {
-        let cancellation_token = cancellation_token.clone();
-        let config = config.clone();
-        let pool = pool.clone();
+/// Run the data source together with the data processor and the database task
+///
+/// The data processor and the database task are restarted by `supervisor` when they fail.
+/// Returns when the source has sent all its data and the data is processed. Ctrl+C stops the
+/// source, the data sent by the source is still processed. The remaining tasks of `task_tracker`
+/// are cancelled before returning.
+async fn process_data_source(
+    pool: &sqlx::PgPool,
+    config: &Config,
+    source: impl DataSource,
+    processed_data_tx: broadcast::Sender<Tables>,
+    task_tracker: TaskTracker,
+    cancellation_token: CancellationToken,
+    supervisor: &Supervisor,
+) -> anyhow::Result<()> {
+    // Channel for vehicle data and streaming data
+    let (vehicle_data_tx, vehicle_data_rx) = mpsc::channel::<DataTypes>(1);
+
+    // Transmits the vehicle data and the streaming data, the processor exits after processing
+    // all data once the source returns and drops the sender
+    let source_cancellation_token = cancellation_token.child_token();
+    {
+        let cancellation_token = source_cancellation_token.clone();
         task_tracker.spawn(async move {
-            database_task(
-                database_rx,
-                database_resp_tx,
-                config,
-                cancellation_token,
-                &pool,
-            )
-            .await;
-        })
-    };
+            let name = source.name();
+            log::info!("Reading data from {name}");
+            match source.run(vehicle_data_tx, cancellation_token).await {
+                Ok(()) => log::info!("All data read from {name}"),
+                Err(e) => log::error!("Error reading data from {name}: {e}"),
+            }
+        });
+    }
+
+    // Receives polling and streaming data, parses the data and writes it to the database. The
+    // processor and the database task are restarted together, a restarted processor must not
+    // receive the response to a request of the failed one.
+    let vehicle_data_rx = Arc::new(tokio::sync::Mutex::new(vehicle_data_rx));
+    let mut data_processing_handle = supervisor.spawn_until_done(
+        &task_tracker,
+        "data_processing_task",
+        cancellation_token.clone(),
+        {
+            let config = config.clone();
+            let pool = pool.clone();
+            let cancellation_token = cancellation_token.clone();
+            move || {
+                let vehicle_data_rx = vehicle_data_rx.clone();
+                let processed_data_tx = processed_data_tx.clone();
+                let config = config.clone();
+                let pool = pool.clone();
+                let cancellation_token = cancellation_token.clone();
+                async move {
+                    let mut vehicle_data_rx = vehicle_data_rx.lock().await;
+                    // channel to send date to database task
+                    let (database_tx, database_rx) = mpsc::channel::<DatabaseDataType>(1);
+                    // channel to receive response from database task
+                    let (database_resp_tx, database_resp_rx) = mpsc::channel::<DatabaseRespType>(1);
+
+                    // The database task exits when the processor exits and drops `database_tx`
+                    tokio::join!(
+                        data_processor_task(
+                            &mut vehicle_data_rx,
+                            processed_data_tx,
+                            database_tx,
+                            database_resp_rx,
+                            config.clone(),
+                            cancellation_token.clone(),
+                            &pool,
+                        ),
+                        database_task(
+                            database_rx,
+                            database_resp_tx,
+                            config,
+                            cancellation_token,
+                            &pool,
+                        ),
+                    );
+                    Ok(())
+                }
+            }
+        },
+    );
 
     // After spawning all the tasks, close the tracker
     task_tracker.close();
 
-    // Wait for any one of the tasks to exit
+    // The data processing task is done when all data of the source is processed. On Ctrl+C the
+    // source is stopped and the data it sent is processed, unless Ctrl+C is pressed again.
     tokio::select! {
-        status = data_processor_task_handle => tracing::info!("logger task done: {:?}", status),
-        status = data_stream_task_handle => tracing::info!("data stream task done: {:?}", status),
-        status = data_polling_task_handle => tracing::info!("data polling task done: {:?}", status),
-        status = database_task_handle => tracing::info!("database task done: {:?}", status),
-        status = web_server_task_handle => tracing::info!("web server task done: {:?}", status),
-        _ = tokio::signal::ctrl_c() => tracing::info!("Ctrl+C received"),
+        status = &mut data_processing_handle => tracing::info!("data processing task done: {:?}", status),
+        _ = tokio::signal::ctrl_c() => {
+            tracing::info!("Ctrl+C received, processing the data received so far");
+            source_cancellation_token.cancel();
+            tokio::select! {
+                status = data_processing_handle => tracing::info!("data processing task done: {:?}", status),
+                _ = tokio::signal::ctrl_c() => tracing::info!("Ctrl+C received again"),
+            }
+        }
     }
 
     tracing::info!("stopping tasks and exiting...");
-    // One or more tasks exited, tell the remaining tasks to exit
+    // Tell the remaining tasks to exit
     cancellation_token.cancel();
     // Wait for all tasks to exit
     task_tracker.wait().await;
@@ -202,35 +410,86 @@ pub async fn run(pool: &sqlx::PgPool, config: &mut Config) -> anyhow::Result<()>
     Ok(())
 }
 
-async fn get_ids(tesla_client: &mut TeslaClient) -> Option<(u64, u64)> {
-    match tesla_api::get_vehicles(tesla_client).await {
-        Ok(vehicles) => {
-            let vehicle = vehicles.first(); // FIXME: Use the first vehicle for now
-            let Some(car_id) = vehicle.and_then(|v| v.id) else {
-                log::error!("Cannot read id field from vehicle_data");
-                return None;
-            };
-            let Some(vehicle_id) = vehicle.and_then(|v| v.vehicle_id) else {
-                log::error!("Cannot read vehicle_id field from vehicle_data");
-                return None;
-            };
-            Some((car_id, vehicle_id))
-        }
-        Err(e) => {
-            match e {
-                TeslaError::TokenExpired(_) => (),
-                TeslaError::Retry(e) => log::warn!("{e}"),
-                e => log::error!("Error: {e}"),
+/// Create a Tesla API client which stores the refreshed tokens in the config
+pub(crate) fn get_tesla_client(
+    config: &Config,
+    tokens: AuthResponse,
+) -> Result<TeslaClient, TeslaError> {
+    let config_clone = config.clone();
+    tesla_api::get_tesla_client_with_backend(
+        tokens,
+        Some(Box::new(move |tokens| {
+            handle_token_expiry(&config_clone, tokens)
+        })),
+        config.tesla_backend.clone(),
+    )
+}
+
+/// Tokens currently stored in the config, these are updated whenever the tokens are refreshed
+pub(crate) fn tokens_from_config(config: &Config) -> AuthResponse {
+    AuthResponse {
+        access_token: get_config!(config.access_token).unwrap_or_default(),
+        refresh_token: get_config!(config.refresh_token).unwrap_or_default(),
+        ..Default::default()
+    }
+}
+
+/// Get the IDs of all vehicles and the energy sites in the account
+async fn get_products(tesla_client: &mut TeslaClient) -> (Vec<VehicleIds>, Vec<EnergySite>) {
+    let mut vehicles = vec![];
+    let mut energy_sites = vec![];
+    match tesla_api::get_products(tesla_client).await {
+        Ok(products) => {
+            for product in products {
+                match product {
+                    Product::Vehicle(v) => {
+                        let (Some(id), Some(vehicle_id), Some(vin)) =
+                            (v.id, v.vehicle_id, v.vin.clone())
+                        else {
+                            log::error!(
+                                "Cannot read id, vehicle_id or vin field from vehicle: {v:?}"
+                            );
+                            continue;
+                        };
+                        vehicles.push(VehicleIds {
+                            id,
+                            vehicle_id,
+                            vin,
+                        });
+                    }
+                    Product::EnergySite(site) => energy_sites.push(site),
+                }
             }
-            None
         }
+        Err(e) => match e {
+            TeslaError::TokenExpired(_) => (),
+            TeslaError::Retry(e) => log::warn!("{e}"),
+            e => log::error!("Error: {e}"),
+        },
     }
+    (vehicles, energy_sites)
 }
 
+/// Convert the vehicle data from `car_data` of the database `CAR_DATA_DATABASE_URL` to drives,
+/// charging processes, states and positions
+///
+/// The rows are processed like the data of any other data source, see `process_data_source`.
+/// The checkpoint of the converted rows is kept for the source database and the time range, an
+/// interrupted conversion of the same range continues after the checkpoint unless `restart` is
+/// set. The checkpoint is removed once all rows of the range are converted.
+///
+/// # Arguments
+///
+/// * `num_rows_to_fetch` - maximum number of rows to convert, 0 to convert all rows
+/// * `start` - convert the rows starting at this time
+/// * `end` - convert the rows up to this time
 pub async fn convert_db(
     pool: &sqlx::PgPool,
     config: &Config,
     num_rows_to_fetch: i64,
+    start: Option<DateTime<Utc>>,
+    end: Option<DateTime<Utc>>,
+    restart: bool,
 ) -> anyhow::Result<()> {
     let car_data_database_url = config
         .car_data_database_url
@@ -245,106 +504,62 @@ pub async fn convert_db(
     };
     let car_data_pool = database::initialize_car_data(car_data_database_url).await?;
 
-    // Channel for vehicle data and streaming data
-    let (vehicle_data_tx, vehicle_data_rx) = mpsc::channel::<DataTypes>(1);
-    // channel for parsed data
-    let (processed_data_tx, _data_rx) = broadcast::channel::<Tables>(1);
-    // channel to send date to database task
-    let (database_tx, database_rx) = mpsc::channel::<DatabaseDataType>(1);
-    // channel to receive response from database task
-    let (database_resp_tx, database_resp_rx) = mpsc::channel::<DatabaseRespType>(1);
-
-    let cancellation_token = CancellationToken::new();
-    let task_tracker = TaskTracker::new();
-
-    // Receives polling and streaming data, parse the data and transmits the processed data
-    let data_processor_task_handle = {
-        let cancellation_token = cancellation_token.clone();
-        let data_tx = processed_data_tx.clone();
-        let config = config.clone();
-        let database_tx = database_tx.clone();
-        let pool = pool.clone();
-        task_tracker.spawn(async move {
-            data_processor_task(
-                vehicle_data_rx,
-                data_tx,
-                database_tx,
-                database_resp_rx,
-                config,
-                cancellation_token,
-                &pool,
-            )
-            .await;
-        })
-    };
+    if !vehicle_data::has_timestamp_index(&car_data_pool).await? {
+        log::warn!(
+            "`car_data` has no index on the timestamp column, converting will be slow. Create the index using `CREATE INDEX ON car_data (timestamp)`"
+        );
+    }
 
-    let database_task_handle = {
-        let cancellation_token = cancellation_token.clone();
-        let config = config.clone();
-        let pool = pool.clone();
-        task_tracker.spawn(async move {
-            database_task(
-                database_rx,
-                database_resp_tx,
-                config,
-                cancellation_token,
-                &pool,
-            )
-            .await;
-        })
-    };
+    let range_start = start.map_or(i64::MIN, |start| start.timestamp_millis());
+    let range_end = end.map_or(i64::MAX, |end| end.timestamp_millis());
+    let mut checkpoint = ConvertDbCheckpoint::new(
+        convert_db_checkpoint::source_name(car_data_database_url),
+        range_start,
+        range_end,
+    );
+    if restart {
+        convert_db_checkpoint::db_delete(pool, &checkpoint).await?;
+    } else if let Some(saved) =
+        convert_db_checkpoint::db_get(pool, &checkpoint.source, range_start, range_end).await?
+    {
+        log::info!(
+            "Continuing after {} converted rows, use --restart to start from the beginning",
+            saved.num_rows
+        );
+        checkpoint = saved;
+    }
+    let num_rows_before = checkpoint.num_rows;
 
-    let num_rows = vehicle_data::num_car_data_rows(&car_data_pool).await?;
-    let batch_size = if num_rows_to_fetch < 10_000 {
-        num_rows_to_fetch
+    let mut source = CarDataSource::new(car_data_pool.clone(), start, end);
+    if num_rows_to_fetch > 0 {
+        source = source.with_max_rows(num_rows_to_fetch);
+    }
+    let (source, checkpoint_rx) = source.with_checkpoint(pool.clone(), checkpoint);
+
+    let (processed_data_tx, _) = broadcast::channel::<Tables>(1);
+    process_data_source(
+        pool,
+        &config.without_car_data_database(),
+        source,
+        processed_data_tx,
+        TaskTracker::new(),
+        CancellationToken::new(),
+        &Supervisor::new(),
+    )
+    .await?;
+
+    // All rows sent by the source are processed
+    let checkpoint = checkpoint_rx.borrow().clone();
+    log::info!("Converted {} rows", checkpoint.num_rows - num_rows_before);
+    let rows_left =
+        vehicle_data::db_get_page(&car_data_pool, checkpoint.last_timestamp, range_end, 1).await?;
+    if rows_left.is_empty() {
+        // Converting the same rows again starts from the beginning
+        convert_db_checkpoint::db_delete(pool, &checkpoint).await?;
     } else {
-        10_000
-    };
-    let mut row_offset = num_rows - num_rows_to_fetch;
-
-    let fetch_data_task = tokio::task::spawn({
-        async move {
-            while (row_offset - batch_size) < num_rows {
-                let data_list = vehicle_data::db_get(&car_data_pool, batch_size, row_offset)
-                    .await
-                    .map_err(|e| anyhow!(e))?;
-
-                for data in data_list {
-                    let data_str = match serde_json::to_string::<VehicleData>(data.deref()) {
-                        Ok(v) => v,
-                        Err(e) => {
-                            log::error!("Error converting vehicle data to string: {e}");
-                            anyhow::bail!("Error converting vehicle data to string: {e}");
-                        }
-                    };
-
-                    if let Err(e) = vehicle_data_tx.send(DataTypes::VehicleData(data_str)).await {
-                        log::error!("{e}");
-                        anyhow::bail!(e);
-                    }
-                }
-                row_offset += batch_size;
-            }
-            Ok(())
-        }
-    });
-
-    // After spawning all the tasks, close the tracker
-    task_tracker.close();
-
-    // Wait for any one of the tasks to exit
-    tokio::select! {
-        status = data_processor_task_handle => tracing::info!("logger task done: {:?}", status),
-        status = database_task_handle => tracing::info!("database task done: {:?}", status),
-        status = fetch_data_task => log::warn!("fetch data task exited: {status:?}"),
-        _ = tokio::signal::ctrl_c() => tracing::info!("Ctrl+C received"),
+        convert_db_checkpoint::db_update(pool, &checkpoint).await?;
+        log::info!("Run `convertdb` again to continue after the last converted row");
     }
 
-    tracing::info!("stopping tasks and exiting convertdb...");
-    // One or more tasks exited, tell the remaining tasks to exit
-    cancellation_token.cancel();
-    // Wait for all tasks to exit
-    task_tracker.wait().await;
-
     Ok(())
 }
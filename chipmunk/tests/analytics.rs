@@ -0,0 +1,77 @@
+#![cfg_attr(rustfmt, rustfmt_skip)]
+
+pub mod common;
+
+use std::io::Write;
+
+use chipmunk::config::Config;
+use chipmunk::data_source::JsonlSource;
+use chipmunk::database::tables::{car::Car, charging_process::ChargingProcess};
+use chipmunk::database::DBTable;
+use chipmunk::server::analytics::handle_command;
+use chipmunk::tasks;
+use chrono::Utc;
+use rand::Rng;
+use serde_json::json;
+use tesla_api::vehicle_data::ShiftState;
+use ui_common::{CapacityHistory, Topic, WsMessage};
+
+use crate::common::test_data;
+use crate::common::utils::{create_mock_osm_server, init_test_database};
+
+#[tokio::test]
+async fn efficiency_and_capacity_history() {
+    chipmunk::init_log();
+
+    let random_http_port = rand::thread_rng().gen_range(4000..60000);
+    unsafe { std::env::set_var("HTTP_PORT", random_http_port.to_string()); }
+
+    let _osm_mock = create_mock_osm_server().await;
+    let pool = init_test_database("test_analytics").await;
+    let config = Config::new(&pool).await;
+
+    // Charge from 20 % to 50 % in 30 minutes, adding 22.5 kWh and 90 miles of rated range, then park
+    let start_time = Utc::now() - chrono::Duration::try_hours(2).unwrap();
+    let jsonl_path = std::env::temp_dir().join(format!("chipmunk_analytics_{random_http_port}.jsonl"));
+    let mut file = std::fs::File::create(&jsonl_path).unwrap();
+    for i in 0..=30 {
+        let mut data = test_data::data_charging(start_time + chrono::Duration::try_minutes(i).unwrap(), 20 + i as i16);
+        let charge_state = data.charge_state.as_mut().unwrap();
+        charge_state.charge_energy_added = Some(i as f32 * 0.75);
+        charge_state.battery_range = Some(100.0 + i as f32 * 3.0);
+        writeln!(file, "{}", serde_json::to_string(&data).unwrap()).unwrap();
+    }
+    // The charge of the first parked data point is the last charge of the charging process
+    let mut parked = test_data::data_with_shift(start_time + chrono::Duration::try_minutes(31).unwrap(), Some(ShiftState::P));
+    let charge_state = parked.charge_state.as_mut().unwrap();
+    (charge_state.battery_level, charge_state.charge_energy_added, charge_state.battery_range) = (Some(50), Some(22.5), Some(190.0));
+    writeln!(file, "{}", serde_json::to_string(&parked).unwrap()).unwrap();
+    drop(file);
+    tasks::simulate(&pool, &config, JsonlSource::new(&jsonl_path)).await.unwrap();
+    std::fs::remove_file(&jsonl_path).unwrap();
+
+    // The efficiency is updated when charging is finished
+    let cp = ChargingProcess::db_get_last(&pool).await.unwrap();
+    assert_eq!((cp.start_battery_level, cp.end_battery_level), (Some(20), Some(50)));
+    let range_added = cp.end_rated_range_km.unwrap() - cp.start_rated_range_km.unwrap();
+    let expected_efficiency = (cp.charge_energy_added.unwrap() * 1000.0 / range_added).round() / 1000.0;
+    assert_eq!(expected_efficiency, 0.155);
+    let car = Car::db_get_id(&pool, cp.car_id as i64).await.unwrap();
+    assert_eq!(car.efficiency, Some(expected_efficiency));
+
+    let msg = WsMessage::command(Topic::GetCapacityHistory, Some(json!({"car_id": cp.car_id})));
+    let response = handle_command(&pool, &msg).await;
+    assert_eq!(response["status"], true, "{response}");
+    let history: CapacityHistory = serde_json::from_value(response["capacity_history"].clone()).unwrap();
+    assert_eq!(history.car_id, cp.car_id);
+    assert_eq!(history.efficiency, Some(expected_efficiency));
+    assert_eq!(history.capacity.len(), 1);
+    assert_eq!(history.capacity[0].charging_process_id, cp.id);
+    assert_eq!(history.capacity[0].capacity_kwh, 75.0);
+
+    // Unknown car and missing car ID
+    let msg = WsMessage::command(Topic::GetCapacityHistory, Some(json!({"car_id": 1000})));
+    assert_eq!(handle_command(&pool, &msg).await["status"], false);
+    let msg = WsMessage::command(Topic::GetCapacityHistory, None);
+    assert_eq!(handle_command(&pool, &msg).await["status"], false);
+}
@@ -0,0 +1,130 @@
+#![cfg_attr(rustfmt, rustfmt_skip)]
+
+pub mod common;
+
+use std::io::Write;
+
+use chipmunk::config::Config;
+use chipmunk::data_source::JsonlSource;
+use chipmunk::database::tables::{charging_process::ChargingProcess, charging_tier::ChargingTier, geofence::Geofence, tariff::Tariff};
+use chipmunk::database::types::BillingType;
+use chipmunk::database::DBTable;
+use chipmunk::geofence::GeofenceMatcher;
+use chipmunk::server::{geofence::handle_command, tariff};
+use chipmunk::tasks;
+use chrono::Utc;
+use rand::Rng;
+use serde_json::json;
+use ui_common::{Topic, WsMessage};
+
+use crate::common::test_data;
+use crate::common::utils::{create_mock_osm_server, init_test_database};
+
+#[tokio::test]
+async fn charging_cost_from_geofence() {
+    chipmunk::init_log();
+
+    let random_http_port = rand::thread_rng().gen_range(4000..60000);
+    unsafe { std::env::set_var("HTTP_PORT", random_http_port.to_string()); }
+
+    let _osm_mock = create_mock_osm_server().await;
+    let pool = init_test_database("test_charging_cost").await;
+    let config = Config::new(&pool).await;
+
+    // The test data is at latitude 0, longitude 0
+    let geofence = Geofence {
+        name: "Charger".into(),
+        radius: 100,
+        cost_per_unit: Some(0.5),
+        session_fee: Some(1.0),
+        billing_type: BillingType::PerMinute,
+        ..Default::default()
+    };
+    geofence.db_insert(&pool).await.unwrap();
+    GeofenceMatcher::invalidate_cache();
+
+    // Charge for 10 minutes
+    let start_time = Utc::now() - chrono::Duration::try_hours(1).unwrap();
+    let jsonl_path = std::env::temp_dir().join(format!("chipmunk_charging_cost_{random_http_port}.jsonl"));
+    let mut file = std::fs::File::create(&jsonl_path).unwrap();
+    for i in 0..=10 {
+        let data = test_data::data_charging(start_time + chrono::Duration::try_minutes(i).unwrap(), 50 + i as i16);
+        writeln!(file, "{}", serde_json::to_string(&data).unwrap()).unwrap();
+    }
+    drop(file);
+    tasks::simulate(&pool, &config, JsonlSource::new(&jsonl_path)).await.unwrap();
+    std::fs::remove_file(&jsonl_path).unwrap();
+
+    assert_eq!(ChargingProcess::db_num_rows(&pool).await.unwrap(), 1);
+    let charging_process = ChargingProcess::db_get_last(&pool).await.unwrap();
+    assert!(charging_process.geofence_id.is_some());
+    // 10 minutes at 0.5 per minute plus the session fee
+    assert_eq!(charging_process.cost, Some(6.0));
+
+    // Charging at a Supercharger is free if the car has free Supercharging
+    sqlx::query("UPDATE charges SET fast_charger_brand = 'Tesla'").execute(&pool).await.unwrap();
+    let mut conn = pool.acquire().await.unwrap();
+    assert_eq!(ChargingProcess::db_recalculate(&mut conn, charging_process.id).await.unwrap(), Some(6.0));
+    sqlx::query("UPDATE car_settings SET free_supercharging = true").execute(&pool).await.unwrap();
+    assert_eq!(ChargingProcess::db_recalculate(&mut conn, charging_process.id).await.unwrap(), Some(0.0));
+    assert_eq!(ChargingProcess::db_get_last(&pool).await.unwrap().cost, Some(0.0));
+
+    // Time of use tariffs are priced per kWh of the energy used, the whole day in this test
+    sqlx::query("UPDATE car_settings SET free_supercharging = false").execute(&pool).await.unwrap();
+    sqlx::query("UPDATE charges SET charger_power = 11").execute(&pool).await.unwrap();
+    let geofence_id = charging_process.geofence_id.unwrap();
+    let tariff = Tariff { geofence_id, name: "Flat".into(), price_per_kwh: 0.2, ..Default::default() };
+    let tariff_id = tariff.db_insert(&pool).await.unwrap() as i32;
+    let cost = ChargingProcess::db_recalculate(&mut conn, charging_process.id).await.unwrap().unwrap();
+    let charging_process = ChargingProcess::db_get_last(&pool).await.unwrap();
+    let energy_used = charging_process.charge_energy_used.unwrap();
+    // 10 minutes at 11 kW
+    assert!((energy_used - 11.0 / 6.0).abs() < 0.01);
+    assert_eq!(cost, 1.37);
+
+    let tiers = ChargingTier::db_get_for_charging_process(&pool, charging_process.id).await.unwrap();
+    assert_eq!(tiers.len(), 1);
+    assert_eq!((tiers[0].tariff_id, tiers[0].name.as_str()), (Some(tariff_id), "Flat"));
+    assert!((tiers[0].energy_kwh - energy_used).abs() < 0.01);
+
+    // Recalculating replaces the tiers, deleting the tariff keeps the tier without a tariff
+    ChargingProcess::db_recalculate(&mut conn, charging_process.id).await.unwrap();
+    Tariff::db_delete(&pool, tariff_id as i64).await.unwrap();
+    let tiers = ChargingTier::db_get_for_charging_process(&pool, charging_process.id).await.unwrap();
+    assert_eq!(tiers.len(), 1);
+    assert_eq!(tiers[0].tariff_id, None);
+
+    // Changing the prices of the geofence recalculates the cost
+    let changed = ui_common::Geofence { session_fee: Some(2.0), ..Geofence::db_get_id(&pool, geofence_id as i64).await.unwrap().to_ui_struct() };
+    let msg = WsMessage::command(Topic::UpdateGeofence, Some(serde_json::to_value(&changed).unwrap()));
+    assert_eq!(handle_command(&pool, &msg).await["status"], true);
+    assert_eq!(ChargingProcess::db_get_last(&pool).await.unwrap().cost, Some(7.0));
+    assert!(ChargingTier::db_get_for_charging_process(&pool, charging_process.id).await.unwrap().is_empty());
+
+    // Adding, changing and removing a tariff recalculates the cost
+    let tariffs = |response: serde_json::Value| -> Vec<ui_common::Tariff> { serde_json::from_value(response["tariffs"].clone()).unwrap() };
+    let new_tariff = ui_common::Tariff { geofence_id, name: "Flat".into(), weekdays: 0b111_1111, price_per_kwh: 0.2, time_zone: "UTC".into(), ..Default::default() };
+    let msg = WsMessage::command(Topic::CreateTariff, Some(serde_json::to_value(&new_tariff).unwrap()));
+    let created = tariffs(tariff::handle_command(&pool, &msg).await);
+    assert_eq!(created.len(), 1);
+    assert_eq!(ChargingProcess::db_get_last(&pool).await.unwrap().cost, Some(2.37));
+
+    let changed = ui_common::Tariff { price_per_kwh: 0.5, ..created[0].clone() };
+    let msg = WsMessage::command(Topic::UpdateTariff, Some(serde_json::to_value(&changed).unwrap()));
+    assert_eq!(tariffs(tariff::handle_command(&pool, &msg).await), vec![changed.clone()]);
+    assert_eq!(ChargingProcess::db_get_last(&pool).await.unwrap().cost, Some(2.92));
+
+    let invalid = ui_common::Tariff { time_zone: "Mars/Olympus_Mons".into(), ..changed.clone() };
+    let msg = WsMessage::command(Topic::UpdateTariff, Some(serde_json::to_value(&invalid).unwrap()));
+    assert_eq!(tariff::handle_command(&pool, &msg).await["status"], false);
+
+    let msg = WsMessage::command(Topic::DeleteTariff, Some(json!({"id": changed.id})));
+    assert!(tariffs(tariff::handle_command(&pool, &msg).await).is_empty());
+    assert_eq!(ChargingProcess::db_get_last(&pool).await.unwrap().cost, Some(7.0));
+
+    // The charging process has no cost without its geofence
+    let msg = WsMessage::command(Topic::DeleteGeofence, Some(json!({"id": geofence_id})));
+    assert_eq!(handle_command(&pool, &msg).await["status"], true);
+    let charging_process = ChargingProcess::db_get_last(&pool).await.unwrap();
+    assert_eq!((charging_process.geofence_id, charging_process.cost), (None, None));
+}
@@ -0,0 +1,97 @@
+#![cfg_attr(rustfmt, rustfmt_skip)]
+
+use chipmunk::charging::calculate_energy_used;
+use chipmunk::database::tables::charges::Charges;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::common::utils::init_test_database;
+
+pub mod common;
+
+/// Synthetic charging session modelled on the charges logged by TeslaMate, e.g. three phase cars
+/// reporting the phase to phase voltage. `teslamate_energy_used` is the result of TeslaMate's
+/// query in common/teslamate_charge_energy_used.sql for the charges, rounded to Wh.
+#[derive(Deserialize)]
+struct ChargingSession {
+    name: String,
+    teslamate_energy_used: Option<f32>,
+    /// Expected energy used if it is different from TeslaMate, i.e. with the lower power of the
+    /// charges around the gaps in the data
+    expected_energy_used: Option<f32>,
+    charges: Vec<SessionCharge>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct SessionCharge {
+    date: DateTime<Utc>,
+    charger_actual_current: Option<i16>,
+    charger_voltage: Option<i16>,
+    charger_phases: Option<i16>,
+    charger_power: Option<i16>,
+}
+
+impl SessionCharge {
+    fn to_charges(&self) -> Charges {
+        Charges {
+            date: Some(self.date),
+            charger_actual_current: self.charger_actual_current,
+            charger_voltage: self.charger_voltage,
+            charger_phases: self.charger_phases,
+            charger_power: self.charger_power,
+            ..Default::default()
+        }
+    }
+}
+
+#[test]
+fn energy_used_matches_teslamate() {
+    let sessions = std::fs::read_to_string("tests/common/charging_sessions.json").unwrap();
+    let sessions: Vec<ChargingSession> = serde_json::from_str(&sessions).unwrap();
+    assert!(!sessions.is_empty());
+
+    for session in sessions {
+        let charges: Vec<Charges> = session.charges.iter().map(SessionCharge::to_charges).collect();
+        let energy_used = calculate_energy_used(&charges);
+        let expected = session.expected_energy_used.or(session.teslamate_energy_used);
+        match (energy_used, expected) {
+            (Some(energy_used), Some(expected)) => assert!(
+                (energy_used - expected).abs() <= 0.1,
+                "{}: energy used {energy_used} kWh, expected {expected} kWh", session.name
+            ),
+            (energy_used, expected) => assert_eq!(energy_used, expected, "{}", session.name),
+        }
+    }
+}
+
+#[test]
+fn energy_used_with_gaps() {
+    let sessions = std::fs::read_to_string("tests/common/charging_sessions.json").unwrap();
+    let sessions: Vec<ChargingSession> = serde_json::from_str(&sessions).unwrap();
+    let session = sessions.iter().find(|s| s.expected_energy_used.is_some()).unwrap();
+    let mut charges: Vec<Charges> = session.charges.iter().map(SessionCharge::to_charges).collect();
+    let energy_used = calculate_energy_used(&charges).unwrap();
+
+    // TeslaMate counts the gap while charging was paused at the power after the gap
+    assert!(energy_used < session.teslamate_energy_used.unwrap() - 1.0);
+
+    // Charges without a timestamp and the order of the charges do not change the result
+    charges.reverse();
+    charges.insert(10, Charges { charger_power: Some(11), ..Default::default() });
+    approx_eq!(calculate_energy_used(&charges), Some(energy_used));
+}
+
+#[tokio::test]
+async fn teslamate_energy_used_from_teslamate_query() {
+    let pool = init_test_database("test_teslamate_energy_used").await;
+    let query = std::fs::read_to_string("tests/common/teslamate_charge_energy_used.sql").unwrap();
+    let sessions = std::fs::read_to_string("tests/common/charging_sessions.json").unwrap();
+    let sessions: Vec<ChargingSession> = serde_json::from_str(&sessions).unwrap();
+
+    for session in sessions {
+        let charges = serde_json::to_string(&session.charges).unwrap();
+        let energy_used: Option<f64> = sqlx::query_scalar(&query).bind(charges).fetch_one(&pool).await.unwrap();
+        let energy_used = energy_used.map(|e| ((e * 1000.0).round() / 1000.0) as f32);
+        assert_eq!(energy_used, session.teslamate_energy_used, "{}", session.name);
+    }
+}
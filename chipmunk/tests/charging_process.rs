@@ -375,7 +375,7 @@ pub async fn test_charging_process() {
     assert_eq!(charging_from_db.car_id, charging_calculated.car_id);
     approx_eq!(charging_from_db.start_rated_range_km, charging_calculated.start_rated_range_km);
     approx_eq!(charging_from_db.end_rated_range_km, charging_calculated.end_rated_range_km);
-    approx_eq!(charging_from_db.charge_energy_used, charging_calculated.charge_energy_used, 5.0); // FIXME: Using lower precision to make this test pass, need to fix charge_energy_used calculation to match teslamate
+    approx_eq!(charging_from_db.charge_energy_used, charging_calculated.charge_energy_used, 0.1);
     assert_eq!(charging_from_db.cost, charging_calculated.cost);
     // IGNORE THIS assert_eq!(charging.position_id, expected.position_id);
     // IGNORE THIS assert_eq!(charging.id, expected.id);
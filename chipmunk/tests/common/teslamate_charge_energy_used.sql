@@ -0,0 +1,44 @@
+-- Energy used by a charging session in kWh as calculated by TeslaMate
+--
+-- SQL translation of the Ecto queries in `determine_phases/1` and `calculate_energy_used/1` of
+-- TeslaMate's lib/teslamate/log.ex, the queries are otherwise unchanged. The charges are passed
+-- as a JSON array in $1 instead of being selected by the charging process ID, the column types
+-- match the `charges` table. Used to produce the `teslamate_energy_used` values of
+-- charging_sessions.json, see tests/charging_energy.rs.
+WITH charges AS (
+    SELECT * FROM json_to_recordset($1::json) AS c(
+        date TIMESTAMP,
+        charger_actual_current SMALLINT,
+        charger_voltage SMALLINT,
+        charger_phases SMALLINT,
+        charger_power SMALLINT
+    )
+),
+-- determine_phases/1
+phases AS (
+    SELECT
+        avg(charger_power * 1.0 / nullif(charger_actual_current * charger_voltage, 0)) * 1000 AS p,
+        avg(charger_phases)::INTEGER AS r,
+        avg(charger_voltage)::FLOAT AS v,
+        count(*) AS n
+    FROM charges
+),
+determined_phases AS (
+    SELECT CASE
+        WHEN p IS NULL OR p <= 0 OR n <= 15 THEN NULL
+        WHEN r = round(p) THEN r::FLOAT
+        WHEN r = 3 AND abs(p / sqrt(r) - 1) <= 0.1 THEN sqrt(r)
+        WHEN abs(round(p) - p) <= 0.3 THEN round(p)::FLOAT
+    END AS phases
+    FROM phases
+),
+-- calculate_energy_used/1
+energy AS (
+    SELECT
+        CASE
+            WHEN charger_phases IS NULL THEN charger_power
+            ELSE charger_actual_current * charger_voltage * (SELECT phases FROM determined_phases) / 1000.0
+        END * EXTRACT(epoch FROM (date - lag(date) OVER (ORDER BY date))) / 3600 AS energy_used
+    FROM charges
+)
+SELECT sum(energy_used)::FLOAT8 FROM energy WHERE energy_used > 0
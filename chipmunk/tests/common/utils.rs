@@ -59,6 +59,8 @@ fn create_vehicles_response_json() -> String {
     let vehicle = Vehicles {
         id: Some(1),
         vehicle_id: Some(1234),
+        vin: Some("EWABCD123UWE23456".into()),
+        state: Some("online".into()),
         ..Vehicles::default()
     };
     let vehicles = vec![vehicle];
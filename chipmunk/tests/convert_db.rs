@@ -0,0 +1,76 @@
+#![cfg_attr(rustfmt, rustfmt_skip)]
+
+pub mod common;
+
+use chipmunk::config::Config;
+use chipmunk::database::{tables::{convert_db_checkpoint, vehicle_data}, DBTable};
+use chipmunk::tasks::convert_db;
+use chrono::{Duration, Utc};
+use reqwest::Url;
+use tesla_api::vehicle_data::ShiftState;
+
+use crate::common::{test_data::data_with_shift, utils::{create_mock_osm_server, init_test_database, ts_no_nanos}};
+
+async fn count(pool: &sqlx::PgPool, table: &str) -> i64 {
+    sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {table}")).fetch_one(pool).await.unwrap()
+}
+
+#[tokio::test]
+async fn convert_db_with_checkpoint() {
+    use ShiftState::*;
+
+    chipmunk::init_log();
+    let _osm_mock = create_mock_osm_server().await;
+
+    // Vehicle data of a drive in the car data database, the third and the fourth data points have the same timestamp
+    let car_data_pool = init_test_database("test_convert_db_car_data").await;
+    let start_time = ts_no_nanos(Utc::now()) - Duration::try_hours(1).unwrap();
+    let points = [(0, P), (1, D), (2, D), (2, D), (3, D), (4, P), (5, P)];
+    for (minute, shift) in points {
+        data_with_shift(start_time + Duration::try_minutes(minute).unwrap(), Some(shift)).db_insert(&car_data_pool).await.unwrap();
+    }
+
+    // All rows with the timestamp of the last row of a page are returned
+    let page = vehicle_data::db_get_page(&car_data_pool, i64::MIN, i64::MAX, 3).await.unwrap();
+    assert_eq!(page.len(), 4);
+    let page = vehicle_data::db_get_page(&car_data_pool, page[3].timestamp, i64::MAX, 3).await.unwrap();
+    assert_eq!(page.len(), 3);
+
+    let mut url = Url::parse(&std::env::var("TEST_DATABASE_URL").unwrap()).unwrap();
+    url.set_path("test_convert_db_car_data");
+    unsafe { std::env::set_var("CAR_DATA_DATABASE_URL", url.as_str()); }
+
+    let pool = init_test_database("test_convert_db").await;
+    let config = Config::new(&pool).await;
+    let source = convert_db_checkpoint::source_name(url.as_str());
+    assert!(!source.contains('@'), "{source}");
+
+    // Convert the first rows, the rows sharing a timestamp are converted together
+    convert_db(&pool, &config, 3, None, None, false).await.unwrap();
+    let checkpoint = convert_db_checkpoint::db_get(&pool, &source, i64::MIN, i64::MAX).await.unwrap().unwrap();
+    assert_eq!(checkpoint.num_rows, 4);
+    assert_eq!(count(&pool, "car_data").await, 4);
+    assert_eq!(count(&pool, "drives").await, 1);
+
+    // Continue after the checkpoint, the checkpoint is removed when all rows are converted
+    convert_db(&pool, &config, 0, None, None, false).await.unwrap();
+    assert_eq!(convert_db_checkpoint::db_get(&pool, &source, i64::MIN, i64::MAX).await.unwrap(), None);
+    assert_eq!(count(&pool, "car_data").await, 7);
+    assert_eq!(count(&pool, "drives").await, 1);
+    assert_eq!(count(&pool, "states").await, 3);
+    let in_progress: bool = sqlx::query_scalar("SELECT in_progress FROM drives").fetch_one(&pool).await.unwrap();
+    assert!(!in_progress);
+
+    // The checkpoint of a time range does not continue the conversion of another range
+    let from = start_time + Duration::try_minutes(4).unwrap();
+    convert_db(&pool, &config, 1, Some(from), None, false).await.unwrap();
+    let checkpoint = convert_db_checkpoint::db_get(&pool, &source, from.timestamp_millis(), i64::MAX).await.unwrap().unwrap();
+    assert_eq!((checkpoint.num_rows, checkpoint.last_timestamp), (1, from.timestamp_millis()));
+    assert_eq!(convert_db_checkpoint::db_get(&pool, &source, i64::MIN, i64::MAX).await.unwrap(), None);
+    assert_eq!(count(&pool, "car_data").await, 8);
+
+    // Restarting converts the rows of the range again
+    convert_db(&pool, &config, 0, Some(from), None, true).await.unwrap();
+    assert_eq!(convert_db_checkpoint::db_get(&pool, &source, from.timestamp_millis(), i64::MAX).await.unwrap(), None);
+    assert_eq!(count(&pool, "car_data").await, 10);
+}
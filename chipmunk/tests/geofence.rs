@@ -0,0 +1,77 @@
+#![cfg_attr(rustfmt, rustfmt_skip)]
+
+pub mod common;
+
+use chipmunk::config::Config;
+use chipmunk::data_source::GpxSimulator;
+use chipmunk::server::geofence::handle_command;
+use chipmunk::tasks;
+use chipmunk::utils::location::Location;
+use rand::Rng;
+use serde_json::json;
+use tokio::time::Duration;
+use ui_common::{BillingType, Geofence, Topic, WsMessage};
+
+use crate::common::utils::{create_mock_osm_server, init_test_database};
+
+async fn start_geofence_id(pool: &sqlx::PgPool) -> Option<i32> {
+    sqlx::query_scalar("SELECT start_geofence_id FROM drives").fetch_one(pool).await.unwrap()
+}
+
+fn geofences(response: &serde_json::Value) -> Vec<Geofence> {
+    assert_eq!(response["status"], true, "{response}");
+    serde_json::from_value(response["geofences"].clone()).unwrap()
+}
+
+#[tokio::test]
+async fn geofence_commands() {
+    chipmunk::init_log();
+
+    let random_http_port = rand::thread_rng().gen_range(4000..60000);
+    unsafe { std::env::set_var("HTTP_PORT", random_http_port.to_string()); }
+
+    let _osm_mock = create_mock_osm_server().await;
+    let pool = init_test_database("test_geofence_commands").await;
+    let config = Config::new(&pool).await;
+
+    let track: Vec<Location> = (0..20).map(|i| Location::new(37.4 + f64::from(i) * 0.001, -122.1)).collect();
+    tasks::simulate(&pool, &config, GpxSimulator::from_locations(track, Duration::ZERO)).await.unwrap();
+    assert_eq!(start_geofence_id(&pool).await, None);
+
+    let response = handle_command(&pool, &WsMessage::command(Topic::GetGeofences, None)).await;
+    assert!(geofences(&response).is_empty());
+
+    // Creating a geofence assigns it to the existing drive
+    let home = Geofence {
+        name: "Home".into(),
+        latitude: 37.4,
+        longitude: -122.1,
+        radius: 50,
+        cost_per_unit: Some(0.3),
+        billing_type: BillingType::PerKwh,
+        ..Default::default()
+    };
+    let msg = WsMessage::command(Topic::CreateGeofence, Some(serde_json::to_value(&home).unwrap()));
+    let created = geofences(&handle_command(&pool, &msg).await);
+    assert_eq!(created.len(), 1);
+    let home = Geofence { id: created[0].id, ..home };
+    assert_eq!(created[0], home);
+    assert_eq!(start_geofence_id(&pool).await, Some(home.id));
+
+    // Moving the geofence away from the start of the drive removes it from the drive
+    let moved = Geofence { latitude: 37.5, session_fee: Some(1.5), billing_type: BillingType::PerMinute, ..home.clone() };
+    let msg = WsMessage::command(Topic::UpdateGeofence, Some(serde_json::to_value(&moved).unwrap()));
+    assert_eq!(geofences(&handle_command(&pool, &msg).await), vec![moved.clone()]);
+    assert_eq!(start_geofence_id(&pool).await, None);
+
+    // Invalid geofences and unknown IDs are rejected
+    let msg = WsMessage::command(Topic::UpdateGeofence, Some(serde_json::to_value(Geofence { id: 1000, ..moved.clone() }).unwrap()));
+    assert_eq!(handle_command(&pool, &msg).await["status"], false);
+    let msg = WsMessage::command(Topic::CreateGeofence, Some(serde_json::to_value(Geofence { radius: -5, ..moved.clone() }).unwrap()));
+    assert_eq!(handle_command(&pool, &msg).await["status"], false);
+    let msg = WsMessage::command(Topic::DeleteGeofence, Some(json!({"id": 1000})));
+    assert_eq!(handle_command(&pool, &msg).await["status"], false);
+
+    let msg = WsMessage::command(Topic::DeleteGeofence, Some(json!({"id": moved.id})));
+    assert!(geofences(&handle_command(&pool, &msg).await).is_empty());
+}
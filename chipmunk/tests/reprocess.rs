@@ -0,0 +1,71 @@
+#![cfg_attr(rustfmt, rustfmt_skip)]
+
+pub mod common;
+
+use chipmunk::database::{tables::{car::Car, car_settings::CarSettings, position::Position}, DBTable};
+use chipmunk::reprocess::reprocess;
+use chrono::{Duration, Utc};
+use tesla_api::vehicle_data::ShiftState;
+
+use crate::common::{test_data::data_with_shift, utils::{create_mock_osm_server, init_test_database, ts_no_nanos}};
+
+async fn count(pool: &sqlx::PgPool, table: &str) -> i64 {
+    sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {table}")).fetch_one(pool).await.unwrap()
+}
+
+#[tokio::test]
+async fn reprocess_drive() {
+    use ShiftState::*;
+
+    chipmunk::init_log();
+    let _osm_mock = create_mock_osm_server().await;
+    let pool = init_test_database("test_reprocess").await;
+
+    // Store the vehicle data of a drive in car_data without creating the derived tables
+    let start_time = ts_no_nanos(Utc::now()) - Duration::try_hours(1).unwrap();
+    let shifts = [P, D, D, D, P, P];
+    let data_list: Vec<_> = shifts.into_iter().enumerate()
+        .map(|(i, shift)| data_with_shift(start_time + Duration::try_minutes(i as i64).unwrap(), Some(shift)))
+        .collect();
+    let settings_id = CarSettings::default().db_insert(&pool).await.unwrap();
+    let car_id = Car::from(&data_list[0], settings_id).unwrap().db_insert(&pool).await.unwrap() as i16;
+    for data in &data_list {
+        data.db_insert(&pool).await.unwrap();
+    }
+    let end_time = start_time + Duration::try_minutes(10).unwrap();
+
+    // Position logged from the streaming API during the drive, it is not stored in car_data
+    let streamed = Position { car_id, date: Some(start_time + Duration::try_seconds(90).unwrap()), latitude: Some(12.34), longitude: Some(34.56), streamed: true, ..Default::default() };
+    let streamed_id = streamed.db_insert(&pool).await.unwrap();
+
+    // Dry run does not change the database
+    let diff = reprocess(&pool, car_id, start_time, end_time, true).await.unwrap();
+    assert_eq!(diff.num_data_points, 6);
+    assert_eq!(diff.added.len(), 5); // 3 states, 1 drive, positions
+    assert!(diff.added.iter().any(|line| line.starts_with("drive ")));
+    assert_eq!(count(&pool, "states").await, 0);
+    assert_eq!(count(&pool, "drives").await, 0);
+    assert_eq!(count(&pool, "positions").await, 1);
+
+    let diff = reprocess(&pool, car_id, start_time, end_time, false).await.unwrap();
+    assert_eq!(diff.added.len(), 5);
+    assert_eq!(count(&pool, "states").await, 3);
+    assert_eq!(count(&pool, "drives").await, 1);
+    assert_eq!(count(&pool, "positions").await, 7);
+    let (drive_id, in_progress): (i32, bool) = sqlx::query_as("SELECT id, in_progress FROM drives").fetch_one(&pool).await.unwrap();
+    assert!(!in_progress);
+    // The streamed position is kept and belongs to the re-created drive
+    let streamed_drive_id: Option<i32> = sqlx::query_scalar("SELECT drive_id FROM positions WHERE id = $1").bind(streamed_id as i32).fetch_one(&pool).await.unwrap();
+    assert_eq!(streamed_drive_id, Some(drive_id));
+
+    // Re-processing a range inside the drive re-processes the whole drive and creates the same rows
+    let diff = reprocess(&pool, car_id, start_time + Duration::try_seconds(150).unwrap(), start_time + Duration::try_seconds(160).unwrap(), false).await.unwrap();
+    assert!(diff.start <= start_time + Duration::try_minutes(1).unwrap());
+    assert!(diff.added.is_empty(), "{:?}", diff.added);
+    assert!(diff.removed.is_empty(), "{:?}", diff.removed);
+    assert_eq!(count(&pool, "states").await, 3);
+    assert_eq!(count(&pool, "drives").await, 1);
+    assert_eq!(count(&pool, "positions").await, 7);
+
+    assert!(reprocess(&pool, car_id, end_time, start_time, true).await.is_err());
+}
@@ -0,0 +1,121 @@
+#![cfg_attr(rustfmt, rustfmt_skip)]
+
+pub mod common;
+
+use std::io::Write;
+
+use chipmunk::config::Config;
+use chipmunk::database::tables::geofence::Geofence;
+use chipmunk::database::DBTable;
+use chipmunk::data_source::{DataSource, GpxSimulator, JsonlSource, Recorder, ReplaySource};
+use chipmunk::{geofence, tasks};
+use chipmunk::utils::location::Location;
+use chrono::Utc;
+use rand::Rng;
+use tokio::time::Duration;
+
+use crate::common::utils::{create_mock_osm_server, init_test_database};
+
+async fn count(pool: &sqlx::PgPool, table: &str) -> i64 {
+    sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {table}")).fetch_one(pool).await.unwrap()
+}
+
+#[tokio::test]
+async fn simulate_gpx_and_jsonl() {
+    chipmunk::init_log();
+
+    let random_http_port = rand::thread_rng().gen_range(4000..60000);
+    unsafe { std::env::set_var("HTTP_PORT", random_http_port.to_string()); }
+
+    let _osm_mock = create_mock_osm_server().await;
+    let pool = init_test_database("test_simulate").await;
+    let config = Config::new(&pool).await;
+
+    // The drives start inside a geofence
+    let home = Geofence { name: "Home".into(), latitude: 37.4, longitude: -122.1, radius: 50, ..Default::default() };
+    let home_id = home.db_insert(&pool).await.unwrap() as i32;
+    geofence::GeofenceMatcher::invalidate_cache();
+
+    let track: Vec<Location> = (0..20).map(|i| Location::new(37.4 + f64::from(i) * 0.001, -122.1)).collect();
+    let simulator = GpxSimulator::from_locations(track, Duration::ZERO);
+
+    // Simulated data of a drive two hours ago stored as a JSONL file
+    let jsonl_path = std::env::temp_dir().join(format!("chipmunk_simulate_{random_http_port}.jsonl"));
+    let mut file = std::fs::File::create(&jsonl_path).unwrap();
+    for data in simulator.vehicle_data(Utc::now() - chrono::Duration::try_hours(2).unwrap()) {
+        writeln!(file, "{}", serde_json::to_string(&data).unwrap()).unwrap();
+    }
+    drop(file);
+
+    // The simulation returns once all data of the source is processed
+    tasks::simulate(&pool, &config, JsonlSource::new(&jsonl_path)).await.unwrap();
+    std::fs::remove_file(&jsonl_path).unwrap();
+    assert_eq!(count(&pool, "cars").await, 1);
+    assert_eq!(count(&pool, "drives").await, 1);
+
+    // Drive again starting now
+    tasks::simulate(&pool, &config, simulator).await.unwrap();
+    assert_eq!(count(&pool, "cars").await, 1);
+    assert_eq!(count(&pool, "drives").await, 2);
+    let in_progress: Vec<bool> = sqlx::query_scalar("SELECT in_progress FROM drives").fetch_all(&pool).await.unwrap();
+    assert_eq!(in_progress, vec![false, false]);
+    let distance: Option<f32> = sqlx::query_scalar("SELECT distance FROM drives ORDER BY id LIMIT 1").fetch_one(&pool).await.unwrap();
+    let distance = distance.unwrap();
+    assert!((distance - 2.1).abs() < 0.1, "{distance}");
+
+    let geofence_ids = || async {
+        let ids: Vec<(Option<i32>, Option<i32>)> = sqlx::query_as("SELECT start_geofence_id, end_geofence_id FROM drives ORDER BY id")
+            .fetch_all(&pool).await.unwrap();
+        ids
+    };
+    assert_eq!(geofence_ids().await, vec![(Some(home_id), None), (Some(home_id), None)]);
+
+    // Geofences added later are assigned to the existing drives
+    let work = Geofence { name: "Work".into(), latitude: 37.419, longitude: -122.1, radius: 50, ..Default::default() };
+    let work_id = work.db_insert(&pool).await.unwrap() as i32;
+    // The logger keeps using the cached geofences until the cache is invalidated
+    geofence::GeofenceMatcher::invalidate_cache();
+    let mut conn = pool.acquire().await.unwrap();
+    assert_eq!(geofence::backfill(&mut conn, &[]).await.unwrap(), 2);
+    assert_eq!(geofence::backfill(&mut conn, &[]).await.unwrap(), 0);
+    assert_eq!(geofence_ids().await, vec![(Some(home_id), Some(work_id)), (Some(home_id), Some(work_id))]);
+
+    // New drives use the added geofence as well
+    let track: Vec<Location> = (0..20).map(|i| Location::new(37.4 + f64::from(i) * 0.001, -122.1)).collect();
+    tasks::simulate(&pool, &config, GpxSimulator::from_locations(track, Duration::ZERO)).await.unwrap();
+    assert_eq!(geofence_ids().await.last(), Some(&(Some(home_id), Some(work_id))));
+}
+
+#[tokio::test]
+async fn record_and_replay() {
+    let random_http_port = rand::thread_rng().gen_range(4000..60000);
+    unsafe { std::env::set_var("HTTP_PORT", random_http_port.to_string()); }
+
+    let _osm_mock = create_mock_osm_server().await;
+    let pool = init_test_database("test_record_and_replay").await;
+    let config = Config::new(&pool).await;
+
+    // Record the simulated data while passing it on
+    let track: Vec<Location> = (0..20).map(|i| Location::new(37.4, -122.1 + f64::from(i) * 0.001)).collect();
+    let recording = std::env::temp_dir().join(format!("chipmunk_record_{random_http_port}.jsonl.zst"));
+    let _ = std::fs::remove_file(&recording);
+    let (data_tx, mut data_rx) = tokio::sync::mpsc::channel(1);
+    let receiver = tokio::spawn(async move {
+        let mut num_data_points = 0;
+        while data_rx.recv().await.is_some() {
+            num_data_points += 1;
+        }
+        num_data_points
+    });
+    Recorder::new(GpxSimulator::from_locations(track, Duration::ZERO), &recording)
+        .run(data_tx, tokio_util::sync::CancellationToken::new())
+        .await
+        .unwrap();
+    assert_eq!(receiver.await.unwrap(), 40);
+
+    // Replaying the recording creates the drive
+    tasks::simulate(&pool, &config, ReplaySource::new(&recording, 0.0)).await.unwrap();
+    std::fs::remove_file(&recording).unwrap();
+    assert_eq!(count(&pool, "drives").await, 1);
+    assert_eq!(count(&pool, "positions").await, 40);
+}
@@ -0,0 +1,92 @@
+#![cfg_attr(rustfmt, rustfmt_skip)]
+
+pub mod common;
+
+use chipmunk::database::tables::{position::Position, state::StateStatus, Tables};
+use chipmunk::task_data_processor::{create_tables, create_tables_from_streaming_data, create_tables_with_streamed};
+use chrono::{DateTime, Duration, Utc};
+use tesla_api::stream::StreamingData;
+use tesla_api::vehicle_data::ShiftState;
+
+use crate::common::{test_data::data_with_shift, utils::ts_no_nanos};
+
+fn streaming_data(timestamp: DateTime<Utc>, shift_state: Option<&str>) -> StreamingData {
+    StreamingData {
+        timestamp: Some(ts_no_nanos(timestamp)),
+        speed: Some(if shift_state == Some("D") { 30.0 } else { 0.0 }),
+        odometer: Some(12345),
+        soc: Some(80.0),
+        est_lat: Some(12.34),
+        est_lng: Some(34.56),
+        power: Some(10.0),
+        shift_state: shift_state.map(|s| s.into()),
+        ..Default::default()
+    }
+}
+
+fn state(tables: &Tables) -> StateStatus {
+    tables.state.as_ref().unwrap().state
+}
+
+#[tokio::test]
+async fn streaming_drive() {
+    let car_id = 1i16;
+
+    let parking_start_time = ts_no_nanos(Utc::now());
+    let t = create_tables(&data_with_shift(parking_start_time, Some(ShiftState::P)), &Tables::default(), car_id).await.unwrap();
+    let mut parked_tables = t[0].clone();
+    parked_tables.position.as_mut().unwrap().id = Some(1);
+
+    // Streaming data is not logged while parked
+    let ts = parking_start_time + Duration::try_seconds(1).unwrap();
+    assert!(create_tables_from_streaming_data(&streaming_data(ts, Some("P")), &parked_tables, car_id).await.is_none());
+    assert!(create_tables_from_streaming_data(&streaming_data(ts, None), &parked_tables, car_id).await.is_none());
+
+    // Shift state from the streaming data starts a drive
+    let drive_start_time = parking_start_time + Duration::try_seconds(2).unwrap();
+    let t = create_tables_from_streaming_data(&streaming_data(drive_start_time, Some("D")), &parked_tables, car_id).await.unwrap();
+    assert_eq!(t.len(), 2);
+    assert_eq!(state(&t[0]), StateStatus::Parked);
+    assert_eq!(state(&t[1]), StateStatus::Driving);
+    assert_eq!(t[1].state.as_ref().unwrap().start_date, drive_start_time);
+    assert!(t[1].drive.as_ref().unwrap().in_progress);
+    let mut drive_tables = t[1].clone();
+    drive_tables.position.as_mut().unwrap().id = Some(2);
+
+    // Continue logging the drive
+    let ts = drive_start_time + Duration::try_milliseconds(500).unwrap();
+    let t = create_tables_from_streaming_data(&streaming_data(ts, Some("D")), &drive_tables, car_id).await.unwrap();
+    assert_eq!(t.len(), 1);
+    assert_eq!(state(&t[0]), StateStatus::Driving);
+    assert!(t[0].drive.is_some());
+    assert_eq!(t[0].position.as_ref().unwrap().date, Some(ts));
+    let mut drive_tables = t[0].clone();
+    drive_tables.position.as_mut().unwrap().id = Some(3);
+
+    // Data points received again after reconnecting are skipped
+    assert!(create_tables_from_streaming_data(&streaming_data(ts, Some("D")), &drive_tables, car_id).await.is_none());
+
+    // Position from the vehicle data is not logged again if it was already streamed
+    let polled_ts = ts + Duration::try_milliseconds(200).unwrap();
+    let t = create_tables_with_streamed(&data_with_shift(polled_ts, Some(ShiftState::D)), &drive_tables, car_id, Some(ts)).await.unwrap();
+    assert_eq!(t.len(), 1);
+    let position: &Position = t[0].position.as_ref().unwrap();
+    assert_eq!(position.id, Some(3));
+    assert_eq!(position.date, Some(ts));
+    assert!(t[0].raw_data.is_some());
+
+    // Position from the vehicle data is logged if the streaming data is older
+    let polled_ts = ts + Duration::try_seconds(5).unwrap();
+    let t = create_tables_with_streamed(&data_with_shift(polled_ts, Some(ShiftState::D)), &drive_tables, car_id, Some(ts)).await.unwrap();
+    assert_eq!(t[0].position.as_ref().unwrap().id, None);
+    assert_eq!(t[0].position.as_ref().unwrap().date, Some(ts_no_nanos(polled_ts)));
+
+    // Shift state from the streaming data ends the drive
+    let drive_end_time = ts + Duration::try_seconds(1).unwrap();
+    let t = create_tables_from_streaming_data(&streaming_data(drive_end_time, Some("P")), &drive_tables, car_id).await.unwrap();
+    assert_eq!(t.len(), 2);
+    assert_eq!(state(&t[0]), StateStatus::Driving);
+    assert!(!t[0].drive.as_ref().unwrap().in_progress);
+    assert_eq!(state(&t[1]), StateStatus::Parked);
+    assert_eq!(t[1].state.as_ref().unwrap().start_date, drive_end_time);
+}
@@ -1,8 +1,6 @@
-use std::collections::HashMap;
-
 use serde::{Deserialize, Serialize};
 
-use crate::{auth_url, TeslaError};
+use crate::{backend::Backend, TeslaError};
 
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct AuthResponse {
@@ -14,17 +12,17 @@ pub struct AuthResponse {
 }
 
 // TODO: https://stateful.com/blog/oauth-refresh-token-best-practices
-pub async fn refresh_access_token(refresh_token: &str) -> Result<AuthResponse, TeslaError> {
-    let mut map = HashMap::new();
-    map.insert("grant_type", "refresh_token");
-    map.insert("client_id", "ownerapi");
-    map.insert("refresh_token", refresh_token);
-    map.insert("scope", "openid email offline_access");
-
+pub async fn refresh_access_token(
+    backend: &dyn Backend,
+    refresh_token: &str,
+) -> Result<AuthResponse, TeslaError> {
     let client = reqwest::ClientBuilder::new()
         .timeout(std::time::Duration::from_secs(10))
         .build()?;
-    let res = client.post(auth_url()).json(&map).send().await?;
+    let res = backend
+        .refresh_token_request(&client, refresh_token)
+        .send()
+        .await?;
 
     if res.status().is_success() {
         let token = res.json::<AuthResponse>().await?;
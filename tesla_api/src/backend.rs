@@ -0,0 +1,266 @@
+use std::collections::HashMap;
+
+use crate::vehicle_data::VehicleDataEndpoints;
+use crate::{auth_url, AUTH_URL, BASE_URL, STREAMING_URL};
+
+const FLEET_AUTH_URL: &str = "https://fleet-auth.prd.vn.cloud.tesla.com/oauth2/v3/token";
+
+/// API used to communicate with Tesla servers
+///
+/// `TeslaClient` uses the backend to build the request URLs and the token refresh request,
+/// this allows switching between the legacy Owner API and the Fleet API.
+pub trait Backend: Send + Sync {
+    /// Name of the backend, used for logging
+    fn name(&self) -> &'static str;
+
+    /// Base URL of the REST API, e.g. `https://owner-api.teslamotors.com/api/1`
+    fn base_url(&self) -> String;
+
+    /// URL of the OAuth token endpoint
+    fn auth_url(&self) -> String;
+
+    /// Create the request to exchange a refresh token for a new set of tokens
+    fn refresh_token_request(
+        &self,
+        client: &reqwest::Client,
+        refresh_token: &str,
+    ) -> reqwest::RequestBuilder;
+
+    /// Sections of the `vehicle_data` request supported by the backend
+    fn vehicle_data_endpoints(&self) -> VehicleDataEndpoints;
+
+    /// URL of the streaming WebSocket server, `None` if the backend does not support streaming
+    fn streaming_url(&self) -> Option<String>;
+}
+
+/// Legacy Owner API used by the Tesla mobile app
+#[derive(Debug, Default, Clone)]
+pub struct OwnerApi;
+
+impl Backend for OwnerApi {
+    fn name(&self) -> &'static str {
+        "Owner API"
+    }
+
+    fn base_url(&self) -> String {
+        BASE_URL.to_string()
+    }
+
+    fn auth_url(&self) -> String {
+        AUTH_URL.to_string()
+    }
+
+    fn refresh_token_request(
+        &self,
+        client: &reqwest::Client,
+        refresh_token: &str,
+    ) -> reqwest::RequestBuilder {
+        let mut map = HashMap::new();
+        map.insert("grant_type", "refresh_token");
+        map.insert("client_id", "ownerapi");
+        map.insert("refresh_token", refresh_token);
+        map.insert("scope", "openid email offline_access");
+
+        client.post(auth_url(self)).json(&map)
+    }
+
+    fn vehicle_data_endpoints(&self) -> VehicleDataEndpoints {
+        VehicleDataEndpoints::all()
+    }
+
+    fn streaming_url(&self) -> Option<String> {
+        Some(STREAMING_URL.to_string())
+    }
+}
+
+/// Region of the Fleet API server, the account has to be accessed through the server of the
+/// region it is registered in
+#[derive(Debug, Clone, PartialEq)]
+pub enum FleetRegion {
+    /// North America and Asia-Pacific (excluding China)
+    NorthAmerica,
+    Europe,
+    China,
+}
+
+impl FleetRegion {
+    pub fn base_url(&self) -> &'static str {
+        match self {
+            FleetRegion::NorthAmerica => "https://fleet-api.prd.na.vn.cloud.tesla.com/api/1",
+            FleetRegion::Europe => "https://fleet-api.prd.eu.vn.cloud.tesla.com/api/1",
+            FleetRegion::China => "https://fleet-api.prd.cn.vn.cloud.tesla.cn/api/1",
+        }
+    }
+}
+
+impl std::str::FromStr for FleetRegion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "na" | "ap" => Ok(FleetRegion::NorthAmerica),
+            "eu" => Ok(FleetRegion::Europe),
+            "cn" => Ok(FleetRegion::China),
+            unknown => Err(format!(
+                "Unknown Fleet API region `{unknown}`, expected one of `na`, `eu` or `cn`"
+            )),
+        }
+    }
+}
+
+/// Fleet API for registered third party applications
+///
+/// Unlike the Owner API, the Fleet API needs the OAuth client id (and optionally the client
+/// secret) of the partner application to refresh the tokens. `vehicle_data` only returns the
+/// location when `location_data` is requested and does not accept `vehicle_data_combo`.
+#[derive(Debug, Clone)]
+pub struct FleetApi {
+    base_url: String,
+    auth_url: String,
+    client_id: String,
+    client_secret: Option<String>,
+}
+
+impl FleetApi {
+    pub fn new(region: FleetRegion, client_id: String, client_secret: Option<String>) -> Self {
+        Self {
+            base_url: region.base_url().to_string(),
+            auth_url: FLEET_AUTH_URL.to_string(),
+            client_id,
+            client_secret,
+        }
+    }
+
+    /// Use a custom API server instead of the regional server
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Use a custom OAuth token endpoint
+    pub fn with_auth_url(mut self, auth_url: &str) -> Self {
+        self.auth_url = auth_url.to_string();
+        self
+    }
+}
+
+impl Backend for FleetApi {
+    fn name(&self) -> &'static str {
+        "Fleet API"
+    }
+
+    fn base_url(&self) -> String {
+        self.base_url.clone()
+    }
+
+    fn auth_url(&self) -> String {
+        self.auth_url.clone()
+    }
+
+    fn refresh_token_request(
+        &self,
+        client: &reqwest::Client,
+        refresh_token: &str,
+    ) -> reqwest::RequestBuilder {
+        let mut params = vec![
+            ("grant_type", "refresh_token"),
+            ("client_id", self.client_id.as_str()),
+            ("refresh_token", refresh_token),
+        ];
+        if let Some(ref secret) = self.client_secret {
+            params.push(("client_secret", secret.as_str()));
+        }
+
+        client.post(auth_url(self)).form(&params)
+    }
+
+    fn vehicle_data_endpoints(&self) -> VehicleDataEndpoints {
+        VehicleDataEndpoints::all() - VehicleDataEndpoints::VEHICLE_DATA_COMBO
+    }
+
+    /// The Fleet API replaces the streaming API with Fleet Telemetry, which needs a server
+    /// reachable by the vehicles
+    fn streaming_url(&self) -> Option<String> {
+        None
+    }
+}
+
+#[tokio::test]
+async fn fleet_api() {
+    use crate::auth::{self, AuthResponse};
+    use crate::vehicle_data::VehicleData;
+    use mockito::Matcher;
+
+    let mut server = mockito::Server::new_async().await;
+    let tokens = AuthResponse {
+        access_token: "new_access_token".into(),
+        refresh_token: "new_refresh_token".into(),
+        ..Default::default()
+    };
+    let auth_mock = server
+        .mock("POST", "/oauth2/v3/token")
+        .match_body(Matcher::AllOf(vec![
+            Matcher::UrlEncoded("grant_type".into(), "refresh_token".into()),
+            Matcher::UrlEncoded("client_id".into(), "client_id".into()),
+            Matcher::UrlEncoded("client_secret".into(), "secret".into()),
+            Matcher::UrlEncoded("refresh_token".into(), "refresh_token".into()),
+        ]))
+        .with_body(serde_json::to_string(&tokens).unwrap())
+        .create_async()
+        .await;
+    let vehicle_data_mock = server
+        .mock("GET", "/api/1/vehicles/1/vehicle_data")
+        .match_query(Matcher::UrlEncoded(
+            "endpoints".into(),
+            FleetApi::new(FleetRegion::Europe, "".into(), None)
+                .vehicle_data_endpoints()
+                .query_value(),
+        ))
+        .match_header("authorization", "Bearer new_access_token")
+        .with_body(r#"{"response":{"id":1}}"#)
+        .create_async()
+        .await;
+    // `vehicle_data_combo` is not supported by the Fleet API and dropped from the request
+    let charge_state_mock = server
+        .mock("GET", "/api/1/vehicles/1/vehicle_data")
+        .match_query(Matcher::UrlEncoded(
+            "endpoints".into(),
+            "charge_state".into(),
+        ))
+        .with_body(r#"{"response":{"id":1,"charge_state":{"battery_level":42,"charging_state":"Charging"}}}"#)
+        .create_async()
+        .await;
+
+    let backend = FleetApi::new(
+        FleetRegion::Europe,
+        "client_id".into(),
+        Some("secret".into()),
+    )
+    .with_base_url(&format!("{}/api/1", server.url()))
+    .with_auth_url(&format!("{}/oauth2/v3/token", server.url()));
+    let backend = std::sync::Arc::new(backend);
+
+    let tokens = auth::refresh_access_token(backend.as_ref(), "refresh_token")
+        .await
+        .unwrap();
+    assert_eq!(tokens.refresh_token, "new_refresh_token");
+
+    let mut client = crate::get_tesla_client_with_backend(tokens, None, backend).unwrap();
+    let data = crate::get_vehicle_data(&mut client, 1).await.unwrap();
+    assert_eq!(data, r#"{"id":1}"#);
+
+    let data = crate::get_vehicle_data_with_endpoints(
+        &mut client,
+        1,
+        VehicleDataEndpoints::CHARGE_STATE | VehicleDataEndpoints::VEHICLE_DATA_COMBO,
+    )
+    .await
+    .unwrap();
+    let data = VehicleData::from_response_json(&data).unwrap();
+    assert_eq!(data.charge_state.unwrap().battery_level, Some(42));
+    assert!(data.drive_state.is_none());
+
+    auth_mock.assert_async().await;
+    vehicle_data_mock.assert_async().await;
+    charge_state_mock.assert_async().await;
+}
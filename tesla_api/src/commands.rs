@@ -0,0 +1,383 @@
+use std::time::{Duration, Instant};
+
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+
+use crate::vehicle_data::{OnlineState, Vehicles};
+use crate::{
+    auth, get_base_url, get_vehicles, rate_limiter, ApiResponse, TeslaClient, TeslaError,
+    TeslaResponseCode, NUM_RETRY,
+};
+
+/// Interval between the online state checks while waiting for the vehicle to wake up
+const WAKE_UP_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Response returned by the vehicle command endpoints
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct CommandResponse {
+    pub result: bool,
+    #[serde(default)]
+    pub reason: String,
+}
+
+/// Commands that can be sent to a vehicle using the `/vehicles/{id}/command/{command}` endpoint
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    DoorLock,
+    DoorUnlock,
+    ClimateStart,
+    ClimateStop,
+    /// Temperatures are in Celsius
+    SetTemps {
+        driver_temp: f32,
+        passenger_temp: f32,
+    },
+    ChargeStart,
+    ChargeStop,
+    /// Charge limit as a percentage of the battery capacity
+    SetChargeLimit {
+        percent: u8,
+    },
+    SetChargingAmps {
+        charging_amps: u8,
+    },
+    HonkHorn,
+    FlashLights,
+}
+
+impl Command {
+    /// Name of the command endpoint
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::DoorLock => "door_lock",
+            Command::DoorUnlock => "door_unlock",
+            Command::ClimateStart => "auto_conditioning_start",
+            Command::ClimateStop => "auto_conditioning_stop",
+            Command::SetTemps { .. } => "set_temps",
+            Command::ChargeStart => "charge_start",
+            Command::ChargeStop => "charge_stop",
+            Command::SetChargeLimit { .. } => "set_charge_limit",
+            Command::SetChargingAmps { .. } => "set_charging_amps",
+            Command::HonkHorn => "honk_horn",
+            Command::FlashLights => "flash_lights",
+        }
+    }
+
+    /// JSON body to send along with the command
+    pub fn body(&self) -> serde_json::Value {
+        match self {
+            Command::SetTemps {
+                driver_temp,
+                passenger_temp,
+            } => serde_json::json!({
+                "driver_temp": driver_temp,
+                "passenger_temp": passenger_temp,
+            }),
+            Command::SetChargeLimit { percent } => serde_json::json!({ "percent": percent }),
+            Command::SetChargingAmps { charging_amps } => {
+                serde_json::json!({ "charging_amps": charging_amps })
+            }
+            _ => serde_json::json!({}),
+        }
+    }
+}
+
+impl TeslaClient {
+    /// Wake up the vehicle
+    ///
+    /// The vehicle takes a few seconds to come online after this request returns, check the `state`
+    /// field of the returned value to find out whether the vehicle is online yet.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - value of `get_vehicles().id` field and not the `vehicle_id` field
+    pub async fn wake_up(&mut self, id: u64) -> Result<Vehicles, TeslaError> {
+        let mut retry_count = NUM_RETRY;
+        loop {
+            match self.wake_up_local(id).await {
+                Ok(v) => return Ok(v),
+                Err(TeslaError::Retry(e)) => {
+                    log::warn!("{e}, retry #{}", NUM_RETRY - retry_count + 1);
+                    if retry_count > 0 {
+                        retry_count -= 1;
+                    } else {
+                        return Err(TeslaError::Retry(e));
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    async fn wake_up_local(&mut self, id: u64) -> Result<Vehicles, TeslaError> {
+        log::debug!("Waking up vehicle {id}");
+        rate_limiter::acquire(&self.rate_limiter).await;
+        let res = self
+            .client
+            .post(format!(
+                "{}/vehicles/{id}/wake_up",
+                get_base_url(self.backend.as_ref())
+            ))
+            .send()
+            .await?;
+        log::debug!("Received response: {res:?}");
+        read_response_json!(res, Vehicles, self)
+    }
+
+    /// Get the online state of the vehicle from the `/products` response
+    ///
+    /// Unlike `get_vehicle_data`, this does not wake up the vehicle or keep it awake.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - value of `get_vehicles().id` field and not the `vehicle_id` field
+    pub async fn online_state(&mut self, id: u64) -> Result<OnlineState, TeslaError> {
+        get_vehicles(self)
+            .await?
+            .iter()
+            .find(|v| v.id == Some(id))
+            .map(|v| v.online_state())
+            .ok_or_else(|| TeslaError::InvalidResponse(format!("Vehicle {id} not found")))
+    }
+
+    /// Wait until the vehicle is online
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - value of `get_vehicles().id` field and not the `vehicle_id` field
+    /// * `wake_up` - send a wake up request if the vehicle is not online, otherwise wait for the
+    ///   vehicle to wake up on its own
+    /// * `timeout` - maximum time to wait
+    ///
+    /// # Returns
+    ///
+    /// Returns `TeslaError::NotOnline` if the vehicle did not come online within `timeout`.
+    pub async fn wait_until_online(
+        &mut self,
+        id: u64,
+        wake_up: bool,
+        timeout: Duration,
+    ) -> Result<(), TeslaError> {
+        let start = Instant::now();
+        let mut wake_up_sent = false;
+        loop {
+            let state = self.online_state(id).await?;
+            if state == OnlineState::Online {
+                return Ok(());
+            }
+            if wake_up && !wake_up_sent {
+                self.wake_up(id).await?;
+                wake_up_sent = true;
+            }
+            if start.elapsed() + WAKE_UP_POLL_INTERVAL > timeout {
+                log::debug!(
+                    "Vehicle {id} did not come online within {timeout:?}, state: {state:?}"
+                );
+                return Err(TeslaError::NotOnline);
+            }
+            tokio::time::sleep(WAKE_UP_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Send a command to the vehicle
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - value of `get_vehicles().id` field and not the `vehicle_id` field
+    /// * `command` - command to send
+    ///
+    /// # Returns
+    ///
+    /// Returns the response from the vehicle if the command succeeded and
+    /// `TeslaError::CommandFailed` with the reason if the vehicle rejected the command.
+    pub async fn send_command(
+        &mut self,
+        id: u64,
+        command: Command,
+    ) -> Result<CommandResponse, TeslaError> {
+        let mut retry_count = NUM_RETRY;
+        loop {
+            match self.send_command_local(id, command).await {
+                Ok(resp) if resp.result => return Ok(resp),
+                Ok(resp) => {
+                    return Err(TeslaError::CommandFailed(format!(
+                        "{}: {}",
+                        command.name(),
+                        resp.reason
+                    )));
+                }
+                Err(TeslaError::Retry(e)) => {
+                    log::warn!("{e}, retry #{}", NUM_RETRY - retry_count + 1);
+                    if retry_count > 0 {
+                        retry_count -= 1;
+                    } else {
+                        return Err(TeslaError::Retry(e));
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    async fn send_command_local(
+        &mut self,
+        id: u64,
+        command: Command,
+    ) -> Result<CommandResponse, TeslaError> {
+        log::debug!("Sending command `{}` to vehicle {id}", command.name());
+        rate_limiter::acquire(&self.rate_limiter).await;
+        let res = self
+            .client
+            .post(format!(
+                "{}/vehicles/{id}/command/{}",
+                get_base_url(self.backend.as_ref()),
+                command.name()
+            ))
+            .json(&command.body())
+            .send()
+            .await?;
+        log::debug!("Received response: {res:?}");
+        read_response_json!(res, CommandResponse, self)
+    }
+
+    pub async fn door_lock(&mut self, id: u64) -> Result<CommandResponse, TeslaError> {
+        self.send_command(id, Command::DoorLock).await
+    }
+
+    pub async fn door_unlock(&mut self, id: u64) -> Result<CommandResponse, TeslaError> {
+        self.send_command(id, Command::DoorUnlock).await
+    }
+
+    pub async fn climate_start(&mut self, id: u64) -> Result<CommandResponse, TeslaError> {
+        self.send_command(id, Command::ClimateStart).await
+    }
+
+    pub async fn climate_stop(&mut self, id: u64) -> Result<CommandResponse, TeslaError> {
+        self.send_command(id, Command::ClimateStop).await
+    }
+
+    /// Set the driver and passenger temperatures in Celsius
+    pub async fn set_temps(
+        &mut self,
+        id: u64,
+        driver_temp: f32,
+        passenger_temp: f32,
+    ) -> Result<CommandResponse, TeslaError> {
+        self.send_command(
+            id,
+            Command::SetTemps {
+                driver_temp,
+                passenger_temp,
+            },
+        )
+        .await
+    }
+
+    pub async fn charge_start(&mut self, id: u64) -> Result<CommandResponse, TeslaError> {
+        self.send_command(id, Command::ChargeStart).await
+    }
+
+    pub async fn charge_stop(&mut self, id: u64) -> Result<CommandResponse, TeslaError> {
+        self.send_command(id, Command::ChargeStop).await
+    }
+
+    /// Set the charge limit as a percentage of the battery capacity
+    pub async fn set_charge_limit(
+        &mut self,
+        id: u64,
+        percent: u8,
+    ) -> Result<CommandResponse, TeslaError> {
+        self.send_command(id, Command::SetChargeLimit { percent })
+            .await
+    }
+
+    pub async fn set_charging_amps(
+        &mut self,
+        id: u64,
+        charging_amps: u8,
+    ) -> Result<CommandResponse, TeslaError> {
+        self.send_command(id, Command::SetChargingAmps { charging_amps })
+            .await
+    }
+
+    pub async fn honk_horn(&mut self, id: u64) -> Result<CommandResponse, TeslaError> {
+        self.send_command(id, Command::HonkHorn).await
+    }
+
+    pub async fn flash_lights(&mut self, id: u64) -> Result<CommandResponse, TeslaError> {
+        self.send_command(id, Command::FlashLights).await
+    }
+}
+
+#[test]
+fn command_body() {
+    assert_eq!(Command::HonkHorn.body(), serde_json::json!({}));
+    assert_eq!(
+        Command::SetChargeLimit { percent: 80 }.body(),
+        serde_json::json!({"percent": 80})
+    );
+    assert_eq!(
+        Command::SetTemps {
+            driver_temp: 21.5,
+            passenger_temp: 20.0
+        }
+        .body(),
+        serde_json::json!({"driver_temp": 21.5, "passenger_temp": 20.0})
+    );
+
+    let resp: CommandResponse = serde_json::from_str(
+        r#"{"result":false,"reason":"cabin comfort remote settings not enabled"}"#,
+    )
+    .unwrap();
+    assert!(!resp.result);
+}
+
+#[tokio::test]
+async fn wait_until_online() {
+    use crate::auth::AuthResponse;
+    use crate::backend::{FleetApi, FleetRegion};
+
+    let mut server = mockito::Server::new_async().await;
+    let products_mock = server
+        .mock("GET", "/api/1/products")
+        .with_body(
+            r#"{"response":[{"id":1,"vehicle_id":11,"state":"asleep"},
+            {"id":2,"vehicle_id":12,"state":"online"}]}"#,
+        )
+        .expect(4)
+        .create_async()
+        .await;
+    let wake_up_mock = server
+        .mock("POST", "/api/1/vehicles/1/wake_up")
+        .with_body(r#"{"response":{"id":1,"vehicle_id":11,"state":"asleep"}}"#)
+        .expect(1)
+        .create_async()
+        .await;
+
+    let backend = FleetApi::new(FleetRegion::Europe, "client_id".into(), None)
+        .with_base_url(&format!("{}/api/1", server.url()));
+    let mut client = crate::get_tesla_client_with_backend(
+        AuthResponse::default(),
+        None,
+        std::sync::Arc::new(backend),
+    )
+    .unwrap();
+
+    assert_eq!(client.online_state(1).await.unwrap(), OnlineState::Asleep);
+    assert!(client
+        .wait_until_online(2, false, Duration::ZERO)
+        .await
+        .is_ok());
+    // Vehicle does not wake up on its own, checking the state does not send a wake up request
+    assert!(matches!(
+        client.wait_until_online(1, false, Duration::ZERO).await,
+        Err(TeslaError::NotOnline)
+    ));
+    assert!(matches!(
+        client.wait_until_online(1, true, Duration::ZERO).await,
+        Err(TeslaError::NotOnline)
+    ));
+
+    products_mock.assert_async().await;
+    wake_up_mock.assert_async().await;
+}
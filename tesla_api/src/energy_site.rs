@@ -0,0 +1,321 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth, get_base_url, rate_limiter, ApiResponse, TeslaClient, TeslaError, TeslaResponseCode,
+    NUM_RETRY,
+};
+
+/// Energy product (Powerwall or solar) in the `/products` response
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct EnergySite {
+    /// ID used by the energy site endpoints
+    pub energy_site_id: u64,
+    /// `battery` for Powerwall, `solar` for solar only sites
+    pub resource_type: Option<String>,
+    pub site_name: Option<String>,
+    pub id: Option<String>,
+    pub gateway_id: Option<String>,
+    pub asset_site_id: Option<String>,
+    pub energy_left: Option<f64>,
+    pub total_pack_energy: Option<f64>,
+    pub percentage_charged: Option<f64>,
+    pub battery_type: Option<String>,
+    pub backup_capable: Option<bool>,
+    pub battery_power: Option<f64>,
+    pub storm_mode_enabled: Option<bool>,
+}
+
+/// Current power flow of an energy site, power values are in watts and energy values in
+/// watt-hours
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct LiveStatus {
+    pub solar_power: Option<f64>,
+    pub energy_left: Option<f64>,
+    pub total_pack_energy: Option<f64>,
+    /// Powerwall state of charge in percent
+    pub percentage_charged: Option<f64>,
+    pub backup_capable: Option<bool>,
+    /// Positive when the Powerwall is discharging
+    pub battery_power: Option<f64>,
+    /// Power used by the home
+    pub load_power: Option<f64>,
+    pub grid_status: Option<String>,
+    pub grid_services_active: Option<bool>,
+    /// Positive when importing from the grid
+    pub grid_power: Option<f64>,
+    pub grid_services_power: Option<f64>,
+    pub generator_power: Option<f64>,
+    pub island_status: Option<String>,
+    pub storm_mode_active: Option<bool>,
+    pub timestamp: Option<DateTime<Utc>>,
+}
+
+/// Type of the data returned by the `calendar_history` endpoint
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HistoryKind {
+    /// Energy totals in watt-hours
+    Energy,
+    /// Average power in watts
+    Power,
+}
+
+impl HistoryKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HistoryKind::Energy => "energy",
+            HistoryKind::Power => "power",
+        }
+    }
+}
+
+/// Time span covered by the `calendar_history` response, ending at `end_date`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HistoryPeriod {
+    Day,
+    Week,
+    Month,
+    Year,
+    Lifetime,
+}
+
+impl HistoryPeriod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HistoryPeriod::Day => "day",
+            HistoryPeriod::Week => "week",
+            HistoryPeriod::Month => "month",
+            HistoryPeriod::Year => "year",
+            HistoryPeriod::Lifetime => "lifetime",
+        }
+    }
+}
+
+/// One interval of the `calendar_history` response
+///
+/// Energy requests fill the `*_energy_*` fields and power requests fill the `*_power` fields.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub timestamp: Option<DateTime<Utc>>,
+    pub solar_energy_exported: Option<f64>,
+    pub grid_energy_imported: Option<f64>,
+    pub grid_energy_exported_from_solar: Option<f64>,
+    pub grid_energy_exported_from_battery: Option<f64>,
+    pub battery_energy_exported: Option<f64>,
+    pub battery_energy_imported_from_grid: Option<f64>,
+    pub battery_energy_imported_from_solar: Option<f64>,
+    pub consumer_energy_imported_from_grid: Option<f64>,
+    pub consumer_energy_imported_from_solar: Option<f64>,
+    pub consumer_energy_imported_from_battery: Option<f64>,
+    pub solar_power: Option<f64>,
+    pub battery_power: Option<f64>,
+    pub grid_power: Option<f64>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct CalendarHistory {
+    pub serial_number: Option<String>,
+    pub period: Option<String>,
+    pub installation_time_zone: Option<String>,
+    #[serde(default)]
+    pub time_series: Vec<HistoryEntry>,
+}
+
+impl TeslaClient {
+    /// Get the current solar production, home usage, grid power and Powerwall charge
+    ///
+    /// # Arguments
+    ///
+    /// * `site_id` - value of the `energy_site_id` field of the energy site
+    pub async fn energy_site_live_status(
+        &mut self,
+        site_id: u64,
+    ) -> Result<LiveStatus, TeslaError> {
+        let mut retry_count = NUM_RETRY;
+        loop {
+            match self.energy_site_live_status_local(site_id).await {
+                Ok(v) => return Ok(v),
+                Err(TeslaError::Retry(e)) => {
+                    log::warn!("{e}, retry #{}", NUM_RETRY - retry_count + 1);
+                    if retry_count > 0 {
+                        retry_count -= 1;
+                    } else {
+                        return Err(TeslaError::Retry(e));
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    async fn energy_site_live_status_local(
+        &mut self,
+        site_id: u64,
+    ) -> Result<LiveStatus, TeslaError> {
+        log::debug!("Getting live status of energy site {site_id}");
+        rate_limiter::acquire(&self.rate_limiter).await;
+        let res = self
+            .client
+            .get(format!(
+                "{}/energy_sites/{site_id}/live_status",
+                get_base_url(self.backend.as_ref())
+            ))
+            .send()
+            .await?;
+        log::debug!("Received response: {res:?}");
+        read_response_json!(res, LiveStatus, self)
+    }
+
+    /// Get the energy or power history of an energy site
+    ///
+    /// # Arguments
+    ///
+    /// * `site_id` - value of the `energy_site_id` field of the energy site
+    /// * `kind` - whether to get the energy totals or the average power of each interval
+    /// * `period` - time span of the history
+    /// * `end_date` - end of the time span
+    /// * `time_zone` - IANA time zone used to split the history into days, e.g. `Europe/Berlin`
+    pub async fn energy_site_calendar_history(
+        &mut self,
+        site_id: u64,
+        kind: HistoryKind,
+        period: HistoryPeriod,
+        end_date: DateTime<Utc>,
+        time_zone: &str,
+    ) -> Result<CalendarHistory, TeslaError> {
+        let mut retry_count = NUM_RETRY;
+        loop {
+            match self
+                .energy_site_calendar_history_local(site_id, kind, period, end_date, time_zone)
+                .await
+            {
+                Ok(v) => return Ok(v),
+                Err(TeslaError::Retry(e)) => {
+                    log::warn!("{e}, retry #{}", NUM_RETRY - retry_count + 1);
+                    if retry_count > 0 {
+                        retry_count -= 1;
+                    } else {
+                        return Err(TeslaError::Retry(e));
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    async fn energy_site_calendar_history_local(
+        &mut self,
+        site_id: u64,
+        kind: HistoryKind,
+        period: HistoryPeriod,
+        end_date: DateTime<Utc>,
+        time_zone: &str,
+    ) -> Result<CalendarHistory, TeslaError> {
+        log::debug!("Getting calendar history of energy site {site_id}");
+        rate_limiter::acquire(&self.rate_limiter).await;
+        let res = self
+            .client
+            .get(format!(
+                "{}/energy_sites/{site_id}/calendar_history",
+                get_base_url(self.backend.as_ref())
+            ))
+            .query(&[
+                ("kind", kind.as_str()),
+                ("period", period.as_str()),
+                (
+                    "end_date",
+                    end_date.to_rfc3339_opts(SecondsFormat::Secs, true).as_str(),
+                ),
+                ("time_zone", time_zone),
+            ])
+            .send()
+            .await?;
+        log::debug!("Received response: {res:?}");
+        read_response_json!(res, CalendarHistory, self)
+    }
+}
+
+#[tokio::test]
+async fn energy_site() {
+    use crate::auth::AuthResponse;
+    use crate::backend::{FleetApi, FleetRegion};
+    use mockito::Matcher;
+
+    let mut server = mockito::Server::new_async().await;
+    let live_status_mock = server
+        .mock("GET", "/api/1/energy_sites/42/live_status")
+        .with_body(
+            r#"{"response":{"solar_power":3500,"percentage_charged":87.5,"battery_power":-1200,
+            "load_power":1800,"grid_power":-500,"grid_status":"Active",
+            "timestamp":"2024-06-01T12:00:00+02:00","wall_connectors":[]}}"#,
+        )
+        .create_async()
+        .await;
+    let history_mock = server
+        .mock("GET", "/api/1/energy_sites/42/calendar_history")
+        .match_query(Matcher::AllOf(vec![
+            Matcher::UrlEncoded("kind".into(), "energy".into()),
+            Matcher::UrlEncoded("period".into(), "day".into()),
+            Matcher::UrlEncoded("end_date".into(), "2024-06-01T23:59:59Z".into()),
+            Matcher::UrlEncoded("time_zone".into(), "Europe/Berlin".into()),
+        ]))
+        .with_body(
+            r#"{"response":{"serial_number":"1234","period":"day","time_series":[
+            {"timestamp":"2024-06-01T00:00:00+02:00","solar_energy_exported":25000,
+            "consumer_energy_imported_from_solar":8000}]}}"#,
+        )
+        .create_async()
+        .await;
+
+    let backend = FleetApi::new(FleetRegion::Europe, "client_id".into(), None)
+        .with_base_url(&format!("{}/api/1", server.url()));
+    let mut client = crate::get_tesla_client_with_backend(
+        AuthResponse::default(),
+        None,
+        std::sync::Arc::new(backend),
+    )
+    .unwrap();
+
+    let status = client.energy_site_live_status(42).await.unwrap();
+    assert_eq!(status.solar_power, Some(3500.0));
+    assert_eq!(status.percentage_charged, Some(87.5));
+    assert_eq!(
+        status.timestamp,
+        Some("2024-06-01T10:00:00Z".parse::<DateTime<Utc>>().unwrap())
+    );
+
+    let end_date = "2024-06-01T23:59:59Z".parse::<DateTime<Utc>>().unwrap();
+    let history = client
+        .energy_site_calendar_history(
+            42,
+            HistoryKind::Energy,
+            HistoryPeriod::Day,
+            end_date,
+            "Europe/Berlin",
+        )
+        .await
+        .unwrap();
+    assert_eq!(history.time_series.len(), 1);
+    assert_eq!(history.time_series[0].solar_energy_exported, Some(25000.0));
+
+    live_status_mock.assert_async().await;
+    history_mock.assert_async().await;
+}
+
+#[test]
+fn products() {
+    use crate::Product;
+
+    let products: Vec<Product> = serde_json::from_str(
+        r#"[
+            {"id":1,"vehicle_id":2,"vin":"EWABCD123UWE23456","state":"online"},
+            {"energy_site_id":42,"resource_type":"battery","site_name":"Home","percentage_charged":55}
+        ]"#,
+    )
+    .unwrap();
+    assert!(matches!(&products[0], Product::Vehicle(v) if v.vehicle_id == Some(2)));
+    assert!(
+        matches!(&products[1], Product::EnergySite(s) if s.energy_site_id == 42 && s.percentage_charged == Some(55.0))
+    );
+}
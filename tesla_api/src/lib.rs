@@ -1,15 +1,23 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
 use auth::AuthResponse;
+use backend::{Backend, OwnerApi};
 use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
 
 pub mod auth;
+pub mod backend;
+pub mod rate_limiter;
 pub mod response_codes;
 pub mod stream;
 pub mod utils;
 pub mod vehicle_data;
 
+use energy_site::EnergySite;
+use rate_limiter::RateLimiter;
 pub use response_codes::TeslaResponseCode;
-use vehicle_data::Vehicles;
+use vehicle_data::{VehicleDataEndpoints, Vehicles};
 
 const BASE_URL: &str = "https://owner-api.teslamotors.com/api/1";
 const AUTH_URL: &str = "https://auth.tesla.com/oauth2/v3/token";
@@ -17,20 +25,41 @@ const STREAMING_URL: &str = "wss://streaming.vn.teslamotors.com/streaming/";
 
 const NUM_RETRY: u8 = 2;
 
-type ErrorHandlerType = Box<dyn FnMut() + Send + Sync>;
+/// Called with the new tokens after the client refreshes an expired access token
+type ErrorHandlerType = Box<dyn FnMut(&AuthResponse) + Send + Sync>;
 
 pub struct TeslaClient {
     client: reqwest::Client,
     tokens: AuthResponse,
     handle_token_expiry: Option<ErrorHandlerType>,
+    backend: Arc<dyn Backend>,
+    rate_limiter: Arc<Mutex<RateLimiter>>,
+}
+
+impl TeslaClient {
+    /// Rate limiter used by the client, can be shared with other clients using the same account
+    pub fn rate_limiter(&self) -> Arc<Mutex<RateLimiter>> {
+        self.rate_limiter.clone()
+    }
+
+    pub fn set_rate_limiter(&mut self, rate_limiter: Arc<Mutex<RateLimiter>>) {
+        self.rate_limiter = rate_limiter;
+    }
+
+    /// Use new tokens for the subsequent requests, e.g. after the tokens are refreshed elsewhere
+    pub fn set_tokens(&mut self, tokens: AuthResponse) -> Result<(), TeslaError> {
+        self.client = build_http_client(&tokens.access_token)?;
+        self.tokens = tokens;
+        Ok(())
+    }
 }
 
-fn get_base_url() -> String {
-    std::env::var("MOCK_TESLA_BASE_URL").unwrap_or_else(|_| BASE_URL.to_string())
+fn get_base_url(backend: &dyn Backend) -> String {
+    std::env::var("MOCK_TESLA_BASE_URL").unwrap_or_else(|_| backend.base_url())
 }
 
-pub fn auth_url() -> String {
-    std::env::var("MOCK_TESLA_BASE_URL").unwrap_or_else(|_| AUTH_URL.to_string())
+pub fn auth_url(backend: &dyn Backend) -> String {
+    std::env::var("MOCK_TESLA_BASE_URL").unwrap_or_else(|_| backend.auth_url())
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -52,7 +81,7 @@ pub enum TeslaError {
     #[error("Url parse error `{0}`")]
     ParseError(url::ParseError),
     #[error("WebSocket error `{0}`")]
-    WebSocketError(tungstenite::Error),
+    WebSocketError(Box<tungstenite::Error>),
     #[error("Access token expired, {0}")]
     TokenExpired(String),
     #[error("Error decoding json, {0}")]
@@ -61,6 +90,10 @@ pub enum TeslaError {
     TestInProgress,
     #[error("{0}, retry")]
     Retry(String),
+    #[error("Vehicle command failed, {0}")]
+    CommandFailed(String),
+    #[error("Rate limited by Tesla server, retry after {retry_after:?}")]
+    RateLimited { retry_after: Duration },
 }
 
 impl From<url::ParseError> for TeslaError {
@@ -71,7 +104,7 @@ impl From<url::ParseError> for TeslaError {
 
 impl From<tungstenite::Error> for TeslaError {
     fn from(e: tungstenite::Error) -> TeslaError {
-        TeslaError::WebSocketError(e)
+        TeslaError::WebSocketError(Box::new(e))
     }
 }
 
@@ -120,6 +153,7 @@ macro_rules! read_response_json {
     ($response:expr, $generic:ty, $tesla:expr) => {
         match $response.status() {
             StatusCode::OK => {
+                rate_limiter::success(&$tesla.rate_limiter);
                 let text = $response.text().await?;
                 if text == "chipmunk_test_in_progress" {
                     return Err(TeslaError::TestInProgress);
@@ -138,20 +172,35 @@ macro_rules! read_response_json {
 macro_rules! parse_error {
     ($response:expr, $tesla:expr) => {{
         match $response.status() {
+            // Back off on rate limit and server errors, `retry_after` is the time until the next request is allowed
+            status_code if status_code == StatusCode::TOO_MANY_REQUESTS || status_code.is_server_error() => {
+                let retry_after = rate_limiter::backoff(
+                    &$tesla.rate_limiter,
+                    rate_limiter::parse_retry_after($response.headers()),
+                );
+                log::warn!("Received `{status_code}`, backing off for {retry_after:?}");
+                if status_code == StatusCode::TOO_MANY_REQUESTS {
+                    Err(TeslaError::RateLimited { retry_after })
+                } else if let Ok(response_code) = TeslaResponseCode::from_http_status(status_code) {
+                    Err(TeslaError::ApiError(response_code))
+                } else {
+                    Err(TeslaError::Request(status_code))
+                }
+            }
             // Check if the status code is a custom Tesla response code
             status_code => if let Ok(response_code) = TeslaResponseCode::from_http_status(status_code)
             {
                 match response_code {
                     TeslaResponseCode::UNAUTHORIZED => {
+                        log::info!("Access token expired, refreshing..");
+                        let tokens = auth::refresh_access_token($tesla.backend.as_ref(), &$tesla.tokens.refresh_token).await?;
+                        $tesla.set_tokens(tokens.clone())?;
+
                         if let Some(ref mut t) = $tesla.handle_token_expiry {
-                            t();
+                            t(&tokens);
                         } else {
                             log::error!("Callback is None");
                         }
-
-                        log::info!("Access token expired, refreshing..");
-                        let tokens = auth::refresh_access_token(&$tesla.tokens.refresh_token).await?;
-                        $tesla.client = get_tesla_client(tokens, None)?.client;
                         return Err(TeslaError::Retry("Access token refreshed".into()));
                     }
                     TeslaResponseCode::DEVICE_NOT_AVAILABLE => Err(TeslaError::NotOnline), // Vehicle is not online
@@ -168,12 +217,13 @@ macro_rules! parse_error {
     }};
 }
 
-pub fn get_tesla_client(
-    tokens: AuthResponse,
-    handle_token_expiry: Option<ErrorHandlerType>,
-) -> Result<TeslaClient, TeslaError> {
+// Declared after the macros above so that the module can use them
+pub mod commands;
+pub mod energy_site;
+
+fn build_http_client(access_token: &str) -> Result<reqwest::Client, TeslaError> {
     let mut headers = reqwest::header::HeaderMap::new();
-    let key = format!("Bearer {}", tokens.access_token);
+    let key = format!("Bearer {access_token}");
     let mut auth_value = match reqwest::header::HeaderValue::from_str(&key) {
         Ok(value) => value,
         Err(e) => return Err(TeslaError::InvalidHeader(e)),
@@ -181,21 +231,69 @@ pub fn get_tesla_client(
     auth_value.set_sensitive(true);
     headers.insert(reqwest::header::AUTHORIZATION, auth_value);
 
-    let client = reqwest::Client::builder()
+    Ok(reqwest::Client::builder()
         .default_headers(headers)
-        .build()?;
+        .build()?)
+}
+
+pub fn get_tesla_client(
+    tokens: AuthResponse,
+    handle_token_expiry: Option<ErrorHandlerType>,
+) -> Result<TeslaClient, TeslaError> {
+    get_tesla_client_with_backend(tokens, handle_token_expiry, Arc::new(OwnerApi))
+}
+
+pub fn get_tesla_client_with_backend(
+    tokens: AuthResponse,
+    handle_token_expiry: Option<ErrorHandlerType>,
+    backend: Arc<dyn Backend>,
+) -> Result<TeslaClient, TeslaError> {
+    let client = build_http_client(&tokens.access_token)?;
+
+    // Don't space out the requests sent to the mock server used by the tests
+    let rate_limiter = if std::env::var("MOCK_TESLA_BASE_URL").is_ok() {
+        RateLimiter::new(u32::MAX, Duration::ZERO)
+    } else {
+        RateLimiter::default()
+    };
 
     Ok(TeslaClient {
         client,
         tokens,
         handle_token_expiry,
+        backend,
+        rate_limiter: Arc::new(Mutex::new(rate_limiter)),
     })
 }
 
+/// Product in the Tesla account
+///
+/// Energy sites are identified by the `energy_site_id` field, everything else is parsed as a
+/// vehicle.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Product {
+    EnergySite(EnergySite),
+    Vehicle(Vehicles),
+}
+
+/// Get the vehicles in the account, energy products are skipped
 pub async fn get_vehicles(tesla: &mut TeslaClient) -> Result<Vec<Vehicles>, TeslaError> {
+    Ok(get_products(tesla)
+        .await?
+        .into_iter()
+        .filter_map(|p| match p {
+            Product::Vehicle(v) => Some(v),
+            Product::EnergySite(_) => None,
+        })
+        .collect())
+}
+
+/// Get the vehicles and energy sites (Powerwall, solar) in the account
+pub async fn get_products(tesla: &mut TeslaClient) -> Result<Vec<Product>, TeslaError> {
     let mut retry_count = NUM_RETRY;
     loop {
-        match get_vehicles_local(tesla).await {
+        match get_products_local(tesla).await {
             Ok(v) => return Ok(v),
             Err(e) => match e {
                 TeslaError::Retry(e) => {
@@ -213,21 +311,56 @@ pub async fn get_vehicles(tesla: &mut TeslaClient) -> Result<Vec<Vehicles>, Tesl
     }
 }
 
-async fn get_vehicles_local(tesla: &mut TeslaClient) -> Result<Vec<Vehicles>, TeslaError> {
-    log::debug!("Getting list of vehicles");
+async fn get_products_local(tesla: &mut TeslaClient) -> Result<Vec<Product>, TeslaError> {
+    log::debug!("Getting list of products");
+    rate_limiter::acquire(&tesla.rate_limiter).await;
     let res = tesla
         .client
-        .get(format!("{}/products", get_base_url()))
+        .get(format!("{}/products", get_base_url(tesla.backend.as_ref())))
         .send()
         .await?;
     log::debug!("Received response: {:?}", res);
-    read_response_json!(res, Vec<Vehicles>, tesla)
+    read_response_json!(res, Vec<Product>, tesla)
 }
 
 pub async fn get_vehicle_data(tesla: &mut TeslaClient, id: u64) -> Result<String, TeslaError> {
+    let endpoints = tesla.backend.vehicle_data_endpoints();
+    Ok(get_vehicle_data_retry(tesla, id, endpoints)
+        .await?
+        .to_string())
+}
+
+/// Get the vehicle data, only requesting the given sections
+///
+/// Sections not supported by the backend are dropped from the request. Requesting fewer sections
+/// reduces the size of the response, e.g. only `CHARGE_STATE` while the vehicle is charging.
+/// Skipping `LOCATION_DATA` avoids the errors returned for accounts without location permission.
+///
+/// # Arguments
+///
+/// * `id` - value of `get_vehicles().id` field and not the `vehicle_id` field
+/// * `endpoints` - sections to request, the other sections are missing from the response
+///
+/// Returns the response as received, parse it with `VehicleData::from_response_json`.
+pub async fn get_vehicle_data_with_endpoints(
+    tesla: &mut TeslaClient,
+    id: u64,
+    endpoints: VehicleDataEndpoints,
+) -> Result<String, TeslaError> {
+    let endpoints = endpoints & tesla.backend.vehicle_data_endpoints();
+    Ok(get_vehicle_data_retry(tesla, id, endpoints)
+        .await?
+        .to_string())
+}
+
+async fn get_vehicle_data_retry(
+    tesla: &mut TeslaClient,
+    id: u64,
+    endpoints: VehicleDataEndpoints,
+) -> Result<serde_json::Value, TeslaError> {
     let mut retry_count = NUM_RETRY;
     loop {
-        match get_vehicle_data_local(tesla, id).await {
+        match get_vehicle_data_local(tesla, id, endpoints).await {
             Ok(v) => return Ok(v),
             Err(e) => match e {
                 TeslaError::Retry(e) => {
@@ -248,17 +381,25 @@ pub async fn get_vehicle_data(tesla: &mut TeslaClient, id: u64) -> Result<String
 /*
  * id: value of `get_vehicles().id` field and not the `vehicle_id` field
 */
-async fn get_vehicle_data_local(tesla: &mut TeslaClient, id: u64) -> Result<String, TeslaError> {
+async fn get_vehicle_data_local(
+    tesla: &mut TeslaClient,
+    id: u64,
+    endpoints: VehicleDataEndpoints,
+) -> Result<serde_json::Value, TeslaError> {
     log::debug!("Getting vehicle data");
+    rate_limiter::acquire(&tesla.rate_limiter).await;
     let res = tesla
         .client
-        .get(format!("{}/vehicles/{id}/vehicle_data", get_base_url()))
-        .query(&[("endpoints", "charge_state;climate_state;closures_state;drive_state;gui_settings;location_data;vehicle_config;vehicle_state;vehicle_data_combo")])
+        .get(format!(
+            "{}/vehicles/{id}/vehicle_data",
+            get_base_url(tesla.backend.as_ref())
+        ))
+        .query(&[("endpoints", endpoints.query_value())])
         .send()
         .await?;
 
     log::debug!("Received response: {:?}", res);
-    Ok(read_response_json!(res, serde_json::Value, tesla)?.to_string())
+    read_response_json!(res, serde_json::Value, tesla)
 }
 
 pub struct Vehicle;
@@ -0,0 +1,194 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(10 * 60);
+
+/// Token bucket used to space out the requests sent to the Tesla servers
+///
+/// Each request takes a token from the bucket and the bucket is refilled with one token every
+/// `refill_interval`, so up to `capacity` requests can be sent back to back before the requests
+/// are spaced out. When the server responds with 429 or 5xx, all requests are blocked for an
+/// exponentially increasing duration (or the duration requested by the server using the
+/// `Retry-After` header, whichever is longer).
+#[derive(Debug)]
+pub struct RateLimiter {
+    capacity: f64,
+    /// Number of available tokens, a negative value means that tokens are reserved by requests
+    /// waiting for the bucket to refill
+    tokens: f64,
+    refill_interval: Duration,
+    last_refill: Instant,
+    blocked_until: Option<Instant>,
+    backoff: Duration,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new(5, Duration::from_secs(1))
+    }
+}
+
+impl RateLimiter {
+    pub fn new(capacity: u32, refill_interval: Duration) -> Self {
+        let capacity = capacity.max(1) as f64;
+        Self {
+            capacity,
+            tokens: capacity,
+            refill_interval,
+            last_refill: Instant::now(),
+            blocked_until: None,
+            backoff: INITIAL_BACKOFF,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        let new_tokens = if self.refill_interval.is_zero() {
+            self.capacity
+        } else {
+            elapsed.as_secs_f64() / self.refill_interval.as_secs_f64()
+        };
+        self.tokens = (self.tokens + new_tokens).min(self.capacity);
+        self.last_refill = now;
+    }
+
+    /// Take a token from the bucket and return how long to wait before sending the request
+    fn reserve(&mut self, now: Instant) -> Duration {
+        self.refill(now);
+        self.tokens -= 1.0;
+
+        let bucket_delay = if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            self.refill_interval.mul_f64(-self.tokens)
+        };
+        let blocked_delay = self
+            .blocked_until
+            .map(|t| t.saturating_duration_since(now))
+            .unwrap_or_default();
+
+        bucket_delay.max(blocked_delay)
+    }
+
+    /// Block all requests after receiving a 429 or 5xx response
+    ///
+    /// # Arguments
+    ///
+    /// * `retry_after` - value of the `Retry-After` header if the server sent one
+    /// * `now` - current time
+    ///
+    /// # Returns
+    ///
+    /// Returns the duration for which the requests are blocked
+    fn backoff(&mut self, retry_after: Option<Duration>, now: Instant) -> Duration {
+        let delay = retry_after.unwrap_or_default().max(self.backoff);
+        self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+        self.blocked_until = Some(now + delay);
+        delay
+    }
+
+    /// Reset the backoff duration after a successful request
+    fn reset_backoff(&mut self) {
+        self.backoff = INITIAL_BACKOFF;
+    }
+}
+
+/// Wait until the rate limiter allows sending the next request
+pub(crate) async fn acquire(limiter: &Mutex<RateLimiter>) {
+    let delay = match limiter.lock() {
+        Ok(mut l) => l.reserve(Instant::now()),
+        Err(e) => {
+            log::error!("Error getting lock on rate limiter: {e}");
+            Duration::ZERO
+        }
+    };
+
+    if !delay.is_zero() {
+        log::debug!("Rate limiting, waiting {delay:?} before sending the request");
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// Notify the rate limiter about a 429 or 5xx response, returns the backoff duration
+pub(crate) fn backoff(limiter: &Mutex<RateLimiter>, retry_after: Option<Duration>) -> Duration {
+    match limiter.lock() {
+        Ok(mut l) => l.backoff(retry_after, Instant::now()),
+        Err(e) => {
+            log::error!("Error getting lock on rate limiter: {e}");
+            retry_after.unwrap_or(INITIAL_BACKOFF)
+        }
+    }
+}
+
+/// Notify the rate limiter about a successful request
+pub(crate) fn success(limiter: &Mutex<RateLimiter>) {
+    match limiter.lock() {
+        Ok(mut l) => l.reset_backoff(),
+        Err(e) => log::error!("Error getting lock on rate limiter: {e}"),
+    }
+}
+
+/// Parse the `Retry-After` header, the value is either the number of seconds to wait or an HTTP
+/// date
+pub(crate) fn parse_retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    (date.with_timezone(&chrono::Utc) - chrono::Utc::now())
+        .to_std()
+        .ok()
+}
+
+#[test]
+fn token_bucket() {
+    let start = Instant::now();
+    let mut limiter = RateLimiter::new(2, Duration::from_secs(1));
+    limiter.last_refill = start;
+
+    // Burst of `capacity` requests are allowed, the rest are spaced out
+    assert_eq!(limiter.reserve(start), Duration::ZERO);
+    assert_eq!(limiter.reserve(start), Duration::ZERO);
+    assert_eq!(limiter.reserve(start), Duration::from_secs(1));
+    assert_eq!(limiter.reserve(start), Duration::from_secs(2));
+
+    // Bucket refills over time
+    let later = start + Duration::from_secs(10);
+    assert_eq!(limiter.reserve(later), Duration::ZERO);
+
+    // Exponential backoff, Retry-After is honored if it is longer
+    assert_eq!(limiter.backoff(None, later), Duration::from_secs(1));
+    assert_eq!(limiter.backoff(None, later), Duration::from_secs(2));
+    assert_eq!(
+        limiter.backoff(Some(Duration::from_secs(30)), later),
+        Duration::from_secs(30)
+    );
+    assert_eq!(limiter.reserve(later), Duration::from_secs(30));
+    assert_eq!(
+        limiter.reserve(later + Duration::from_secs(40)),
+        Duration::ZERO
+    );
+
+    limiter.reset_backoff();
+    assert_eq!(limiter.backoff(None, later), Duration::from_secs(1));
+}
+
+#[test]
+fn retry_after_header() {
+    let mut headers = HeaderMap::new();
+    assert_eq!(parse_retry_after(&headers), None);
+
+    headers.insert(RETRY_AFTER, "120".parse().unwrap());
+    assert_eq!(parse_retry_after(&headers), Some(Duration::from_secs(120)));
+
+    let date = (chrono::Utc::now() + chrono::Duration::try_seconds(60).unwrap()).to_rfc2822();
+    headers.insert(RETRY_AFTER, date.parse().unwrap());
+    let retry_after = parse_retry_after(&headers).unwrap();
+    assert!(retry_after > Duration::from_secs(55) && retry_after <= Duration::from_secs(60));
+}
@@ -1,12 +1,51 @@
+use std::sync::Arc;
+use std::time::Duration;
+
 use chrono::{DateTime, Utc};
+use futures_util::{SinkExt, StreamExt};
+use rand::Rng;
 use serde::{Deserialize, Serialize};
+use tokio::net::TcpStream;
 use tokio::sync::mpsc;
-use tungstenite::{connect, stream::MaybeTlsStream, Message, WebSocket};
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+use tungstenite::Message;
 use url::Url;
 
-use crate::{utils::timestamp_to_datetime, TeslaError, STREAMING_URL};
+use crate::{
+    auth::{self, AuthResponse},
+    backend::Backend,
+    utils::timestamp_to_datetime,
+    TeslaError,
+};
+
+const INITIAL_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(5 * 60);
+/// Reconnect if nothing is received from the server for this long
+const READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Fields subscribed to when no field list is configured
+pub const DEFAULT_FIELDS: [&str; 12] = [
+    "speed",
+    "odometer",
+    "soc",
+    "elevation",
+    "est_heading",
+    "est_lat",
+    "est_lng",
+    "power",
+    "shift_state",
+    "range",
+    "est_range",
+    "heading",
+];
 
-#[derive(Debug, Default, Clone)]
+pub fn default_fields() -> Vec<String> {
+    DEFAULT_FIELDS.iter().map(|f| f.to_string()).collect()
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 #[allow(dead_code)]
 pub struct StreamingData {
     pub timestamp: Option<DateTime<Utc>>,
@@ -25,42 +64,63 @@ pub struct StreamingData {
 }
 
 impl StreamingData {
-    fn from(csv: Option<String>) -> anyhow::Result<Self> {
-        if csv.is_none() {
+    /// Parse a `data:update` message, the first column is the timestamp followed by the values of
+    /// the subscribed `fields` in the same order
+    ///
+    /// Values of unknown fields are ignored and the missing values are set to `None`.
+    fn from(csv: Option<String>, fields: &[String]) -> anyhow::Result<Self> {
+        let Some(csv) = csv else {
             log::warn!("Invalid streaming data");
             anyhow::bail!("Invalid streaming data");
-        }
+        };
 
-        let csv = csv.expect("Invalid streamig data");
-        let parts: Vec<&str> = csv.split(',').collect();
-
-        if parts.len() != 13 {
-            log::debug!("{:?}", parts);
-            anyhow::bail!("Expected 13 datafields, received {}", parts.len());
-        }
-
-        let streaming_data = StreamingData {
-            timestamp: timestamp_to_datetime(parts[0].parse::<u64>().ok()),
-            speed: parts[1].parse::<f32>().ok(),
-            odometer: parts[2].parse::<u32>().ok(),
-            soc: parts[3].parse::<f32>().ok(),
-            elevation: parts[4].parse::<f32>().ok(),
-            est_heading: parts[5].parse::<f32>().ok(),
-            est_lat: parts[6].parse::<f64>().ok(),
-            est_lng: parts[7].parse::<f64>().ok(),
-            power: parts[8].parse::<f32>().ok(),
-            shift_state: if parts[9].is_empty() {
-                None
-            } else {
-                Some(parts[9].to_string())
-            },
-            range: parts[10].parse::<i32>().ok(),
-            est_range: parts[11].parse::<i32>().ok(),
-            heading: parts[12].parse::<f32>().ok(),
+        let mut parts = csv.split(',');
+        let mut streaming_data = StreamingData {
+            timestamp: timestamp_to_datetime(parts.next().and_then(|t| t.parse::<u64>().ok())),
+            ..Default::default()
         };
 
+        let values: Vec<&str> = parts.collect();
+        if values.len() != fields.len() {
+            log::debug!(
+                "Expected {} datafields, received {}: {values:?}",
+                fields.len(),
+                values.len()
+            );
+        }
+
+        for (field, value) in fields.iter().zip(values) {
+            streaming_data.set(field, value);
+        }
+
         Ok(streaming_data)
     }
+
+    fn set(&mut self, field: &str, value: &str) {
+        match field {
+            "speed" => self.speed = value.parse().ok(),
+            "odometer" => self.odometer = value.parse().ok(),
+            "soc" => self.soc = value.parse().ok(),
+            "elevation" => self.elevation = value.parse().ok(),
+            "est_heading" => self.est_heading = value.parse().ok(),
+            "est_lat" => self.est_lat = value.parse().ok(),
+            "est_lng" => self.est_lng = value.parse().ok(),
+            "power" => self.power = value.parse().ok(),
+            "shift_state" => {
+                self.shift_state = if value.is_empty() {
+                    None
+                } else {
+                    Some(value.to_string())
+                }
+            }
+            "range" => self.range = value.parse().ok(),
+            "est_range" => self.est_range = value.parse().ok(),
+            "heading" => self.heading = value.parse().ok(),
+            unknown => {
+                log::trace!("Ignoring value `{value}` of unknown streaming field `{unknown}`")
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -105,10 +165,10 @@ fn parse_client_error(msg: Option<String>) -> StreamError {
 
 #[allow(dead_code)]
 impl WebSocketResponse {
-    fn parse(&self) -> MessageType {
+    fn parse(&self, fields: &[String]) -> MessageType {
         match self.msg_type.as_str() {
             "control:hello" => MessageType::Start,
-            "data:update" => match StreamingData::from(self.value.clone()) {
+            "data:update" => match StreamingData::from(self.value.clone(), fields) {
                 Ok(v) => MessageType::Data(Some(v)),
                 Err(e) => {
                     log::error!("{e}");
@@ -139,103 +199,406 @@ impl WebSocketResponse {
     }
 }
 
-/*
- * vehicle_id: value of `get_vehicles().vehicle_id` field and not the `id` field
- */
+/// Events emitted by the streaming client
+#[derive(Debug, Clone)]
+pub enum StreamEvent {
+    /// Subscribed to the vehicle data stream
+    Hello,
+    Data(StreamingData),
+    /// Vehicle is offline or asleep, the client reconnects after a delay
+    VehicleDisconnected,
+    /// No data received from the vehicle for a while, the client reconnects after a delay
+    Timeout,
+    /// Access token was rejected by the server, contains the refreshed tokens if the refresh
+    /// succeeded. The refresh token is rotated on each refresh, store it to avoid losing access.
+    TokenExpired(Option<AuthResponse>),
+}
+
+/// Exponential backoff with jitter for reconnecting to the streaming server
+struct Backoff {
+    delay: Duration,
+}
+
+impl Backoff {
+    fn new() -> Self {
+        Self {
+            delay: INITIAL_RECONNECT_DELAY,
+        }
+    }
+
+    /// Returns a random delay between half and all of the current delay and doubles the delay
+    fn next(&mut self) -> Duration {
+        let jitter = rand::thread_rng().gen_range(0.5..=1.0);
+        let delay = self.delay.mul_f64(jitter);
+        self.delay = (self.delay * 2).min(MAX_RECONNECT_DELAY);
+        delay
+    }
+
+    fn reset(&mut self) {
+        self.delay = INITIAL_RECONNECT_DELAY;
+    }
+}
+
+fn streaming_url(backend: &dyn Backend) -> Option<String> {
+    std::env::var("MOCK_TESLA_STREAMING_URL")
+        .ok()
+        .or_else(|| backend.streaming_url())
+}
+
+fn subscribe_message(access_token: &str, vehicle_id: u64, fields: &[String]) -> String {
+    serde_json::json!({
+        "msg_type": "data:subscribe_oauth",
+        "token": access_token,
+        "value": fields.join(","),
+        "tag": vehicle_id.to_string(),
+    })
+    .to_string()
+}
+
+/// Send an event to the receiver, returns false if the receiver is dropped
+async fn send_event(event_tx: &mpsc::Sender<StreamEvent>, event: StreamEvent) -> bool {
+    match event_tx.send(event).await {
+        Ok(_) => true,
+        Err(e) => {
+            log::warn!("Error sending streaming event over mpsc: {e}");
+            false
+        }
+    }
+}
+
+/// Stream vehicle data, reconnects to the server with a jittered backoff when the connection is
+/// closed and refreshes the access token when it expires
+///
+/// # Arguments
+///
+/// * `backend` - backend used to get the streaming URL and to refresh the tokens
+/// * `tokens` - tokens used to subscribe to the stream
+/// * `vehicle_id` - value of `get_vehicles().vehicle_id` field and not the `id` field
+/// * `fields` - fields to subscribe to, `DEFAULT_FIELDS` are used if the list is empty
+/// * `event_tx` - channel to send the streaming events to, streaming stops when the receiver is
+///   dropped
+/// * `cancellation_token` - cancel to stop streaming
 pub async fn start(
-    access_token: &str,
+    backend: Arc<dyn Backend>,
+    mut tokens: AuthResponse,
     vehicle_id: u64,
-    data_tx: mpsc::Sender<StreamingData>,
+    fields: Vec<String>,
+    event_tx: mpsc::Sender<StreamEvent>,
     cancellation_token: tokio_util::sync::CancellationToken,
 ) -> Result<(), TeslaError> {
-    let create_websocket =
-        || -> Result<WebSocket<MaybeTlsStream<std::net::TcpStream>>, TeslaError> {
-            let url = Url::parse(STREAMING_URL)?;
-            let (socket, _response) = match connect(url) {
-                Ok(r) => r,
-                Err(e) => {
-                    log::error!("Error connecting to Tesla streaming websocket: {e}");
-                    return Err(TeslaError::WebSocketError(e));
-                }
-            };
-            Ok(socket)
-        };
+    let fields = if fields.is_empty() {
+        default_fields()
+    } else {
+        fields
+    };
 
-    let init_streaming =
-        |socket: &mut WebSocket<MaybeTlsStream<std::net::TcpStream>>| -> Result<(), TeslaError> {
-            let subscrib_message_oauth = format!(
-                "{{
-                    \"msg_type\": \"data:subscribe_oauth\",
-                    \"token\": \"{access_token}\",
-                    \"value\": \"speed,odometer,soc,elevation,est_heading,est_lat,est_lng,power,shift_state,range,est_range,heading\",
-                    \"tag\": \"{vehicle_id}\"
-                }}"
-            );
+    let Some(url) = streaming_url(backend.as_ref()) else {
+        log::warn!("{} does not support streaming", backend.name());
+        cancellation_token.cancelled().await;
+        return Ok(());
+    };
+    let url = Url::parse(&url)?;
 
-            if let Err(e) = socket.send(Message::Text(subscrib_message_oauth)) {
-                log::error!("{e}");
-                return Err(TeslaError::WebSocketError(e));
+    let mut backoff = Backoff::new();
+    loop {
+        tokio::select! {
+            _ = cancellation_token.cancelled() => return Ok(()),
+            result = stream(&url, backend.as_ref(), &mut tokens, vehicle_id, &fields, &event_tx, &mut backoff) => {
+                if let Err(e) = result {
+                    log::error!("Streaming error: {e}");
+                }
             }
+        }
 
-            Ok(())
-        };
-
-    let mut socket = create_websocket()?;
-    init_streaming(&mut socket)?;
+        if event_tx.is_closed() {
+            log::warn!("Streaming event receiver dropped, stopping streaming");
+            return Ok(());
+        }
 
-    loop {
-        if cancellation_token.is_cancelled() {
-            break;
+        let delay = backoff.next();
+        log::info!("Reconnecting to streaming server in {delay:?}");
+        tokio::select! {
+            _ = cancellation_token.cancelled() => return Ok(()),
+            _ = tokio::time::sleep(delay) => (),
         }
+    }
+}
 
-        let msg: WebSocketResponse = match socket.read() {
-            Ok(v) => {
-                if v.is_close() {
-                    log::warn!("WebSocket is closing");
-                    socket.close(None)?;
-                    socket = create_websocket()?;
-                    continue;
-                }
-                serde_json::from_str(v.to_text()?)?
+/// Connect to the streaming server and forward the events until the connection needs to be
+/// re-established
+async fn stream(
+    url: &Url,
+    backend: &dyn Backend,
+    tokens: &mut AuthResponse,
+    vehicle_id: u64,
+    fields: &[String],
+    event_tx: &mpsc::Sender<StreamEvent>,
+    backoff: &mut Backoff,
+) -> Result<(), TeslaError> {
+    let (mut socket, _response): (Socket, _) = connect_async(url.as_str()).await?;
+    socket
+        .send(Message::Text(subscribe_message(
+            &tokens.access_token,
+            vehicle_id,
+            fields,
+        )))
+        .await?;
+
+    loop {
+        let msg = match tokio::time::timeout(READ_TIMEOUT, socket.next()).await {
+            Ok(Some(msg)) => msg?,
+            Ok(None) => {
+                log::warn!("WebSocket closed");
+                return Ok(());
+            }
+            Err(_) => {
+                log::warn!("No message received from streaming server for {READ_TIMEOUT:?}");
+                send_event(event_tx, StreamEvent::Timeout).await;
+                return Ok(());
             }
-            Err(e) => {
-                log::error!("{e}");
-                socket.close(None)?;
-                socket = create_websocket()?;
-                continue;
+        };
+
+        let msg: WebSocketResponse = match msg {
+            Message::Text(text) => serde_json::from_str(&text)?,
+            Message::Binary(data) => serde_json::from_slice(&data)?,
+            Message::Close(frame) => {
+                log::warn!("WebSocket is closing: {frame:?}");
+                return Ok(());
             }
+            Message::Ping(_) | Message::Pong(_) | Message::Frame(_) => continue,
         };
 
-        match msg.parse() {
-            MessageType::Start => log::info!("TODO: handle Streaming started"),
+        match msg.parse(fields) {
+            MessageType::Start => {
+                log::info!("Streaming started");
+                if !send_event(event_tx, StreamEvent::Hello).await {
+                    return Ok(());
+                }
+            }
             MessageType::Data(data) => {
                 if let Some(d) = data {
                     log::debug!("{d:?}");
-                    if let Err(e) = data_tx.send(d).await {
-                        log::error!("Error sending streaming data over mpsc: {e}");
-                    };
+                    // The server also says hello to a sleeping car and then disconnects, the
+                    // connection only works once the car sends data
+                    backoff.reset();
+                    if !send_event(event_tx, StreamEvent::Data(d)).await {
+                        return Ok(());
+                    }
                 }
             }
-            MessageType::Error(e) => match e {
-                StreamError::UnknownError(e) => log::warn!("TODO: handle error {e}"),
-                StreamError::OwnerApiError(e) => log::warn!("TODO: handle error {e}"),
-                StreamError::TokensExpired(e) => {
-                    log::info!("Tokens expired: {e}");
-                    return Err(TeslaError::TokenExpired(e));
+            MessageType::Error(e) => {
+                match e {
+                    StreamError::UnknownError(e) => log::warn!("Streaming error: {e}"),
+                    StreamError::OwnerApiError(e) => log::warn!("Owner API error: {e}"),
+                    StreamError::TokensExpired(e) => {
+                        log::info!("Tokens expired: {e}, refreshing..");
+                        let new_tokens = match auth::refresh_access_token(
+                            backend,
+                            &tokens.refresh_token,
+                        )
+                        .await
+                        {
+                            Ok(t) => {
+                                *tokens = t.clone();
+                                Some(t)
+                            }
+                            Err(e) => {
+                                log::error!("Error refreshing access token: {e}");
+                                None
+                            }
+                        };
+                        send_event(event_tx, StreamEvent::TokenExpired(new_tokens)).await;
+                    }
                 }
-            },
+                return Ok(());
+            }
             MessageType::VehicleDisconnected => {
-                log::info!("Vehicle disconnected, trying to re-connect");
-                init_streaming(&mut socket)?;
-                continue;
+                log::info!("Vehicle disconnected");
+                send_event(event_tx, StreamEvent::VehicleDisconnected).await;
+                return Ok(());
+            }
+            MessageType::VehicleError => {
+                log::warn!("Vehicle error");
+                return Ok(());
             }
-            MessageType::VehicleError => log::warn!("TODO: handle vehicle error"),
-            MessageType::Timeout => log::warn!("TODO: handle Streaming timeout"),
-            MessageType::Unknown(msg) => {
-                log::warn!("TODO: handle Unknown message from WebSocket: {msg}");
+            MessageType::Timeout => {
+                log::info!("Streaming timeout");
+                send_event(event_tx, StreamEvent::Timeout).await;
+                return Ok(());
             }
+            MessageType::Unknown(msg) => log::warn!("Unknown message from WebSocket: {msg}"),
+        };
+    }
+}
+
+#[tokio::test]
+async fn reconnect_and_refresh_tokens() {
+    use tokio::net::TcpListener;
+
+    struct TestBackend {
+        auth_url: String,
+        streaming_url: String,
+    }
+
+    impl Backend for TestBackend {
+        fn name(&self) -> &'static str {
+            "Test API"
+        }
+
+        fn base_url(&self) -> String {
+            "http://localhost".into()
+        }
+
+        fn auth_url(&self) -> String {
+            self.auth_url.clone()
+        }
+
+        fn refresh_token_request(
+            &self,
+            client: &reqwest::Client,
+            refresh_token: &str,
+        ) -> reqwest::RequestBuilder {
+            client
+                .post(self.auth_url())
+                .json(&serde_json::json!({ "refresh_token": refresh_token }))
+        }
+
+        fn vehicle_data_endpoints(&self) -> crate::vehicle_data::VehicleDataEndpoints {
+            crate::vehicle_data::VehicleDataEndpoints::all()
+        }
+
+        fn streaming_url(&self) -> Option<String> {
+            Some(self.streaming_url.clone())
+        }
+    }
+
+    let new_tokens = AuthResponse {
+        access_token: "new_access_token".into(),
+        refresh_token: "new_refresh_token".into(),
+        ..Default::default()
+    };
+    let mut auth_server = mockito::Server::new_async().await;
+    let auth_mock = auth_server
+        .mock("POST", "/")
+        .match_body(mockito::Matcher::Json(
+            serde_json::json!({ "refresh_token": "refresh_token" }),
+        ))
+        .with_body(serde_json::to_string(&new_tokens).unwrap())
+        .create_async()
+        .await;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let message = |msg_type: &str, error_type: Option<&str>, value: Option<&str>| {
+            Message::Binary(
+                serde_json::json!({
+                    "msg_type": msg_type,
+                    "tag": "1234",
+                    "error_type": error_type,
+                    "value": value,
+                })
+                .to_string()
+                .into_bytes(),
+            )
         };
-        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+
+        let mut tokens = vec![];
+        // First connection streams some data and rejects the token
+        for msg in [
+            vec![
+                message("control:hello", None, None),
+                message(
+                    "data:update",
+                    None,
+                    Some("1700000000000,55,1000,80,100,90,37.1,-122.1,10,D,200,180,90"),
+                ),
+                message(
+                    "data:error",
+                    Some("client_error"),
+                    Some("Can't validate token: expired"),
+                ),
+            ],
+            vec![message("control:hello", None, None)],
+        ] {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut socket = tokio_tungstenite::accept_async(stream).await.unwrap();
+            let subscribe = socket.next().await.unwrap().unwrap();
+            let subscribe: serde_json::Value =
+                serde_json::from_str(subscribe.to_text().unwrap()).unwrap();
+            tokens.push(subscribe["token"].as_str().unwrap().to_string());
+            for m in msg {
+                socket.send(m).await.unwrap();
+            }
+        }
+        tokens
+    });
+
+    let backend = Arc::new(TestBackend {
+        auth_url: auth_server.url(),
+        streaming_url: format!("ws://{addr}"),
+    });
+    let tokens = AuthResponse {
+        access_token: "access_token".into(),
+        refresh_token: "refresh_token".into(),
+        ..Default::default()
+    };
+    let (event_tx, mut event_rx) = mpsc::channel(10);
+    let cancellation_token = tokio_util::sync::CancellationToken::new();
+    let client = tokio::spawn(start(
+        backend,
+        tokens,
+        1234,
+        vec![],
+        event_tx,
+        cancellation_token.clone(),
+    ));
+
+    assert!(matches!(event_rx.recv().await, Some(StreamEvent::Hello)));
+    match event_rx.recv().await {
+        Some(StreamEvent::Data(data)) => {
+            assert_eq!(data.speed, Some(55.0));
+            assert_eq!(data.shift_state, Some("D".into()));
+        }
+        other => panic!("Expected streaming data, received {other:?}"),
+    }
+    match event_rx.recv().await {
+        Some(StreamEvent::TokenExpired(Some(t))) => {
+            assert_eq!(t.refresh_token, "new_refresh_token")
+        }
+        other => panic!("Expected refreshed tokens, received {other:?}"),
     }
-    Ok(())
+    // Reconnects using the new access token
+    assert!(matches!(event_rx.recv().await, Some(StreamEvent::Hello)));
+
+    cancellation_token.cancel();
+    client.await.unwrap().unwrap();
+    assert_eq!(
+        server.await.unwrap(),
+        vec!["access_token".to_string(), "new_access_token".to_string()]
+    );
+    auth_mock.assert_async().await;
+}
+
+#[test]
+fn streaming_fields() {
+    let fields: Vec<String> = ["shift_state", "unknown_field", "speed", "power"]
+        .iter()
+        .map(|f| f.to_string())
+        .collect();
+
+    let data = StreamingData::from(Some("1700000000000,R,1,25,-10".into()), &fields).unwrap();
+    assert!(data.timestamp.is_some());
+    assert_eq!(data.shift_state, Some("R".into()));
+    assert_eq!(data.speed, Some(25.0));
+    assert_eq!(data.power, Some(-10.0));
+    assert_eq!(data.odometer, None);
+
+    // Missing columns and empty values are set to None
+    let data = StreamingData::from(Some("1700000000000,,1".into()), &fields).unwrap();
+    assert_eq!(data.shift_state, None);
+    assert_eq!(data.speed, None);
+    assert_eq!(data.power, None);
+
+    assert!(StreamingData::from(None, &fields).is_err());
 }
@@ -43,14 +43,16 @@ fn from_charging_state_str<'de, T>(deserializer: T) -> Result<Option<ChargingSta
 where
     T: Deserializer<'de>,
 {
-    let s: &str = match Deserialize::deserialize(deserializer) {
-        Ok(s) => s,
+    // Owned to also deserialize from a `serde_json::Value`, which cannot lend a `&str`
+    let s: String = match Deserialize::deserialize(deserializer) {
+        Ok(Some(s)) => s,
+        Ok(None) => return Ok(None),
         Err(e) => {
             log::warn!("Error deserializing charging state: {}", e);
             return Ok(None);
         }
     };
-    let state = match s {
+    let state = match s.as_str() {
         "Complete" => ChargingState::Complete,
         "Charging" => ChargingState::Charging,
         "NoPower" => ChargingState::NoPower,
@@ -463,6 +465,37 @@ impl VehicleData {
     }
 }
 
+bitflags::bitflags! {
+    /// Sections of the vehicle data to request from the `vehicle_data` endpoint
+    ///
+    /// Sections that are not requested are `None` in the returned `VehicleData`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct VehicleDataEndpoints: u16 {
+        const CHARGE_STATE = 1 << 0;
+        const CLIMATE_STATE = 1 << 1;
+        const CLOSURES_STATE = 1 << 2;
+        const DRIVE_STATE = 1 << 3;
+        const GUI_SETTINGS = 1 << 4;
+        /// Needed to get the location in `drive_state` on newer firmware versions, the request
+        /// fails if the account does not have the location permission
+        const LOCATION_DATA = 1 << 5;
+        const VEHICLE_CONFIG = 1 << 6;
+        const VEHICLE_STATE = 1 << 7;
+        /// Owner API only, returns the data even if some sections are unavailable
+        const VEHICLE_DATA_COMBO = 1 << 8;
+    }
+}
+
+impl VehicleDataEndpoints {
+    /// Value of the `endpoints` query parameter, e.g. `charge_state;drive_state`
+    pub fn query_value(&self) -> String {
+        self.iter_names()
+            .map(|(name, _)| name.to_lowercase())
+            .collect::<Vec<_>>()
+            .join(";")
+    }
+}
+
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct Vehicles {
     pub id: Option<u64>,
@@ -480,3 +513,56 @@ pub struct Vehicles {
     pub backseat_token: Option<String>,
     pub backseat_token_updated_at: Option<i32>,
 }
+
+/// Value of the `state` field of a vehicle in the `/products` response
+#[derive(Debug, Clone, PartialEq)]
+pub enum OnlineState {
+    Online,
+    Asleep,
+    Offline,
+    Unknown(String),
+}
+
+impl Vehicles {
+    /// Online state of the vehicle, reading it does not wake up the vehicle
+    pub fn online_state(&self) -> OnlineState {
+        match self.state.as_deref().unwrap_or_default() {
+            "online" => OnlineState::Online,
+            "asleep" => OnlineState::Asleep,
+            "offline" => OnlineState::Offline,
+            unknown => OnlineState::Unknown(unknown.to_string()),
+        }
+    }
+}
+
+#[test]
+fn vehicle_data_endpoints() {
+    assert_eq!(VehicleDataEndpoints::empty().query_value(), "");
+    assert_eq!(
+        (VehicleDataEndpoints::CHARGE_STATE | VehicleDataEndpoints::DRIVE_STATE).query_value(),
+        "charge_state;drive_state"
+    );
+    assert_eq!(
+        VehicleDataEndpoints::all().query_value(),
+        "charge_state;climate_state;closures_state;drive_state;gui_settings;location_data;vehicle_config;vehicle_state;vehicle_data_combo"
+    );
+
+    // Sections that are not requested are missing from the response
+    let data = VehicleData::from_response_json(
+        r#"{"id":1,"charge_state":{"battery_level":80,"charging_state":"Charging"}}"#,
+    )
+    .unwrap();
+    assert_eq!(data.charge_state.unwrap().battery_level, Some(80));
+    assert!(data.drive_state.is_none());
+}
+
+#[test]
+fn charging_state_from_value() {
+    let json = serde_json::json!({"charging_state": "Charging", "battery_level": 50});
+    let charge_state: ChargeState = serde_json::from_value(json).unwrap();
+    assert_eq!(charge_state.charging_state, Some(ChargingState::Charging));
+
+    let json = serde_json::json!({"charging_state": null});
+    let charge_state: ChargeState = serde_json::from_value(json).unwrap();
+    assert_eq!(charge_state.charging_state, None);
+}
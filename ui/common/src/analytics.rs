@@ -0,0 +1,28 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use macros::Json;
+
+/// Usable battery capacity estimated from the energy added by a charging process
+#[derive(Debug, Serialize, Deserialize, Default, Clone, PartialEq)]
+pub struct BatteryCapacity {
+    pub charging_process_id: i32,
+    /// End of the charging process
+    pub date: DateTime<Utc>,
+    pub start_battery_level: i16,
+    pub end_battery_level: i16,
+    /// Energy added in kWh
+    pub charge_energy_added: f32,
+    /// Estimated usable capacity in kWh
+    pub capacity_kwh: f32,
+}
+
+/// Efficiency and battery capacity of a car derived from its charging processes
+#[derive(Debug, Serialize, Deserialize, Default, Clone, PartialEq, Json)]
+pub struct CapacityHistory {
+    pub car_id: i16,
+    /// Energy in kWh per km of the preferred range, same as in TeslaMate
+    pub efficiency: Option<f32>,
+    /// Capacity estimates, oldest first
+    pub capacity: Vec<BatteryCapacity>,
+}
@@ -0,0 +1,63 @@
+use chrono::{NaiveDate, NaiveTime};
+use serde::{Deserialize, Serialize};
+
+use macros::Json;
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BillingType {
+    #[default]
+    PerKwh,
+    PerMinute,
+}
+
+impl BillingType {
+    pub fn to_str(&self) -> &'static str {
+        match self {
+            BillingType::PerKwh => "per kWh",
+            BillingType::PerMinute => "per minute",
+        }
+    }
+}
+
+/// A circular area, drives and charging processes starting or ending inside it are assigned to
+/// the geofence
+#[derive(Debug, Serialize, Deserialize, Default, Clone, PartialEq, Json)]
+pub struct Geofence {
+    /// Database ID, 0 for a geofence which is not created yet
+    pub id: i32,
+    pub name: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    /// Radius in meters
+    pub radius: i16,
+    /// Charging cost per kWh or per minute depending on `billing_type`
+    pub cost_per_unit: Option<f32>,
+    /// Fixed cost added to each charging session
+    pub session_fee: Option<f32>,
+    pub billing_type: BillingType,
+}
+
+/// Price per kWh at a geofence during a time window, used for time of use electricity rates
+///
+/// A time window with the end time before the start time ends on the next day, a window with
+/// equal times lasts the whole day.
+#[derive(Debug, Serialize, Deserialize, Default, Clone, PartialEq, Json)]
+pub struct Tariff {
+    /// Database ID, 0 for a tariff which is not created yet
+    pub id: i32,
+    pub geofence_id: i32,
+    /// Name of the tier, e.g. "Peak" or "Off-peak"
+    pub name: String,
+    /// Days the tariff applies, bit 0 is Monday and bit 6 is Sunday
+    pub weekdays: i16,
+    pub start_time: NaiveTime,
+    pub end_time: NaiveTime,
+    pub price_per_kwh: f32,
+    /// IANA time zone of the time window, e.g. "America/Los_Angeles"
+    pub time_zone: String,
+    /// First day the tariff applies
+    pub valid_from: Option<NaiveDate>,
+    /// Last day the tariff applies
+    pub valid_until: Option<NaiveDate>,
+}
@@ -1,3 +1,5 @@
+mod analytics;
+mod geofence;
 mod status;
 pub mod units;
 
@@ -5,9 +7,11 @@ use macros::Json;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+pub use analytics::{BatteryCapacity, CapacityHistory};
+pub use geofence::{BillingType, Geofence, Tariff};
 pub use status::{
     Charging, ClimateState, Driving, Location, Logging, Offline, Parked, Sleeping, State, Status,
-    Vehicle,
+    TaskStatus, Vehicle,
 };
 
 #[derive(Debug, Default, Serialize, Deserialize, PartialEq, Clone)]
@@ -28,6 +32,24 @@ pub enum Topic {
     LoggingStatus,
     #[serde(rename = "set-unit")]
     SetUnit,
+    #[serde(rename = "get-geofences")]
+    GetGeofences,
+    #[serde(rename = "create-geofence")]
+    CreateGeofence,
+    #[serde(rename = "update-geofence")]
+    UpdateGeofence,
+    #[serde(rename = "delete-geofence")]
+    DeleteGeofence,
+    #[serde(rename = "get-tariffs")]
+    GetTariffs,
+    #[serde(rename = "create-tariff")]
+    CreateTariff,
+    #[serde(rename = "update-tariff")]
+    UpdateTariff,
+    #[serde(rename = "delete-tariff")]
+    DeleteTariff,
+    #[serde(rename = "get-capacity-history")]
+    GetCapacityHistory,
     #[default]
     #[serde(rename = "unknown")]
     Unknown,
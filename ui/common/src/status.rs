@@ -51,7 +51,8 @@ pub struct Charging {
     pub starting_battery_level: Option<i16>,
     pub current_battery_level: Option<i16>,
     pub charge_added: f32,
-    pub cost: u32,
+    /// Cost so far, `None` if the location has no charging prices
+    pub cost: Option<f32>,
     pub time_remaining_sec: u32,
 }
 
@@ -106,8 +107,22 @@ pub struct Logging {
     pub unit_of_pressure: PressureUnit,
 }
 
+/// Health of a background task of the logger
+#[derive(Debug, Serialize, Deserialize, Default, Clone, PartialEq)]
+pub struct TaskStatus {
+    pub name: String,
+    pub running: bool,
+    /// Number of times the task was restarted after a failure
+    pub restarts: u32,
+    pub last_error: Option<String>,
+    pub last_restart: Option<DateTime<Utc>>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Default, Clone, Json)]
 pub struct Status {
+    /// ID of the car in the database
+    #[serde(default)]
+    pub car_id: i16,
     pub timestamp: DateTime<Utc>,
     pub app_start_time: DateTime<Utc>,
     pub state: State,
@@ -118,4 +133,7 @@ pub struct Status {
     pub parked: Option<Parked>,
     pub offline: Option<Offline>,
     pub sleeping: Option<Sleeping>,
+    /// Status of the logger tasks
+    #[serde(default)]
+    pub tasks: Vec<TaskStatus>,
 }
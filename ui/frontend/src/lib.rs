@@ -16,6 +16,7 @@ use crate::pages::home::Home;
 use crate::pages::settings::Settings;
 
 use leptos_leaflet::prelude::Position;
+use std::collections::BTreeMap;
 use std::sync::Arc;
 use ui_common::{Status, Topic, WsMessage};
 
@@ -24,8 +25,12 @@ pub struct WebsocketContext {
     pub message: Signal<Option<String>>,
     send: Arc<dyn Fn(&String) + Send + Sync>, // use Rc to make it easily cloneable
     ready_state: Signal<ConnectionReadyState>,
-    logging_status: ReadSignal<Status>,
-    is_logging: ReadSignal<bool>,
+    /// Logging status of each car, keyed by the car ID
+    statuses: ReadSignal<BTreeMap<i16, Status>>,
+    /// Car selected by the user, the first car is shown if no car is selected
+    selected_car: RwSignal<Option<i16>>,
+    logging_status: Signal<Status>,
+    is_logging: Signal<bool>,
     location: ReadSignal<Position>,
 }
 
@@ -34,14 +39,28 @@ impl WebsocketContext {
         message: Signal<Option<String>>,
         send: Arc<dyn Fn(&String) + Send + Sync>,
         ready_state: Signal<ConnectionReadyState>,
-        logging_status: ReadSignal<Status>,
-        is_logging: ReadSignal<bool>,
+        statuses: ReadSignal<BTreeMap<i16, Status>>,
+        selected_car: RwSignal<Option<i16>>,
         location: ReadSignal<Position>,
     ) -> Self {
+        let logging_status = Signal::derive(move || {
+            statuses.with(|s| {
+                selected_car
+                    .get()
+                    .and_then(|id| s.get(&id))
+                    .or_else(|| s.values().next())
+                    .cloned()
+                    .unwrap_or_default()
+            })
+        });
+        let is_logging = Signal::derive(move || logging_status.with(|s| s.logging.enabled));
+
         Self {
             message,
             send,
             ready_state,
+            statuses,
+            selected_car,
             logging_status,
             is_logging,
             location,
@@ -134,8 +153,8 @@ fn Navbar() -> impl IntoView {
 pub fn App() -> impl IntoView {
     provide_meta_context();
 
-    let (is_logging, set_is_logging) = signal(false);
-    let (logging_status, set_logging_status) = signal(Status::default());
+    let (statuses, set_statuses) = signal(BTreeMap::<i16, Status>::new());
+    let selected_car = RwSignal::new(None::<i16>);
 
     // let (is_dark_mode, set_is_dark_mode) = signal(true);
 
@@ -146,11 +165,14 @@ pub fn App() -> impl IntoView {
         Ok(m) => {
             if let Topic::LoggingStatus = m.topic {
                 let status = ui_common::Status::from_value(m.data.unwrap()).unwrap();
-                set_is_logging(status.logging.enabled);
-                set_logging_status(status.clone());
-                if let Some(l) = status.vehicle.location.coords {
-                    set_location(Position::new(l.0, l.1))
-                }
+                let car_id = status.car_id;
+                set_statuses.update(|s| {
+                    // Car ID 0 is used by the server until the data from the first car is received
+                    if car_id != 0 {
+                        s.remove(&0);
+                    }
+                    s.insert(car_id, status);
+                });
             }
         }
         Err(e) => logging::log!(
@@ -175,14 +197,24 @@ pub fn App() -> impl IntoView {
             .on_message_raw(on_message_callback),
     );
 
-    provide_context(WebsocketContext::new(
+    let websocket = WebsocketContext::new(
         message,
         Arc::new(send.clone()),
         ready_state,
-        logging_status,
-        is_logging,
+        statuses,
+        selected_car,
         location,
-    ));
+    );
+
+    // Show the location of the selected car on the map
+    let logging_status = websocket.logging_status;
+    Effect::new(move |_| {
+        if let Some(l) = logging_status.with(|s| s.vehicle.location.coords) {
+            set_location(Position::new(l.0, l.1))
+        }
+    });
+
+    provide_context(websocket);
 
     view! {
         <Html/>
@@ -1,11 +1,419 @@
 use leptos::prelude::*;
+use leptos::wasm_bindgen::JsCast;
 use leptos::*;
+use leptos_leaflet::leaflet;
+use leptos_leaflet::prelude::{
+    Circle, DragEvents, MapContainer, MapEvents, Marker, Position, TileLayer,
+};
+use leptos_use::core::ConnectionReadyState;
+use serde_json::json;
+
+use ui_common::{
+    BillingType, Geofence as GeofenceData, MessageType, Tariff as TariffData, Topic, WsMessage,
+};
+
+use crate::WebsocketContext;
+
+const DEFAULT_RADIUS_M: i16 = 25;
+const WEEKDAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+const ALL_WEEKDAYS: i16 = 0b111_1111;
+
+fn is_geofence_topic(topic: &Topic) -> bool {
+    matches!(
+        topic,
+        Topic::GetGeofences | Topic::CreateGeofence | Topic::UpdateGeofence | Topic::DeleteGeofence
+    )
+}
+
+fn is_tariff_topic(topic: &Topic) -> bool {
+    matches!(
+        topic,
+        Topic::GetTariffs | Topic::CreateTariff | Topic::UpdateTariff | Topic::DeleteTariff
+    )
+}
+
+/// Days a tariff applies, e.g. "Mon Tue Wed"
+fn weekdays_to_string(weekdays: i16) -> String {
+    WEEKDAYS
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| weekdays & (1 << i) != 0)
+        .map(|(_, day)| *day)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
 
 #[component]
 pub fn Geofence() -> impl IntoView {
+    let websocket = expect_context::<WebsocketContext>();
+
+    let geofences = RwSignal::new(Vec::<GeofenceData>::new());
+    // Geofence being edited, a new geofence if the ID is 0
+    let new_geofence = move || {
+        let location = websocket.location.get_untracked();
+        GeofenceData {
+            latitude: location.lat,
+            longitude: location.lng,
+            radius: DEFAULT_RADIUS_M,
+            ..Default::default()
+        }
+    };
+    let form = RwSignal::new(new_geofence());
+    let (error, set_error) = signal(None::<String>);
+
+    let ws_send = websocket.send.clone();
+    let send =
+        move |topic: Topic, data: Option<serde_json::Value>| match WsMessage::command(topic, data)
+            .to_string()
+        {
+            Ok(msg) => ws_send(&msg),
+            Err(e) => log::error!("{e}"),
+        };
+
+    // Request the geofences whenever the connection to the server is (re-)established
+    let send_get = send.clone();
+    Effect::new(move |_| {
+        if websocket.ready_state.get() == ConnectionReadyState::Open {
+            send_get(Topic::GetGeofences, None);
+        }
+    });
+
+    // Every geofence command is answered with the list of all geofences
+    Effect::new(move |_| {
+        let Some(msg) = websocket.message.get() else {
+            return;
+        };
+        let Ok(msg) = WsMessage::from_string(msg.as_str()) else {
+            return;
+        };
+        if msg.r#type != MessageType::Response || !is_geofence_topic(&msg.topic) {
+            return;
+        }
+
+        let data = msg.data.unwrap_or_default();
+        if data["status"] != true {
+            set_error(Some(
+                data["reason"]
+                    .as_str()
+                    .unwrap_or("Unknown error")
+                    .to_string(),
+            ));
+            return;
+        }
+        match serde_json::from_value(data["geofences"].clone()) {
+            Ok(list) => {
+                geofences.set(list);
+                set_error(None);
+                if msg.topic != Topic::GetGeofences {
+                    form.set(new_geofence());
+                }
+            }
+            Err(e) => log::error!("Cannot parse geofences: {e}"),
+        }
+    });
+
+    let center = Signal::derive(move || form.with(|g| Position::new(g.latitude, g.longitude)));
+    let radius = Signal::derive(move || form.with(|g| f64::from(g.radius)));
+    let set_center = move |lat: f64, lng: f64| {
+        form.update(|g| {
+            g.latitude = lat;
+            g.longitude = lng;
+        })
+    };
+
+    // Move the geofence by clicking on the map or by dragging the marker
+    let map_events = MapEvents::new().mouse_click(move |e: leaflet::MouseEvent| {
+        let lat_lng = e.lat_lng();
+        set_center(lat_lng.lat(), lat_lng.lng());
+    });
+    let drag_events = DragEvents::new().drag_end(move |e: leaflet::DragEndEvent| {
+        let lat_lng = e.target().unchecked_into::<leaflet::Marker>().get_lat_lng();
+        set_center(lat_lng.lat(), lat_lng.lng());
+    });
+
+    let parse_cost = |value: String| value.trim().parse::<f32>().ok();
+
+    let send_save = send.clone();
+    let save = move |_| {
+        let geofence = form.get_untracked();
+        let topic = if geofence.id == 0 {
+            Topic::CreateGeofence
+        } else {
+            Topic::UpdateGeofence
+        };
+        match serde_json::to_value(&geofence) {
+            Ok(data) => send_save(topic, Some(data)),
+            Err(e) => log::error!("{e}"),
+        }
+    };
+
+    let geofence_id = Memo::new(move |_| form.with(|g| g.id));
+    let tariffs = RwSignal::new(Vec::<TariffData>::new());
+    // Tariff being edited, a new tariff of the edited geofence if the ID is 0
+    let new_tariff = move || TariffData {
+        geofence_id: geofence_id.get_untracked(),
+        weekdays: ALL_WEEKDAYS,
+        time_zone: "UTC".to_string(),
+        ..Default::default()
+    };
+    let tariff_form = RwSignal::new(new_tariff());
+    let (tariff_error, set_tariff_error) = signal(None::<String>);
+
+    // Request the tariffs whenever another geofence is edited
+    let send_get_tariffs = send.clone();
+    Effect::new(move |_| {
+        let id = geofence_id.get();
+        tariffs.set(vec![]);
+        tariff_form.set(new_tariff());
+        set_tariff_error(None);
+        if id != 0 && websocket.ready_state.get() == ConnectionReadyState::Open {
+            send_get_tariffs(Topic::GetTariffs, Some(json!({"geofence_id": id})));
+        }
+    });
+
+    // Every tariff command is answered with the tariffs of the geofence
+    Effect::new(move |_| {
+        let Some(msg) = websocket.message.get() else {
+            return;
+        };
+        let Ok(msg) = WsMessage::from_string(msg.as_str()) else {
+            return;
+        };
+        if msg.r#type != MessageType::Response || !is_tariff_topic(&msg.topic) {
+            return;
+        }
+
+        let data = msg.data.unwrap_or_default();
+        if data["status"] != true {
+            set_tariff_error(Some(
+                data["reason"]
+                    .as_str()
+                    .unwrap_or("Unknown error")
+                    .to_string(),
+            ));
+            return;
+        }
+        // Another geofence may have been selected in the meantime
+        if data["geofence_id"] != geofence_id.get_untracked() {
+            return;
+        }
+        match serde_json::from_value(data["tariffs"].clone()) {
+            Ok(list) => {
+                tariffs.set(list);
+                set_tariff_error(None);
+                if msg.topic != Topic::GetTariffs {
+                    tariff_form.set(new_tariff());
+                }
+            }
+            Err(e) => log::error!("Cannot parse tariffs: {e}"),
+        }
+    });
+
+    let send_save_tariff = send.clone();
+    let save_tariff = move |_| {
+        let tariff = tariff_form.get_untracked();
+        let topic = if tariff.id == 0 {
+            Topic::CreateTariff
+        } else {
+            Topic::UpdateTariff
+        };
+        match serde_json::to_value(&tariff) {
+            Ok(data) => send_save_tariff(topic, Some(data)),
+            Err(e) => log::error!("{e}"),
+        }
+    };
+
+    let send_delete_tariff = send.clone();
+    let tariff_list = move || {
+        tariffs
+            .get()
+            .into_iter()
+            .map(|t| {
+                let send_delete_tariff = send_delete_tariff.clone();
+                let id = t.id;
+                let edit = t.clone();
+                let validity = match (t.valid_from, t.valid_until) {
+                    (Some(from), Some(until)) => format!(", {from} to {until}"),
+                    (Some(from), None) => format!(", from {from}"),
+                    (None, Some(until)) => format!(", until {until}"),
+                    (None, None) => "".to_string(),
+                };
+                let window = format!(
+                    "{} {}-{} {}{validity}",
+                    weekdays_to_string(t.weekdays),
+                    t.start_time.format("%H:%M"),
+                    t.end_time.format("%H:%M"),
+                    t.time_zone
+                );
+                view! {
+                    <li class="flex items-center justify-between py-2">
+                        <div>
+                            <p class="text-content-1">{t.name}" "{format!("{} per kWh", t.price_per_kwh)}</p>
+                            <p class="text-sm font-thin text-content-2">{window}</p>
+                        </div>
+                        <div class="flex">
+                            <button type="button" on:click=move |_| tariff_form.set(edit.clone()) class="ml-2 rounded-lg border border-content-2 px-3 py-1 text-sm text-content-1 hover:bg-bkg-2">Edit</button>
+                            <button type="button" on:click=move |_| send_delete_tariff(Topic::DeleteTariff, Some(json!({"id": id}))) class="ml-2 rounded-lg bg-red-700 px-3 py-1 text-sm text-bkg-2 hover:bg-red-800">Delete</button>
+                        </div>
+                    </li>
+                }
+            })
+            .collect_view()
+    };
+
+    let weekday_inputs = WEEKDAYS
+        .iter()
+        .enumerate()
+        .map(|(i, day)| {
+            let bit = 1i16 << i;
+            view! {
+                <label class="mr-3 inline-flex items-center text-sm text-content-1">
+                    <input type="checkbox" prop:checked=move || tariff_form.with(|t| t.weekdays & bit != 0) on:change=move |ev| tariff_form.update(|t| if event_target_checked(&ev) { t.weekdays |= bit } else { t.weekdays &= !bit }) class="mr-1" />
+                    {*day}
+                </label>
+            }
+        })
+        .collect_view();
+
+    let send_delete = send;
+    let geofence_list = move || {
+        geofences
+            .get()
+            .into_iter()
+            .map(|g| {
+                let send_delete = send_delete.clone();
+                let id = g.id;
+                let edit = g.clone();
+                let cost = match (g.cost_per_unit, g.session_fee) {
+                    (Some(c), Some(f)) => format!("{c} {} + {f} per session", g.billing_type.to_str()),
+                    (Some(c), None) => format!("{c} {}", g.billing_type.to_str()),
+                    (None, Some(f)) => format!("{f} per session"),
+                    (None, None) => "".to_string(),
+                };
+                view! {
+                    <li class="flex items-center justify-between py-2">
+                        <div>
+                            <p class="text-content-1">{g.name}</p>
+                            <p class="text-sm font-thin text-content-2">{format!("{} m", g.radius)}" "{cost}</p>
+                        </div>
+                        <div class="flex">
+                            <button type="button" on:click=move |_| form.set(edit.clone()) class="ml-2 rounded-lg border border-content-2 px-3 py-1 text-sm text-content-1 hover:bg-bkg-2">Edit</button>
+                            <button type="button" on:click=move |_| send_delete(Topic::DeleteGeofence, Some(json!({"id": id}))) class="ml-2 rounded-lg bg-red-700 px-3 py-1 text-sm text-bkg-2 hover:bg-red-800">Delete</button>
+                        </div>
+                    </li>
+                }
+            })
+            .collect_view()
+    };
+
+    let input_class = "block w-full rounded-lg border border-content-2 bg-bkg-2 text-sm text-content-1 focus:border-blue-500 focus:ring-blue-500";
+    let label_class = "mb-2 block text-sm font-medium text-content-1";
+
     view! {
-        <div class="border border-rose-500 mt-8 rounded-lg">
-            <h1>Geo-fence</h1>
+        <MapContainer style="height: 400px" center=websocket.location.get_untracked() zoom=17.0 set_view=true events=map_events class="z-0">
+            <TileLayer url="https://tile.openstreetmap.org/{z}/{x}/{y}.png"/>
+            <Circle center=center radius=radius color="blue" />
+            <Marker position=center draggable=true drag_events=drag_events />
+        </MapContainer>
+        <div class="mx-auto max-w-sm pt-8">
+            <p class="mb-5 text-sm font-thin text-content-2">Click on the map or drag the marker to move the geofence</p>
+            <div class="mb-5">
+                <label for="geofence_name" class=label_class>Name</label>
+                <input type="text" id="geofence_name" prop:value=move || form.with(|g| g.name.clone()) on:input=move |ev| form.update(|g| g.name = event_target_value(&ev)) class=input_class />
+            </div>
+            <div class="mb-5">
+                <label for="geofence_radius" class=label_class>Radius (m)</label>
+                <input type="number" id="geofence_radius" min="1" prop:value=move || form.with(|g| g.radius.to_string()) on:input=move |ev| form.update(|g| g.radius = event_target_value(&ev).parse().unwrap_or(g.radius)) class=input_class />
+            </div>
+            <div class="mb-5">
+                <label for="geofence_billing_type" class=label_class>Billing</label>
+                <select
+                    id="geofence_billing_type"
+                    prop:value=move || form.with(|g| if g.billing_type == BillingType::PerMinute { "per_minute" } else { "per_kwh" })
+                    on:change=move |ev| form.update(|g| g.billing_type = if event_target_value(&ev) == "per_minute" { BillingType::PerMinute } else { BillingType::PerKwh })
+                    class=input_class
+                >
+                    <option value="per_kwh">{BillingType::PerKwh.to_str()}</option>
+                    <option value="per_minute">{BillingType::PerMinute.to_str()}</option>
+                </select>
+            </div>
+            <div class="mb-5 flex">
+                <div class="mr-2 w-full">
+                    <label for="geofence_cost" class=label_class>Cost per unit</label>
+                    <input type="number" id="geofence_cost" min="0" step="0.01" prop:value=move || form.with(|g| g.cost_per_unit.map(|c| c.to_string()).unwrap_or_default()) on:input=move |ev| form.update(|g| g.cost_per_unit = parse_cost(event_target_value(&ev))) class=input_class />
+                </div>
+                <div class="w-full">
+                    <label for="geofence_session_fee" class=label_class>Session fee</label>
+                    <input type="number" id="geofence_session_fee" min="0" step="0.01" prop:value=move || form.with(|g| g.session_fee.map(|c| c.to_string()).unwrap_or_default()) on:input=move |ev| form.update(|g| g.session_fee = parse_cost(event_target_value(&ev))) class=input_class />
+                </div>
+            </div>
+            {move || error.get().map(|e| view! { <p class="mb-5 text-sm text-red-600">{e}</p> })}
+            <div class="mb-5 flex">
+                <button type="button" on:click=save class="rounded-lg bg-blue-700 px-5 py-2.5 text-center text-sm font-medium text-bkg-2 hover:bg-blue-800 focus:outline-none focus:ring-4 focus:ring-blue-300">
+                    {move || if form.with(|g| g.id == 0) { "Add" } else { "Save" }}
+                </button>
+                <Show when=move || form.with(|g| g.id != 0)>
+                    <button type="button" on:click=move |_| form.set(new_geofence()) class="ml-2 rounded-lg border border-content-2 px-5 py-2.5 text-sm text-content-1 hover:bg-bkg-2">Cancel</button>
+                </Show>
+            </div>
+            <ul class="divide-y divide-border">
+                {geofence_list}
+            </ul>
+            // Tariffs of the edited geofence
+            <div class:hidden=move || geofence_id.get() == 0 class="pt-8">
+                <p class="mb-2 text-lg font-medium text-content-1">Tariffs</p>
+                <p class="mb-5 text-sm font-thin text-content-2">Price per kWh during a time window, the cost of the geofence applies outside of the tariffs. A window ending before its start time ends on the next day.</p>
+                <div class="mb-5 flex">
+                    <div class="mr-2 w-full">
+                        <label for="tariff_name" class=label_class>Name</label>
+                        <input type="text" id="tariff_name" prop:value=move || tariff_form.with(|t| t.name.clone()) on:input=move |ev| tariff_form.update(|t| t.name = event_target_value(&ev)) class=input_class />
+                    </div>
+                    <div class="w-full">
+                        <label for="tariff_price" class=label_class>Price per kWh</label>
+                        <input type="number" id="tariff_price" min="0" step="0.01" prop:value=move || tariff_form.with(|t| t.price_per_kwh.to_string()) on:input=move |ev| tariff_form.update(|t| t.price_per_kwh = event_target_value(&ev).trim().parse().unwrap_or(t.price_per_kwh)) class=input_class />
+                    </div>
+                </div>
+                <div class="mb-5 flex">
+                    <div class="mr-2 w-full">
+                        <label for="tariff_start_time" class=label_class>Start</label>
+                        <input type="time" id="tariff_start_time" prop:value=move || tariff_form.with(|t| t.start_time.format("%H:%M").to_string()) on:change=move |ev| tariff_form.update(|t| t.start_time = event_target_value(&ev).parse().unwrap_or(t.start_time)) class=input_class />
+                    </div>
+                    <div class="w-full">
+                        <label for="tariff_end_time" class=label_class>End</label>
+                        <input type="time" id="tariff_end_time" prop:value=move || tariff_form.with(|t| t.end_time.format("%H:%M").to_string()) on:change=move |ev| tariff_form.update(|t| t.end_time = event_target_value(&ev).parse().unwrap_or(t.end_time)) class=input_class />
+                    </div>
+                </div>
+                <div class="mb-5">
+                    <p class=label_class>Days</p>
+                    {weekday_inputs}
+                </div>
+                <div class="mb-5">
+                    <label for="tariff_time_zone" class=label_class>Time zone</label>
+                    <input type="text" id="tariff_time_zone" placeholder="Europe/Berlin" prop:value=move || tariff_form.with(|t| t.time_zone.clone()) on:input=move |ev| tariff_form.update(|t| t.time_zone = event_target_value(&ev).trim().to_string()) class=input_class />
+                </div>
+                <div class="mb-5 flex">
+                    <div class="mr-2 w-full">
+                        <label for="tariff_valid_from" class=label_class>Valid from</label>
+                        <input type="date" id="tariff_valid_from" prop:value=move || tariff_form.with(|t| t.valid_from.map(|d| d.to_string()).unwrap_or_default()) on:change=move |ev| tariff_form.update(|t| t.valid_from = event_target_value(&ev).parse().ok()) class=input_class />
+                    </div>
+                    <div class="w-full">
+                        <label for="tariff_valid_until" class=label_class>Valid until</label>
+                        <input type="date" id="tariff_valid_until" prop:value=move || tariff_form.with(|t| t.valid_until.map(|d| d.to_string()).unwrap_or_default()) on:change=move |ev| tariff_form.update(|t| t.valid_until = event_target_value(&ev).parse().ok()) class=input_class />
+                    </div>
+                </div>
+                {move || tariff_error.get().map(|e| view! { <p class="mb-5 text-sm text-red-600">{e}</p> })}
+                <div class="mb-5 flex">
+                    <button type="button" on:click=save_tariff class="rounded-lg bg-blue-700 px-5 py-2.5 text-center text-sm font-medium text-bkg-2 hover:bg-blue-800 focus:outline-none focus:ring-4 focus:ring-blue-300">
+                        {move || if tariff_form.with(|t| t.id == 0) { "Add tariff" } else { "Save tariff" }}
+                    </button>
+                    <Show when=move || tariff_form.with(|t| t.id != 0)>
+                        <button type="button" on:click=move |_| tariff_form.set(new_tariff()) class="ml-2 rounded-lg border border-content-2 px-5 py-2.5 text-sm text-content-1 hover:bg-bkg-2">Cancel</button>
+                    </Show>
+                </div>
+                <ul class="divide-y divide-border">
+                    {tariff_list}
+                </ul>
+            </div>
         </div>
     }
 }
@@ -144,11 +144,55 @@ fn vehicle_status(status: Status) -> impl IntoView {
                     </div>
                 </div>
                 <p class="font-normal text-content-2">{status.logging.total_num_points} points logged</p>
+                // Tasks restarted after a failure, e.g. when the Tesla API was not reachable
+                {status.tasks.into_iter().filter(|t| t.restarts > 0).map(|t| view! {
+                    <p class="font-thin text-sm text-content-2" title=t.last_error.clone().unwrap_or_default()>
+                        {format!("{} restarted {} times{}", t.name, t.restarts, if t.running { "" } else { ", not running" })}
+                    </p>
+                }).collect_view()}
             </div>
         </div>
     }
 }
 
+/// Drop down list to select the car shown on the page, hidden if there is only one car
+#[component]
+fn CarSelector() -> impl IntoView {
+    let websocket = expect_context::<WebsocketContext>();
+    let statuses = websocket.statuses;
+    let selected_car = websocket.selected_car;
+    let logging_status = websocket.logging_status;
+
+    let options = move || {
+        statuses
+            .get()
+            .into_iter()
+            .map(|(car_id, status)| {
+                let name = if status.vehicle.name.is_empty() {
+                    format!("Car {car_id}")
+                } else {
+                    status.vehicle.name
+                };
+                view! { <option value=car_id.to_string()>{name}</option> }
+            })
+            .collect_view()
+    };
+
+    view! {
+        <Show when=move || statuses.with(|s| s.len() > 1)>
+            <div class="flex justify-center pt-4">
+                <select
+                    on:change=move |ev| selected_car.set(event_target_value(&ev).parse::<i16>().ok())
+                    prop:value=move || logging_status.with(|s| s.car_id.to_string())
+                    class="block rounded-lg border border-content-2 bg-bkg-1 p-2 text-sm text-content-1 focus:border-blue-500 focus:ring-blue-500"
+                >
+                    {options}
+                </select>
+            </div>
+        </Show>
+    }
+}
+
 #[component]
 fn DriveDetails() -> impl IntoView {
     view! {
@@ -274,6 +318,7 @@ pub fn Home() -> impl IntoView {
                     </Popup>
                 </Marker>
             </MapContainer>
+            <CarSelector />
             <div class="grid gap-4 sm:grid-cols-1 md:grid-cols-2 lg:grid-cols-3 xl:grid-cols-4 pt-4">
                 <div class="rounded md:border border-border bg-bkg-1">
                     {move || vehicle_status(websocket.logging_status.get())}